@@ -0,0 +1,152 @@
+//! Which seats are next to each other. Trains can list the neighbours of
+//! each seat; in trains that don't, seats are next to each other when
+//! they're side by side in the layout of their coach, or, in coaches without
+//! a layout, when they're numbered one apart.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::train::{Coach, Seat, SeatId};
+
+/// The seats next to each seat. Being next to each other goes both ways,
+/// even if the train data only lists it for one of the seats.
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct Adjacency {
+    neighbours: HashMap<SeatId, HashSet<SeatId>>,
+}
+
+impl Adjacency {
+    pub fn new(seats: &HashMap<SeatId, Seat>, coaches: &HashMap<String, Coach>) -> Self {
+        let mut adjacency = Adjacency::default();
+        if seats.values().any(|seat| !seat.neighbours().is_empty()) {
+            for (seat_id, seat) in seats {
+                for neighbour in seat.neighbours() {
+                    if seats.contains_key(neighbour) && neighbour != seat_id {
+                        adjacency.connect(seat_id, neighbour);
+                    }
+                }
+            }
+        } else {
+            let by_number: HashMap<(&str, &str), &SeatId> = seats
+                .iter()
+                .map(|(seat_id, seat)| ((seat.coach(), seat.seat_number()), seat_id))
+                .collect();
+            for (seat_id, seat) in seats {
+                let Ok(number) = seat.seat_number().parse::<usize>() else {
+                    continue;
+                };
+                let next = (number + 1).to_string();
+                let Some(neighbour) = by_number.get(&(seat.coach(), next.as_str())) else {
+                    continue;
+                };
+                let layout = coaches.get(seat.coach()).and_then(|coach| coach.layout);
+                let side_by_side = layout.is_none_or(|layout| {
+                    match (layout.place(seat.seat_number()), layout.place(&next)) {
+                        (Some(a), Some(b)) => layout.are_adjacent(a, b),
+                        _ => false,
+                    }
+                });
+                if side_by_side {
+                    adjacency.connect(seat_id, neighbour);
+                }
+            }
+        }
+        adjacency
+    }
+
+    fn connect(&mut self, a: &SeatId, b: &SeatId) {
+        self.neighbours
+            .entry(a.clone())
+            .or_default()
+            .insert(b.clone());
+        self.neighbours
+            .entry(b.clone())
+            .or_default()
+            .insert(a.clone());
+    }
+
+    pub fn neighbours(&self, seat_id: &SeatId) -> impl Iterator<Item = &SeatId> {
+        self.neighbours.get(seat_id).into_iter().flatten()
+    }
+
+    #[cfg(test)]
+    pub fn are_adjacent(&self, a: &SeatId, b: &SeatId) -> bool {
+        self.neighbours
+            .get(a)
+            .is_some_and(|neighbours| neighbours.contains(b))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::Layout;
+
+    fn seats(seats: &[(&str, &[&str])]) -> HashMap<SeatId, Seat> {
+        seats
+            .iter()
+            .map(|(seat_id, neighbours)| {
+                let seat_id = SeatId::new(seat_id);
+                let (seat_number, coach) = seat_id.split();
+                let seat = Seat::new(seat_number.to_string(), coach.to_string())
+                    .with_neighbours(neighbours.iter().map(|id| SeatId::new(id)).collect());
+                (seat_id, seat)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_numbered_neighbours() {
+        let adjacency = Adjacency::new(
+            &seats(&[("1A", &[]), ("2A", &[]), ("3A", &[]), ("1B", &[])]),
+            &HashMap::new(),
+        );
+        let id = SeatId::new;
+        assert!(adjacency.are_adjacent(&id("1A"), &id("2A")));
+        assert!(adjacency.are_adjacent(&id("3A"), &id("2A")));
+        assert!(!adjacency.are_adjacent(&id("1A"), &id("3A")));
+        assert!(!adjacency.are_adjacent(&id("1A"), &id("1B")));
+    }
+
+    #[test]
+    fn test_listed_neighbours() {
+        // 1A and 3A face each other across a table; 2A is across the aisle
+        let adjacency = Adjacency::new(
+            &seats(&[("1A", &["3A", "9Z"]), ("2A", &[]), ("3A", &[])]),
+            &HashMap::new(),
+        );
+        let id = SeatId::new;
+        assert!(adjacency.are_adjacent(&id("3A"), &id("1A")));
+        assert!(!adjacency.are_adjacent(&id("1A"), &id("2A")));
+        assert_eq!(
+            adjacency.neighbours(&id("1A")).collect::<Vec<_>>(),
+            [&id("3A")]
+        );
+        assert_eq!(adjacency.neighbours(&id("2A")).count(), 0);
+    }
+
+    #[test]
+    fn test_layout_neighbours() {
+        // two seats on each side of the aisle, in two rows
+        let coach = Coach {
+            layout: Some(Layout {
+                rows: 2,
+                columns: 4,
+                aisle_after: Some(2),
+            }),
+            ..Coach::default()
+        };
+        let seats = seats(&[
+            ("1A", &[]),
+            ("2A", &[]),
+            ("3A", &[]),
+            ("4A", &[]),
+            ("5A", &[]),
+        ]);
+        let adjacency = Adjacency::new(&seats, &HashMap::from([("A".to_string(), coach)]));
+        let id = SeatId::new;
+        assert!(adjacency.are_adjacent(&id("1A"), &id("2A")));
+        assert!(adjacency.are_adjacent(&id("3A"), &id("4A")));
+        assert!(!adjacency.are_adjacent(&id("2A"), &id("3A")));
+        assert!(!adjacency.are_adjacent(&id("4A"), &id("5A")));
+    }
+}
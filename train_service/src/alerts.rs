@@ -0,0 +1,104 @@
+//! Telling operators when a train is nearly full, so exercises can react to
+//! capacity running out.
+
+use std::sync::Arc;
+
+use crate::notify::{Event, Notification, Notifier};
+use crate::occupancy::Counts;
+use crate::train::TrainId;
+
+/// A train went over the near-full threshold.
+#[derive(Debug, PartialEq, Eq, Clone, serde::Serialize)]
+pub struct NearlyFull {
+    pub train_id: TrainId,
+    pub reserved: usize,
+    pub total: usize,
+    pub remaining: usize,
+}
+
+/// Sends an alert when a train goes over the fraction of reserved seats in
+/// `threshold`. It's sent again only after the train has dropped below it.
+pub struct NearFullAlert {
+    threshold: f64,
+    notifier: Arc<dyn Notifier>,
+}
+
+impl std::fmt::Debug for NearFullAlert {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("NearFullAlert")
+            .field("threshold", &self.threshold)
+            .finish_non_exhaustive()
+    }
+}
+
+impl NearFullAlert {
+    pub fn new(threshold: f64, notifier: Arc<dyn Notifier>) -> Self {
+        NearFullAlert {
+            threshold,
+            notifier,
+        }
+    }
+
+    /// Check a train that changed from `before`, if it was known, to `after`.
+    pub fn check(&self, train_id: &TrainId, before: Option<Counts>, after: Counts) {
+        let is_nearly_full = |counts: Counts| {
+            counts.total > 0 && counts.reserved as f64 >= self.threshold * counts.total as f64
+        };
+        if is_nearly_full(after) && !before.is_some_and(is_nearly_full) {
+            self.notifier
+                .notify(&Notification::new(Event::NearlyFull(NearlyFull {
+                    train_id: train_id.clone(),
+                    reserved: after.reserved,
+                    total: after.total,
+                    remaining: after.free(),
+                })));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    #[derive(Default)]
+    struct RecordingNotifier(Mutex<Vec<Event>>);
+
+    impl Notifier for RecordingNotifier {
+        fn notify(&self, notification: &Notification) {
+            self.0.lock().unwrap().push(notification.event.clone());
+        }
+    }
+
+    #[test]
+    fn test_near_full_alert() {
+        let notifier = Arc::new(RecordingNotifier::default());
+        let alert = NearFullAlert::new(0.9, notifier.clone());
+        let train_id = TrainId::new("local_1000");
+        let counts = |reserved| Counts {
+            reserved,
+            total: 10,
+        };
+        alert.check(&train_id, Some(counts(7)), counts(8));
+        assert!(notifier.0.lock().unwrap().is_empty());
+
+        alert.check(&train_id, Some(counts(8)), counts(9));
+        assert_eq!(
+            *notifier.0.lock().unwrap(),
+            [Event::NearlyFull(NearlyFull {
+                train_id: train_id.clone(),
+                reserved: 9,
+                total: 10,
+                remaining: 1,
+            })]
+        );
+        // not again while it stays nearly full
+        alert.check(&train_id, Some(counts(9)), counts(10));
+        assert_eq!(notifier.0.lock().unwrap().len(), 1);
+        // but again once it filled up after dropping below
+        alert.check(&train_id, Some(counts(10)), counts(5));
+        alert.check(&train_id, Some(counts(5)), counts(10));
+        assert_eq!(notifier.0.lock().unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,607 @@
+//! Picking free seats for a number of passengers, so clients don't have to
+//! choose seat ids themselves.
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use crate::adjacency::Adjacency;
+use crate::train::{Error, Seat, SeatId, SeatPosition, Train};
+
+/// What passengers would like; these are satisfied as well as possible, but
+/// never make a reservation fail.
+#[derive(Debug, Default, PartialEq, Eq, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Preferences {
+    pub window: bool,
+    pub aisle: bool,
+    /// `true` to ask for a quiet coach, `false` to avoid one.
+    pub quiet: Option<bool>,
+    /// A seat to sit close to, for instance one reserved earlier.
+    pub near: Option<SeatId>,
+    /// Whether accessible seats are needed. Those are only handed out
+    /// without this if there's nothing else left.
+    pub accessible: bool,
+}
+
+/// For each preference that was asked for, whether it was met.
+#[derive(Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct PreferencesMet {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub window: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aisle: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quiet: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub near: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub accessible: Option<bool>,
+}
+
+/// How to pick seats for a group that can't sit next to each other.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Strategy {
+    /// The seats closest to each other, so the group is spread out as
+    /// little as possible.
+    #[default]
+    Compact,
+    /// The seats that best match the preferences, wherever they are.
+    Preferences,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Allocation {
+    pub seats: Vec<SeatId>,
+    pub preferences_met: PreferencesMet,
+    /// How spread out the seats are: the distances between every two of
+    /// them, added up.
+    pub spread: usize,
+}
+
+/// How far apart seats are. In the same coach that's the difference in seat
+/// numbers; each coach further along adds the length of a coach.
+pub struct SeatDistance<'a> {
+    // in the order they're coupled
+    coaches: Vec<&'a str>,
+}
+
+// a coach counts as this many seats
+const COACH_LENGTH: usize = 100;
+
+impl<'a> SeatDistance<'a> {
+    pub fn new(train: &'a Train) -> Self {
+        let coaches: BTreeSet<&str> = train.seats().map(|(_, seat)| seat.coach()).collect();
+        SeatDistance {
+            coaches: coaches.into_iter().collect(),
+        }
+    }
+
+    /// The distances between every two seats, added up.
+    pub fn spread(&self, seats: &[(&SeatId, &Seat)]) -> usize {
+        seats
+            .iter()
+            .enumerate()
+            .flat_map(|(i, (_, a))| seats[i + 1..].iter().map(|(_, b)| self.between(a, b)))
+            .sum()
+    }
+
+    // where a seat is along the train, for sorting
+    fn position(&self, seat: &Seat) -> (usize, usize) {
+        (self.coach(seat), seat_number(seat))
+    }
+
+    fn coach(&self, seat: &Seat) -> usize {
+        self.coaches
+            .iter()
+            .position(|coach| *coach == seat.coach())
+            .unwrap_or_default()
+    }
+
+    pub fn between(&self, a: &Seat, b: &Seat) -> usize {
+        self.coach(a).abs_diff(self.coach(b)) * COACH_LENGTH
+            + seat_number(a).abs_diff(seat_number(b))
+    }
+}
+
+/// The free seats closest to a seat, nearest first, with how far away they
+/// are. Accessible seats are only included when asked for.
+pub fn nearest<'a>(
+    train: &'a Train,
+    seat_id: &SeatId,
+    count: usize,
+    accessible: bool,
+) -> Option<Vec<(&'a SeatId, usize)>> {
+    let seat = train.get(seat_id)?;
+    let distance = SeatDistance::new(train);
+    let mut free = train
+        .seats()
+        .filter(|(other_id, other)| {
+            *other_id != seat_id
+                && train.is_available(other)
+                && (accessible || !other.is_accessible())
+        })
+        .collect::<Vec<_>>();
+    free.sort_by_key(|(_, other)| {
+        (
+            distance.between(seat, other),
+            other.coach(),
+            seat_number(other),
+        )
+    });
+    Some(
+        free.into_iter()
+            .take(count)
+            .map(|(other_id, other)| (other_id, distance.between(seat, other)))
+            .collect(),
+    )
+}
+
+/// Choose `seat_count` free seats on a train. Seats in a single coach are
+/// preferred, and seats next to each other most of all; within that, the
+/// seats that best match the preferences win. When the group can't sit next
+/// to each other, the strategy decides.
+pub fn allocate(
+    train: &Train,
+    seat_count: usize,
+    preferences: &Preferences,
+    strategy: Strategy,
+) -> Result<Allocation, Error> {
+    let _span = tracing::info_span!("allocate", seat_count).entered();
+    let near = preferences
+        .near
+        .as_ref()
+        .and_then(|seat_id| train.get(seat_id));
+    let distance = SeatDistance::new(train);
+    let rank = |seat: &Seat| {
+        (
+            Reverse(score(train, seat, preferences, near)),
+            near.map(|near| distance.between(seat, near)),
+            seat_number(seat),
+        )
+    };
+
+    let free = train
+        .seats()
+        .filter(|(_, seat)| train.is_available(seat))
+        .collect::<Vec<_>>();
+    if seat_count == 0 || free.len() < seat_count {
+        return Err(Error::NotEnoughFreeSeats(seat_count));
+    }
+    // leave accessible seats alone unless they're needed, or there's no
+    // room otherwise
+    let others = free
+        .iter()
+        .filter(|(_, seat)| !seat.is_accessible())
+        .count();
+    let skip_accessible = !preferences.accessible && others >= seat_count;
+    let mut by_coach: BTreeMap<&str, Vec<(&SeatId, &Seat)>> = BTreeMap::new();
+    for (seat_id, seat) in free {
+        if !(skip_accessible && seat.is_accessible()) {
+            by_coach
+                .entry(seat.coach())
+                .or_default()
+                .push((seat_id, seat));
+        }
+    }
+
+    let total = |seats: &[(&SeatId, &Seat)]| -> i64 {
+        seats
+            .iter()
+            .map(|(_, seat)| score(train, seat, preferences, near))
+            .sum()
+    };
+    let mut best: Option<(bool, i64, Seats)> = None;
+    for seats in by_coach.values_mut() {
+        if seats.len() < seat_count {
+            continue;
+        }
+        seats.sort_by_key(|(_, seat)| rank(seat));
+        let (together, chosen) = match together(train.adjacency(), seats, seat_count, total) {
+            Some(chosen) => (true, chosen),
+            None => (false, apart(strategy, &distance, seats, seat_count, total)),
+        };
+        let total = total(&chosen);
+        if best.as_ref().is_none_or(|(best_together, best_total, _)| {
+            (together, total) > (*best_together, *best_total)
+        }) {
+            best = Some((together, total, chosen));
+        }
+    }
+    let chosen = match best {
+        Some((_, _, chosen)) => chosen,
+        // nobody can sit together, so take the best seats anywhere
+        None => {
+            let mut seats = by_coach.into_values().flatten().collect::<Vec<_>>();
+            seats.sort_by_key(|(_, seat)| rank(seat));
+            apart(strategy, &distance, &seats, seat_count, total)
+        }
+    };
+
+    let preferences_met = PreferencesMet {
+        window: preferences
+            .window
+            .then(|| has_position(&chosen, SeatPosition::Window)),
+        aisle: preferences
+            .aisle
+            .then(|| has_position(&chosen, SeatPosition::Aisle)),
+        quiet: preferences.quiet.map(|quiet| {
+            chosen
+                .iter()
+                .all(|(_, seat)| train.is_quiet(seat.coach()) == quiet)
+        }),
+        near: preferences.near.as_ref().map(|_| {
+            near.is_some_and(|near| chosen.iter().all(|(_, seat)| seat.coach() == near.coach()))
+        }),
+        accessible: preferences
+            .accessible
+            .then(|| chosen.iter().any(|(_, seat)| seat.is_accessible())),
+    };
+    Ok(Allocation {
+        spread: distance.spread(&chosen),
+        seats: chosen
+            .into_iter()
+            .map(|(seat_id, _)| seat_id.clone())
+            .collect(),
+        preferences_met,
+    })
+}
+
+type Seats<'a> = Vec<(&'a SeatId, &'a Seat)>;
+
+// the best group of seats next to each other, if there is one; each free
+// seat in turn starts a group, which grows by the best ranked seat next to
+// it until it's big enough. `seats` are sorted by rank.
+fn together<'a>(
+    adjacency: &Adjacency,
+    seats: &[(&'a SeatId, &'a Seat)],
+    seat_count: usize,
+    total: impl Fn(&[(&SeatId, &Seat)]) -> i64,
+) -> Option<Seats<'a>> {
+    let index: HashMap<&SeatId, usize> = seats
+        .iter()
+        .enumerate()
+        .map(|(i, (seat_id, _))| (*seat_id, i))
+        .collect();
+    let mut best: Option<(i64, Seats<'a>)> = None;
+    for start in 0..seats.len() {
+        let mut group = vec![start];
+        while group.len() < seat_count {
+            let next = group
+                .iter()
+                .flat_map(|&i| adjacency.neighbours(seats[i].0))
+                .filter_map(|seat_id| index.get(seat_id).copied())
+                .filter(|i| !group.contains(i))
+                .min();
+            match next {
+                Some(next) => group.push(next),
+                None => break,
+            }
+        }
+        if group.len() < seat_count {
+            continue;
+        }
+        // back in order of rank
+        group.sort();
+        let chosen = group.into_iter().map(|i| seats[i]).collect::<Vec<_>>();
+        let total = total(&chosen);
+        if best
+            .as_ref()
+            .is_none_or(|(best_total, _)| total > *best_total)
+        {
+            best = Some((total, chosen));
+        }
+    }
+    best.map(|(_, chosen)| chosen)
+}
+
+// seats for a group that can't sit together; `seats` are sorted by rank
+fn apart<'a>(
+    strategy: Strategy,
+    distance: &SeatDistance,
+    seats: &[(&'a SeatId, &'a Seat)],
+    seat_count: usize,
+    total: impl Fn(&[(&SeatId, &Seat)]) -> i64,
+) -> Seats<'a> {
+    match strategy {
+        Strategy::Compact => compact(distance, seats, seat_count, total),
+        Strategy::Preferences => seats[..seat_count].to_vec(),
+    }
+}
+
+// the run of seats along the train that's spread out the least, and of
+// those, the one that best matches the preferences; `seats` are sorted by
+// rank, and so is the result
+fn compact<'a>(
+    distance: &SeatDistance,
+    seats: &[(&'a SeatId, &'a Seat)],
+    seat_count: usize,
+    total: impl Fn(&[(&SeatId, &Seat)]) -> i64,
+) -> Seats<'a> {
+    let mut along = (0..seats.len()).collect::<Vec<_>>();
+    along.sort_by_key(|&i| distance.position(seats[i].1));
+    along
+        .windows(seat_count)
+        .map(|run| {
+            let mut run = run.to_vec();
+            run.sort();
+            run.into_iter().map(|i| seats[i]).collect::<Vec<_>>()
+        })
+        .min_by_key(|chosen| (distance.spread(chosen), Reverse(total(chosen))))
+        .unwrap_or_default()
+}
+
+// how well a seat matches the preferences; higher is better
+fn score(train: &Train, seat: &Seat, preferences: &Preferences, near: Option<&Seat>) -> i64 {
+    let mut score = 0;
+    if preferences.window && seat.position() == Some(SeatPosition::Window) {
+        score += 1;
+    }
+    if preferences.aisle && seat.position() == Some(SeatPosition::Aisle) {
+        score += 1;
+    }
+    if preferences.quiet == Some(train.is_quiet(seat.coach())) {
+        score += 2;
+    }
+    if near.is_some_and(|near| near.coach() == seat.coach()) {
+        score += 2;
+    }
+    if preferences.accessible && seat.is_accessible() {
+        score += 4;
+    }
+    score
+}
+
+fn has_position(seats: &[(&SeatId, &Seat)], position: SeatPosition) -> bool {
+    seats
+        .iter()
+        .any(|(_, seat)| seat.position() == Some(position))
+}
+
+fn seat_number(seat: &Seat) -> usize {
+    seat.seat_number().parse().unwrap_or(usize::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::booking_reference::BookingReference;
+    use crate::train::Coach;
+
+    // coach A has 4 seats with windows at 1 and 4, coach B is a quiet coach
+    // with 4 seats, coach C has 1 accessible seat; 1A is reserved
+    fn train() -> Train {
+        let mut seats = HashMap::new();
+        for (coach, count) in [("A", 4), ("B", 4), ("C", 1)] {
+            for number in 1..=count {
+                let mut seat = Seat::new(number.to_string(), coach.to_string());
+                if number == 1 || number == count {
+                    seat = seat.with_position(SeatPosition::Window);
+                } else {
+                    seat = seat.with_position(SeatPosition::Aisle);
+                }
+                if number == 1 && coach == "A" {
+                    seat = seat.with_booking_reference(Some(BookingReference::new("existing")));
+                }
+                if coach == "C" {
+                    seat = seat.with_accessible(true);
+                }
+                seats.insert(
+                    SeatId::from_parts(&number.to_string(), coach).unwrap(),
+                    seat,
+                );
+            }
+        }
+        Train::new(seats).with_coaches(HashMap::from([(
+            "B".to_string(),
+            Coach {
+                quiet: true,
+                ..Coach::default()
+            },
+        )]))
+    }
+
+    fn seat_ids(seat_ids: &[&str]) -> Vec<SeatId> {
+        seat_ids
+            .iter()
+            .map(|seat_id| SeatId::new(seat_id))
+            .collect()
+    }
+
+    #[test]
+    fn test_allocate_without_preferences() {
+        let allocation =
+            allocate(&train(), 2, &Preferences::default(), Strategy::default()).unwrap();
+        assert_eq!(allocation.seats, seat_ids(&["2A", "3A"]));
+        assert_eq!(allocation.preferences_met, PreferencesMet::default());
+    }
+
+    #[test]
+    fn test_allocate_window() {
+        let preferences = Preferences {
+            window: true,
+            ..Preferences::default()
+        };
+        let allocation = allocate(&train(), 1, &preferences, Strategy::default()).unwrap();
+        assert_eq!(allocation.seats, seat_ids(&["4A"]));
+        assert_eq!(allocation.preferences_met.window, Some(true));
+    }
+
+    #[test]
+    fn test_allocate_together() {
+        // the window seat at 1A is taken, so the window seat goes with the
+        // one next to it rather than the first aisle seat
+        let preferences = Preferences {
+            window: true,
+            ..Preferences::default()
+        };
+        let allocation = allocate(&train(), 2, &preferences, Strategy::default()).unwrap();
+        assert_eq!(allocation.seats, seat_ids(&["4A", "3A"]));
+
+        // 1A and 3A face each other across a table
+        let train: Train = serde_json::from_str(
+            r#"{"seats": {
+                "1A": {"seat_number": "1", "coach": "A", "neighbours": ["3A"]},
+                "2A": {"seat_number": "2", "coach": "A", "neighbours": ["4A"]},
+                "3A": {"seat_number": "3", "coach": "A"},
+                "4A": {"seat_number": "4", "coach": "A"}
+            }}"#,
+        )
+        .unwrap();
+        let allocation = allocate(&train, 2, &Preferences::default(), Strategy::default()).unwrap();
+        assert_eq!(allocation.seats, seat_ids(&["1A", "3A"]));
+        // nobody can sit next to each other, but they're still in one coach
+        let allocation = allocate(&train, 3, &Preferences::default(), Strategy::default()).unwrap();
+        assert_eq!(allocation.seats, seat_ids(&["1A", "2A", "3A"]));
+    }
+
+    #[test]
+    fn test_allocate_strategy() {
+        // with 2A and 4A taken, three passengers can't sit together
+        let train: Train = serde_json::from_str(
+            r#"{"seats": {
+                "1A": {"seat_number": "1", "coach": "A", "position": "window"},
+                "2A": {"seat_number": "2", "coach": "A", "booking_reference": "abc"},
+                "3A": {"seat_number": "3", "coach": "A"},
+                "4A": {"seat_number": "4", "coach": "A", "booking_reference": "abc"},
+                "5A": {"seat_number": "5", "coach": "A"},
+                "6A": {"seat_number": "6", "coach": "A"}
+            }}"#,
+        )
+        .unwrap();
+        let preferences = Preferences {
+            window: true,
+            ..Preferences::default()
+        };
+        let allocation = allocate(&train, 3, &preferences, Strategy::Compact).unwrap();
+        assert_eq!(allocation.seats, seat_ids(&["3A", "5A", "6A"]));
+        assert_eq!(allocation.spread, 6);
+        assert_eq!(allocation.preferences_met.window, Some(false));
+
+        let allocation = allocate(&train, 3, &preferences, Strategy::Preferences).unwrap();
+        assert_eq!(allocation.seats, seat_ids(&["1A", "3A", "5A"]));
+        assert_eq!(allocation.spread, 8);
+        assert_eq!(allocation.preferences_met.window, Some(true));
+
+        // a group that sits together is spread as little as can be anyway
+        let allocation = allocate(&train, 2, &preferences, Strategy::Preferences).unwrap();
+        assert_eq!(allocation.seats, seat_ids(&["5A", "6A"]));
+        assert_eq!(allocation.spread, 1);
+    }
+
+    #[test]
+    fn test_allocate_quiet() {
+        let preferences = Preferences {
+            quiet: Some(true),
+            ..Preferences::default()
+        };
+        let allocation = allocate(&train(), 2, &preferences, Strategy::default()).unwrap();
+        assert_eq!(allocation.seats, seat_ids(&["1B", "2B"]));
+        assert_eq!(allocation.preferences_met.quiet, Some(true));
+
+        // only the quiet coach has room for four
+        let preferences = Preferences {
+            quiet: Some(false),
+            ..Preferences::default()
+        };
+        let allocation = allocate(&train(), 4, &preferences, Strategy::default()).unwrap();
+        assert_eq!(allocation.seats, seat_ids(&["1B", "2B", "3B", "4B"]));
+        assert_eq!(allocation.preferences_met.quiet, Some(false));
+    }
+
+    #[test]
+    fn test_allocate_near() {
+        let preferences = Preferences {
+            near: Some(SeatId::new("1B")),
+            ..Preferences::default()
+        };
+        let allocation = allocate(&train(), 2, &preferences, Strategy::default()).unwrap();
+        assert_eq!(allocation.seats, seat_ids(&["1B", "2B"]));
+        assert_eq!(allocation.preferences_met.near, Some(true));
+
+        let preferences = Preferences {
+            near: Some(SeatId::new("1A")),
+            ..Preferences::default()
+        };
+        let allocation = allocate(&train(), 2, &preferences, Strategy::default()).unwrap();
+        assert_eq!(allocation.seats, seat_ids(&["2A", "3A"]));
+        assert_eq!(allocation.preferences_met.near, Some(true));
+    }
+
+    #[test]
+    fn test_allocate_accessible() {
+        let preferences = Preferences {
+            accessible: true,
+            ..Preferences::default()
+        };
+        let allocation = allocate(&train(), 1, &preferences, Strategy::default()).unwrap();
+        assert_eq!(allocation.seats, seat_ids(&["1C"]));
+        assert_eq!(allocation.preferences_met.accessible, Some(true));
+    }
+
+    #[test]
+    fn test_allocate_skips_closed_coach() {
+        let mut train = train();
+        train.set_closed("A", true).unwrap();
+        let allocation = allocate(&train, 2, &Preferences::default(), Strategy::default()).unwrap();
+        assert_eq!(allocation.seats, seat_ids(&["1B", "2B"]));
+    }
+
+    #[test]
+    fn test_allocate_across_coaches() {
+        let allocation =
+            allocate(&train(), 7, &Preferences::default(), Strategy::default()).unwrap();
+        assert_eq!(allocation.seats.len(), 7);
+        assert!(!allocation.seats.contains(&SeatId::new("1C")));
+        // the accessible seat is only used when there's nothing else
+        let allocation =
+            allocate(&train(), 8, &Preferences::default(), Strategy::default()).unwrap();
+        assert!(allocation.seats.contains(&SeatId::new("1C")));
+        assert_eq!(
+            allocate(&train(), 9, &Preferences::default(), Strategy::default()),
+            Err(Error::NotEnoughFreeSeats(9))
+        );
+        assert_eq!(
+            allocate(&train(), 0, &Preferences::default(), Strategy::default()),
+            Err(Error::NotEnoughFreeSeats(0))
+        );
+    }
+
+    #[test]
+    fn test_seat_distance() {
+        let train = train();
+        let distance = SeatDistance::new(&train);
+        let seat = |seat_id: &str| train.get(&SeatId::new(seat_id)).unwrap();
+        assert_eq!(distance.between(seat("1A"), seat("3A")), 2);
+        assert_eq!(distance.between(seat("3A"), seat("1B")), 102);
+        assert_eq!(distance.between(seat("1C"), seat("4A")), 203);
+    }
+
+    #[test]
+    fn test_nearest() {
+        let train = train();
+        let near = |seat_id: &str, count: usize, accessible: bool| {
+            nearest(&train, &SeatId::new(seat_id), count, accessible).map(|seats| {
+                seats
+                    .into_iter()
+                    .map(|(seat_id, distance)| (seat_id.as_str(), distance))
+                    .collect::<Vec<_>>()
+            })
+        };
+        assert_eq!(near("3A", 2, false), Some(vec![("2A", 1), ("4A", 1)]));
+        // 1A is reserved; the same row in the next coach comes next
+        assert_eq!(
+            near("2A", 4, false),
+            Some(vec![("3A", 1), ("4A", 2), ("2B", 100), ("1B", 101)])
+        );
+        assert_eq!(near("4B", 1, true), Some(vec![("3B", 1)]));
+        // the accessible seat is left out unless asked for
+        assert_eq!(near("1B", 1, false), Some(vec![("2B", 1)]));
+        assert_eq!(near("1B", 1, true), Some(vec![("2B", 1)]));
+        assert_eq!(near("4B", 2, true), Some(vec![("3B", 1), ("2B", 2)]));
+        assert_eq!(near("1C", 1, false), Some(vec![("1B", 100)]));
+        assert_eq!(near("9Z", 1, false), None);
+    }
+}
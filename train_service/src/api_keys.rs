@@ -0,0 +1,133 @@
+use std::collections::HashMap;
+
+use crate::train::Error;
+
+/// The API keys clients may use, by key. When these are configured, every
+/// request needs an `X-API-Key` header with one of them.
+#[derive(Debug, Default, PartialEq, Eq, Clone, serde::Deserialize)]
+pub struct ApiKeys(HashMap<String, ApiKey>);
+
+/// A client of the service, like a travel agency.
+#[derive(Debug, PartialEq, Eq, Clone, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ApiKey {
+    pub name: String,
+    /// The most seats this client may reserve; unlimited if not given.
+    #[serde(default)]
+    pub seat_quota: Option<usize>,
+    /// The tenant this client belongs to. A client of a tenant can only use
+    /// the endpoints under `/tenants/<tenant>`.
+    #[serde(default)]
+    pub tenant: Option<String>,
+}
+
+impl ApiKeys {
+    pub fn get(&self, key: &str) -> Option<&ApiKey> {
+        self.0.get(key)
+    }
+}
+
+/// How many seats each client has reserved, by client name.
+#[derive(Debug, Default)]
+pub struct Usage(HashMap<String, usize>);
+
+/// What's left of a client's quota.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Allowance {
+    pub limit: usize,
+    pub remaining: usize,
+}
+
+impl Usage {
+    /// Check whether a client may reserve this many more seats.
+    pub fn check(&self, api_key: &ApiKey, seats: usize) -> Result<Option<Allowance>, Error> {
+        let Some(limit) = api_key.seat_quota else {
+            return Ok(None);
+        };
+        if limit == 0 {
+            return Err(Error::ReservationNotAllowed(api_key.name.clone()));
+        }
+        let remaining = limit.saturating_sub(self.used(api_key));
+        if seats > remaining {
+            return Err(Error::QuotaExceeded(Allowance { limit, remaining }));
+        }
+        Ok(Some(Allowance { limit, remaining }))
+    }
+
+    /// Count reserved seats against a client's quota, returning what's left.
+    pub fn record(&mut self, api_key: &ApiKey, seats: usize) -> Option<Allowance> {
+        let used = self.0.entry(api_key.name.clone()).or_default();
+        *used += seats;
+        api_key.seat_quota.map(|limit| Allowance {
+            limit,
+            remaining: limit.saturating_sub(*used),
+        })
+    }
+
+    fn used(&self, api_key: &ApiKey) -> usize {
+        self.0.get(&api_key.name).copied().unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn agency(seat_quota: Option<usize>) -> ApiKey {
+        ApiKey {
+            name: "agency".to_string(),
+            seat_quota,
+            tenant: None,
+        }
+    }
+
+    #[test]
+    fn test_deserialize() {
+        let api_keys: ApiKeys = serde_json::from_str(
+            r#"{"secret": {"name": "agency", "seat_quota": 4}, "other": {"name": "office"}}"#,
+        )
+        .unwrap();
+        assert_eq!(api_keys.get("secret"), Some(&agency(Some(4))));
+        assert_eq!(api_keys.get("other").unwrap().seat_quota, None);
+        assert_eq!(api_keys.get("unknown"), None);
+    }
+
+    #[test]
+    fn test_quota() {
+        let mut usage = Usage::default();
+        let api_key = agency(Some(4));
+        assert_eq!(
+            usage.check(&api_key, 3),
+            Ok(Some(Allowance {
+                limit: 4,
+                remaining: 4
+            }))
+        );
+        assert_eq!(
+            usage.record(&api_key, 3),
+            Some(Allowance {
+                limit: 4,
+                remaining: 1
+            })
+        );
+        assert_eq!(
+            usage.check(&api_key, 2),
+            Err(Error::QuotaExceeded(Allowance {
+                limit: 4,
+                remaining: 1
+            }))
+        );
+        assert!(usage.check(&api_key, 1).is_ok());
+    }
+
+    #[test]
+    fn test_no_quota() {
+        let mut usage = Usage::default();
+        assert_eq!(usage.check(&agency(None), 100), Ok(None));
+        assert_eq!(usage.record(&agency(None), 100), None);
+        assert_eq!(
+            usage.check(&agency(Some(0)), 1),
+            Err(Error::ReservationNotAllowed("agency".to_string()))
+        );
+    }
+}
@@ -0,0 +1,59 @@
+//! The kata's ticket office, as a reference solution: it reserves seats
+//! through a running train service, from the command line or as a service.
+
+use std::time::Duration;
+
+use clap::Parser;
+
+use train_service::http_client::HttpClient;
+use train_service::ticket_office::TicketOffice;
+
+/// The ticket office of the train reservation kata.
+#[derive(Debug, Parser)]
+struct Args {
+    /// The URL of the train service.
+    #[arg(long, default_value = "http://localhost:8081")]
+    train_service: String,
+
+    /// Serve `POST /reserve` on this port, instead of making one
+    /// reservation.
+    #[arg(long, conflicts_with_all = ["train_id", "seat_count"])]
+    port: Option<u16>,
+
+    /// How many seconds to wait for the train service.
+    #[arg(long, default_value_t = 5)]
+    timeout: u64,
+
+    /// The train to reserve seats on.
+    #[arg(required_unless_present = "port")]
+    train_id: Option<String>,
+
+    /// How many seats to reserve.
+    #[arg(required_unless_present = "port")]
+    seat_count: Option<usize>,
+}
+
+#[tokio::main]
+async fn main() {
+    let args = Args::parse();
+    let client = HttpClient::new(Duration::from_secs(args.timeout));
+    let office = TicketOffice::new(client, args.train_service);
+    if let Some(port) = args.port {
+        let listener = tokio::net::TcpListener::bind(("0.0.0.0", port))
+            .await
+            .unwrap();
+        println!("Listening on port {}", port);
+        axum::serve(listener, office.routes()).await.unwrap();
+        return;
+    }
+    let (Some(train_id), Some(seat_count)) = (args.train_id, args.seat_count) else {
+        unreachable!("clap requires both without --port");
+    };
+    match office.book(&train_id, seat_count).await {
+        Ok(booking) => println!("{}", serde_json::to_string_pretty(&booking).unwrap()),
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    }
+}
@@ -1,25 +1,53 @@
+use std::sync::Arc;
+
 pub struct BookingReferenceService {
+    start: u64,
     counter: u64,
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, serde::Serialize, serde::Deserialize)]
-pub struct BookingReference(String);
+#[derive(
+    Debug, PartialEq, Eq, Clone, Hash, serde::Serialize, serde::Deserialize, schemars::JsonSchema,
+)]
+pub struct BookingReference(Arc<str>);
 
 impl BookingReference {
-    pub fn new<S: Into<String>>(id: S) -> Self {
+    pub fn new<S: Into<Arc<str>>>(id: S) -> Self {
         Self(id.into())
     }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
 }
 
 impl BookingReferenceService {
     pub fn new(start: u64) -> Self {
-        BookingReferenceService { counter: start }
+        BookingReferenceService {
+            start,
+            counter: start,
+        }
+    }
+
+    /// Start handing out booking references from the start again.
+    pub fn reset(&mut self) {
+        self.counter = self.start;
+    }
+
+    pub fn counter(&self) -> u64 {
+        self.counter
     }
 
     pub fn booking_reference(&mut self) -> BookingReference {
+        let booking_reference = self.next();
         self.counter += 1;
+        booking_reference
+    }
+
+    /// The booking reference that's handed out next, without handing it out
+    /// yet.
+    pub fn next(&self) -> BookingReference {
         // return a hex number
-        BookingReference::new(format!("{:x}", self.counter))
+        BookingReference::new(format!("{:x}", self.counter + 1))
     }
 }
 
@@ -41,4 +69,21 @@ mod tests {
         let booking_reference2 = service.booking_reference();
         assert_ne!(booking_reference1, booking_reference2);
     }
+
+    #[test]
+    fn test_next() {
+        let mut service = BookingReferenceService::new(123456789);
+        let next = service.next();
+        assert_eq!(service.next(), next);
+        assert_eq!(service.booking_reference(), next);
+        assert_ne!(service.next(), next);
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut service = BookingReferenceService::new(123456789);
+        let booking_reference = service.booking_reference();
+        service.reset();
+        assert_eq!(service.booking_reference(), booking_reference);
+    }
 }
@@ -0,0 +1,78 @@
+//! The time, as far as the service is concerned. Everything that depends on
+//! the time asks a clock, so tests can control it.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub trait Clock: Send + Sync {
+    /// The current time, in seconds since the Unix epoch.
+    fn now(&self) -> u64;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs())
+    }
+}
+
+/// A clock that only moves when told to, for tests and demos. Clones share
+/// the same time.
+#[derive(Debug, Clone, Default)]
+pub struct SimulatedClock(Arc<AtomicU64>);
+
+impl SimulatedClock {
+    pub fn new(now: u64) -> Self {
+        SimulatedClock(Arc::new(AtomicU64::new(now)))
+    }
+
+    #[cfg(test)]
+    pub fn set(&self, now: u64) {
+        self.0.store(now, Ordering::SeqCst);
+    }
+
+    /// Move the clock forward, returning the new time, or `None` if that's
+    /// later than the clock can tell; the clock stays where it is then.
+    pub fn advance(&self, seconds: u64) -> Option<u64> {
+        let before = self
+            .0
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |now| {
+                now.checked_add(seconds)
+            })
+            .ok()?;
+        Some(before + seconds)
+    }
+}
+
+impl Clock for SimulatedClock {
+    fn now(&self) -> u64 {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_simulated_clock() {
+        let clock = SimulatedClock::new(100);
+        let other = clock.clone();
+        assert_eq!(other.advance(20), Some(120));
+        assert_eq!(clock.now(), 120);
+        assert_eq!(clock.advance(u64::MAX), None);
+        assert_eq!(clock.now(), 120);
+        clock.set(5);
+        assert_eq!(other.now(), 5);
+    }
+
+    #[test]
+    fn test_system_clock() {
+        // some time after this was written
+        assert!(SystemClock.now() > 1_700_000_000);
+    }
+}
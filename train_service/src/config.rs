@@ -0,0 +1,309 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use clap::Parser;
+
+use crate::middleware::AccessLogFormat;
+use crate::pricing::{FixedPricing, OccupancyPricing, Pricing, PricingStrategy, Surcharge};
+use crate::refunds::RefundPolicy;
+use crate::rules::Policies;
+use crate::schedule::{ResetSchedule, TimeOfDay};
+
+// the longest interval in minutes that can be given, a year
+const MAX_MINUTES: u64 = 365 * 24 * 60;
+
+/// The train service, for the train reservation kata.
+#[derive(Debug, Parser)]
+pub struct Config {
+    /// The port to listen on.
+    #[arg(long, default_value_t = 8081)]
+    pub port: u16,
+
+    /// Listen on this Unix domain socket too.
+    #[arg(long)]
+    pub unix_socket: Option<PathBuf>,
+
+    /// Don't listen on the port, only on the Unix domain socket.
+    #[arg(long, requires = "unix_socket", conflicts_with = "all_in_one")]
+    pub no_tcp: bool,
+
+    /// Accept no more connections than this at a time; others wait until
+    /// one closes.
+    #[arg(long)]
+    pub max_connections: Option<usize>,
+
+    /// Close connections that have been idle for this many seconds. With 0,
+    /// connections are closed after each request.
+    #[arg(long)]
+    pub keep_alive: Option<u64>,
+
+    /// Refuse requests with more headers than this.
+    #[arg(long)]
+    pub max_headers: Option<usize>,
+
+    /// Refuse requests whose request line and headers are larger than this
+    /// many bytes, at least 8192.
+    #[arg(long)]
+    pub max_header_bytes: Option<usize>,
+
+    /// How many seconds a request may take before it's answered with a
+    /// timeout error.
+    #[arg(long, default_value_t = 5)]
+    pub request_timeout: u64,
+
+    /// A file with trains data, used instead of the bundled trains. This can
+    /// be a .json or .yaml file, or a .csv file with one row per seat.
+    #[arg(long)]
+    pub trains: Option<PathBuf>,
+
+    /// A directory with a JSON file for each train, named after the train.
+    /// These trains are loaded when they're first used, rather than at
+    /// startup.
+    #[arg(long)]
+    pub trains_dir: Option<PathBuf>,
+
+    /// Keep at most this many trains from the trains directory in memory,
+    /// writing changed trains back to it before dropping them.
+    #[arg(long, requires = "trains_dir")]
+    pub max_loaded_trains: Option<usize>,
+
+    /// Refuse to start when loading a JSON trains file takes longer than
+    /// this many seconds.
+    #[arg(long)]
+    pub load_budget: Option<u64>,
+
+    /// A JSON or YAML file with train templates, used instead of the bundled ones.
+    #[arg(long)]
+    pub templates: Option<PathBuf>,
+
+    /// Write an access log line to standard output for each request, in
+    /// this format.
+    #[arg(long, value_enum)]
+    pub access_log: Option<AccessLogFormat>,
+
+    /// A JSON or YAML file with the optional middleware layers to run, like
+    /// rate limiting, CORS and chaos.
+    #[arg(long)]
+    pub middleware: Option<PathBuf>,
+
+    /// A JSON or YAML file with API keys. When this is given, every request
+    /// needs an `X-API-Key` header with one of the keys.
+    #[arg(long)]
+    pub api_keys: Option<PathBuf>,
+
+    /// A JSON or YAML file with deprecated routes. Responses from them get
+    /// `Deprecation` and `Sunset` headers, and their use is logged.
+    #[arg(long)]
+    pub deprecations: Option<PathBuf>,
+
+    /// A JSON or YAML file with the policies for the reservation rules. The
+    /// options for individual rules below override what's in it.
+    #[arg(long)]
+    pub policies: Option<PathBuf>,
+
+    /// A JSON or YAML file that switches experimental features on or off,
+    /// like `{"holds": false}`. They're all on unless switched off.
+    #[arg(long)]
+    pub flags: Option<PathBuf>,
+
+    /// The most seats a single booking reference may hold on a train. There's
+    /// no limit if this isn't given.
+    #[arg(long)]
+    pub max_seats_per_booking: Option<usize>,
+
+    /// Refuse reservations that would take a train above this fraction of
+    /// reserved seats, like 0.7.
+    #[arg(long)]
+    pub max_occupancy: Option<f64>,
+
+    /// Send a notification when a train goes over this fraction of reserved
+    /// seats, like 0.9, with how many seats it has left.
+    #[arg(long)]
+    pub near_full_alert: Option<f64>,
+
+    /// Refuse reservations with seats in more than one coach.
+    #[arg(long)]
+    pub same_coach: bool,
+
+    /// How to work out quoted prices.
+    #[arg(long, value_enum, default_value_t)]
+    pub pricing: Pricing,
+
+    /// With occupancy pricing, raise prices by PERCENT once a train is above
+    /// OCCUPANCY, given as OCCUPANCY:PERCENT. This can be given more than
+    /// once. Without it, prices go up 10% at 0.5 and 25% at 0.8.
+    #[arg(long)]
+    pub surcharge: Vec<Surcharge>,
+
+    /// Cancelling a booking at least this many hours before departure
+    /// refunds it in full.
+    #[arg(long, default_value_t = 24)]
+    pub refund_cutoff_hours: u64,
+
+    /// The percentage refunded when a booking is cancelled later than that.
+    #[arg(long, default_value_t = 50)]
+    pub late_refund_percent: u64,
+
+    /// Refuse to cancel bookings once a train departs within this many
+    /// minutes. Bookings can always be cancelled if this isn't given.
+    #[arg(long)]
+    pub cancellation_cutoff_minutes: Option<u64>,
+
+    /// Start a clock at the current time that only moves forward with
+    /// `POST /admin/time/advance`, to demonstrate things that depend on time.
+    #[arg(long)]
+    pub simulated_time: bool,
+
+    /// Also serve a separate set of the trains under `/tenants/<NAME>`, with
+    /// its own booking references, so teams can share a service. This can be
+    /// given more than once.
+    #[arg(long, value_parser = parse_tenant)]
+    pub tenant: Vec<String>,
+
+    /// A JSON or YAML file with the request and seat quotas of tenants, by
+    /// tenant name. The tenants in it are served too.
+    #[arg(long)]
+    pub tenants: Option<PathBuf>,
+
+    /// Reset all trains every this many minutes, for a demo that cleans itself
+    /// up.
+    #[arg(
+        long,
+        conflicts_with = "reset_at",
+        value_parser = clap::value_parser!(u64).range(1..=MAX_MINUTES),
+    )]
+    pub reset_every_minutes: Option<u64>,
+
+    /// Reset all trains every day at this time, in UTC, given as HH:MM. This
+    /// can be given more than once.
+    #[arg(long)]
+    pub reset_at: Vec<TimeOfDay>,
+
+    /// Start booking references over when the trains are reset on a
+    /// schedule.
+    #[arg(long)]
+    pub reset_booking_references: bool,
+
+    /// Let clients start their own sandbox session with `POST /sandbox`, with
+    /// its own copy of the trains.
+    #[arg(long)]
+    pub sandbox: bool,
+
+    /// Drop sandbox sessions that haven't been used for this many minutes.
+    #[arg(
+        long,
+        default_value_t = 30,
+        value_parser = clap::value_parser!(u64).range(1..=MAX_MINUTES),
+    )]
+    pub sandbox_expiry_minutes: u64,
+
+    /// Keep no more than this many sandbox sessions at a time; starting
+    /// another one responds with 503 Service Unavailable.
+    #[arg(
+        long,
+        default_value_t = 100,
+        value_parser = clap::value_parser!(u64).range(1..),
+    )]
+    pub sandbox_max_sessions: u64,
+
+    /// Send trains, seats and reservations as JSON:API documents, unless
+    /// clients ask for plain JSON with their `Accept` header.
+    #[arg(long)]
+    pub json_api: bool,
+
+    /// Wrap responses in an envelope with `data`, `error` and `meta`, unless
+    /// clients ask not to with an `X-Envelope: false` header.
+    #[arg(long)]
+    pub envelope: bool,
+
+    /// Serve the kata's ticket office under `/ticket_office` too, for a
+    /// reference solution without running a second process.
+    #[arg(long)]
+    pub all_in_one: bool,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// Things to do instead of running the service.
+#[derive(Debug, clap::Subcommand)]
+pub enum Command {
+    /// Give the seats of a train in a trains file new ids, keeping their
+    /// reservations, and write the trains as JSON to standard output.
+    Renumber {
+        /// The trains file, in any format `--trains` accepts.
+        trains: PathBuf,
+        /// The train with the seats to renumber.
+        train_id: String,
+        /// A JSON or YAML file with the new seat ids by old seat id, like
+        /// `{"1A": "101A"}`.
+        mapping: PathBuf,
+    },
+}
+
+fn minutes_duration(minutes: u64) -> Duration {
+    Duration::from_secs(minutes.saturating_mul(60))
+}
+
+pub fn parse_tenant(s: &str) -> Result<String, String> {
+    if s.is_empty()
+        || !s
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(format!(
+            "a tenant name can only have letters, digits, - and _, not {:?}",
+            s
+        ));
+    }
+    Ok(s.to_string())
+}
+
+impl Config {
+    pub fn reset_schedule(&self) -> Option<ResetSchedule> {
+        match self.reset_every_minutes {
+            Some(minutes) => Some(ResetSchedule::Every(minutes_duration(minutes))),
+            None if self.reset_at.is_empty() => None,
+            None => Some(ResetSchedule::At(self.reset_at.clone())),
+        }
+    }
+
+    pub fn sandbox_expiry(&self) -> Duration {
+        minutes_duration(self.sandbox_expiry_minutes)
+    }
+
+    pub fn sandbox_max_sessions(&self) -> usize {
+        usize::try_from(self.sandbox_max_sessions).unwrap_or(usize::MAX)
+    }
+
+    pub fn refund_policy(&self) -> RefundPolicy {
+        RefundPolicy {
+            cutoff_hours: self.refund_cutoff_hours,
+            late_percent: self.late_refund_percent.min(100),
+            cancellation_cutoff_minutes: self.cancellation_cutoff_minutes,
+        }
+    }
+
+    pub fn pricing_strategy(&self) -> Box<dyn PricingStrategy> {
+        match self.pricing {
+            Pricing::Fixed => Box::new(FixedPricing),
+            Pricing::Occupancy if self.surcharge.is_empty() => {
+                Box::new(OccupancyPricing::default())
+            }
+            Pricing::Occupancy => Box::new(OccupancyPricing::new(self.surcharge.clone())),
+        }
+    }
+
+    /// Override policies with the ones given on the command line.
+    pub fn override_policies(&self, policies: &mut Policies) {
+        if let Some(max) = self.max_seats_per_booking {
+            policies.max_seats_per_booking = Some(max);
+        }
+        if let Some(max) = self.max_occupancy {
+            policies.max_occupancy = Some(max);
+        }
+        if self.same_coach {
+            policies.same_coach = true;
+        }
+    }
+}
@@ -0,0 +1,58 @@
+//! The correlation id of the request being handled, so the events it causes
+//! can be traced back to it by the services that get them. Callers pass it
+//! in an `X-Correlation-Id` header, or else an `X-Request-Id` header; a
+//! request without either gets a new one.
+
+use std::future::Future;
+
+use axum::http::HeaderMap;
+
+pub const HEADER: &str = "x-correlation-id";
+
+tokio::task_local! {
+    static CORRELATION_ID: String;
+}
+
+/// The correlation id a request has, or a new one.
+pub fn from_headers(headers: &HeaderMap) -> String {
+    [HEADER, "x-request-id"]
+        .into_iter()
+        .filter_map(|name| headers.get(name)?.to_str().ok())
+        .find(|id| !id.is_empty())
+        .map(str::to_string)
+        .unwrap_or_else(|| format!("{:016x}", rand::random::<u64>()))
+}
+
+/// Run `f` with `id` as the current correlation id.
+pub async fn scope<F: Future>(id: String, f: F) -> F::Output {
+    CORRELATION_ID.scope(id, f).await
+}
+
+/// The correlation id of the request being handled, if any.
+pub fn current() -> Option<String> {
+    CORRELATION_ID.try_with(String::clone).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    #[test]
+    fn test_from_headers() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(from_headers(&headers).len(), 16);
+        headers.insert("x-request-id", HeaderValue::from_static("request"));
+        assert_eq!(from_headers(&headers), "request");
+        headers.insert(HEADER, HeaderValue::from_static("correlation"));
+        assert_eq!(from_headers(&headers), "correlation");
+    }
+
+    #[tokio::test]
+    async fn test_scope() {
+        assert_eq!(current(), None);
+        let id = scope("abc".to_string(), async { current() }).await;
+        assert_eq!(id, Some("abc".to_string()));
+    }
+}
@@ -0,0 +1,143 @@
+//! Routes on their way out. Responses from deprecated routes say so in their
+//! `Deprecation` header, and in their `Sunset` header when the route goes
+//! away, so clients can move on before it does. Each use is logged with how
+//! often the route was used, to see who still needs it.
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use crate::lock::LockExt;
+
+/// A deprecated route, with times in seconds since the Unix epoch.
+#[derive(Debug, PartialEq, Eq, Clone, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Deprecation {
+    /// The route as it's matched, like `/train/:train_id/reserve`.
+    pub route: String,
+    /// Only this method of the route; all of them if not given.
+    #[serde(default)]
+    pub method: Option<String>,
+    /// When the route was deprecated.
+    pub since: u64,
+    /// When the route stops working, if that's known.
+    #[serde(default)]
+    pub sunset: Option<u64>,
+    /// Where to read about what to use instead.
+    #[serde(default)]
+    pub link: Option<String>,
+}
+
+/// The deprecated routes, and how often they were used.
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(from = "Vec<Deprecation>")]
+pub struct Deprecations {
+    routes: Vec<Deprecation>,
+    // by route and method
+    usage: Mutex<BTreeMap<(String, String), u64>>,
+}
+
+impl From<Vec<Deprecation>> for Deprecations {
+    fn from(routes: Vec<Deprecation>) -> Self {
+        Deprecations {
+            routes,
+            usage: Mutex::default(),
+        }
+    }
+}
+
+impl Deprecations {
+    pub fn get(&self, method: &str, route: &str) -> Option<&Deprecation> {
+        self.routes.iter().find(|deprecation| {
+            deprecation.route == route
+                && deprecation
+                    .method
+                    .as_deref()
+                    .is_none_or(|deprecated| deprecated.eq_ignore_ascii_case(method))
+        })
+    }
+
+    /// Count a use of a deprecated route, returning how often it was used.
+    pub fn record_use(&self, method: &str, route: &str) -> u64 {
+        let mut usage = self.usage.lock_or_recover();
+        let count = usage
+            .entry((route.to_string(), method.to_string()))
+            .or_default();
+        *count += 1;
+        *count
+    }
+}
+
+/// A time as an HTTP date, like `Sun, 06 Nov 1994 08:49:37 GMT`.
+pub fn http_date(seconds: u64) -> String {
+    const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let days = seconds / 86_400;
+    let time = seconds % 86_400;
+    let (year, month, day) = civil_from_days(days);
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+        WEEKDAYS[(days % 7) as usize],
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
+
+// the date of a number of days since 1970-01-01, after Howard Hinnant's
+// `civil_from_days`
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let day_of_era = z - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_http_date() {
+        assert_eq!(http_date(0), "Thu, 01 Jan 1970 00:00:00 GMT");
+        assert_eq!(http_date(784111777), "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert_eq!(http_date(951782400), "Tue, 29 Feb 2000 00:00:00 GMT");
+        assert_eq!(http_date(1798761599), "Thu, 31 Dec 2026 23:59:59 GMT");
+    }
+
+    #[test]
+    fn test_deprecations() {
+        let deprecation = |route: &str, method: Option<&str>| Deprecation {
+            route: route.to_string(),
+            method: method.map(str::to_string),
+            since: 0,
+            sunset: None,
+            link: None,
+        };
+        let deprecations = Deprecations::from(vec![
+            deprecation("/reserve", None),
+            deprecation("/train/:train_id", Some("get")),
+        ]);
+        assert!(deprecations.get("POST", "/reserve").is_some());
+        assert!(deprecations.get("GET", "/train/:train_id").is_some());
+        assert!(deprecations.get("DELETE", "/train/:train_id").is_none());
+        assert!(deprecations
+            .get("POST", "/train/:train_id/reserve")
+            .is_none());
+
+        assert_eq!(deprecations.record_use("POST", "/reserve"), 1);
+        assert_eq!(deprecations.record_use("POST", "/reserve"), 2);
+        assert_eq!(deprecations.record_use("GET", "/reserve"), 1);
+    }
+}
@@ -0,0 +1,150 @@
+//! Responses wrapped in an envelope, for clients whose frameworks expect
+//! every response to look the same:
+//!
+//! ```json
+//! { "data": ..., "error": null, "meta": { "request_id": "...", "version": "0.1.0" } }
+//! ```
+//!
+//! Successful responses have their body under `data`, failed ones under
+//! `error`; errors that are plain text become `{"message": ...}`. Clients
+//! ask for envelopes with an `X-Envelope: true` header, or the service can
+//! send them by default, in which case `X-Envelope: false` turns them off.
+
+use axum::http::{HeaderMap, StatusCode};
+
+pub const HEADER: &str = "x-envelope";
+
+#[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Envelope {
+    pub data: Option<serde_json::Value>,
+    pub error: Option<serde_json::Value>,
+    pub meta: Meta,
+}
+
+#[derive(Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Meta {
+    pub request_id: String,
+    pub version: String,
+}
+
+/// Whether the client wants an envelope, when the service sends them by
+/// default or not.
+pub fn wanted(headers: &HeaderMap, by_default: bool) -> bool {
+    match headers.get(HEADER).and_then(|value| value.to_str().ok()) {
+        Some(value) if value.eq_ignore_ascii_case("true") => true,
+        Some(value) if value.eq_ignore_ascii_case("false") => false,
+        _ => by_default,
+    }
+}
+
+/// Whether a response with this status and content type is wrapped: only
+/// JSON bodies are, and plain text errors.
+pub fn wraps(status: StatusCode, content_type: &str) -> bool {
+    content_type == mime::APPLICATION_JSON.as_ref()
+        || (failed(status) && content_type.starts_with(mime::TEXT_PLAIN.as_ref()))
+}
+
+fn failed(status: StatusCode) -> bool {
+    status.is_client_error() || status.is_server_error()
+}
+
+/// The body of a response in an envelope, or `None` if it stays as it is,
+/// as [`wraps`] says.
+pub fn wrap(
+    status: StatusCode,
+    content_type: &str,
+    body: &[u8],
+    request_id: String,
+) -> Option<Envelope> {
+    if !wraps(status, content_type) {
+        return None;
+    }
+    let failed = failed(status);
+    let value = if content_type == mime::APPLICATION_JSON.as_ref() {
+        serde_json::from_slice(body).ok()?
+    } else {
+        serde_json::json!({ "message": String::from_utf8_lossy(body) })
+    };
+    let meta = Meta {
+        request_id,
+        version: env!("CARGO_PKG_VERSION").to_string(),
+    };
+    Some(if failed {
+        Envelope {
+            data: None,
+            error: Some(value),
+            meta,
+        }
+    } else {
+        Envelope {
+            data: Some(value),
+            error: None,
+            meta,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    #[test]
+    fn test_wanted() {
+        let mut headers = HeaderMap::new();
+        assert!(!wanted(&headers, false));
+        assert!(wanted(&headers, true));
+        headers.insert(HEADER, HeaderValue::from_static("true"));
+        assert!(wanted(&headers, false));
+        headers.insert(HEADER, HeaderValue::from_static("false"));
+        assert!(!wanted(&headers, true));
+    }
+
+    #[test]
+    fn test_wrap() {
+        let envelope = wrap(
+            StatusCode::OK,
+            "application/json",
+            br#"{"seats": ["1A"]}"#,
+            "abc".to_string(),
+        )
+        .unwrap();
+        assert_eq!(envelope.data, Some(serde_json::json!({ "seats": ["1A"] })));
+        assert_eq!(envelope.error, None);
+        assert_eq!(envelope.meta.request_id, "abc");
+
+        let envelope = wrap(
+            StatusCode::NOT_FOUND,
+            "text/plain; charset=utf-8",
+            b"Train x does not exist",
+            "abc".to_string(),
+        )
+        .unwrap();
+        assert_eq!(envelope.data, None);
+        assert_eq!(
+            envelope.error,
+            Some(serde_json::json!({ "message": "Train x does not exist" }))
+        );
+
+        // metrics, say
+        assert_eq!(
+            wrap(
+                StatusCode::OK,
+                "text/plain; version=0.0.4",
+                b"",
+                "abc".to_string()
+            ),
+            None
+        );
+        assert_eq!(
+            wrap(
+                StatusCode::OK,
+                "application/vnd.api+json",
+                b"{}",
+                "abc".to_string()
+            ),
+            None
+        );
+    }
+}
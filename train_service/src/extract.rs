@@ -0,0 +1,224 @@
+use axum::async_trait;
+use axum::body::Bytes;
+use axum::extract::rejection::BytesRejection;
+use axum::extract::{FromRequest, Request};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use serde::de::DeserializeOwned;
+
+/// A JSON body extractor like `axum::Json`, but which reports problems with
+/// the body as a structured JSON error that says where the problem is.
+pub struct Json<T>(pub T);
+
+#[derive(Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct BodyError {
+    pub message: String,
+    // the path to the offending field, like `seats[0]`
+    pub path: Option<String>,
+    // what the field should have been, like `a sequence`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expected: Option<String>,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+}
+
+impl BodyError {
+    // a problem with the request as a whole, rather than somewhere in the body
+    fn new(message: String) -> Self {
+        BodyError {
+            message,
+            path: None,
+            expected: None,
+            line: None,
+            column: None,
+        }
+    }
+}
+
+pub enum JsonRejection {
+    // with the content type the request had, if any
+    UnsupportedMediaType(Option<String>),
+    // the body isn't JSON at all
+    Syntax(BodyError),
+    // the body is JSON, but it doesn't have the right shape
+    Data(BodyError),
+    Body(BytesRejection),
+}
+
+#[async_trait]
+impl<T, S> FromRequest<S> for Json<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = JsonRejection;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        check_content_type(req.headers())?;
+        let bytes = Bytes::from_request(req, state)
+            .await
+            .map_err(JsonRejection::Body)?;
+        deserialize(&bytes).map(Json)
+    }
+}
+
+fn deserialize<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, JsonRejection> {
+    let mut deserializer = serde_json::Deserializer::from_slice(bytes);
+    let value = serde_path_to_error::deserialize(&mut deserializer).map_err(|err| {
+        let path = err.path().to_string();
+        let inner = err.into_inner();
+        let message = inner.to_string();
+        let body_error = BodyError {
+            expected: expected(&message),
+            message,
+            // serde_path_to_error reports "." when the problem is at the top
+            path: (path != ".").then_some(path),
+            line: Some(inner.line()),
+            column: Some(inner.column()),
+        };
+        if inner.is_data() {
+            JsonRejection::Data(body_error)
+        } else {
+            JsonRejection::Syntax(body_error)
+        }
+    })?;
+    // complain about trailing garbage after the JSON value
+    deserializer.end().map_err(|err| {
+        JsonRejection::Syntax(BodyError {
+            message: err.to_string(),
+            path: None,
+            expected: None,
+            line: Some(err.line()),
+            column: Some(err.column()),
+        })
+    })?;
+    Ok(value)
+}
+
+// serde words type errors like "invalid type: string \"1A\", expected a
+// sequence at line 1 column 14"
+fn expected(message: &str) -> Option<String> {
+    let (_, expected) = message.split_once(", expected ")?;
+    let expected = match expected.rfind(" at line ") {
+        Some(index) => &expected[..index],
+        None => expected,
+    };
+    Some(expected.to_string())
+}
+
+/// Refuse requests whose body isn't JSON, going by their `Content-Type`.
+pub fn check_content_type(headers: &HeaderMap) -> Result<(), JsonRejection> {
+    if json_content_type(headers) {
+        return Ok(());
+    }
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .map(|content_type| String::from_utf8_lossy(content_type.as_bytes()).into_owned());
+    Err(JsonRejection::UnsupportedMediaType(content_type))
+}
+
+fn json_content_type(headers: &HeaderMap) -> bool {
+    let Some(content_type) = headers.get(header::CONTENT_TYPE) else {
+        return false;
+    };
+    let Ok(content_type) = content_type.to_str() else {
+        return false;
+    };
+    let Ok(mime) = content_type.parse::<mime::Mime>() else {
+        return false;
+    };
+    mime.type_() == "application"
+        && (mime.subtype() == "json" || mime.suffix().is_some_and(|name| name == "json"))
+}
+
+impl IntoResponse for JsonRejection {
+    fn into_response(self) -> Response {
+        match self {
+            JsonRejection::UnsupportedMediaType(content_type) => {
+                let message = match content_type {
+                    Some(content_type) => format!(
+                        "Expected request with `Content-Type: application/json`, not `{}`",
+                        content_type
+                    ),
+                    None => "Expected request with `Content-Type: application/json`, \
+                        but it has no `Content-Type`"
+                        .to_string(),
+                };
+                (
+                    StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                    axum::Json(BodyError::new(message)),
+                )
+                    .into_response()
+            }
+            JsonRejection::Syntax(body_error) => {
+                (StatusCode::BAD_REQUEST, axum::Json(body_error)).into_response()
+            }
+            JsonRejection::Data(body_error) => {
+                (StatusCode::UNPROCESSABLE_ENTITY, axum::Json(body_error)).into_response()
+            }
+            JsonRejection::Body(rejection) => (
+                rejection.status(),
+                axum::Json(BodyError::new(rejection.body_text())),
+            )
+                .into_response(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::train::Reservation;
+
+    #[test]
+    fn test_deserialize_unknown_field() {
+        let result =
+            deserialize::<Reservation>(br#"{"seats": [], "booking_reference": "abc", "extra": 1}"#);
+        let Err(JsonRejection::Data(body_error)) = result else {
+            panic!("expected a data error");
+        };
+        assert!(body_error.message.starts_with("unknown field `extra`"));
+        assert_eq!(body_error.line, Some(1));
+    }
+
+    #[test]
+    fn test_deserialize_wrong_type_reports_path() {
+        let result = deserialize::<Reservation>(br#"{"seats": [1], "booking_reference": "abc"}"#);
+        let Err(JsonRejection::Data(body_error)) = result else {
+            panic!("expected a data error");
+        };
+        assert_eq!(body_error.path, Some("seats[0]".to_string()));
+        assert_eq!(body_error.expected, Some("a string".to_string()));
+    }
+
+    #[test]
+    fn test_expected() {
+        assert_eq!(
+            expected("invalid type: string \"1A\", expected a sequence at line 1 column 14"),
+            Some("a sequence".to_string())
+        );
+        assert_eq!(expected("missing field `seats` at line 1 column 2"), None);
+    }
+
+    #[test]
+    fn test_unsupported_media_type_is_json() {
+        let response = JsonRejection::UnsupportedMediaType(None).into_response();
+        assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            mime::APPLICATION_JSON.as_ref()
+        );
+    }
+
+    #[test]
+    fn test_deserialize_syntax_error() {
+        let result = deserialize::<Reservation>(br#"{"seats": "#);
+        assert!(matches!(result, Err(JsonRejection::Syntax(_))));
+    }
+
+    #[test]
+    fn test_deserialize_trailing_characters() {
+        let result = deserialize::<Reservation>(br#"{"seats": [], "booking_reference": "a"} x"#);
+        assert!(matches!(result, Err(JsonRejection::Syntax(_))));
+    }
+}
@@ -0,0 +1,208 @@
+//! Fare buckets: seats sold at a price, like ten cheap seats per coach. A
+//! bucket is used up as seats are sold from it, independently of which
+//! seats those are; when the cheap buckets are gone, seats cost more.
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::train::SeatId;
+
+#[derive(Debug, PartialEq, Eq, Clone, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FareBucket {
+    pub name: String,
+    /// The price of a seat, in cents.
+    pub price: u64,
+    /// How many seats in each coach can be sold from this bucket; there's
+    /// no limit if this isn't given.
+    #[serde(default)]
+    pub seats_per_coach: Option<usize>,
+    /// How many seats were sold, by coach.
+    #[serde(default)]
+    pub sold: BTreeMap<String, usize>,
+}
+
+impl FareBucket {
+    fn sold(&self, coach: &str) -> usize {
+        self.sold.get(coach).copied().unwrap_or_default()
+    }
+
+    fn has_room(&self, coach: &str, taken: usize) -> bool {
+        self.seats_per_coach
+            .is_none_or(|limit| self.sold(coach) + taken < limit)
+    }
+}
+
+/// The fare buckets of a train, cheapest first.
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct Fares(Vec<FareBucket>);
+
+/// The price of a seat, and the bucket it comes from.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct SeatFare {
+    pub seat_id: SeatId,
+    pub fare: String,
+    pub price: u64,
+}
+
+impl Fares {
+    pub fn new(mut buckets: Vec<FareBucket>) -> Self {
+        buckets.sort_by_key(|bucket| bucket.price);
+        Fares(buckets)
+    }
+
+    pub fn buckets(&self) -> impl Iterator<Item = &FareBucket> {
+        self.0.iter()
+    }
+
+    /// Price seats in the given coaches from the cheapest buckets that have
+    /// room. Seats that can't be priced have no fare.
+    pub fn quote<'a>(
+        &self,
+        seats: impl IntoIterator<Item = (&'a SeatId, &'a str)>,
+    ) -> Vec<(&'a SeatId, Option<&FareBucket>)> {
+        // seats earlier in the same quote take room in a bucket too
+        let mut taken: HashMap<(usize, &str), usize> = HashMap::new();
+        seats
+            .into_iter()
+            .map(|(seat_id, coach)| {
+                let bucket = self.0.iter().enumerate().find(|(index, bucket)| {
+                    bucket.has_room(coach, taken.get(&(*index, coach)).copied().unwrap_or(0))
+                });
+                if let Some((index, _)) = bucket {
+                    *taken.entry((index, coach)).or_default() += 1;
+                }
+                (seat_id, bucket.map(|(_, bucket)| bucket))
+            })
+            .collect()
+    }
+
+    /// Count seats in the given coaches as sold from the cheapest buckets,
+    /// returning what they cost.
+    pub fn sell<'a>(&mut self, coaches: impl IntoIterator<Item = &'a str>) -> u64 {
+        let mut price = 0;
+        for coach in coaches {
+            if let Some(bucket) = self.0.iter_mut().find(|bucket| bucket.has_room(coach, 0)) {
+                *bucket.sold.entry(coach.to_string()).or_default() += 1;
+                price += bucket.price;
+            }
+        }
+        price
+    }
+
+    /// Take seats in the given coaches back into their buckets, like they
+    /// were never sold. The most expensive buckets were sold from last, so
+    /// they're emptied first.
+    pub fn unsell<'a>(&mut self, coaches: impl IntoIterator<Item = &'a str>) {
+        for coach in coaches {
+            let bucket = self
+                .0
+                .iter_mut()
+                .rev()
+                .find(|bucket| bucket.sold(coach) > 0);
+            if let Some(bucket) = bucket {
+                let sold = bucket.sold.get_mut(coach).unwrap();
+                *sold -= 1;
+                if *sold == 0 {
+                    bucket.sold.remove(coach);
+                }
+            }
+        }
+    }
+
+    pub fn reset(&mut self) {
+        for bucket in &mut self.0 {
+            bucket.sold.clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fares() -> Fares {
+        Fares::new(vec![
+            FareBucket {
+                name: "standard".to_string(),
+                price: 4500,
+                seats_per_coach: None,
+                sold: BTreeMap::new(),
+            },
+            FareBucket {
+                name: "saver".to_string(),
+                price: 1900,
+                seats_per_coach: Some(2),
+                sold: BTreeMap::from([("A".to_string(), 1)]),
+            },
+        ])
+    }
+
+    fn names<'a>(quote: &[(&SeatId, Option<&'a FareBucket>)]) -> Vec<Option<&'a str>> {
+        quote
+            .iter()
+            .map(|(_, bucket)| bucket.map(|bucket| bucket.name.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn test_quote() {
+        let fares = fares();
+        let seat_ids = [SeatId::new("1A"), SeatId::new("2A"), SeatId::new("1B")];
+        let quote = fares.quote([
+            (&seat_ids[0], "A"),
+            (&seat_ids[1], "A"),
+            (&seat_ids[2], "B"),
+        ]);
+        assert_eq!(
+            names(&quote),
+            vec![Some("saver"), Some("standard"), Some("saver")]
+        );
+    }
+
+    #[test]
+    fn test_sell_and_reset() {
+        let mut fares = fares();
+        assert_eq!(fares.sell(["A", "A"]), 1900 + 4500);
+        let saver = fares.buckets().next().unwrap();
+        assert_eq!(saver.sold("A"), 2);
+        let standard = fares.buckets().nth(1).unwrap();
+        assert_eq!(standard.sold("A"), 1);
+
+        let seat_id = SeatId::new("3A");
+        assert_eq!(
+            names(&fares.quote([(&seat_id, "A")])),
+            vec![Some("standard")]
+        );
+        fares.reset();
+        assert_eq!(names(&fares.quote([(&seat_id, "A")])), vec![Some("saver")]);
+    }
+
+    #[test]
+    fn test_unsell() {
+        let mut fares = fares();
+        fares.sell(["A", "A"]);
+        fares.unsell(["A"]);
+        let standard = fares.buckets().nth(1).unwrap();
+        assert_eq!(standard.sold("A"), 0);
+        assert!(standard.sold.is_empty());
+        let seat_id = SeatId::new("3A");
+        assert_eq!(
+            names(&fares.quote([(&seat_id, "A")])),
+            vec![Some("standard")]
+        );
+        fares.unsell(["A", "A", "B"]);
+        assert_eq!(names(&fares.quote([(&seat_id, "A")])), vec![Some("saver")]);
+    }
+
+    #[test]
+    fn test_quote_without_room() {
+        let fares = Fares::new(vec![FareBucket {
+            name: "saver".to_string(),
+            price: 1900,
+            seats_per_coach: Some(0),
+            sold: BTreeMap::new(),
+        }]);
+        let seat_id = SeatId::new("1A");
+        assert_eq!(names(&fares.quote([(&seat_id, "A")])), vec![None]);
+    }
+}
@@ -0,0 +1,86 @@
+//! Choosing which fields of seats to send, like
+//! `?fields=seat_number,coach,booking_reference`, for clients that only need
+//! a few of them.
+
+use std::collections::HashSet;
+use std::str::FromStr;
+
+use crate::train::Error;
+
+/// A field of a seat in responses.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum Field {
+    SeatNumber,
+    Coach,
+    BookingReference,
+    HeldUntil,
+    Position,
+    Quiet,
+    Accessible,
+    Blocked,
+    Neighbours,
+}
+
+impl FromStr for Field {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "seat_number" => Ok(Field::SeatNumber),
+            "coach" => Ok(Field::Coach),
+            "booking_reference" => Ok(Field::BookingReference),
+            "held_until" => Ok(Field::HeldUntil),
+            "position" => Ok(Field::Position),
+            "quiet" => Ok(Field::Quiet),
+            "accessible" => Ok(Field::Accessible),
+            "blocked" => Ok(Field::Blocked),
+            "neighbours" => Ok(Field::Neighbours),
+            _ => Err(Error::InvalidFields(format!("unknown field {}", s))),
+        }
+    }
+}
+
+/// The fields of seats a client asked for.
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct Fields {
+    fields: HashSet<Field>,
+}
+
+impl FromStr for Fields {
+    type Err = Error;
+
+    /// Comma separated field names.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Fields {
+            fields: s
+                .split(',')
+                .filter(|field| !field.is_empty())
+                .map(Field::from_str)
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+impl Fields {
+    pub fn contains(&self, field: Field) -> bool {
+        self.fields.contains(&field)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_fields() {
+        let fields = "seat_number,booking_reference".parse::<Fields>().unwrap();
+        assert!(fields.contains(Field::SeatNumber));
+        assert!(fields.contains(Field::BookingReference));
+        assert!(!fields.contains(Field::Coach));
+        assert_eq!("".parse::<Fields>(), Ok(Fields::default()));
+        assert_eq!(
+            "coach,colour".parse::<Fields>(),
+            Err(Error::InvalidFields("unknown field colour".to_string()))
+        );
+    }
+}
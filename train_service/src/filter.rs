@@ -0,0 +1,166 @@
+//! Choosing which seats of a train to show, like
+//! `?free=true&coach=A&class=first&attr=window`, so clients don't have to
+//! filter seats themselves.
+
+use std::collections::HashSet;
+use std::str::FromStr;
+
+use crate::train::{Class, Error, Seat, SeatPosition, Train};
+
+/// The seats to show, as given in the query string. Seats have to match
+/// everything that's given.
+#[derive(Debug, Default, PartialEq, Eq, serde::Deserialize)]
+pub struct SeatFilter {
+    /// Only seats that can be reserved, or only those that can't.
+    pub free: Option<bool>,
+    /// Comma separated coaches.
+    pub coach: Option<String>,
+    pub class: Option<Class>,
+    /// Comma separated attributes the seats all need to have.
+    pub attr: Option<String>,
+}
+
+// something a seat can have going for it
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Attribute {
+    Position(SeatPosition),
+    Accessible,
+    Quiet,
+}
+
+impl FromStr for Attribute {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "window" => Ok(Attribute::Position(SeatPosition::Window)),
+            "middle" => Ok(Attribute::Position(SeatPosition::Middle)),
+            "aisle" => Ok(Attribute::Position(SeatPosition::Aisle)),
+            "accessible" => Ok(Attribute::Accessible),
+            "quiet" => Ok(Attribute::Quiet),
+            _ => Err(Error::InvalidSeatFilter(format!("unknown attribute {}", s))),
+        }
+    }
+}
+
+impl Attribute {
+    fn holds(&self, train: &Train, seat: &Seat) -> bool {
+        match self {
+            Attribute::Position(position) => seat.position() == Some(*position),
+            Attribute::Accessible => seat.is_accessible(),
+            Attribute::Quiet => train.is_quiet(seat.coach()),
+        }
+    }
+}
+
+impl SeatFilter {
+    pub fn is_empty(&self) -> bool {
+        *self == SeatFilter::default()
+    }
+
+    /// Turn the filter into a test for seats. The query is parsed once here,
+    /// rather than for every seat.
+    pub fn compile(&self) -> Result<impl Fn(&Train, &Seat) -> bool + '_, Error> {
+        let coaches: Option<HashSet<&str>> = self.coach.as_deref().map(|coaches| {
+            coaches
+                .split(',')
+                .filter(|coach| !coach.is_empty())
+                .collect()
+        });
+        let attributes = self
+            .attr
+            .as_deref()
+            .unwrap_or_default()
+            .split(',')
+            .filter(|attribute| !attribute.is_empty())
+            .map(Attribute::from_str)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(move |train: &Train, seat: &Seat| {
+            self.free
+                .is_none_or(|free| train.is_available(seat) == free)
+                && coaches
+                    .as_ref()
+                    .is_none_or(|coaches| coaches.contains(seat.coach()))
+                && self
+                    .class
+                    .is_none_or(|class| train.class(seat.coach()) == class)
+                && attributes
+                    .iter()
+                    .all(|attribute| attribute.holds(train, seat))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::booking_reference::BookingReference;
+    use crate::train::{Coach, SeatId};
+
+    // coach A is a first class quiet coach with a window seat at 1A, which is
+    // reserved; coach B has an accessible aisle seat at 1B
+    fn train() -> Train {
+        let seats = HashMap::from([
+            (
+                SeatId::new("1A"),
+                Seat::new("1".to_string(), "A".to_string())
+                    .with_position(SeatPosition::Window)
+                    .with_booking_reference(Some(BookingReference::new("abc"))),
+            ),
+            (
+                SeatId::new("2A"),
+                Seat::new("2".to_string(), "A".to_string()),
+            ),
+            (
+                SeatId::new("1B"),
+                Seat::new("1".to_string(), "B".to_string())
+                    .with_position(SeatPosition::Aisle)
+                    .with_accessible(true),
+            ),
+        ]);
+        let coach = Coach {
+            quiet: true,
+            class: Class::First,
+            ..Coach::default()
+        };
+        Train::new(seats).with_coaches(HashMap::from([("A".to_string(), coach)]))
+    }
+
+    fn matching(query: &str) -> Result<Vec<String>, Error> {
+        let filter: SeatFilter = serde_json::from_str(query).unwrap();
+        let train = train();
+        let matches = filter.compile()?;
+        let mut seat_ids = train
+            .seats()
+            .filter(|(_, seat)| matches(&train, seat))
+            .map(|(seat_id, _)| seat_id.as_str().to_string())
+            .collect::<Vec<_>>();
+        seat_ids.sort();
+        Ok(seat_ids)
+    }
+
+    #[test]
+    fn test_filter() {
+        assert_eq!(matching(r#"{}"#).unwrap(), ["1A", "1B", "2A"]);
+        assert_eq!(matching(r#"{"free": true}"#).unwrap(), ["1B", "2A"]);
+        assert_eq!(matching(r#"{"free": false}"#).unwrap(), ["1A"]);
+        assert_eq!(matching(r#"{"coach": "B"}"#).unwrap(), ["1B"]);
+        assert_eq!(matching(r#"{"coach": "A,B"}"#).unwrap(), ["1A", "1B", "2A"]);
+        assert_eq!(matching(r#"{"class": "first"}"#).unwrap(), ["1A", "2A"]);
+        assert_eq!(matching(r#"{"attr": "window"}"#).unwrap(), ["1A"]);
+        assert_eq!(matching(r#"{"attr": "quiet"}"#).unwrap(), ["1A", "2A"]);
+        assert_eq!(matching(r#"{"attr": "aisle,accessible"}"#).unwrap(), ["1B"]);
+        assert_eq!(
+            matching(r#"{"free": true, "class": "first", "attr": "window"}"#).unwrap(),
+            Vec::<String>::new()
+        );
+        assert_eq!(
+            matching(r#"{"attr": "sunroof"}"#),
+            Err(Error::InvalidSeatFilter(
+                "unknown attribute sunroof".to_string()
+            ))
+        );
+    }
+}
@@ -0,0 +1,200 @@
+//! Building trains to test against, for unit tests and generated scenarios,
+//! like `TrainBuilder::coaches(3).seats_per_coach(20).reserved(0.5).build()`.
+//!
+//! Coaches are named `A`, `B` and so on, and their seats are numbered from 1.
+
+#[cfg(test)]
+use std::collections::HashMap;
+
+use rand::seq::SliceRandom;
+use rand::Rng;
+
+use crate::booking_reference::BookingReference;
+use crate::rules::Rules;
+use crate::spec::{CoachSpec, TrainSpec};
+#[cfg(test)]
+use crate::train::Seat;
+use crate::train::{Error, Reservation, SeatId, Train};
+
+// reservations of random trains have up to this many seats
+const MAX_GROUP: usize = 4;
+
+#[cfg(test)]
+type SeatChange = Box<dyn FnOnce(Seat) -> Seat>;
+
+pub struct TrainBuilder {
+    coaches: usize,
+    seats_per_coach: usize,
+    // the fraction of seats that's reserved already
+    reserved: f64,
+    #[cfg(test)]
+    changes: Vec<(SeatId, SeatChange)>,
+}
+
+impl TrainBuilder {
+    /// A train with this many coaches, of 10 seats each unless told otherwise.
+    pub fn coaches(coaches: usize) -> Self {
+        TrainBuilder {
+            coaches,
+            seats_per_coach: 10,
+            reserved: 0.0,
+            #[cfg(test)]
+            changes: Vec::new(),
+        }
+    }
+
+    pub fn seats_per_coach(self, seats_per_coach: usize) -> Self {
+        TrainBuilder {
+            seats_per_coach,
+            ..self
+        }
+    }
+
+    /// Reserve this fraction of the seats.
+    pub fn reserved(self, reserved: f64) -> Self {
+        TrainBuilder { reserved, ..self }
+    }
+
+    /// Change a seat, like to make it accessible.
+    #[cfg(test)]
+    pub fn seat(mut self, seat_id: &str, change: impl FnOnce(Seat) -> Seat + 'static) -> Self {
+        self.changes.push((SeatId::new(seat_id), Box::new(change)));
+        self
+    }
+
+    /// Reserve a seat with this booking reference.
+    #[cfg(test)]
+    pub fn reserve(self, seat_id: &str, booking_reference: &str) -> Self {
+        let booking_reference = BookingReference::new(booking_reference);
+        self.seat(seat_id, move |seat| {
+            seat.with_booking_reference(Some(booking_reference))
+        })
+    }
+
+    fn spec(&self) -> TrainSpec {
+        TrainSpec {
+            coaches: (0..self.coaches)
+                .map(|index| CoachSpec {
+                    id: char::from(b'A' + index as u8).to_string(),
+                    seats: self.seats_per_coach,
+                    layout: None,
+                })
+                .collect(),
+        }
+    }
+
+    // by coach, then by seat number
+    fn seat_ids(&self) -> Vec<SeatId> {
+        (0..self.coaches)
+            .flat_map(|index| {
+                let coach = char::from(b'A' + index as u8).to_string();
+                (1..=self.seats_per_coach).map(move |number| {
+                    SeatId::from_parts(&number.to_string(), &coach).expect("valid seat id")
+                })
+            })
+            .collect()
+    }
+
+    fn reserved_count(&self, seats: usize) -> usize {
+        (seats as f64 * self.reserved).round() as usize
+    }
+
+    /// The train, with the first seats reserved, all with booking reference
+    /// `existing`.
+    #[cfg(test)]
+    pub fn build(self) -> Train {
+        let seat_ids = self.seat_ids();
+        let reserved = self.reserved_count(seat_ids.len());
+        let mut seats: HashMap<SeatId, Seat> = seat_ids
+            .into_iter()
+            .enumerate()
+            .map(|(index, seat_id)| {
+                let (number, coach) = seat_id.split();
+                let seat = Seat::new(number.to_string(), coach.to_string()).with_booking_reference(
+                    (index < reserved).then(|| BookingReference::new("existing")),
+                );
+                (seat_id, seat)
+            })
+            .collect();
+        for (seat_id, change) in self.changes {
+            let seat = seats.remove(&seat_id).expect("changed seat exists");
+            seats.insert(seat_id, change(seat));
+        }
+        Train::new(seats)
+    }
+
+    /// The train, with random seats reserved in groups of up to four, each
+    /// with a booking reference of its own.
+    pub fn build_random(
+        &self,
+        rng: &mut impl Rng,
+        mut booking_reference: impl FnMut() -> BookingReference,
+    ) -> Result<Train, Error> {
+        let mut train = self.spec().build()?;
+        let mut seat_ids = self.seat_ids();
+        seat_ids.shuffle(rng);
+        let reserved = self.reserved_count(seat_ids.len());
+        let mut seat_ids = &seat_ids[..reserved];
+        while !seat_ids.is_empty() {
+            let size = rng.gen_range(1..=MAX_GROUP).min(seat_ids.len());
+            let (group, rest) = seat_ids.split_at(size);
+            let reservation = Reservation {
+                seats: group.to_vec(),
+                booking_reference: booking_reference(),
+                accessible: true,
+            };
+            train.reserve(&reservation, &Rules::default())?;
+            seat_ids = rest;
+        }
+        Ok(train)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::*;
+
+    #[test]
+    fn test_build() {
+        let train = TrainBuilder::coaches(2)
+            .seats_per_coach(5)
+            .reserved(0.3)
+            .seat("2B", |seat| seat.with_accessible(true))
+            .build();
+        assert_eq!(train.occupancy().train().total, 10);
+        assert_eq!(train.occupancy().coach("A").unwrap().reserved, 3);
+        assert!(train.get(&SeatId::new("2B")).unwrap().is_accessible());
+        assert!(train.get(&SeatId::new("6A")).is_none());
+    }
+
+    #[test]
+    fn test_build_random() {
+        let builder = TrainBuilder::coaches(3).seats_per_coach(10).reserved(0.5);
+        let mut counter = 0;
+        let train = builder
+            .build_random(&mut StdRng::seed_from_u64(1), || {
+                counter += 1;
+                BookingReference::new(counter.to_string())
+            })
+            .unwrap();
+        assert_eq!(train.occupancy().train().reserved, 15);
+
+        // the same seed picks the same seats
+        let again = builder
+            .build_random(&mut StdRng::seed_from_u64(1), || BookingReference::new("x"))
+            .unwrap();
+        let reserved = |train: &Train| {
+            let mut seat_ids: Vec<String> = train
+                .seats()
+                .filter(|(_, seat)| seat.booking_reference().is_some())
+                .map(|(seat_id, _)| seat_id.to_string())
+                .collect();
+            seat_ids.sort();
+            seat_ids
+        };
+        assert_eq!(reserved(&train), reserved(&again));
+    }
+}
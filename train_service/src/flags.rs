@@ -0,0 +1,74 @@
+//! Switching experimental features on and off while the service runs, so the
+//! same service can be used for the basic kata, without them, and for the
+//! advanced variants.
+
+use crate::train::Error;
+
+/// A feature that can be switched off.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Flag {
+    /// Picking the seats for a reservation with `POST /reserve`.
+    AutoAllocation,
+    /// Holding seats for a while with `POST /train/<train_id>/hold`.
+    Holds,
+}
+
+impl Flag {
+    pub fn name(self) -> &'static str {
+        match self {
+            Flag::AutoAllocation => "auto_allocation",
+            Flag::Holds => "holds",
+        }
+    }
+}
+
+/// Which features are switched on. They all are unless switched off.
+#[derive(Debug, PartialEq, Eq, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Flags {
+    pub auto_allocation: bool,
+    pub holds: bool,
+}
+
+impl Default for Flags {
+    fn default() -> Self {
+        Flags {
+            auto_allocation: true,
+            holds: true,
+        }
+    }
+}
+
+impl Flags {
+    pub fn is_on(&self, flag: Flag) -> bool {
+        match flag {
+            Flag::AutoAllocation => self.auto_allocation,
+            Flag::Holds => self.holds,
+        }
+    }
+
+    /// Refuse to go on with a feature that's switched off.
+    pub fn check(&self, flag: Flag) -> Result<(), Error> {
+        if self.is_on(flag) {
+            Ok(())
+        } else {
+            Err(Error::FeatureOff(flag))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flags() {
+        let flags: Flags = serde_json::from_value(serde_json::json!({ "holds": false })).unwrap();
+        assert!(flags.check(Flag::AutoAllocation).is_ok());
+        assert_eq!(
+            flags.check(Flag::Holds),
+            Err(Error::FeatureOff(Flag::Holds))
+        );
+        assert!(serde_json::from_value::<Flags>(serde_json::json!({ "webhooks": true })).is_err());
+    }
+}
@@ -0,0 +1,124 @@
+//! Guessing when a train fills up, from how fast it filled up recently: a
+//! straight line is fitted through the latest occupancy samples and followed
+//! to where it reaches a level.
+
+use crate::metrics::OccupancySample;
+
+// how many of the latest samples the line is fitted through
+const RECENT_SAMPLES: usize = 20;
+
+/// When a train is expected to reach a level of reserved seats.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Projection {
+    /// At this time, in seconds since the Unix epoch. For a level the train
+    /// is at already, this is when it got there.
+    At(u64),
+    /// The train isn't filling up, or not fast enough to tell.
+    NotExpected,
+}
+
+impl serde::Serialize for Projection {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Projection::At(at) => serializer.serialize_u64(*at),
+            Projection::NotExpected => serializer.serialize_str("not expected"),
+        }
+    }
+}
+
+/// Project when the train reaches `fraction` of its seats, given its
+/// occupancy samples, oldest first.
+pub fn project(samples: &[OccupancySample], fraction: f64) -> Projection {
+    let Some(latest) = samples.last() else {
+        return Projection::NotExpected;
+    };
+    let reaches = |sample: &OccupancySample| {
+        sample.total > 0 && sample.reserved as f64 >= fraction * sample.total as f64
+    };
+    if reaches(latest) {
+        // when it last got there
+        let since = samples
+            .iter()
+            .rev()
+            .take_while(|sample| reaches(sample))
+            .last()
+            .unwrap_or(latest);
+        return Projection::At(since.at);
+    }
+    let recent = &samples[samples.len().saturating_sub(RECENT_SAMPLES)..];
+    let Some(slope) = slope(recent) else {
+        return Projection::NotExpected;
+    };
+    if slope <= 0.0 {
+        return Projection::NotExpected;
+    }
+    let target = fraction * latest.total as f64;
+    let seconds = ((target - latest.reserved as f64) / slope).ceil();
+    Projection::At(latest.at + seconds as u64)
+}
+
+// the seats reserved per second, fitted by least squares
+fn slope(samples: &[OccupancySample]) -> Option<f64> {
+    let first = samples.first()?.at;
+    let n = samples.len() as f64;
+    let points = samples.iter().map(|sample| {
+        (
+            sample.at.saturating_sub(first) as f64,
+            sample.reserved as f64,
+        )
+    });
+    let (mean_x, mean_y) = points
+        .clone()
+        .fold((0.0, 0.0), |(x, y), (px, py)| (x + px / n, y + py / n));
+    let (covariance, variance) = points.fold((0.0, 0.0), |(c, v), (x, y)| {
+        (
+            c + (x - mean_x) * (y - mean_y),
+            v + (x - mean_x) * (x - mean_x),
+        )
+    });
+    (variance > 0.0).then(|| covariance / variance)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn samples(samples: &[(u64, usize)]) -> Vec<OccupancySample> {
+        samples
+            .iter()
+            .map(|&(at, reserved)| OccupancySample {
+                at,
+                reserved,
+                total: 10,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_project() {
+        // a seat every minute
+        let filling = samples(&[(1000, 0), (1060, 1), (1120, 2)]);
+        assert_eq!(project(&filling, 0.7), Projection::At(1420));
+        assert_eq!(project(&filling, 1.0), Projection::At(1600));
+        // reached already
+        let full = samples(&[(1000, 0), (1060, 7), (1120, 5), (1180, 8), (1240, 9)]);
+        assert_eq!(project(&full, 0.7), Projection::At(1180));
+    }
+
+    #[test]
+    fn test_not_expected() {
+        assert_eq!(project(&[], 0.7), Projection::NotExpected);
+        assert_eq!(
+            project(&samples(&[(1000, 3)]), 0.7),
+            Projection::NotExpected
+        );
+        assert_eq!(
+            project(&samples(&[(1000, 3), (1060, 3)]), 0.7),
+            Projection::NotExpected
+        );
+        assert_eq!(
+            project(&samples(&[(1000, 5), (1060, 2)]), 0.7),
+            Projection::NotExpected
+        );
+    }
+}
@@ -0,0 +1,128 @@
+//! Random trains with some of their seats reserved already, for varied
+//! scenarios to practise against.
+
+use rand::Rng;
+
+use crate::booking_reference::BookingReference;
+use crate::fixtures::TrainBuilder;
+use crate::train::{Error, Train};
+
+/// What kind of trains to generate.
+#[derive(Debug, PartialEq, Clone, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scenario {
+    #[serde(default = "default_trains")]
+    pub trains: usize,
+    #[serde(default = "default_coaches")]
+    pub coaches: usize,
+    // per coach
+    #[serde(default = "default_seats")]
+    pub seats: usize,
+    // the fraction of seats that's reserved
+    #[serde(default)]
+    pub occupancy: f64,
+    // to generate the same trains again
+    pub seed: Option<u64>,
+}
+
+fn default_trains() -> usize {
+    1
+}
+
+fn default_coaches() -> usize {
+    4
+}
+
+fn default_seats() -> usize {
+    40
+}
+
+impl Scenario {
+    pub fn validate(&self) -> Result<(), Error> {
+        let invalid = |message: &str| Err(Error::InvalidTrainSpec(message.to_string()));
+        if !(1..=100).contains(&self.trains) {
+            return invalid("trains must be between 1 and 100");
+        }
+        if !(1..=26).contains(&self.coaches) {
+            return invalid("coaches must be between 1 and 26");
+        }
+        if !(1..=200).contains(&self.seats) {
+            return invalid("seats must be between 1 and 200");
+        }
+        if !(0.0..=1.0).contains(&self.occupancy) {
+            return invalid("occupancy must be between 0 and 1");
+        }
+        Ok(())
+    }
+
+    /// A train of the scenario, with random seats reserved in groups, each
+    /// with a booking reference of its own.
+    pub fn train(
+        &self,
+        rng: &mut impl Rng,
+        booking_reference: impl FnMut() -> BookingReference,
+    ) -> Result<Train, Error> {
+        TrainBuilder::coaches(self.coaches)
+            .seats_per_coach(self.seats)
+            .reserved(self.occupancy)
+            .build_random(rng, booking_reference)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::*;
+
+    fn scenario(occupancy: f64) -> Scenario {
+        Scenario {
+            trains: 1,
+            coaches: 3,
+            seats: 10,
+            occupancy,
+            seed: None,
+        }
+    }
+
+    #[test]
+    fn test_generate_train() {
+        let mut counter = 0;
+        let mut booking_reference = || {
+            counter += 1;
+            BookingReference::new(counter.to_string())
+        };
+        let train = scenario(0.5)
+            .train(&mut StdRng::seed_from_u64(1), &mut booking_reference)
+            .unwrap();
+        let counts = train.occupancy().train();
+        assert_eq!((counts.reserved, counts.total), (15, 30));
+        assert_eq!(train.occupancy().coaches().count(), 3);
+
+        let again = scenario(0.5)
+            .train(&mut StdRng::seed_from_u64(1), || BookingReference::new("x"))
+            .unwrap();
+        let reserved = |train: &Train| {
+            let mut seat_ids: Vec<String> = train
+                .seats()
+                .filter(|(_, seat)| seat.booking_reference().is_some())
+                .map(|(seat_id, _)| seat_id.to_string())
+                .collect();
+            seat_ids.sort();
+            seat_ids
+        };
+        assert_eq!(reserved(&train), reserved(&again));
+    }
+
+    #[test]
+    fn test_validate() {
+        assert_eq!(scenario(0.3).validate(), Ok(()));
+        assert_eq!(
+            scenario(1.5).validate(),
+            Err(Error::InvalidTrainSpec(
+                "occupancy must be between 0 and 1".to_string()
+            ))
+        );
+    }
+}
@@ -0,0 +1,134 @@
+//! What happened to each seat: who reserved it, when, and when it was
+//! released again. Only the latest reservations of each seat are kept.
+
+use std::collections::{HashMap, VecDeque};
+
+use crate::booking_reference::BookingReference;
+use crate::train::{SeatId, TrainId};
+
+// reservations kept per seat, unless told otherwise
+const DEFAULT_LIMIT: usize = 20;
+
+/// A reservation of a seat, with times in seconds since the Unix epoch.
+#[derive(Debug, PartialEq, Eq, Clone, serde::Serialize, serde::Deserialize)]
+pub struct SeatEvent {
+    pub booking_reference: BookingReference,
+    pub reserved_at: u64,
+    /// When the seat was released again, if it was.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub released_at: Option<u64>,
+}
+
+#[derive(Debug)]
+pub struct SeatHistory {
+    limit: usize,
+    // oldest first
+    events: HashMap<TrainId, HashMap<SeatId, VecDeque<SeatEvent>>>,
+}
+
+impl Default for SeatHistory {
+    fn default() -> Self {
+        SeatHistory::new(DEFAULT_LIMIT)
+    }
+}
+
+impl SeatHistory {
+    pub fn new(limit: usize) -> Self {
+        SeatHistory {
+            limit,
+            events: HashMap::new(),
+        }
+    }
+
+    pub fn reserved(
+        &mut self,
+        train_id: &TrainId,
+        seat_id: &SeatId,
+        booking_reference: &BookingReference,
+        now: u64,
+    ) {
+        if self.limit == 0 {
+            return;
+        }
+        let events = self
+            .events
+            .entry(train_id.clone())
+            .or_default()
+            .entry(seat_id.clone())
+            .or_default();
+        if events.len() == self.limit {
+            events.pop_front();
+        }
+        events.push_back(SeatEvent {
+            booking_reference: booking_reference.clone(),
+            reserved_at: now,
+            released_at: None,
+        });
+    }
+
+    pub fn released(&mut self, train_id: &TrainId, seat_id: &SeatId, now: u64) {
+        let latest = self
+            .events
+            .get_mut(train_id)
+            .and_then(|seats| seats.get_mut(seat_id))
+            .and_then(|events| events.back_mut());
+        if let Some(event) = latest {
+            event.released_at.get_or_insert(now);
+        }
+    }
+
+    /// All seats of a train were released, like when it's reset.
+    pub fn released_all(&mut self, train_id: &TrainId, now: u64) {
+        let Some(seats) = self.events.get_mut(train_id) else {
+            return;
+        };
+        for event in seats.values_mut().filter_map(|events| events.back_mut()) {
+            event.released_at.get_or_insert(now);
+        }
+    }
+
+    pub fn get(&self, train_id: &TrainId, seat_id: &SeatId) -> Vec<SeatEvent> {
+        self.events
+            .get(train_id)
+            .and_then(|seats| seats.get(seat_id))
+            .map_or_else(Vec::new, |events| events.iter().cloned().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seat_history() {
+        let mut history = SeatHistory::new(2);
+        let (train_id, seat_id) = (TrainId::new("t"), SeatId::new("1A"));
+        history.reserved(&train_id, &seat_id, &BookingReference::new("a"), 10);
+        history.released(&train_id, &seat_id, 20);
+        history.reserved(&train_id, &seat_id, &BookingReference::new("b"), 30);
+        assert_eq!(
+            history.get(&train_id, &seat_id),
+            vec![
+                SeatEvent {
+                    booking_reference: BookingReference::new("a"),
+                    reserved_at: 10,
+                    released_at: Some(20),
+                },
+                SeatEvent {
+                    booking_reference: BookingReference::new("b"),
+                    reserved_at: 30,
+                    released_at: None,
+                },
+            ]
+        );
+
+        // the oldest reservation goes when there are too many
+        history.released_all(&train_id, 40);
+        history.reserved(&train_id, &seat_id, &BookingReference::new("c"), 50);
+        let events = history.get(&train_id, &seat_id);
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].released_at, Some(40));
+        assert_eq!(events[1].booking_reference, BookingReference::new("c"));
+        assert!(history.get(&train_id, &SeatId::new("2A")).is_empty());
+    }
+}
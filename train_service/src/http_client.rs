@@ -0,0 +1,375 @@
+//! Calling other services over HTTP, for everything in the service that
+//! does, so they all get connection pooling, timeouts and retries the same
+//! way. Requests pass on the trace and correlation id of the request being
+//! handled, so the calls show up as part of it.
+//!
+//! Only plain `http://` URLs are supported, like for telemetry.
+
+use std::time::Duration;
+
+use axum::body::Bytes;
+use axum::http::{header, Method, StatusCode};
+use reqwest::Url;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::{correlation, telemetry};
+
+// how long an unused connection stays open, and how many are kept per host
+const POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+const POOL_MAX_IDLE_PER_HOST: usize = 8;
+
+#[derive(Debug)]
+pub enum ClientError {
+    InvalidUrl(String),
+    /// The request couldn't be sent.
+    Connect(String),
+    /// The request was sent, but the connection broke before the response
+    /// came back.
+    Broken(String),
+    Timeout(Duration),
+    /// The other service answered, but not with a success.
+    Status(StatusCode, String),
+    /// The body of the response isn't what was expected.
+    Body(String),
+}
+
+impl std::fmt::Display for ClientError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ClientError::InvalidUrl(url) => write!(f, "invalid URL {}", url),
+            ClientError::Connect(message) => write!(f, "cannot connect: {}", message),
+            ClientError::Broken(message) => write!(f, "connection broke: {}", message),
+            ClientError::Timeout(timeout) => write!(f, "no response within {:?}", timeout),
+            ClientError::Status(status, body) => write!(f, "got {}: {}", status, body),
+            ClientError::Body(message) => write!(f, "unexpected response: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+impl ClientError {
+    // whether trying again might help
+    fn is_transient(&self) -> bool {
+        match self {
+            ClientError::Connect(_) | ClientError::Broken(_) | ClientError::Timeout(_) => true,
+            ClientError::Status(status, _) => {
+                *status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+            }
+            ClientError::InvalidUrl(_) | ClientError::Body(_) => false,
+        }
+    }
+}
+
+/// How often to try a request, and how long to wait in between: the wait
+/// doubles after every attempt, up to `max_backoff`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Retry {
+    pub attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for Retry {
+    fn default() -> Self {
+        Retry {
+            attempts: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(2),
+        }
+    }
+}
+
+impl Retry {
+    /// Try only once.
+    pub fn never() -> Self {
+        Retry {
+            attempts: 1,
+            ..Retry::default()
+        }
+    }
+
+    /// How long to wait after the attempt with this number, counting from 0.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        self.initial_backoff
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max_backoff)
+    }
+}
+
+/// A client that keeps connections to services open between requests. It's
+/// cheap to clone, and clones share their connections.
+#[derive(Debug, Clone)]
+pub struct HttpClient {
+    client: reqwest::Client,
+    timeout: Duration,
+    retry: Retry,
+    api_key: Option<String>,
+}
+
+impl HttpClient {
+    /// A client that gives up on a request after `timeout`.
+    pub fn new(timeout: Duration) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(timeout)
+            .pool_idle_timeout(POOL_IDLE_TIMEOUT)
+            .pool_max_idle_per_host(POOL_MAX_IDLE_PER_HOST)
+            .build()
+            // only fails when TLS can't be set up, and we only speak plain HTTP
+            .expect("cannot build HTTP client");
+        HttpClient {
+            client,
+            timeout,
+            retry: Retry::default(),
+            api_key: None,
+        }
+    }
+
+    pub fn with_retry(self, retry: Retry) -> Self {
+        HttpClient { retry, ..self }
+    }
+
+    /// Send an `X-API-Key` header with every request.
+    pub fn with_api_key(self, api_key: impl Into<String>) -> Self {
+        HttpClient {
+            api_key: Some(api_key.into()),
+            ..self
+        }
+    }
+
+    pub async fn get_json<T: DeserializeOwned>(&self, url: &str) -> Result<T, ClientError> {
+        let body = self.send(Method::GET, url, None).await?;
+        serde_json::from_slice(&body).map_err(|err| ClientError::Body(err.to_string()))
+    }
+
+    /// Post a JSON body. POST requests aren't idempotent, so they're only
+    /// tried again when they couldn't be sent at all.
+    pub async fn post_json<B: Serialize, T: DeserializeOwned>(
+        &self,
+        url: &str,
+        body: &B,
+    ) -> Result<T, ClientError> {
+        let body = serde_json::to_vec(body).map_err(|err| ClientError::Body(err.to_string()))?;
+        let body = self.send(Method::POST, url, Some(body.into())).await?;
+        serde_json::from_slice(&body).map_err(|err| ClientError::Body(err.to_string()))
+    }
+
+    async fn send(
+        &self,
+        method: Method,
+        url: &str,
+        body: Option<Bytes>,
+    ) -> Result<Bytes, ClientError> {
+        let url = Url::parse(url).map_err(|_| ClientError::InvalidUrl(url.to_string()))?;
+        if url.scheme() != "http" {
+            return Err(ClientError::InvalidUrl(url.to_string()));
+        }
+        let idempotent = method == Method::GET;
+        let mut attempt = 0;
+        loop {
+            let result = self.send_once(&method, &url, body.clone()).await;
+            let retry = match &result {
+                Err(err @ ClientError::Connect(_)) => err.is_transient(),
+                Err(err) => idempotent && err.is_transient(),
+                Ok(_) => false,
+            };
+            attempt += 1;
+            if !retry || attempt >= self.retry.attempts {
+                return result;
+            }
+            let backoff = self.retry.backoff(attempt - 1);
+            tracing::info!(%method, %url, attempt, ?backoff, "retrying request");
+            tokio::time::sleep(backoff).await;
+        }
+    }
+
+    async fn send_once(
+        &self,
+        method: &Method,
+        url: &Url,
+        body: Option<Bytes>,
+    ) -> Result<Bytes, ClientError> {
+        let mut request = self.client.request(method.clone(), url.clone());
+        if let Some(body) = body {
+            request = request
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(body);
+        }
+        for (name, value) in telemetry::trace_headers() {
+            request = request.header(name, value);
+        }
+        if let Some(id) = correlation::current() {
+            request = request.header(correlation::HEADER, id);
+        }
+        if let Some(api_key) = &self.api_key {
+            request = request.header("x-api-key", api_key);
+        }
+        let response = request.send().await.map_err(|err| self.error(err))?;
+        let status = response.status();
+        let body = response.bytes().await.map_err(|err| self.error(err))?;
+        if !status.is_success() {
+            return Err(ClientError::Status(
+                status,
+                String::from_utf8_lossy(&body).into_owned(),
+            ));
+        }
+        Ok(body)
+    }
+
+    fn error(&self, err: reqwest::Error) -> ClientError {
+        if err.is_timeout() {
+            ClientError::Timeout(self.timeout)
+        } else if err.is_connect() {
+            ClientError::Connect(err.to_string())
+        } else if err.is_builder() {
+            ClientError::InvalidUrl(err.to_string())
+        } else {
+            ClientError::Broken(err.to_string())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+
+    use axum::extract::State;
+    use axum::http::HeaderMap;
+    use axum::routing::{get, post};
+    use axum::Router;
+
+    use super::*;
+
+    // serve a router on a port of its own, returning its URL
+    async fn serve(router: Router) -> String {
+        let listener = tokio::net::TcpListener::bind(("127.0.0.1", 0))
+            .await
+            .unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await });
+        format!("http://{}", address)
+    }
+
+    fn fast_retry(attempts: u32) -> Retry {
+        Retry {
+            attempts,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(1),
+        }
+    }
+
+    #[test]
+    fn test_backoff() {
+        let retry = Retry::default();
+        assert_eq!(retry.backoff(0), Duration::from_millis(100));
+        assert_eq!(retry.backoff(1), Duration::from_millis(200));
+        assert_eq!(retry.backoff(4), Duration::from_millis(1600));
+        assert_eq!(retry.backoff(5), Duration::from_secs(2));
+        assert_eq!(retry.backoff(100), Duration::from_secs(2));
+    }
+
+    #[tokio::test]
+    async fn test_get_and_post_json() {
+        let router = Router::new()
+            .route(
+                "/train",
+                get(|| async { axum::Json(serde_json::json!([1, 2])) }),
+            )
+            .route(
+                "/echo",
+                post(|headers: HeaderMap, body: String| async move {
+                    let correlation_id = headers
+                        .get(correlation::HEADER)
+                        .map(|id| id.to_str().unwrap().to_string());
+                    axum::Json(serde_json::json!({
+                        "body": body,
+                        "correlation_id": correlation_id,
+                    }))
+                }),
+            );
+        let url = serve(router).await;
+        let client = HttpClient::new(Duration::from_secs(5));
+
+        let value: serde_json::Value = client.get_json(&format!("{}/train", url)).await.unwrap();
+        assert_eq!(value, serde_json::json!([1, 2]));
+
+        let echo = correlation::scope(
+            "abc".to_string(),
+            client.post_json::<_, serde_json::Value>(
+                &format!("{}/echo", url),
+                &serde_json::json!({ "seats": 2 }),
+            ),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            echo,
+            serde_json::json!({ "body": r#"{"seats":2}"#, "correlation_id": "abc" })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_retry() {
+        let attempts = Arc::new(AtomicU32::new(0));
+        // unavailable the first two times
+        let flaky = |State(attempts): State<Arc<AtomicU32>>| async move {
+            if attempts.fetch_add(1, Ordering::SeqCst) < 2 {
+                Err(StatusCode::SERVICE_UNAVAILABLE)
+            } else {
+                Ok(axum::Json("fine"))
+            }
+        };
+        let router = Router::new()
+            .route("/flaky", get(flaky).post(flaky))
+            .with_state(attempts.clone());
+        let url = serve(router).await;
+        let url = format!("{}/flaky", url);
+
+        let client = HttpClient::new(Duration::from_secs(5)).with_retry(fast_retry(3));
+        let value: String = client.get_json(&url).await.unwrap();
+        assert_eq!(value, "fine");
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+
+        // not for a POST that got through
+        attempts.store(0, Ordering::SeqCst);
+        let result = client.post_json::<_, String>(&url, &()).await;
+        assert!(matches!(
+            result,
+            Err(ClientError::Status(StatusCode::SERVICE_UNAVAILABLE, _))
+        ));
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+
+        // and only so often
+        attempts.store(0, Ordering::SeqCst);
+        let client = client.with_retry(fast_retry(2));
+        assert!(client.get_json::<String>(&url).await.is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_timeout_and_errors() {
+        let router = Router::new()
+            .route(
+                "/slow",
+                get(|| async {
+                    tokio::time::sleep(Duration::from_secs(10)).await;
+                    "late"
+                }),
+            )
+            .route("/text", get(|| async { "not json" }));
+        let url = serve(router).await;
+        let client = HttpClient::new(Duration::from_millis(50)).with_retry(Retry::never());
+
+        let result = client.get_json::<String>(&format!("{}/slow", url)).await;
+        assert!(matches!(result, Err(ClientError::Timeout(_))));
+        let result = client.get_json::<String>(&format!("{}/text", url)).await;
+        assert!(matches!(result, Err(ClientError::Body(_))));
+        let result = client.get_json::<String>("https://example.com").await;
+        assert!(matches!(result, Err(ClientError::InvalidUrl(_))));
+        // nothing listens on port 1
+        let result = client.get_json::<String>("http://127.0.0.1:1/").await;
+        assert!(matches!(result, Err(ClientError::Connect(_))));
+    }
+}
@@ -0,0 +1,261 @@
+//! Responses as JSON:API documents (https://jsonapi.org), for clients whose
+//! tooling expects them. Clients ask for them with an
+//! `Accept: application/vnd.api+json` header, or the service can send them
+//! by default.
+//!
+//! Trains, seats and reservations are resources of type `trains`, `seats`
+//! and `reservations`. Seats are only unique within their train, so the id
+//! of a seat resource is the train id and seat id, like `express_2000/1A`.
+
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+
+use axum::async_trait;
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
+use axum::http::{header, HeaderMap};
+use axum::response::{IntoResponse, Response};
+
+use crate::fields::Fields;
+use crate::reservations::{Reservation, ReservationStatus};
+use crate::response::{SeatResponse, TrainDetailsResponse};
+use crate::train::{Seat, SeatId, Train, TrainId};
+
+pub const MEDIA_TYPE: &str = "application/vnd.api+json";
+
+/// How the client wants responses, going by its `Accept` header.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Format {
+    Json,
+    JsonApi,
+}
+
+impl Format {
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let json_api = headers
+            .get_all(header::ACCEPT)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .any(|media_type| media_type.trim() == MEDIA_TYPE);
+        if json_api {
+            Format::JsonApi
+        } else {
+            Format::Json
+        }
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Format {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(Format::from_headers(&parts.headers))
+    }
+}
+
+/// A document with a single resource as its primary data.
+#[derive(Debug, serde::Serialize)]
+pub struct Document<A, I = ()> {
+    data: Resource<A>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    included: Vec<Resource<I>>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct Resource<A> {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    id: String,
+    attributes: A,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    relationships: BTreeMap<&'static str, Relationship>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct Relationship {
+    data: Linkage,
+}
+
+#[derive(Debug, serde::Serialize)]
+#[serde(untagged)]
+pub enum Linkage {
+    One(Identifier),
+    Many(Vec<Identifier>),
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct Identifier {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    id: String,
+}
+
+impl Identifier {
+    fn train(train_id: &TrainId) -> Self {
+        Identifier {
+            kind: "trains",
+            id: train_id.as_str().to_string(),
+        }
+    }
+
+    fn seat(train_id: &TrainId, seat_id: &SeatId) -> Self {
+        Identifier {
+            kind: "seats",
+            id: format!("{}/{}", train_id.as_str(), seat_id.as_str()),
+        }
+    }
+}
+
+impl<A: serde::Serialize, I: serde::Serialize> IntoResponse for Document<A, I> {
+    fn into_response(self) -> Response {
+        (
+            [(header::CONTENT_TYPE, MEDIA_TYPE)],
+            serde_json::to_vec(&self).expect("documents serialize to JSON"),
+        )
+            .into_response()
+    }
+}
+
+/// A train, with its seats that match included.
+pub fn train<'a>(
+    train_id: &TrainId,
+    train: &'a Train,
+    matches: impl Fn(&Train, &Seat) -> bool,
+    fields: Option<&'a Fields>,
+) -> Document<TrainDetailsResponse<'a>, SeatResponse<'a>> {
+    let mut seats = train
+        .seats()
+        .filter(|(_, seat)| matches(train, seat))
+        .collect::<Vec<_>>();
+    seats.sort_by(|(a, _), (b, _)| a.as_str().cmp(b.as_str()));
+    let relationships = BTreeMap::from([(
+        "seats",
+        Relationship {
+            data: Linkage::Many(
+                seats
+                    .iter()
+                    .map(|(seat_id, _)| Identifier::seat(train_id, seat_id))
+                    .collect(),
+            ),
+        },
+    )]);
+    Document {
+        data: Resource {
+            kind: "trains",
+            id: train_id.as_str().to_string(),
+            attributes: train.into(),
+            relationships,
+        },
+        included: seats
+            .into_iter()
+            .map(|(seat_id, seat)| seat_resource(train_id, seat_id, train, seat, fields))
+            .collect(),
+    }
+}
+
+/// A seat of a train.
+pub fn seat<'a>(
+    train_id: &TrainId,
+    seat_id: &SeatId,
+    train: &Train,
+    seat: &'a Seat,
+    fields: Option<&'a Fields>,
+) -> Document<SeatResponse<'a>> {
+    Document {
+        data: seat_resource(train_id, seat_id, train, seat, fields),
+        included: Vec::new(),
+    }
+}
+
+fn seat_resource<'a>(
+    train_id: &TrainId,
+    seat_id: &SeatId,
+    train: &Train,
+    seat: &'a Seat,
+    fields: Option<&'a Fields>,
+) -> Resource<SeatResponse<'a>> {
+    let Identifier { kind, id } = Identifier::seat(train_id, seat_id);
+    Resource {
+        kind,
+        id,
+        attributes: SeatResponse::new(train, seat).only(fields),
+        relationships: BTreeMap::from([(
+            "train",
+            Relationship {
+                data: Linkage::One(Identifier::train(train_id)),
+            },
+        )]),
+    }
+}
+
+/// What's left of a reservation once its train and seats are relationships.
+#[derive(Debug, serde::Serialize)]
+pub struct ReservationAttributes<'a> {
+    booking_reference: &'a str,
+    status: ReservationStatus,
+    created_at: u64,
+    updated_at: u64,
+}
+
+/// A reservation, with its train and seats as relationships.
+pub fn reservation(reservation: &Reservation) -> Document<ReservationAttributes<'_>> {
+    let relationships = BTreeMap::from([
+        (
+            "train",
+            Relationship {
+                data: Linkage::One(Identifier::train(&reservation.train_id)),
+            },
+        ),
+        (
+            "seats",
+            Relationship {
+                data: Linkage::Many(
+                    reservation
+                        .seats
+                        .iter()
+                        .map(|seat_id| Identifier::seat(&reservation.train_id, seat_id))
+                        .collect(),
+                ),
+            },
+        ),
+    ]);
+    Document {
+        data: Resource {
+            kind: "reservations",
+            id: reservation.id.to_string(),
+            attributes: ReservationAttributes {
+                booking_reference: reservation.booking_reference.as_str(),
+                status: reservation.status,
+                created_at: reservation.created_at,
+                updated_at: reservation.updated_at,
+            },
+            relationships,
+        },
+        included: Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    #[test]
+    fn test_format_from_headers() {
+        let format = |accept: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::ACCEPT, HeaderValue::from_static(accept));
+            Format::from_headers(&headers)
+        };
+        assert_eq!(Format::from_headers(&HeaderMap::new()), Format::Json);
+        assert_eq!(format("application/json"), Format::Json);
+        assert_eq!(format("application/vnd.api+json"), Format::JsonApi);
+        assert_eq!(
+            format("application/json, application/vnd.api+json"),
+            Format::JsonApi
+        );
+    }
+}
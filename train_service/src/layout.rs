@@ -0,0 +1,100 @@
+//! How the seats of a coach are laid out: rows of seats, with an aisle
+//! between two of the columns.
+
+/// The grid of seats in a coach. Seats are numbered along the rows: in a
+/// coach with 4 columns, seats 1 to 4 are in the first row, 5 to 8 in the
+/// second, and so on.
+#[derive(
+    Debug, PartialEq, Eq, Clone, Copy, serde::Serialize, serde::Deserialize, schemars::JsonSchema,
+)]
+#[serde(deny_unknown_fields)]
+pub struct Layout {
+    pub rows: usize,
+    pub columns: usize,
+    /// The column the aisle comes after, if there is an aisle.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aisle_after: Option<usize>,
+}
+
+/// Where a seat is in its coach, counting rows and columns from 1.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, serde::Serialize)]
+pub struct Place {
+    pub row: usize,
+    pub column: usize,
+}
+
+impl Layout {
+    /// Check that the grid makes sense.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.rows == 0 || self.columns == 0 {
+            return Err("a layout needs at least one row and one column".to_string());
+        }
+        if self
+            .aisle_after
+            .is_some_and(|column| column == 0 || column >= self.columns)
+        {
+            return Err("the aisle has to be between two columns".to_string());
+        }
+        Ok(())
+    }
+
+    /// Where the seat with a number is, if it fits in the grid.
+    pub fn place(&self, seat_number: &str) -> Option<Place> {
+        let number = seat_number.parse::<usize>().ok()?.checked_sub(1)?;
+        (number < self.rows * self.columns).then(|| Place {
+            row: number / self.columns + 1,
+            column: number % self.columns + 1,
+        })
+    }
+
+    /// Whether seats are side by side, without the aisle between them.
+    pub fn are_adjacent(&self, a: Place, b: Place) -> bool {
+        let (left, right) = (a.column.min(b.column), a.column.max(b.column));
+        a.row == b.row && right == left + 1 && self.aisle_after != Some(left)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LAYOUT: Layout = Layout {
+        rows: 2,
+        columns: 4,
+        aisle_after: Some(2),
+    };
+
+    #[test]
+    fn test_place() {
+        assert_eq!(LAYOUT.place("1"), Some(Place { row: 1, column: 1 }));
+        assert_eq!(LAYOUT.place("6"), Some(Place { row: 2, column: 2 }));
+        assert_eq!(LAYOUT.place("8"), Some(Place { row: 2, column: 4 }));
+        assert_eq!(LAYOUT.place("9"), None);
+        assert_eq!(LAYOUT.place("0"), None);
+    }
+
+    #[test]
+    fn test_are_adjacent() {
+        let place = |seat_number| LAYOUT.place(seat_number).unwrap();
+        assert!(LAYOUT.are_adjacent(place("1"), place("2")));
+        assert!(LAYOUT.are_adjacent(place("4"), place("3")));
+        // across the aisle
+        assert!(!LAYOUT.are_adjacent(place("2"), place("3")));
+        // in different rows
+        assert!(!LAYOUT.are_adjacent(place("4"), place("5")));
+        assert!(!LAYOUT.are_adjacent(place("1"), place("5")));
+    }
+
+    #[test]
+    fn test_validate() {
+        assert_eq!(LAYOUT.validate(), Ok(()));
+        let layout = |rows, columns, aisle_after| Layout {
+            rows,
+            columns,
+            aisle_after,
+        };
+        assert!(layout(0, 4, None).validate().is_err());
+        assert!(layout(2, 4, Some(4)).validate().is_err());
+        assert!(layout(2, 4, Some(0)).validate().is_err());
+    }
+}
@@ -0,0 +1,62 @@
+//! The train service of the train reservation kata. It runs as a binary,
+//! but can be used as a library too, to extend it with plugins; see
+//! [`plugin`].
+
+pub mod adjacency;
+pub mod alerts;
+pub mod allocate;
+pub mod api_keys;
+pub mod booking_reference;
+pub mod clock;
+pub mod config;
+pub mod correlation;
+pub mod deprecations;
+pub mod envelope;
+pub mod extract;
+pub mod fares;
+pub mod fields;
+pub mod filter;
+#[cfg(any(test, feature = "fixtures"))]
+pub mod fixtures;
+pub mod flags;
+pub mod forecast;
+#[cfg(feature = "fixtures")]
+pub mod generate;
+pub mod history;
+pub mod http_client;
+pub mod json_api;
+pub mod layout;
+pub mod listen;
+pub mod load;
+pub mod lock;
+pub mod metrics;
+pub mod middleware;
+pub mod migrate;
+pub mod notify;
+pub mod occupancy;
+pub mod plugin;
+pub mod pricing;
+pub mod pseudonyms;
+pub mod published;
+pub mod refunds;
+pub mod renumber;
+pub mod reservations;
+pub mod resource;
+pub mod response;
+pub mod rest;
+pub mod rules;
+pub mod sandbox;
+pub mod schedule;
+pub mod schema;
+pub mod snapshot;
+pub mod spec;
+pub mod stack;
+pub mod store;
+pub mod telemetry;
+pub mod templates;
+pub mod tenants;
+pub mod ticket_office;
+pub mod train;
+pub mod trains_csv;
+pub mod trains_json;
+pub mod vouchers;
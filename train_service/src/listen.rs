@@ -0,0 +1,452 @@
+//! Where the service listens for requests: on a TCP port, on a Unix domain
+//! socket, or on both. A socket per instance saves picking ports when many
+//! instances run on one host, one for each team, say, behind a proxy.
+//!
+//! Connections speak HTTP/1.1, or HTTP/2 without TLS to clients that know in
+//! advance. How they are handled can be tuned, for when many clients share
+//! one instance, like the simulation does.
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use axum::http::Request;
+use axum::Router;
+use hyper::body::Incoming;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tower::ServiceExt;
+
+use crate::lock::LockExt;
+
+// hyper can't buffer less than this
+const MIN_HEADER_BYTES: usize = 8192;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Listen {
+    pub port: Option<u16>,
+    pub unix_socket: Option<PathBuf>,
+    pub tuning: Tuning,
+}
+
+impl Listen {
+    /// Listen on this port only.
+    pub fn port(port: u16) -> Self {
+        Listen {
+            port: Some(port),
+            unix_socket: None,
+            tuning: Tuning::default(),
+        }
+    }
+}
+
+/// Limits on connections. Those that aren't set are left to hyper.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Tuning {
+    /// Accept no more connections than this at a time, for the port and the
+    /// socket together; others wait until one closes.
+    pub max_connections: Option<usize>,
+    /// Close connections that have been idle this long; zero turns keep-alive
+    /// off.
+    pub keep_alive: Option<Duration>,
+    /// Answer requests with more headers than this with a 431 response.
+    pub max_headers: Option<usize>,
+    /// Answer requests with a head larger than this with a 431 response.
+    pub max_header_bytes: Option<usize>,
+}
+
+impl Tuning {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_connections == Some(0) {
+            return Err("max connections must be more than 0".to_string());
+        }
+        if let Some(max_header_bytes) = self.max_header_bytes {
+            if max_header_bytes < MIN_HEADER_BYTES {
+                return Err(format!(
+                    "max header bytes must be at least {}, not {}",
+                    MIN_HEADER_BYTES, max_header_bytes
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Serve the router wherever `listen` says, until that fails.
+pub async fn serve(router: Router, listen: &Listen) -> io::Result<()> {
+    let limit = listen
+        .tuning
+        .max_connections
+        .map(|max_connections| Arc::new(Semaphore::new(max_connections)));
+    let tcp = async {
+        match listen.port {
+            Some(port) => {
+                let listener = tokio::net::TcpListener::bind(("0.0.0.0", port)).await?;
+                println!("Listening on port {}", port);
+                serve_tcp(listener, router.clone(), listen.tuning, limit.clone()).await
+            }
+            None => Ok(()),
+        }
+    };
+    let unix = async {
+        match &listen.unix_socket {
+            Some(path) => {
+                let listener = bind_unix(path)?;
+                println!("Listening on {}", path.display());
+                serve_unix(listener, router.clone(), listen.tuning, limit.clone()).await
+            }
+            None => Ok(()),
+        }
+    };
+    tokio::try_join!(tcp, unix).map(|_| ())
+}
+
+async fn serve_tcp(
+    listener: tokio::net::TcpListener,
+    router: Router,
+    tuning: Tuning,
+    limit: Option<Arc<Semaphore>>,
+) -> io::Result<()> {
+    loop {
+        let permit = acquire(&limit).await;
+        let (stream, _) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(err) => {
+                accept_failed(err).await;
+                continue;
+            }
+        };
+        if let Err(err) = stream.set_nodelay(true) {
+            tracing::debug!(%err, "cannot set TCP_NODELAY");
+        }
+        spawn_connection(stream, router.clone(), tuning, permit);
+    }
+}
+
+async fn serve_unix(
+    listener: tokio::net::UnixListener,
+    router: Router,
+    tuning: Tuning,
+    limit: Option<Arc<Semaphore>>,
+) -> io::Result<()> {
+    loop {
+        let permit = acquire(&limit).await;
+        let (stream, _) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(err) => {
+                accept_failed(err).await;
+                continue;
+            }
+        };
+        spawn_connection(stream, router.clone(), tuning, permit);
+    }
+}
+
+fn bind_unix(path: &Path) -> io::Result<tokio::net::UnixListener> {
+    remove_stale_socket(path)?;
+    tokio::net::UnixListener::bind(path)
+}
+
+// a socket left behind by an instance that's gone would keep the new one
+// from binding; anything else at the path is left alone
+fn remove_stale_socket(path: &Path) -> io::Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => std::fs::remove_file(path),
+        _ => Ok(()),
+    }
+}
+
+async fn acquire(limit: &Option<Arc<Semaphore>>) -> Option<OwnedSemaphorePermit> {
+    match limit {
+        // the semaphore is never closed
+        Some(limit) => limit.clone().acquire_owned().await.ok(),
+        None => None,
+    }
+}
+
+// like running out of file descriptors, which may pass once connections
+// close, so wait a bit instead of giving up
+async fn accept_failed(err: io::Error) {
+    tracing::error!(%err, "cannot accept connection");
+    tokio::time::sleep(Duration::from_secs(1)).await;
+}
+
+// the connection holds on to `permit` until it closes
+fn spawn_connection<S>(
+    stream: S,
+    router: Router,
+    tuning: Tuning,
+    permit: Option<OwnedSemaphorePermit>,
+) where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    tokio::spawn(async move {
+        let _permit = permit;
+        let activity = Arc::new(Activity::new());
+        let service = {
+            let activity = activity.clone();
+            hyper::service::service_fn(move |request: Request<Incoming>| {
+                let busy = activity.start();
+                let router = router.clone();
+                async move {
+                    let _busy = busy;
+                    router.oneshot(request).await
+                }
+            })
+        };
+        // HTTP/1.1, or HTTP/2 when the client starts with its preface, as
+        // h2c clients with prior knowledge do
+        let mut builder = auto::Builder::new(TokioExecutor::new());
+        let mut http1 = builder.http1();
+        http1.keep_alive(tuning.keep_alive != Some(Duration::ZERO));
+        if let Some(max_headers) = tuning.max_headers {
+            http1.max_headers(max_headers);
+        }
+        if let Some(max_header_bytes) = tuning.max_header_bytes {
+            http1.max_buf_size(max_header_bytes);
+            builder
+                .http2()
+                .max_header_list_size(u32::try_from(max_header_bytes).unwrap_or(u32::MAX));
+        }
+        let connection = builder.serve_connection_with_upgrades(TokioIo::new(stream), service);
+        tokio::pin!(connection);
+        let result = match tuning.keep_alive.filter(|keep_alive| !keep_alive.is_zero()) {
+            Some(keep_alive) => loop {
+                tokio::select! {
+                    result = connection.as_mut() => break result,
+                    _ = tokio::time::sleep_until(activity.idle_deadline(keep_alive).into()) => {
+                        if activity.is_idle_for(keep_alive) {
+                            connection.as_mut().graceful_shutdown();
+                            break connection.as_mut().await;
+                        }
+                    }
+                }
+            },
+            None => connection.await,
+        };
+        if let Err(err) = result {
+            tracing::debug!(%err, "connection failed");
+        }
+    });
+}
+
+// whether a connection is handling requests, and since when it isn't
+#[derive(Debug)]
+struct Activity(Mutex<(usize, Instant)>);
+
+impl Activity {
+    fn new() -> Self {
+        Activity(Mutex::new((0, Instant::now())))
+    }
+
+    fn start(self: &Arc<Self>) -> Busy {
+        self.0.lock_or_recover().0 += 1;
+        Busy(self.clone())
+    }
+
+    // when the connection has been idle for `keep_alive`, if nothing
+    // happens until then
+    fn idle_deadline(&self, keep_alive: Duration) -> Instant {
+        let (busy, since) = *self.0.lock_or_recover();
+        if busy > 0 {
+            Instant::now() + keep_alive
+        } else {
+            since + keep_alive
+        }
+    }
+
+    fn is_idle_for(&self, keep_alive: Duration) -> bool {
+        let (busy, since) = *self.0.lock_or_recover();
+        busy == 0 && since.elapsed() >= keep_alive
+    }
+}
+
+// a request being handled
+struct Busy(Arc<Activity>);
+
+impl Drop for Busy {
+    fn drop(&mut self) {
+        let mut activity = self.0 .0.lock_or_recover();
+        activity.0 -= 1;
+        activity.1 = Instant::now();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+    use axum::http::StatusCode;
+    use axum::routing::get;
+    use http_body_util::BodyExt;
+    use hyper::client::conn::http1::SendRequest;
+
+    use super::*;
+
+    fn hello() -> Router {
+        Router::new().route("/", get(|| async { "Hello" }))
+    }
+
+    async fn serve_tcp_on_any_port(tuning: Tuning) -> std::net::SocketAddr {
+        let listener = tokio::net::TcpListener::bind(("127.0.0.1", 0))
+            .await
+            .unwrap();
+        let address = listener.local_addr().unwrap();
+        let limit = tuning
+            .max_connections
+            .map(|max_connections| Arc::new(Semaphore::new(max_connections)));
+        tokio::spawn(serve_tcp(listener, hello(), tuning, limit));
+        address
+    }
+
+    // a connection, and the task that ends when it closes
+    async fn connect<S>(stream: S) -> (SendRequest<Body>, tokio::task::JoinHandle<()>)
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let (sender, connection) = hyper::client::conn::http1::handshake(TokioIo::new(stream))
+            .await
+            .unwrap();
+        let task = tokio::spawn(async move {
+            let _ = connection.await;
+        });
+        (sender, task)
+    }
+
+    async fn get_hello(sender: &mut SendRequest<Body>, headers: usize) -> StatusCode {
+        let mut request = Request::builder().uri("/").header("host", "localhost");
+        for i in 0..headers {
+            request = request.header(format!("x-header-{}", i), "value");
+        }
+        let response = sender
+            .send_request(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        if status == StatusCode::OK {
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            assert_eq!(body, "Hello");
+        }
+        status
+    }
+
+    #[tokio::test]
+    async fn test_unix_socket() {
+        let path = std::env::temp_dir().join(format!(
+            "train_service_test_{:016x}.sock",
+            rand::random::<u64>()
+        ));
+        // left behind by an earlier run
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+        let listen = Listen {
+            port: None,
+            unix_socket: Some(path.clone()),
+            tuning: Tuning::default(),
+        };
+        tokio::spawn(async move { serve(hello(), &listen).await });
+
+        let stream = loop {
+            match tokio::net::UnixStream::connect(&path).await {
+                Ok(stream) => break stream,
+                Err(_) => tokio::time::sleep(Duration::from_millis(10)).await,
+            }
+        };
+        let (mut sender, _) = connect(stream).await;
+        assert_eq!(get_hello(&mut sender, 0).await, StatusCode::OK);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_http2_prior_knowledge() {
+        let address = serve_tcp_on_any_port(Tuning::default()).await;
+        let stream = tokio::net::TcpStream::connect(address).await.unwrap();
+        let (mut sender, connection) =
+            hyper::client::conn::http2::handshake(TokioExecutor::new(), TokioIo::new(stream))
+                .await
+                .unwrap();
+        tokio::spawn(async move {
+            let _ = connection.await;
+        });
+        let request = Request::builder()
+            .uri(format!("http://{}/", address))
+            .body(Body::empty())
+            .unwrap();
+        let response = sender.send_request(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.version(), axum::http::Version::HTTP_2);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "Hello");
+    }
+
+    #[tokio::test]
+    async fn test_keep_alive() {
+        let address = serve_tcp_on_any_port(Tuning {
+            keep_alive: Some(Duration::from_millis(100)),
+            ..Tuning::default()
+        })
+        .await;
+        let stream = tokio::net::TcpStream::connect(address).await.unwrap();
+        let (mut sender, closed) = connect(stream).await;
+        // used again before it's been idle too long
+        assert_eq!(get_hello(&mut sender, 0).await, StatusCode::OK);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(get_hello(&mut sender, 0).await, StatusCode::OK);
+        // and closed after
+        tokio::time::timeout(Duration::from_secs(2), closed)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_max_connections() {
+        let address = serve_tcp_on_any_port(Tuning {
+            max_connections: Some(1),
+            ..Tuning::default()
+        })
+        .await;
+        let stream = tokio::net::TcpStream::connect(address).await.unwrap();
+        let (mut first, first_task) = connect(stream).await;
+        assert_eq!(get_hello(&mut first, 0).await, StatusCode::OK);
+
+        let stream = tokio::net::TcpStream::connect(address).await.unwrap();
+        let (mut second, _) = connect(stream).await;
+        let waiting = tokio::spawn(async move { get_hello(&mut second, 0).await });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!waiting.is_finished());
+
+        drop(first);
+        first_task.await.unwrap();
+        assert_eq!(waiting.await.unwrap(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_max_headers() {
+        let address = serve_tcp_on_any_port(Tuning {
+            max_headers: Some(5),
+            ..Tuning::default()
+        })
+        .await;
+        let stream = tokio::net::TcpStream::connect(address).await.unwrap();
+        let (mut sender, _) = connect(stream).await;
+        assert_eq!(get_hello(&mut sender, 2).await, StatusCode::OK);
+        assert_eq!(
+            get_hello(&mut sender, 10).await,
+            StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        assert_eq!(Tuning::default().validate(), Ok(()));
+        let tuning = Tuning {
+            max_header_bytes: Some(1024),
+            ..Tuning::default()
+        };
+        assert!(tuning.validate().is_err());
+    }
+}
@@ -0,0 +1,228 @@
+use std::fmt::{self, Display, Formatter};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use crate::migrate::{self, MigrationError};
+use crate::train::TrainsData;
+use crate::trains_csv::{self, CsvError};
+use crate::trains_json;
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(PathBuf, std::io::Error),
+    Write(PathBuf, std::io::Error),
+    Json(PathBuf, serde_json::Error),
+    Yaml(PathBuf, serde_yaml::Error),
+    Csv(PathBuf, CsvError),
+    Migration(PathBuf, MigrationError),
+    // the path, and a description of the formats we could have loaded
+    UnsupportedFormat(PathBuf, &'static str),
+}
+
+impl Display for LoadError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Io(path, err) => write!(f, "cannot read {}: {}", path.display(), err),
+            LoadError::Write(path, err) => write!(f, "cannot write {}: {}", path.display(), err),
+            LoadError::Json(path, err) => write!(f, "cannot parse {}: {}", path.display(), err),
+            LoadError::Yaml(path, err) => write!(f, "cannot parse {}: {}", path.display(), err),
+            LoadError::Csv(path, err) => write!(
+                f,
+                "cannot parse {} at line {}: {}",
+                path.display(),
+                err.line,
+                err.message
+            ),
+            LoadError::Migration(path, err) => write!(f, "cannot load {}: {}", path.display(), err),
+            LoadError::UnsupportedFormat(path, expected) => {
+                write!(f, "cannot load {}: expected {}", path.display(), expected)
+            }
+        }
+    }
+}
+
+/// Load data from a JSON or YAML file, depending on its extension.
+pub fn load<T: DeserializeOwned>(path: &Path) -> Result<T, LoadError> {
+    match extension(path) {
+        Some("json") => load_json(path),
+        Some("yaml" | "yml") => load_yaml(path),
+        _ => Err(LoadError::UnsupportedFormat(
+            path.to_path_buf(),
+            "a .json or .yaml file",
+        )),
+    }
+}
+
+/// Load trains data from a file, in a format determined by its extension.
+pub fn load_trains(path: &Path) -> Result<TrainsData, LoadError> {
+    load_trains_within(path, None)
+}
+
+/// Load trains data, giving up on JSON files that take longer than the
+/// budget. JSON files are read one train at a time, so they can be large.
+pub fn load_trains_within(path: &Path, budget: Option<Duration>) -> Result<TrainsData, LoadError> {
+    match extension(path) {
+        Some("csv") => {
+            let contents = read(path)?;
+            trains_csv::parse_trains(&contents)
+                .map_err(|err| LoadError::Csv(path.to_path_buf(), err))
+        }
+        Some("json") => {
+            let file = File::open(path).map_err(|err| LoadError::Io(path.to_path_buf(), err))?;
+            trains_json::read_trains(BufReader::new(file), budget)
+                .map_err(|err| LoadError::Json(path.to_path_buf(), err))
+        }
+        Some("yaml" | "yml") => {
+            let value: Value = load_yaml(path)?;
+            let value = migrate::migrate_trains(value)
+                .map_err(|err| LoadError::Migration(path.to_path_buf(), err))?;
+            let file: TrainsFile = serde_json::from_value(value)
+                .map_err(|err| LoadError::Json(path.to_path_buf(), err))?;
+            Ok(file.trains)
+        }
+        _ => Err(LoadError::UnsupportedFormat(
+            path.to_path_buf(),
+            "a .json, .yaml or .csv file",
+        )),
+    }
+}
+
+// a trains file of the current version; the migrations checked the version
+#[derive(serde::Deserialize)]
+struct TrainsFile {
+    trains: TrainsData,
+}
+
+pub fn load_json<T: DeserializeOwned>(path: &Path) -> Result<T, LoadError> {
+    let contents = read(path)?;
+    serde_json::from_str(&contents).map_err(|err| LoadError::Json(path.to_path_buf(), err))
+}
+
+// YAML deserializes into the same types as JSON, so it's validated the same way
+fn load_yaml<T: DeserializeOwned>(path: &Path) -> Result<T, LoadError> {
+    let file = File::open(path).map_err(|err| LoadError::Io(path.to_path_buf(), err))?;
+    serde_yaml::from_reader(BufReader::new(file))
+        .map_err(|err| LoadError::Yaml(path.to_path_buf(), err))
+}
+
+fn extension(path: &Path) -> Option<&str> {
+    path.extension().and_then(|extension| extension.to_str())
+}
+
+fn read(path: &Path) -> Result<String, LoadError> {
+    std::fs::read_to_string(path).map_err(|err| LoadError::Io(path.to_path_buf(), err))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::response::TrainResponse;
+    use crate::train::{SeatId, TrainId};
+
+    #[test]
+    fn test_load_json_missing_file() {
+        let result = load_json::<serde_json::Value>(Path::new("does_not_exist.json"));
+        let err = result.unwrap_err();
+        assert!(matches!(err, LoadError::Io(_, _)));
+        assert!(err
+            .to_string()
+            .starts_with("cannot read does_not_exist.json"));
+    }
+
+    #[test]
+    fn test_load_trains_unsupported_format() {
+        let result = load_trains(Path::new("trains.txt"));
+        assert!(matches!(result, Err(LoadError::UnsupportedFormat(_, _))));
+    }
+
+    #[test]
+    fn test_load_trains_by_extension() {
+        let dir = std::env::temp_dir().join(format!("train_service_load_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let csv_path = dir.join("trains.csv");
+        std::fs::write(&csv_path, "train_id,coach,seat_number\nt,A,1\n").unwrap();
+        let json_path = dir.join("trains.json");
+        std::fs::write(&json_path, include_str!("trains.json")).unwrap();
+
+        let csv_trains = load_trains(&csv_path).unwrap();
+        assert!(csv_trains.get(&TrainId::new("t")).is_some());
+        let json_trains = load_trains(&json_path).unwrap();
+        assert!(json_trains.get(&TrainId::new("local_1000")).is_some());
+
+        let yaml_path = dir.join("trains.yaml");
+        std::fs::write(
+            &yaml_path,
+            "t:\n  seats:\n    1A: { coach: A, seat_number: '1', booking_reference: null }\n",
+        )
+        .unwrap();
+        let yaml_trains = load_trains(&yaml_path).unwrap();
+        assert!(yaml_trains.get(&TrainId::new("t")).is_some());
+
+        let versioned_path = dir.join("versioned.json");
+        std::fs::write(
+            &versioned_path,
+            r#"{"version": 2, "trains": {"t": {"seats": {}}}}"#,
+        )
+        .unwrap();
+        let versioned_trains = load_trains(&versioned_path).unwrap();
+        assert!(versioned_trains.get(&TrainId::new("t")).is_some());
+        std::fs::write(&versioned_path, r#"{"version": 9, "trains": {}}"#).unwrap();
+        let err = load_trains(&versioned_path).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("unknown version 9, expected at most 2"));
+        let versioned_path = dir.join("versioned.yaml");
+        std::fs::write(&versioned_path, "version: 9\ntrains: {}\n").unwrap();
+        let err = load_trains(&versioned_path).unwrap_err();
+        assert!(matches!(err, LoadError::Migration(_, _)));
+
+        // YAML goes through the same validation as JSON
+        std::fs::write(&yaml_path, "t:\n  seats:\n    1A: { coach: A }\n").unwrap();
+        let err = load_trains(&yaml_path).unwrap_err();
+        assert!(matches!(err, LoadError::Json(_, _)));
+        assert!(err.to_string().contains("missing field `seat_number`"));
+
+        std::fs::write(&yaml_path, "t:\n  seats: [\n").unwrap();
+        let err = load_trains(&yaml_path).unwrap_err();
+        assert!(matches!(err, LoadError::Yaml(_, _)));
+        assert!(err.to_string().contains("line 3"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("src/fixtures")
+            .join(name)
+    }
+
+    #[test]
+    fn test_load_kata_trains() {
+        let trains = load_trains(&fixture("kata_trains.json")).unwrap();
+        let train = trains.get(&TrainId::new("express_2000")).unwrap();
+        // empty booking references are free seats
+        assert_eq!(train.occupancy().train().reserved, 1);
+        assert!(train.get(&SeatId::new("1A")).unwrap().is_free());
+    }
+
+    #[test]
+    fn test_previous_versions_round_trip() {
+        for name in ["kata_trains.json", "v1_trains.json", "v2_trains.json"] {
+            let trains = load_trains(&fixture(name)).unwrap();
+            let written: BTreeMap<&str, TrainResponse> = trains
+                .iter()
+                .map(|(train_id, train)| (train_id.as_str(), train.into()))
+                .collect();
+            let reloaded: TrainsData =
+                serde_json::from_value(serde_json::to_value(written).unwrap()).unwrap();
+            assert_eq!(reloaded, trains, "{} doesn't round-trip", name);
+        }
+    }
+}
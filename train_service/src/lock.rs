@@ -0,0 +1,48 @@
+//! Locks that keep working after a thread panicked while holding them.
+//!
+//! A panicking request handler poisons the lock it holds, and with plain
+//! `lock().unwrap()` every later request would panic too. The state behind
+//! our locks is still usable after a panic: at worst a single change was
+//! left half done, which beats a service that can't handle any request
+//! anymore.
+
+use std::sync::{Mutex, MutexGuard};
+
+pub trait LockExt<T> {
+    /// Lock, recovering the lock if it was poisoned.
+    fn lock_or_recover(&self) -> MutexGuard<'_, T>;
+}
+
+impl<T> LockExt<T> for Mutex<T> {
+    fn lock_or_recover(&self) -> MutexGuard<'_, T> {
+        self.lock().unwrap_or_else(|poisoned| {
+            tracing::warn!("recovering a lock poisoned by a panic");
+            self.clear_poison();
+            poisoned.into_inner()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    #[test]
+    fn test_lock_or_recover() {
+        let mutex = Arc::new(Mutex::new(1));
+        let poisoner = mutex.clone();
+        std::thread::spawn(move || {
+            let _guard = poisoner.lock().unwrap();
+            panic!("poisoning the lock");
+        })
+        .join()
+        .unwrap_err();
+        assert!(mutex.is_poisoned());
+
+        *mutex.lock_or_recover() += 1;
+        assert_eq!(*mutex.lock_or_recover(), 2);
+        assert!(!mutex.is_poisoned());
+    }
+}
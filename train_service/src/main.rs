@@ -1,11 +1,175 @@
-mod booking_reference;
-mod rest;
-mod train;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+use std::time::Duration;
 
-use rest::serve;
+use clap::Parser;
+
+use train_service::clock::{Clock, SimulatedClock, SystemClock};
+use train_service::config::{Command, Config};
+use train_service::flags::Flags;
+use train_service::listen::{Listen, Tuning};
+use train_service::renumber::SeatMapping;
+use train_service::response::{TrainResponse, TrainsFileResponse};
+use train_service::rest::{self, serve_all_in_one, serve_on};
+use train_service::rules::Policies;
+use train_service::stack::Stack;
+use train_service::store::DirectoryStore;
+use train_service::telemetry::TelemetryConfig;
+use train_service::templates::Templates;
+use train_service::tenants::TenantLimits;
+use train_service::train::{Error, TrainDataService, TrainId};
+use train_service::{config, load, migrate, telemetry};
 
 #[tokio::main]
 async fn main() {
-    let app_state = rest::AppState::new();
-    serve(app_state).await
+    let config = Config::parse();
+    if let Some(Command::Renumber {
+        trains,
+        train_id,
+        mapping,
+    }) = &config.command
+    {
+        renumber(trains, train_id, mapping);
+        return;
+    }
+    telemetry::init(TelemetryConfig::from_env()).unwrap_or_else(|err| exit_with(err));
+    // all tenants share the same clock
+    let simulated_clock = config
+        .simulated_time
+        .then(|| SimulatedClock::new(SystemClock.now()));
+    let mut app_state = trains_state(&config, simulated_clock.clone())
+        .with_request_timeout(Duration::from_secs(config.request_timeout))
+        .with_access_log(config.access_log)
+        .with_json_api(config.json_api)
+        .with_envelope(config.envelope);
+    if let Some(threshold) = config.near_full_alert {
+        if !(0.0..=1.0).contains(&threshold) {
+            exit_with(format!(
+                "--near-full-alert must be between 0 and 1, not {}",
+                threshold
+            ));
+        }
+    }
+    let tenant_limits: BTreeMap<String, TenantLimits> = match &config.tenants {
+        Some(path) => load::load(path).unwrap_or_else(|err| exit_with(err)),
+        None => BTreeMap::new(),
+    };
+    let names: BTreeSet<&String> = config.tenant.iter().chain(tenant_limits.keys()).collect();
+    for name in names {
+        config::parse_tenant(name).unwrap_or_else(|err| exit_with(err));
+        app_state =
+            app_state.with_tenant(name.clone(), trains_state(&config, simulated_clock.clone()));
+    }
+    app_state = app_state.with_tenant_limits(tenant_limits);
+    if let Some(path) = &config.api_keys {
+        let api_keys = load::load(path).unwrap_or_else(|err| exit_with(err));
+        app_state = app_state.with_api_keys(Some(api_keys));
+    }
+    if let Some(path) = &config.middleware {
+        let stack: Stack = load::load(path).unwrap_or_else(|err| exit_with(err));
+        stack
+            .validate()
+            .unwrap_or_else(|err| exit_with(format!("invalid middleware: {}", err)));
+        app_state = app_state.with_stack(stack);
+    }
+    if let Some(path) = &config.deprecations {
+        let deprecations = load::load(path).unwrap_or_else(|err| exit_with(err));
+        app_state = app_state.with_deprecations(Some(deprecations));
+    }
+    if config.sandbox {
+        app_state =
+            app_state.with_sandboxes(config.sandbox_expiry(), config.sandbox_max_sessions());
+    }
+    let tuning = Tuning {
+        max_connections: config.max_connections,
+        keep_alive: config.keep_alive.map(Duration::from_secs),
+        max_headers: config.max_headers,
+        max_header_bytes: config.max_header_bytes,
+    };
+    tuning.validate().unwrap_or_else(|err| exit_with(err));
+    let listen = Listen {
+        port: (!config.no_tcp).then_some(config.port),
+        unix_socket: config.unix_socket.clone(),
+        tuning,
+    };
+    if config.all_in_one {
+        serve_all_in_one(app_state, &listen).await
+    } else {
+        serve_on(app_state, &listen).await
+    }
+}
+
+// the state for a set of trains, as configured
+fn trains_state(config: &Config, simulated_clock: Option<SimulatedClock>) -> rest::AppState {
+    let mut app_state = rest::AppState::new().with_near_full_alert(config.near_full_alert);
+    if let Some(path) = &config.trains {
+        let budget = config.load_budget.map(Duration::from_secs);
+        let trains = load::load_trains_within(path, budget).unwrap_or_else(|err| exit_with(err));
+        app_state = app_state.with_trains(trains);
+    }
+    if let Some(dir) = &config.trains_dir {
+        app_state = app_state.with_store(Box::new(DirectoryStore::new(dir.clone())));
+        if let Some(capacity) = config.max_loaded_trains {
+            app_state = app_state.with_max_loaded_trains(capacity);
+        }
+    }
+    if let Some(path) = &config.templates {
+        let templates: Templates = load::load(path).unwrap_or_else(|err| exit_with(err));
+        app_state = app_state.with_templates(templates);
+    }
+    let mut policies: Policies = match &config.policies {
+        Some(path) => load::load(path).unwrap_or_else(|err| exit_with(err)),
+        None => Policies::default(),
+    };
+    config.override_policies(&mut policies);
+    policies
+        .validate()
+        .unwrap_or_else(|err| exit_with(format!("invalid policies: {}", err)));
+    if let Some(path) = &config.flags {
+        let flags: Flags = load::load(path).unwrap_or_else(|err| exit_with(err));
+        app_state = app_state.with_flags(flags);
+    }
+    app_state = app_state
+        .with_policies(policies)
+        .with_pricing(config.pricing_strategy())
+        .with_refund_policy(config.refund_policy());
+    if let Some(clock) = simulated_clock {
+        app_state = app_state.with_simulated_clock(clock);
+    }
+    if let Some(schedule) = config.reset_schedule() {
+        app_state = app_state.with_reset_schedule(schedule, config.reset_booking_references);
+    }
+    app_state
+}
+
+// renumber seats in a trains file, writing the result to standard output
+fn renumber(trains: &Path, train_id: &str, mapping: &Path) {
+    let trains = load::load_trains(trains).unwrap_or_else(|err| exit_with(err));
+    let mapping: SeatMapping = load::load(mapping).unwrap_or_else(|err| exit_with(err));
+    let mut service = TrainDataService::new(trains);
+    let train = service
+        .train_mut(&TrainId::new(train_id))
+        .unwrap_or_else(|_| exit_with(format!("there is no train {}", train_id)));
+    match train.renumber(&mapping) {
+        Ok(()) => {}
+        Err(Error::InvalidSeatMapping(message)) => {
+            exit_with(format!("invalid seat mapping: {}", message))
+        }
+        Err(err) => exit_with(format!("{:?}", err)),
+    }
+    let trains: BTreeMap<&str, TrainResponse> = service
+        .trains()
+        .iter()
+        .map(|(train_id, train)| (train_id.as_str(), train.into()))
+        .collect();
+    let file = TrainsFileResponse {
+        version: migrate::TRAINS_VERSION,
+        trains,
+    };
+    println!("{}", serde_json::to_string_pretty(&file).unwrap());
+}
+
+fn exit_with(err: impl std::fmt::Display) -> ! {
+    eprintln!("{}", err);
+    std::process::exit(1)
 }
@@ -0,0 +1,777 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use crate::alerts::NearFullAlert;
+use crate::lock::LockExt;
+use crate::occupancy::{Counts, Occupancy};
+use crate::stack::Queue;
+use crate::train::TrainId;
+
+// occupancy samples kept per train; older ones are dropped
+const OCCUPANCY_SAMPLES: usize = 10_000;
+
+// upper bounds of the latency histogram buckets, in seconds
+const LATENCY_BUCKETS: [f64; 12] = [
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0,
+];
+
+/// Metrics about the service, which can be rendered in the Prometheus text
+/// format.
+#[derive(Debug)]
+pub struct Metrics {
+    started: Instant,
+    inner: Mutex<Inner>,
+    cache: Arc<CacheMetrics>,
+    near_full: OnceLock<NearFullAlert>,
+    queue: OnceLock<Arc<Queue>>,
+    // the metrics of the trains of each tenant, by tenant name
+    tenants: OnceLock<BTreeMap<String, Arc<Metrics>>>,
+}
+
+/// How full the cache of loaded trains is, kept up to date by the cache
+/// itself.
+#[derive(Debug, Default)]
+pub struct CacheMetrics {
+    capacity: AtomicUsize,
+    trains: AtomicUsize,
+    evictions: AtomicU64,
+}
+
+impl CacheMetrics {
+    pub fn set_capacity(&self, capacity: usize) {
+        self.capacity.store(capacity, Ordering::Relaxed);
+    }
+
+    pub fn set_trains(&self, trains: usize) {
+        self.trains.store(trains, Ordering::Relaxed);
+    }
+
+    pub fn record_eviction(&self) {
+        self.evictions.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // only when there's a cache at all
+    fn stats(&self) -> Option<CacheStats> {
+        let capacity = self.capacity.load(Ordering::Relaxed);
+        (capacity > 0).then(|| CacheStats {
+            capacity,
+            trains: self.trains.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    requests: BTreeMap<RequestKey, u64>,
+    latencies: BTreeMap<RouteKey, Histogram>,
+    reservations: u64,
+    reserved_seats: u64,
+    occupancy: BTreeMap<String, Occupancy>,
+    // oldest first
+    occupancy_history: HashMap<String, VecDeque<OccupancySample>>,
+}
+
+/// How many seats of a train were reserved at a time, in seconds since the
+/// Unix epoch.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, serde::Serialize, serde::Deserialize)]
+pub struct OccupancySample {
+    pub at: u64,
+    pub reserved: usize,
+    pub total: usize,
+}
+
+/// A summary of the metrics, for people rather than Prometheus.
+#[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Stats {
+    pub uptime_seconds: f64,
+    pub requests: u64,
+    pub errors: u64,
+    pub reservations: u64,
+    pub reserved_seats: u64,
+    pub trains: BTreeMap<String, TrainStats>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache: Option<CacheStats>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tenants: BTreeMap<String, TenantMetrics>,
+}
+
+/// What happened to the trains of a tenant. Their requests are counted along
+/// with all others.
+#[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct TenantMetrics {
+    pub reservations: u64,
+    pub reserved_seats: u64,
+    pub trains: BTreeMap<String, TrainStats>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache: Option<CacheStats>,
+}
+
+#[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct CacheStats {
+    pub capacity: usize,
+    pub trains: usize,
+    pub evictions: u64,
+}
+
+#[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct TrainStats {
+    pub reserved: usize,
+    pub total: usize,
+    pub occupancy: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub standing: Option<Counts>,
+    /// Seats left in limited fare buckets, by bucket.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub fares: BTreeMap<String, usize>,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Metrics {
+            started: Instant::now(),
+            inner: Mutex::default(),
+            cache: Arc::default(),
+            near_full: OnceLock::new(),
+            queue: OnceLock::new(),
+            tenants: OnceLock::new(),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct RouteKey {
+    method: String,
+    route: String,
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct RequestKey {
+    route: RouteKey,
+    status: u16,
+}
+
+#[derive(Debug, Default)]
+struct Histogram {
+    // not cumulative; each count is for its own bucket only, with the last
+    // one for everything above the largest bound
+    buckets: [u64; LATENCY_BUCKETS.len() + 1],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        let index = LATENCY_BUCKETS
+            .iter()
+            .position(|bound| value <= *bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        self.buckets[index] += 1;
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+impl Metrics {
+    pub fn record_request(&self, method: &str, route: &str, status: u16, duration: Duration) {
+        let mut inner = self.inner.lock_or_recover();
+        let route_key = || RouteKey {
+            method: method.to_string(),
+            route: route.to_string(),
+        };
+        *inner
+            .requests
+            .entry(RequestKey {
+                route: route_key(),
+                status,
+            })
+            .or_default() += 1;
+        inner
+            .latencies
+            .entry(route_key())
+            .or_default()
+            .observe(duration.as_secs_f64());
+    }
+
+    pub fn record_reservation(&self, seats: usize) {
+        let mut inner = self.inner.lock_or_recover();
+        inner.reservations += 1;
+        inner.reserved_seats += seats as u64;
+    }
+
+    /// Record the occupancy of a train after it changed, keeping a sample of
+    /// it for its history too.
+    pub fn record_occupancy(&self, train_id: &TrainId, occupancy: &Occupancy, now: u64) {
+        let mut inner = self.inner.lock_or_recover();
+        let counts = occupancy.train();
+        let before = inner.occupancy.get(train_id.as_str()).map(Occupancy::train);
+        let samples = inner
+            .occupancy_history
+            .entry(train_id.to_string())
+            .or_default();
+        if samples.len() == OCCUPANCY_SAMPLES {
+            samples.pop_front();
+        }
+        samples.push_back(OccupancySample {
+            at: now,
+            reserved: counts.reserved,
+            total: counts.total,
+        });
+        inner
+            .occupancy
+            .insert(train_id.to_string(), occupancy.clone());
+        drop(inner);
+        if let Some(near_full) = self.near_full.get() {
+            near_full.check(train_id, before, counts);
+        }
+    }
+
+    /// Check trains for being nearly full from now on, whenever their
+    /// occupancy is recorded.
+    pub fn set_near_full_alert(&self, alert: NearFullAlert) {
+        // there's only one, set up along with the routes
+        let _ = self.near_full.set(alert);
+    }
+
+    /// Report on the queue of requests waiting for the trains.
+    pub fn set_queue(&self, queue: Arc<Queue>) {
+        // there's only one, set up along with the routes
+        let _ = self.queue.set(queue);
+    }
+
+    /// Report on the trains of tenants too, labelled with the tenant's name.
+    pub fn set_tenants(&self, tenants: BTreeMap<String, Arc<Metrics>>) {
+        // there's only one set of tenants, set up along with the routes
+        let _ = self.tenants.set(tenants);
+    }
+
+    // these metrics and those of the tenants, with the name of the tenant
+    fn sources(&self) -> Vec<(Option<&str>, &Metrics)> {
+        let tenants = self.tenants.get().into_iter().flatten();
+        std::iter::once((None, self))
+            .chain(tenants.map(|(name, metrics)| (Some(name.as_str()), &**metrics)))
+            .collect()
+    }
+
+    /// The occupancy of a train each time it changed, oldest first.
+    pub fn occupancy_history(&self, train_id: &TrainId) -> Vec<OccupancySample> {
+        self.inner
+            .lock_or_recover()
+            .occupancy_history
+            .get(train_id.as_str())
+            .map(|samples| samples.iter().copied().collect())
+            .unwrap_or_default()
+    }
+
+    pub fn clear_occupancy(&self) {
+        self.inner.lock_or_recover().occupancy.clear();
+    }
+
+    /// The metrics of the cache of loaded trains, for the cache to update.
+    pub fn cache(&self) -> Arc<CacheMetrics> {
+        self.cache.clone()
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        self.render_requests(&mut out);
+        let sources = self.sources();
+
+        header(
+            &mut out,
+            "train_service_train_occupancy_ratio",
+            "gauge",
+            "The fraction of seats of a train that are reserved.",
+        );
+        for (tenant, metrics) in &sources {
+            let inner = metrics.inner.lock_or_recover();
+            for (train_id, occupancy) in &inner.occupancy {
+                writeln!(
+                    out,
+                    "train_service_train_occupancy_ratio{} {}",
+                    labels(*tenant, &format!("train=\"{}\"", escape(train_id))),
+                    ratio(&occupancy.train())
+                )
+                .unwrap();
+            }
+        }
+        header(
+            &mut out,
+            "train_service_coach_occupancy_ratio",
+            "gauge",
+            "The fraction of seats of a coach that are reserved.",
+        );
+        for (tenant, metrics) in &sources {
+            let inner = metrics.inner.lock_or_recover();
+            for (train_id, occupancy) in &inner.occupancy {
+                let coaches: BTreeMap<&str, Counts> = occupancy.coaches().collect();
+                for (coach, counts) in coaches {
+                    let train_and_coach =
+                        format!("train=\"{}\",coach=\"{}\"", escape(train_id), escape(coach));
+                    writeln!(
+                        out,
+                        "train_service_coach_occupancy_ratio{} {}",
+                        labels(*tenant, &train_and_coach),
+                        ratio(&counts)
+                    )
+                    .unwrap();
+                }
+            }
+        }
+
+        let caches = sources
+            .iter()
+            .filter_map(|(tenant, metrics)| Some((labels(*tenant, ""), metrics.cache.stats()?)))
+            .collect::<Vec<_>>();
+        if !caches.is_empty() {
+            header(
+                &mut out,
+                "train_service_cache_trains",
+                "gauge",
+                "Number of trains loaded from the store and kept in memory.",
+            );
+            for (labels, cache) in &caches {
+                writeln!(out, "train_service_cache_trains{} {}", labels, cache.trains).unwrap();
+            }
+            header(
+                &mut out,
+                "train_service_cache_capacity",
+                "gauge",
+                "How many trains can be kept in memory.",
+            );
+            for (labels, cache) in &caches {
+                writeln!(
+                    out,
+                    "train_service_cache_capacity{} {}",
+                    labels, cache.capacity
+                )
+                .unwrap();
+            }
+            header(
+                &mut out,
+                "train_service_cache_evictions_total",
+                "counter",
+                "Number of trains dropped from memory to make room for others.",
+            );
+            for (labels, cache) in &caches {
+                writeln!(
+                    out,
+                    "train_service_cache_evictions_total{} {}",
+                    labels, cache.evictions
+                )
+                .unwrap();
+            }
+        }
+        let queues = sources
+            .iter()
+            .filter_map(|(tenant, metrics)| Some((labels(*tenant, ""), metrics.queue.get()?)))
+            .collect::<Vec<_>>();
+        if !queues.is_empty() {
+            header(
+                &mut out,
+                "train_service_queue_depth",
+                "gauge",
+                "Number of requests waiting for the trains or being handled.",
+            );
+            for (labels, queue) in &queues {
+                writeln!(out, "train_service_queue_depth{} {}", labels, queue.depth()).unwrap();
+            }
+            header(
+                &mut out,
+                "train_service_queue_rejected_total",
+                "counter",
+                "Number of requests turned away because too many were waiting.",
+            );
+            for (labels, queue) in &queues {
+                writeln!(
+                    out,
+                    "train_service_queue_rejected_total{} {}",
+                    labels,
+                    queue.rejected()
+                )
+                .unwrap();
+            }
+        }
+        out
+    }
+
+    // requests are all counted here, tenants' too
+    fn render_requests(&self, out: &mut String) {
+        let inner = self.inner.lock_or_recover();
+        header(
+            out,
+            "train_service_requests_total",
+            "counter",
+            "Number of HTTP requests handled.",
+        );
+        for (key, count) in &inner.requests {
+            writeln!(
+                out,
+                "train_service_requests_total{{method=\"{}\",route=\"{}\",status=\"{}\"}} {}",
+                escape(&key.route.method),
+                escape(&key.route.route),
+                key.status,
+                count
+            )
+            .unwrap();
+        }
+
+        header(
+            out,
+            "train_service_request_duration_seconds",
+            "histogram",
+            "How long HTTP requests took to handle.",
+        );
+        for (key, histogram) in &inner.latencies {
+            let labels = format!(
+                "method=\"{}\",route=\"{}\"",
+                escape(&key.method),
+                escape(&key.route)
+            );
+            let mut cumulative = 0;
+            for (bound, count) in LATENCY_BUCKETS.iter().zip(histogram.buckets) {
+                cumulative += count;
+                writeln!(
+                    out,
+                    "train_service_request_duration_seconds_bucket{{{},le=\"{}\"}} {}",
+                    labels, bound, cumulative
+                )
+                .unwrap();
+            }
+            writeln!(
+                out,
+                "train_service_request_duration_seconds_bucket{{{},le=\"+Inf\"}} {}",
+                labels, histogram.count
+            )
+            .unwrap();
+            writeln!(
+                out,
+                "train_service_request_duration_seconds_sum{{{}}} {}",
+                labels, histogram.sum
+            )
+            .unwrap();
+            writeln!(
+                out,
+                "train_service_request_duration_seconds_count{{{}}} {}",
+                labels, histogram.count
+            )
+            .unwrap();
+        }
+    }
+
+    pub fn stats(&self) -> Stats {
+        let inner = self.inner.lock_or_recover();
+        let count_requests = |error: bool| {
+            inner
+                .requests
+                .iter()
+                .filter(|(key, _)| !error || key.status >= 400)
+                .map(|(_, count)| count)
+                .sum()
+        };
+        let tenants = self.tenants.get().into_iter().flatten();
+        Stats {
+            uptime_seconds: self.started.elapsed().as_secs_f64(),
+            requests: count_requests(false),
+            errors: count_requests(true),
+            reservations: inner.reservations,
+            reserved_seats: inner.reserved_seats,
+            trains: train_stats(&inner),
+            cache: self.cache.stats(),
+            tenants: tenants
+                .map(|(name, metrics)| (name.clone(), metrics.tenant_metrics()))
+                .collect(),
+        }
+    }
+
+    fn tenant_metrics(&self) -> TenantMetrics {
+        let inner = self.inner.lock_or_recover();
+        TenantMetrics {
+            reservations: inner.reservations,
+            reserved_seats: inner.reserved_seats,
+            trains: train_stats(&inner),
+            cache: self.cache.stats(),
+        }
+    }
+}
+
+fn train_stats(inner: &Inner) -> BTreeMap<String, TrainStats> {
+    inner
+        .occupancy
+        .iter()
+        .map(|(train_id, occupancy)| {
+            let counts = occupancy.train();
+            (
+                train_id.clone(),
+                TrainStats {
+                    reserved: counts.reserved,
+                    total: counts.total,
+                    occupancy: ratio(&counts),
+                    standing: occupancy.standing(),
+                    fares: occupancy
+                        .fares()
+                        .map(|(fare, counts)| (fare.to_string(), counts.free()))
+                        .collect(),
+                },
+            )
+        })
+        .collect()
+}
+
+fn ratio(counts: &Counts) -> f64 {
+    if counts.total == 0 {
+        0.0
+    } else {
+        counts.reserved as f64 / counts.total as f64
+    }
+}
+
+// the labels of a line, with the tenant's first when there is one
+fn labels(tenant: Option<&str>, labels: &str) -> String {
+    let tenant = tenant.map(|tenant| format!("tenant=\"{}\"", escape(tenant)));
+    let labels = tenant
+        .iter()
+        .map(String::as_str)
+        .chain(Some(labels).filter(|labels| !labels.is_empty()))
+        .collect::<Vec<_>>();
+    if labels.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", labels.join(","))
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    writeln!(out, "# HELP {} {}", name, help).unwrap();
+    writeln!(out, "# TYPE {} {}", name, kind).unwrap();
+}
+
+// label values are quoted, so quotes, backslashes and newlines need escaping
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::stack::Backpressure;
+
+    use super::*;
+
+    #[test]
+    fn test_request_metrics() {
+        let metrics = Metrics::default();
+        metrics.record_request("GET", "/train/:train_id", 200, Duration::from_millis(3));
+        metrics.record_request("GET", "/train/:train_id", 200, Duration::from_millis(30));
+        metrics.record_request("GET", "/train/:train_id", 404, Duration::from_secs(10));
+        let rendered = metrics.render();
+        assert!(rendered.contains(
+            "train_service_requests_total{method=\"GET\",route=\"/train/:train_id\",status=\"200\"} 2\n"
+        ));
+        assert!(rendered.contains(
+            "train_service_requests_total{method=\"GET\",route=\"/train/:train_id\",status=\"404\"} 1\n"
+        ));
+        let labels = "method=\"GET\",route=\"/train/:train_id\"";
+        for (le, count) in [
+            ("0.001", 0),
+            ("0.005", 1),
+            ("0.05", 2),
+            ("5", 2),
+            ("+Inf", 3),
+        ] {
+            assert!(
+                rendered.contains(&format!(
+                    "train_service_request_duration_seconds_bucket{{{},le=\"{}\"}} {}\n",
+                    labels, le, count
+                )),
+                "bucket {} should have {}",
+                le,
+                count
+            );
+        }
+        assert!(rendered.contains(&format!(
+            "train_service_request_duration_seconds_count{{{}}} 3\n",
+            labels
+        )));
+    }
+
+    #[test]
+    fn test_occupancy_metrics() {
+        let metrics = Metrics::default();
+        let mut occupancy = Occupancy::default();
+        occupancy.add_seat("A", true);
+        occupancy.add_seat("A", false);
+        occupancy.add_seat("A", false);
+        occupancy.add_seat("A", false);
+        metrics.record_occupancy(&TrainId::new("local_1000"), &occupancy, 0);
+        let rendered = metrics.render();
+        assert!(rendered.contains("# TYPE train_service_train_occupancy_ratio gauge\n"));
+        assert!(
+            rendered.contains("train_service_train_occupancy_ratio{train=\"local_1000\"} 0.25\n")
+        );
+        metrics.clear_occupancy();
+        assert!(!metrics.render().contains("local_1000"));
+    }
+
+    #[test]
+    fn test_coach_occupancy_metrics() {
+        let metrics = Metrics::default();
+        let mut occupancy = Occupancy::default();
+        occupancy.add_seat("A", true);
+        occupancy.add_seat("A", false);
+        occupancy.add_seat("A", false);
+        occupancy.add_seat("A", false);
+        occupancy.add_seat("B", true);
+        occupancy.add_seat("B", false);
+        metrics.record_occupancy(&TrainId::new("local_1000"), &occupancy, 0);
+        let rendered = metrics.render();
+        assert!(rendered.contains("# TYPE train_service_coach_occupancy_ratio gauge\n"));
+        assert!(rendered.contains(
+            "train_service_coach_occupancy_ratio{train=\"local_1000\",coach=\"A\"} 0.25\n"
+        ));
+        assert!(rendered.contains(
+            "train_service_coach_occupancy_ratio{train=\"local_1000\",coach=\"B\"} 0.5\n"
+        ));
+        // updated when the train changes
+        occupancy.reserve("A");
+        metrics.record_occupancy(&TrainId::new("local_1000"), &occupancy, 60);
+        assert!(metrics.render().contains(
+            "train_service_coach_occupancy_ratio{train=\"local_1000\",coach=\"A\"} 0.5\n"
+        ));
+    }
+
+    #[test]
+    fn test_occupancy_history() {
+        let metrics = Metrics::default();
+        let train_id = TrainId::new("local_1000");
+        let mut occupancy = Occupancy::default();
+        occupancy.add_seat("A", false);
+        occupancy.add_seat("A", false);
+        metrics.record_occupancy(&train_id, &occupancy, 1000);
+        occupancy.reserve("A");
+        metrics.record_occupancy(&train_id, &occupancy, 1060);
+        // the history outlasts clearing the current occupancy
+        metrics.clear_occupancy();
+        assert_eq!(
+            metrics.occupancy_history(&train_id),
+            [
+                OccupancySample {
+                    at: 1000,
+                    reserved: 0,
+                    total: 2,
+                },
+                OccupancySample {
+                    at: 1060,
+                    reserved: 1,
+                    total: 2,
+                },
+            ]
+        );
+        assert!(metrics
+            .occupancy_history(&TrainId::new("express_2000"))
+            .is_empty());
+    }
+
+    #[test]
+    fn test_stats() {
+        let metrics = Metrics::default();
+        metrics.record_request("GET", "/train/:train_id", 200, Duration::from_millis(3));
+        metrics.record_request("GET", "/train/:train_id", 404, Duration::from_millis(3));
+        metrics.record_request(
+            "POST",
+            "/train/:train_id/reserve",
+            400,
+            Duration::from_millis(3),
+        );
+        metrics.record_reservation(2);
+        metrics.record_reservation(3);
+        let mut occupancy = Occupancy::default();
+        occupancy.add_seat("A", true);
+        occupancy.add_seat("A", false);
+        metrics.record_occupancy(&TrainId::new("local_1000"), &occupancy, 0);
+
+        let stats = metrics.stats();
+        assert_eq!(stats.requests, 3);
+        assert_eq!(stats.errors, 2);
+        assert_eq!(stats.reservations, 2);
+        assert_eq!(stats.reserved_seats, 5);
+        assert_eq!(
+            stats.trains["local_1000"],
+            TrainStats {
+                reserved: 1,
+                total: 2,
+                occupancy: 0.5,
+                standing: None,
+                fares: BTreeMap::new(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_queue_metrics() {
+        let metrics = Metrics::default();
+        assert!(!metrics.render().contains("train_service_queue_depth"));
+        let queue = Arc::new(Queue::new(Backpressure { max_queue: 1 }));
+        metrics.set_queue(queue.clone());
+        let _place = queue.enter().unwrap();
+        queue.enter().unwrap_err();
+        let rendered = metrics.render();
+        assert!(rendered.contains("train_service_queue_depth 1\n"));
+        assert!(rendered.contains("train_service_queue_rejected_total 1\n"));
+    }
+
+    #[test]
+    fn test_cache_metrics() {
+        let metrics = Metrics::default();
+        assert!(!metrics.render().contains("train_service_cache_trains"));
+        assert_eq!(metrics.stats().cache, None);
+        let cache = metrics.cache();
+        cache.set_capacity(2);
+        cache.set_trains(2);
+        cache.record_eviction();
+        let rendered = metrics.render();
+        assert!(rendered.contains("train_service_cache_trains 2\n"));
+        assert!(rendered.contains("train_service_cache_evictions_total 1\n"));
+        assert_eq!(
+            metrics.stats().cache,
+            Some(CacheStats {
+                capacity: 2,
+                trains: 2,
+                evictions: 1,
+            })
+        );
+    }
+
+    #[test]
+    fn test_tenant_metrics() {
+        let metrics = Metrics::default();
+        let red = Arc::new(Metrics::default());
+        metrics.set_tenants(BTreeMap::from([("red".to_string(), red.clone())]));
+        let mut occupancy = Occupancy::default();
+        occupancy.add_seat("A", true);
+        red.record_occupancy(&TrainId::new("local_1000"), &occupancy, 0);
+        red.record_reservation(1);
+        red.cache().set_capacity(2);
+
+        let rendered = metrics.render();
+        assert!(rendered.contains(
+            "train_service_train_occupancy_ratio{tenant=\"red\",train=\"local_1000\"} 1\n"
+        ));
+        assert!(rendered.contains("train_service_cache_capacity{tenant=\"red\"} 2\n"));
+        assert!(!rendered.contains("train_service_cache_capacity 0"));
+        let stats = metrics.stats();
+        assert_eq!(stats.reservations, 0);
+        assert_eq!(stats.tenants["red"].reservations, 1);
+        assert_eq!(stats.tenants["red"].trains["local_1000"].reserved, 1);
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+    }
+}
@@ -0,0 +1,1122 @@
+use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use axum::body::HttpBody;
+use axum::error_handling::HandleErrorLayer;
+use axum::extract::{MatchedPath, OriginalUri, RawPathParams, Request};
+use axum::http::{header, HeaderValue, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::{BoxError, Router};
+use futures_util::FutureExt;
+use tokio::sync::Semaphore;
+use tower::timeout::error::Elapsed;
+use tower::ServiceBuilder;
+use tower_http::compression::predicate::SizeAbove;
+use tower_http::compression::CompressionLayer;
+use tracing::Instrument;
+
+use crate::api_keys::{ApiKey, ApiKeys};
+use crate::booking_reference::BookingReference;
+use crate::correlation;
+use crate::deprecations::{self, Deprecations};
+use crate::envelope;
+use crate::extract;
+use crate::metrics::Metrics;
+use crate::stack::{Chaos, Compression, Cors, LoadShed, Queue, RateLimit, RateLimiter};
+use crate::telemetry;
+use crate::tenants::TenantUsage;
+
+/// The body of errors produced by middleware, rather than by a handler.
+#[derive(Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct MiddlewareError {
+    pub error: String,
+    pub message: String,
+}
+
+/// Answer requests for unknown paths with a JSON 404 response, and requests
+/// with a method a path doesn't support with a JSON 405 response, which keeps
+/// the `Allow` header listing the methods it does support.
+pub fn with_fallback(router: Router) -> Router {
+    router
+        .fallback(not_found)
+        .layer(middleware::map_response(method_not_allowed))
+}
+
+async fn not_found(OriginalUri(uri): OriginalUri) -> Response {
+    (
+        StatusCode::NOT_FOUND,
+        axum::Json(MiddlewareError {
+            error: "not_found".to_string(),
+            message: format!("There is nothing at {}", uri.path()),
+        }),
+    )
+        .into_response()
+}
+
+async fn method_not_allowed(response: Response) -> Response {
+    if response.status() != StatusCode::METHOD_NOT_ALLOWED {
+        return response;
+    }
+    let (mut parts, _) = response.into_parts();
+    let allowed = parts
+        .headers
+        .get(header::ALLOW)
+        .and_then(|allow| allow.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    parts.headers.remove(header::CONTENT_LENGTH);
+    let body = axum::Json(MiddlewareError {
+        error: "method_not_allowed".to_string(),
+        message: format!("Method not allowed; allowed are {}", allowed),
+    });
+    (parts, body).into_response()
+}
+
+/// Refuse request bodies that aren't JSON with a 415 response, also when
+/// they're sent to endpoints that don't read a body, so clients find out
+/// that it's ignored.
+pub fn with_json_bodies(router: Router) -> Router {
+    router.layer(middleware::from_fn(require_json_body))
+}
+
+async fn require_json_body(request: Request, next: Next) -> Response {
+    let has_body = request.body().size_hint().exact() != Some(0);
+    let sends_body = matches!(
+        *request.method(),
+        axum::http::Method::POST | axum::http::Method::PUT | axum::http::Method::PATCH
+    );
+    if sends_body && has_body {
+        if let Err(rejection) = extract::check_content_type(request.headers()) {
+            return rejection.into_response();
+        }
+    }
+    next.run(request).await
+}
+
+/// Give up on requests that take longer than `timeout`, with a 504 response.
+pub fn with_timeout(router: Router, timeout: Duration) -> Router {
+    router.layer(
+        ServiceBuilder::new()
+            .layer(HandleErrorLayer::new(move |err: BoxError| async move {
+                handle_timeout_error(err, timeout)
+            }))
+            .timeout(timeout),
+    )
+}
+
+fn handle_timeout_error(err: BoxError, timeout: Duration) -> Response {
+    if err.is::<Elapsed>() {
+        (
+            StatusCode::GATEWAY_TIMEOUT,
+            axum::Json(MiddlewareError {
+                error: "timeout".to_string(),
+                message: format!("Request did not complete within {:?}", timeout),
+            }),
+        )
+            .into_response()
+    } else {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            axum::Json(MiddlewareError {
+                error: "internal".to_string(),
+                message: err.to_string(),
+            }),
+        )
+            .into_response()
+    }
+}
+
+/// Refuse requests over the rate limit with a 429 response, which says in its
+/// `Retry-After` header how many seconds until requests are taken again.
+pub fn with_rate_limit(router: Router, limit: RateLimit) -> Router {
+    let limiter = Arc::new(RateLimiter::new(limit));
+    router.layer(middleware::from_fn_with_state(limiter, limit_rate))
+}
+
+async fn limit_rate(
+    axum::extract::State(limiter): axum::extract::State<Arc<RateLimiter>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    match limiter.check(Instant::now()) {
+        Ok(()) => next.run(request).await,
+        Err(retry_after) => (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(
+                header::RETRY_AFTER,
+                retry_after.as_secs_f64().ceil().to_string(),
+            )],
+            axum::Json(MiddlewareError {
+                error: "rate_limited".to_string(),
+                message: "Too many requests; try again later".to_string(),
+            }),
+        )
+            .into_response(),
+    }
+}
+
+/// Refuse requests with a 503 response while the most requests the service
+/// takes at a time are being handled, with a `Retry-After` header.
+pub fn with_load_shed(router: Router, load_shed: LoadShed) -> Router {
+    let in_flight = Arc::new(Semaphore::new(load_shed.max_in_flight));
+    router.layer(middleware::from_fn_with_state(
+        (in_flight, load_shed),
+        shed_load,
+    ))
+}
+
+async fn shed_load(
+    axum::extract::State((in_flight, load_shed)): axum::extract::State<(Arc<Semaphore>, LoadShed)>,
+    request: Request,
+    next: Next,
+) -> Response {
+    match in_flight.try_acquire() {
+        Ok(_permit) => next.run(request).await,
+        Err(_) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            [(
+                header::RETRY_AFTER,
+                load_shed.retry_after_seconds.to_string(),
+            )],
+            axum::Json(MiddlewareError {
+                error: "overloaded".to_string(),
+                message: "Too many requests at once; try again later".to_string(),
+            }),
+        )
+            .into_response(),
+    }
+}
+
+/// Refuse requests with a 429 response while the queue is full. Its
+/// `Retry-After` header says how many seconds the requests in the queue are
+/// expected to take.
+pub fn with_backpressure(router: Router, queue: Arc<Queue>) -> Router {
+    router.route_layer(middleware::from_fn_with_state(queue, apply_backpressure))
+}
+
+async fn apply_backpressure(
+    axum::extract::State(queue): axum::extract::State<Arc<Queue>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    match queue.enter() {
+        Ok(_place) => next.run(request).await,
+        Err(retry_after) => (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(
+                header::RETRY_AFTER,
+                retry_after.as_secs_f64().ceil().max(1.0).to_string(),
+            )],
+            axum::Json(MiddlewareError {
+                error: "queue_full".to_string(),
+                message: "Too many requests are waiting; try again later".to_string(),
+            }),
+        )
+            .into_response(),
+    }
+}
+
+/// Slow requests down, and fail some of them with a 503 response, as the
+/// chaos settings say.
+pub fn with_chaos(router: Router, chaos: Chaos) -> Router {
+    router.layer(middleware::from_fn_with_state(chaos, cause_chaos))
+}
+
+async fn cause_chaos(
+    axum::extract::State(chaos): axum::extract::State<Chaos>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if chaos.delay_ms > 0 {
+        tokio::time::sleep(Duration::from_millis(chaos.delay_ms)).await;
+    }
+    if rand::random::<f64>() < chaos.failure_rate {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            axum::Json(MiddlewareError {
+                error: "chaos".to_string(),
+                message: "This request failed on purpose".to_string(),
+            }),
+        )
+            .into_response();
+    }
+    next.run(request).await
+}
+
+// the largest body that's wrapped in an envelope; larger ones go as they are
+const MAX_ENVELOPED_BYTES: usize = 16 * 1024 * 1024;
+
+/// Wrap responses in an envelope for clients that ask for one, or for all
+/// clients that don't say otherwise when `by_default` is set. Bodies that
+/// are streamed, or larger than `MAX_ENVELOPED_BYTES`, aren't wrapped, so
+/// they don't need to be held in memory.
+pub fn with_envelope(router: Router, by_default: bool) -> Router {
+    router.layer(middleware::from_fn_with_state(by_default, wrap_in_envelope))
+}
+
+async fn wrap_in_envelope(
+    axum::extract::State(by_default): axum::extract::State<bool>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if !envelope::wanted(request.headers(), by_default) {
+        return next.run(request).await;
+    }
+    let request_id = request
+        .headers()
+        .get("x-request-id")
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    let response = next.run(request).await;
+    // the correlation id is the request id when the client didn't give one
+    let request_id = request_id
+        .or_else(|| {
+            let id = response.headers().get(correlation::HEADER)?.to_str().ok()?;
+            Some(id.to_string())
+        })
+        .unwrap_or_else(|| format!("{:016x}", rand::random::<u64>()));
+    let content_type = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    let size = response.body().size_hint().exact();
+    if !envelope::wraps(response.status(), &content_type)
+        || size.is_none_or(|size| size > MAX_ENVELOPED_BYTES as u64)
+    {
+        return response;
+    }
+    let (mut parts, body) = response.into_parts();
+    let body = match axum::body::to_bytes(body, MAX_ENVELOPED_BYTES).await {
+        Ok(body) => body,
+        Err(err) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                axum::Json(MiddlewareError {
+                    error: "internal".to_string(),
+                    message: err.to_string(),
+                }),
+            )
+                .into_response()
+        }
+    };
+    let Some(wrapped) = envelope::wrap(parts.status, &content_type, &body, request_id.clone())
+    else {
+        return Response::from_parts(parts, axum::body::Body::from(body));
+    };
+    parts.headers.remove(header::CONTENT_LENGTH);
+    parts.headers.remove(header::CONTENT_TYPE);
+    if let Ok(request_id) = HeaderValue::try_from(request_id) {
+        parts.headers.insert("x-request-id", request_id);
+    }
+    (parts, axum::Json(wrapped)).into_response()
+}
+
+/// Answer CORS preflight requests from the allowed origins, and let them read
+/// the responses to their other requests.
+pub fn with_cors(router: Router, cors: Cors) -> Router {
+    router.layer(middleware::from_fn_with_state(Arc::new(cors), allow_cors))
+}
+
+async fn allow_cors(
+    axum::extract::State(cors): axum::extract::State<Arc<Cors>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let allow_origin = request
+        .headers()
+        .get(header::ORIGIN)
+        .and_then(|origin| origin.to_str().ok())
+        .and_then(|origin| cors.allow_origin(origin))
+        .and_then(|origin| HeaderValue::try_from(origin).ok());
+    let Some(allow_origin) = allow_origin else {
+        return next.run(request).await;
+    };
+    let is_preflight = request.method() == axum::http::Method::OPTIONS
+        && request
+            .headers()
+            .contains_key(header::ACCESS_CONTROL_REQUEST_METHOD);
+    let mut response = if is_preflight {
+        let mut response = StatusCode::NO_CONTENT.into_response();
+        let headers = response.headers_mut();
+        headers.insert(
+            header::ACCESS_CONTROL_ALLOW_METHODS,
+            HeaderValue::from_static("GET, POST, PUT, PATCH, DELETE"),
+        );
+        if let Some(requested) = request
+            .headers()
+            .get(header::ACCESS_CONTROL_REQUEST_HEADERS)
+        {
+            headers.insert(header::ACCESS_CONTROL_ALLOW_HEADERS, requested.clone());
+        }
+        headers.insert(
+            header::ACCESS_CONTROL_MAX_AGE,
+            HeaderValue::from_static("3600"),
+        );
+        response
+    } else {
+        next.run(request).await
+    };
+    let headers = response.headers_mut();
+    if allow_origin != "*" {
+        headers.append(header::VARY, HeaderValue::from_static("origin"));
+    }
+    headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin);
+    response
+}
+
+/// Compress responses bigger than the minimum size for clients that send
+/// `Accept-Encoding: gzip`.
+pub fn with_compression(router: Router, compression: Compression) -> Router {
+    router.layer(CompressionLayer::new().compress_when(SizeAbove::new(compression.min_size)))
+}
+
+/// Handle each request with its correlation id, so the events it causes can
+/// carry it, and send the id back in the `X-Correlation-Id` header.
+pub fn with_correlation_id(router: Router) -> Router {
+    router.layer(middleware::from_fn(correlate))
+}
+
+async fn correlate(request: Request, next: Next) -> Response {
+    let id = correlation::from_headers(request.headers());
+    let mut response = correlation::scope(id.clone(), next.run(request)).await;
+    if let Ok(id) = HeaderValue::try_from(id) {
+        response.headers_mut().insert(correlation::HEADER, id);
+    }
+    response
+}
+
+/// Turn a panic while handling a request into a 500 response, rather than a
+/// dropped connection. The panic is logged with an incident id that's in the
+/// response too, so the two can be matched up.
+pub fn with_panic_recovery(router: Router) -> Router {
+    router.layer(middleware::from_fn(recover_panic))
+}
+
+async fn recover_panic(request: Request, next: Next) -> Response {
+    // in this task rather than one of its own, so that when the request is
+    // given up on, by the timeout say, its handler stops too
+    let handled = AssertUnwindSafe(next.run(request)).catch_unwind().await;
+    let panic = match handled {
+        Ok(response) => return response,
+        Err(panic) => panic,
+    };
+    let incident: u64 = rand::random();
+    let incident = format!("{:016x}", incident);
+    let reason = panic
+        .downcast_ref::<&str>()
+        .map(|reason| reason.to_string())
+        .or_else(|| panic.downcast_ref::<String>().cloned())
+        .unwrap_or_default();
+    tracing::error!(incident, reason, "request handler panicked");
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        axum::Json(MiddlewareError {
+            error: "internal".to_string(),
+            message: format!("Something went wrong; the incident id is {}", incident),
+        }),
+    )
+        .into_response()
+}
+
+/// Treat requests that don't say which media type they accept as if they
+/// accept this one.
+pub fn with_default_accept(router: Router, media_type: &'static str) -> Router {
+    router.layer(middleware::from_fn_with_state(
+        HeaderValue::from_static(media_type),
+        default_accept,
+    ))
+}
+
+async fn default_accept(
+    axum::extract::State(media_type): axum::extract::State<HeaderValue>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let accepts_any = request
+        .headers()
+        .get(header::ACCEPT)
+        .is_none_or(|accept| accept == "*/*");
+    if accepts_any {
+        request.headers_mut().insert(header::ACCEPT, media_type);
+    }
+    next.run(request).await
+}
+
+/// Mark responses from deprecated routes with `Deprecation`, `Sunset` and
+/// `Link` headers, and log each use of them.
+pub fn with_deprecations(router: Router, deprecations: Arc<Deprecations>) -> Router {
+    router.layer(middleware::from_fn_with_state(
+        deprecations,
+        mark_deprecated,
+    ))
+}
+
+async fn mark_deprecated(
+    axum::extract::State(deprecations): axum::extract::State<Arc<Deprecations>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let method = request.method().to_string();
+    let route = matched_path(&request);
+    let Some(deprecation) = deprecations.get(&method, &route) else {
+        return next.run(request).await;
+    };
+    let uses = deprecations.record_use(&method, &route);
+    tracing::warn!(method, route, uses, "deprecated route used");
+    let mut response = next.run(request).await;
+    let headers = response.headers_mut();
+    if let Ok(value) = HeaderValue::try_from(format!("@{}", deprecation.since)) {
+        headers.insert("deprecation", value);
+    }
+    if let Some(sunset) = deprecation.sunset {
+        if let Ok(value) = HeaderValue::try_from(deprecations::http_date(sunset)) {
+            headers.insert("sunset", value);
+        }
+    }
+    if let Some(link) = &deprecation.link {
+        if let Ok(value) = HeaderValue::try_from(format!("<{}>; rel=\"deprecation\"", link)) {
+            headers.append(header::LINK, value);
+        }
+    }
+    response
+}
+
+/// Record the number of requests and how long they took, per route.
+pub fn with_metrics(router: Router, metrics: Arc<Metrics>) -> Router {
+    router.layer(middleware::from_fn_with_state(metrics, measure_request))
+}
+
+async fn measure_request(
+    axum::extract::State(metrics): axum::extract::State<Arc<Metrics>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let method = request.method().to_string();
+    let route = matched_path(&request);
+    let start = Instant::now();
+    let response = next.run(request).await;
+    metrics.record_request(&method, &route, response.status().as_u16(), start.elapsed());
+    response
+}
+
+fn matched_path(request: &Request) -> String {
+    request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string())
+}
+
+/// Run each request in a tracing span, so it can be exported as a trace.
+pub fn with_tracing(router: Router) -> Router {
+    router.layer(middleware::from_fn(trace_request))
+}
+
+async fn trace_request(request: Request, next: Next) -> Response {
+    let route = matched_path(&request);
+    let span = tracing::info_span!(
+        "request",
+        http.method = %request.method(),
+        http.route = route,
+        http.status_code = tracing::field::Empty,
+    );
+    telemetry::continue_trace(&span, request.headers());
+    let response = next.run(request).instrument(span.clone()).await;
+    span.record("http.status_code", response.status().as_u16());
+    response
+}
+
+/// Refuse requests without a known `X-API-Key` header, and make the API key
+/// available to handlers as a request extension.
+pub fn with_api_keys(router: Router, api_keys: Arc<ApiKeys>) -> Router {
+    router.layer(middleware::from_fn_with_state(api_keys, check_api_key))
+}
+
+async fn check_api_key(
+    axum::extract::State(api_keys): axum::extract::State<Arc<ApiKeys>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let api_key = request
+        .headers()
+        .get("x-api-key")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|key| api_keys.get(key));
+    match api_key {
+        Some(api_key) => {
+            let api_key = api_key.clone();
+            request.extensions_mut().insert(api_key);
+            next.run(request).await
+        }
+        None => (
+            StatusCode::UNAUTHORIZED,
+            axum::Json(MiddlewareError {
+                error: "unauthorized".to_string(),
+                message: "A valid X-API-Key header is required".to_string(),
+            }),
+        )
+            .into_response(),
+    }
+}
+
+/// Count the requests of tenants, refusing them once a tenant is out of
+/// requests, and keep clients of a tenant to that tenant's endpoints. This
+/// needs the API key middleware to run first.
+pub fn with_tenants(router: Router, usage: Arc<TenantUsage>) -> Router {
+    router.layer(middleware::from_fn_with_state(usage, check_tenant))
+}
+
+async fn check_tenant(
+    axum::extract::State(usage): axum::extract::State<Arc<TenantUsage>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let path = match request.extensions().get::<OriginalUri>() {
+        Some(OriginalUri(uri)) => uri.path(),
+        None => request.uri().path(),
+    };
+    let tenant = path
+        .strip_prefix("/tenants/")
+        .and_then(|rest| rest.split('/').next())
+        .filter(|tenant| usage.contains(tenant))
+        .map(str::to_string);
+    let api_key_tenant = request
+        .extensions()
+        .get::<ApiKey>()
+        .and_then(|api_key| api_key.tenant.as_ref());
+    if let Some(api_key_tenant) = api_key_tenant {
+        if tenant.as_ref() != Some(api_key_tenant) {
+            return (
+                StatusCode::FORBIDDEN,
+                axum::Json(MiddlewareError {
+                    error: "forbidden".to_string(),
+                    message: format!(
+                        "This API key can only be used under /tenants/{}",
+                        api_key_tenant
+                    ),
+                }),
+            )
+                .into_response();
+        }
+    }
+    if let Some(tenant) = tenant {
+        if let Err(allowance) = usage.record_request(&tenant) {
+            return (
+                StatusCode::TOO_MANY_REQUESTS,
+                axum::Json(MiddlewareError {
+                    error: "request_quota_exceeded".to_string(),
+                    message: format!(
+                        "Tenant {} made all of its {} requests",
+                        tenant, allowance.limit
+                    ),
+                }),
+            )
+                .into_response();
+        }
+    }
+    next.run(request).await
+}
+
+/// The format of access log lines.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum AccessLogFormat {
+    Json,
+    Logfmt,
+}
+
+/// One line of the access log.
+#[derive(Debug, PartialEq, serde::Serialize)]
+pub struct AccessLogEntry {
+    pub method: String,
+    pub path: String,
+    pub status: u16,
+    pub duration_ms: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub train_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub booking_reference: Option<String>,
+}
+
+impl AccessLogEntry {
+    pub fn format(&self, format: AccessLogFormat) -> String {
+        match format {
+            AccessLogFormat::Json => serde_json::to_string(self).unwrap(),
+            AccessLogFormat::Logfmt => {
+                let mut fields = vec![
+                    ("method", self.method.clone()),
+                    ("path", self.path.clone()),
+                    ("status", self.status.to_string()),
+                    ("duration_ms", format!("{:.3}", self.duration_ms)),
+                ];
+                if let Some(train_id) = &self.train_id {
+                    fields.push(("train_id", train_id.clone()));
+                }
+                if let Some(booking_reference) = &self.booking_reference {
+                    fields.push(("booking_reference", booking_reference.clone()));
+                }
+                fields
+                    .iter()
+                    .map(|(key, value)| format!("{}={}", key, logfmt_value(value)))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            }
+        }
+    }
+}
+
+pub(crate) fn logfmt_value(value: &str) -> String {
+    if value.is_empty() || value.contains([' ', '"', '=', '\\', '\n']) {
+        format!("{:?}", value)
+    } else {
+        value.to_string()
+    }
+}
+
+/// Write one access log line per request. Handlers that deal with a booking
+/// reference put it in the response extensions, so it can be logged too.
+pub fn with_access_log<W>(router: Router, format: AccessLogFormat, write: W) -> Router
+where
+    W: Fn(String) + Clone + Send + Sync + 'static,
+{
+    router.layer(middleware::from_fn(
+        move |params: Option<RawPathParams>, request: Request, next: Next| {
+            let write = write.clone();
+            async move {
+                let method = request.method().to_string();
+                let path = request.uri().path().to_string();
+                let train_id = params.and_then(|params| {
+                    params
+                        .iter()
+                        .find(|(key, _)| *key == "train_id")
+                        .map(|(_, value)| value.to_string())
+                });
+                let start = Instant::now();
+                let response = next.run(request).await;
+                let entry = AccessLogEntry {
+                    method,
+                    path,
+                    status: response.status().as_u16(),
+                    duration_ms: start.elapsed().as_secs_f64() * 1000.0,
+                    train_id,
+                    booking_reference: response
+                        .extensions()
+                        .get::<BookingReference>()
+                        .map(|reference| reference.as_str().to_string()),
+                };
+                write(entry.format(format));
+                response
+            }
+        },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Mutex;
+
+    use axum::body::Body;
+    use axum::routing::{get, post};
+    use axum::Extension;
+    use axum_test::TestServer;
+    use tower::ServiceExt;
+
+    use crate::stack::Backpressure;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_panic_recovery() {
+        let router = Router::new()
+            .route("/fine", get(|| async { "fine" }))
+            .route(
+                "/panic",
+                get(|| async {
+                    if true {
+                        panic!("oops");
+                    }
+                    "unreachable"
+                }),
+            );
+        let server = TestServer::new(with_panic_recovery(router)).unwrap();
+
+        let response = server.get("/panic").expect_failure().await;
+        assert_eq!(response.status_code(), 500);
+        let error = response.json::<MiddlewareError>();
+        assert_eq!(error.error, "internal");
+        assert!(error
+            .message
+            .starts_with("Something went wrong; the incident id is "));
+        assert_eq!(server.get("/fine").await.text(), "fine");
+    }
+
+    #[tokio::test]
+    async fn test_timeout() {
+        let router = Router::new()
+            .route(
+                "/slow",
+                get(|| async {
+                    tokio::time::sleep(Duration::from_secs(10)).await;
+                    "done"
+                }),
+            )
+            .route("/fast", get(|| async { "done" }));
+        let server = TestServer::new(with_timeout(router, Duration::from_millis(10))).unwrap();
+
+        let response = server.get("/fast").await;
+        assert_eq!(response.text(), "done");
+
+        let response = server.get("/slow").expect_failure().await;
+        assert_eq!(response.status_code(), 504);
+        assert_eq!(
+            response.json::<MiddlewareError>(),
+            MiddlewareError {
+                error: "timeout".to_string(),
+                message: "Request did not complete within 10ms".to_string(),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_timeout_stops_recovering_handler() {
+        let finished = Arc::new(AtomicBool::new(false));
+        let router = Router::new().route(
+            "/slow",
+            get({
+                let finished = finished.clone();
+                || async move {
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    finished.store(true, Ordering::SeqCst);
+                    "done"
+                }
+            }),
+        );
+        let router = with_timeout(with_panic_recovery(router), Duration::from_millis(10));
+        let server = TestServer::new(router).unwrap();
+
+        let response = server.get("/slow").expect_failure().await;
+        assert_eq!(response.status_code(), 504);
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(!finished.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn test_json_bodies() {
+        let router = Router::new().route("/", post(|| async { "posted" }));
+        let server = TestServer::new(with_json_bodies(router)).unwrap();
+
+        assert_eq!(server.post("/").await.text(), "posted");
+        assert_eq!(
+            server.post("/").json(&serde_json::json!({})).await.text(),
+            "posted"
+        );
+        let response = server.post("/").text("seats=1A").expect_failure().await;
+        assert_eq!(response.status_code(), 415);
+        assert_eq!(
+            response.json::<extract::BodyError>().message,
+            "Expected request with `Content-Type: application/json`, not `text/plain`"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_envelope() {
+        let router = Router::new()
+            .route("/", get(|| async { axum::Json(serde_json::json!([1, 2])) }))
+            .route(
+                "/fail",
+                get(|| async { (StatusCode::CONFLICT, "Seat 1A is taken") }),
+            )
+            .route(
+                "/stream",
+                get(|| async {
+                    let chunks = ["[1,", "2]"].map(Ok::<_, std::convert::Infallible>);
+                    (
+                        [(header::CONTENT_TYPE, "application/json")],
+                        axum::body::Body::from_stream(futures_util::stream::iter(chunks)),
+                    )
+                }),
+            );
+        let server = TestServer::new(with_envelope(router, false)).unwrap();
+        let envelope = || HeaderValue::from_static("true");
+
+        assert_eq!(
+            server.get("/").await.json::<serde_json::Value>(),
+            serde_json::json!([1, 2])
+        );
+        let response = server
+            .get("/")
+            .add_header(
+                header::HeaderName::from_static(envelope::HEADER),
+                envelope(),
+            )
+            .add_header(
+                header::HeaderName::from_static("x-request-id"),
+                HeaderValue::from_static("abc"),
+            )
+            .await;
+        assert_eq!(response.header("x-request-id"), "abc");
+        assert_eq!(
+            response.json::<serde_json::Value>(),
+            serde_json::json!({
+                "data": [1, 2],
+                "error": null,
+                "meta": { "request_id": "abc", "version": env!("CARGO_PKG_VERSION") },
+            })
+        );
+
+        let response = server
+            .get("/fail")
+            .add_header(
+                header::HeaderName::from_static(envelope::HEADER),
+                envelope(),
+            )
+            .expect_failure()
+            .await;
+        assert_eq!(response.status_code(), 409);
+        let wrapped = response.json::<envelope::Envelope>();
+        assert_eq!(wrapped.data, None);
+        assert_eq!(
+            wrapped.error,
+            Some(serde_json::json!({ "message": "Seat 1A is taken" }))
+        );
+        assert_eq!(response.header("x-request-id"), wrapped.meta.request_id);
+
+        // a streamed body goes as it is
+        let response = server
+            .get("/stream")
+            .add_header(
+                header::HeaderName::from_static(envelope::HEADER),
+                envelope(),
+            )
+            .await;
+        assert_eq!(
+            response.json::<serde_json::Value>(),
+            serde_json::json!([1, 2])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit() {
+        let router = Router::new().route("/", get(|| async { "fine" }));
+        let limit = RateLimit {
+            requests: 2,
+            per_seconds: 60,
+        };
+        let server = TestServer::new(with_rate_limit(router, limit)).unwrap();
+
+        server.get("/").await;
+        server.get("/").await;
+        let response = server.get("/").expect_failure().await;
+        assert_eq!(response.status_code(), 429);
+        assert_eq!(response.header("retry-after"), "60");
+        assert_eq!(response.json::<MiddlewareError>().error, "rate_limited");
+    }
+
+    #[tokio::test]
+    async fn test_load_shed() {
+        let router = Router::new().route(
+            "/",
+            get(|| async {
+                tokio::time::sleep(Duration::from_millis(200)).await;
+                "fine"
+            }),
+        );
+        let load_shed = LoadShed {
+            max_in_flight: 1,
+            retry_after_seconds: 2,
+        };
+        let router = with_load_shed(router, load_shed);
+        let get_root = || {
+            let request = Request::builder().uri("/").body(Body::empty()).unwrap();
+            router.clone().oneshot(request)
+        };
+
+        let (first, second) = tokio::join!(get_root(), async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            get_root().await
+        });
+        assert_eq!(first.unwrap().status(), StatusCode::OK);
+        let second = second.unwrap();
+        assert_eq!(second.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(second.headers()[header::RETRY_AFTER], "2");
+        // taken again once the first is done
+        assert_eq!(get_root().await.unwrap().status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_backpressure() {
+        let router = Router::new().route(
+            "/",
+            get(|| async {
+                tokio::time::sleep(Duration::from_millis(200)).await;
+                "fine"
+            }),
+        );
+        let queue = Arc::new(Queue::new(Backpressure { max_queue: 1 }));
+        let router = with_backpressure(router, queue.clone());
+        let get_root = || {
+            let request = Request::builder().uri("/").body(Body::empty()).unwrap();
+            router.clone().oneshot(request)
+        };
+
+        let (first, second) = tokio::join!(get_root(), async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            get_root().await
+        });
+        assert_eq!(first.unwrap().status(), StatusCode::OK);
+        let second = second.unwrap();
+        assert_eq!(second.status(), StatusCode::TOO_MANY_REQUESTS);
+        // at least a second
+        assert_eq!(second.headers()[header::RETRY_AFTER], "1");
+        assert_eq!(queue.depth(), 0);
+        assert_eq!(queue.rejected(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_chaos() {
+        let router = Router::new().route("/", get(|| async { "fine" }));
+        let chaos = Chaos {
+            failure_rate: 1.0,
+            delay_ms: 0,
+        };
+        let server = TestServer::new(with_chaos(router.clone(), chaos)).unwrap();
+        let response = server.get("/").expect_failure().await;
+        assert_eq!(response.status_code(), 503);
+        assert_eq!(response.json::<MiddlewareError>().error, "chaos");
+
+        let server = TestServer::new(with_chaos(router, Chaos::default())).unwrap();
+        assert_eq!(server.get("/").await.text(), "fine");
+    }
+
+    #[tokio::test]
+    async fn test_compression() {
+        let router = Router::new()
+            .route("/big", get(|| async { "seat ".repeat(100) }))
+            .route("/small", get(|| async { "seat" }));
+        let server = TestServer::new(with_compression(router, Compression::default())).unwrap();
+        let gzip = HeaderValue::from_static("gzip");
+
+        let response = server
+            .get("/big")
+            .add_header(header::ACCEPT_ENCODING, gzip.clone())
+            .await;
+        assert_eq!(response.header(header::CONTENT_ENCODING), "gzip");
+        assert!(response.as_bytes().len() < 500);
+        // only when the client can read it, and when it's worth it
+        let response = server.get("/big").await;
+        assert!(response.maybe_header(header::CONTENT_ENCODING).is_none());
+        assert_eq!(response.text(), "seat ".repeat(100));
+        let response = server
+            .get("/small")
+            .add_header(header::ACCEPT_ENCODING, gzip)
+            .await;
+        assert!(response.maybe_header(header::CONTENT_ENCODING).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_cors() {
+        let router = Router::new().route("/", get(|| async { "fine" }).post(|| async { "posted" }));
+        let cors = Cors {
+            origins: vec!["http://localhost:3000".to_string()],
+        };
+        let server = TestServer::new(with_cors(router, cors)).unwrap();
+        let origin = |origin| HeaderValue::from_static(origin);
+
+        let response = server
+            .method(axum::http::Method::OPTIONS, "/")
+            .add_header(header::ORIGIN, origin("http://localhost:3000"))
+            .add_header(
+                header::ACCESS_CONTROL_REQUEST_METHOD,
+                HeaderValue::from_static("POST"),
+            )
+            .add_header(
+                header::ACCESS_CONTROL_REQUEST_HEADERS,
+                HeaderValue::from_static("content-type"),
+            )
+            .await;
+        assert_eq!(response.status_code(), 204);
+        assert_eq!(
+            response.header(header::ACCESS_CONTROL_ALLOW_ORIGIN),
+            "http://localhost:3000"
+        );
+        assert_eq!(
+            response.header(header::ACCESS_CONTROL_ALLOW_HEADERS),
+            "content-type"
+        );
+
+        let response = server
+            .get("/")
+            .add_header(header::ORIGIN, origin("http://localhost:3000"))
+            .await;
+        assert_eq!(response.text(), "fine");
+        assert_eq!(
+            response.header(header::ACCESS_CONTROL_ALLOW_ORIGIN),
+            "http://localhost:3000"
+        );
+        assert_eq!(response.header(header::VARY), "origin");
+
+        let response = server
+            .get("/")
+            .add_header(header::ORIGIN, origin("http://example.com"))
+            .await;
+        assert!(response
+            .maybe_header(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_access_log() {
+        let router = Router::new()
+            .route("/train/:train_id", get(|| async { "train" }))
+            .route(
+                "/booking_reference",
+                post(|| async { (Extension(BookingReference::new("75bcd15")), "reference") }),
+            );
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let written = lines.clone();
+        let router = with_access_log(router, AccessLogFormat::Json, move |line| {
+            written.lock().unwrap().push(line)
+        });
+        let server = TestServer::new(router).unwrap();
+
+        server.get("/train/local_1000").await;
+        server.post("/booking_reference").await;
+        server.get("/unknown").expect_failure().await;
+
+        let lines = lines.lock().unwrap();
+        let entries = lines
+            .iter()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0]["method"], "GET");
+        assert_eq!(entries[0]["path"], "/train/local_1000");
+        assert_eq!(entries[0]["status"], 200);
+        assert_eq!(entries[0]["train_id"], "local_1000");
+        assert!(entries[0].get("booking_reference").is_none());
+        assert_eq!(entries[1]["booking_reference"], "75bcd15");
+        assert!(entries[1].get("train_id").is_none());
+        assert_eq!(entries[2]["path"], "/unknown");
+        assert_eq!(entries[2]["status"], 404);
+    }
+
+    #[test]
+    fn test_access_log_logfmt() {
+        let entry = AccessLogEntry {
+            method: "POST".to_string(),
+            path: "/train/local 1000/reserve".to_string(),
+            status: 200,
+            duration_ms: 1.5,
+            train_id: Some("local 1000".to_string()),
+            booking_reference: Some("75bcd15".to_string()),
+        };
+        assert_eq!(
+            entry.format(AccessLogFormat::Logfmt),
+            r#"method=POST path="/train/local 1000/reserve" status=200 duration_ms=1.500 train_id="local 1000" booking_reference=75bcd15"#
+        );
+    }
+}
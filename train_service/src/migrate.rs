@@ -0,0 +1,107 @@
+//! Upgrading trains files written for older versions of the service, before
+//! they're deserialized.
+//!
+//! Version 1 is a plain map of trains, the format of the original kata. From
+//! version 2 on the trains are in a document with its version:
+//! `{"version": 2, "trains": {...}}`.
+
+use std::fmt::{self, Display, Formatter};
+
+use serde_json::{json, Value};
+
+/// The version of trains files this service writes.
+pub const TRAINS_VERSION: u64 = 2;
+
+// the migration at index i upgrades version i + 1 to i + 2
+const MIGRATIONS: [fn(Value) -> Value; 1] = [add_version];
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum MigrationError {
+    UnknownVersion(u64),
+    InvalidVersion(Value),
+}
+
+impl Display for MigrationError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            MigrationError::UnknownVersion(version) => write!(
+                f,
+                "unknown version {}, expected at most {}",
+                version, TRAINS_VERSION
+            ),
+            MigrationError::InvalidVersion(version) => {
+                write!(f, "invalid version {}, expected a number", version)
+            }
+        }
+    }
+}
+
+/// The version of a trains file.
+pub fn version(value: &Value) -> Result<u64, MigrationError> {
+    match value.get("version") {
+        // a train could be called "version"; trains are objects
+        None | Some(Value::Object(_)) => Ok(1),
+        Some(version) => check_version(version),
+    }
+}
+
+/// Check that the version given in a trains file is one we know.
+pub fn check_version(version: &Value) -> Result<u64, MigrationError> {
+    match version.as_u64() {
+        Some(version @ 1..=TRAINS_VERSION) => Ok(version),
+        Some(version) => Err(MigrationError::UnknownVersion(version)),
+        None => Err(MigrationError::InvalidVersion(version.clone())),
+    }
+}
+
+/// Upgrade a trains file to the current version.
+pub fn migrate_trains(value: Value) -> Result<Value, MigrationError> {
+    let version = version(&value)?;
+    Ok(MIGRATIONS[(version - 1) as usize..]
+        .iter()
+        .fold(value, |value, migration| migration(value)))
+}
+
+fn add_version(trains: Value) -> Value {
+    json!({ "version": 2, "trains": trains })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_migrate_plain_trains() {
+        let trains = json!({ "t": { "seats": {} } });
+        assert_eq!(
+            migrate_trains(trains.clone()),
+            Ok(json!({ "version": 2, "trains": trains }))
+        );
+        // a train called "version" is a train
+        let trains = json!({ "version": { "seats": {} } });
+        assert_eq!(version(&trains), Ok(1));
+    }
+
+    #[test]
+    fn test_migrate_current_version() {
+        let document = json!({ "version": 2, "trains": {} });
+        assert_eq!(migrate_trains(document.clone()), Ok(document));
+    }
+
+    #[test]
+    fn test_migrate_unknown_version() {
+        assert_eq!(
+            migrate_trains(json!({ "version": 3, "trains": {} })),
+            Err(MigrationError::UnknownVersion(3))
+        );
+        assert_eq!(
+            migrate_trains(json!({ "version": 0, "trains": {} })),
+            Err(MigrationError::UnknownVersion(0))
+        );
+        let err = migrate_trains(json!({ "version": "two", "trains": {} })).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid version \"two\", expected a number"
+        );
+    }
+}
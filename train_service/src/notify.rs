@@ -0,0 +1,78 @@
+//! Telling the outside world about things that happened, like a refund that
+//! needs to be paid out.
+
+use crate::alerts::NearlyFull;
+use crate::correlation;
+use crate::refunds::Refund;
+
+#[derive(Debug, PartialEq, Eq, Clone, serde::Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    Refund(Refund),
+    NearlyFull(NearlyFull),
+}
+
+/// An event, with the correlation id of the request that caused it.
+#[derive(Debug, PartialEq, Eq, Clone, serde::Serialize)]
+pub struct Notification {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub correlation_id: Option<String>,
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+impl Notification {
+    /// A notification of an event caused by the current request, if any.
+    pub fn new(event: Event) -> Self {
+        Notification {
+            correlation_id: correlation::current(),
+            event,
+        }
+    }
+}
+
+pub trait Notifier: Send + Sync {
+    fn notify(&self, notification: &Notification);
+}
+
+/// Log events, for lack of anywhere better to send them.
+pub struct LogNotifier;
+
+impl Notifier for LogNotifier {
+    fn notify(&self, notification: &Notification) {
+        match serde_json::to_string(notification) {
+            Ok(event) => tracing::info!(%event, "notification"),
+            Err(err) => tracing::error!(%err, "could not serialize notification"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::train::TrainId;
+
+    #[test]
+    fn test_notification() {
+        let notification = Notification {
+            correlation_id: Some("abc".to_string()),
+            event: Event::NearlyFull(NearlyFull {
+                train_id: TrainId::new("express_2000"),
+                reserved: 15,
+                total: 16,
+                remaining: 1,
+            }),
+        };
+        assert_eq!(
+            serde_json::to_value(&notification).unwrap(),
+            serde_json::json!({
+                "correlation_id": "abc",
+                "event": "nearly_full",
+                "train_id": "express_2000",
+                "reserved": 15,
+                "total": 16,
+                "remaining": 1,
+            })
+        );
+    }
+}
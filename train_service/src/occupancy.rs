@@ -0,0 +1,242 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::resource::Reservable;
+
+/// Reserved and total seat counts for a train, kept per coach.
+///
+/// This is maintained alongside the seats of a train so that occupancy
+/// questions (such as the 70% rule) don't need to look at every seat.
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct Occupancy {
+    train: Counts,
+    coaches: HashMap<String, Counts>,
+    // standing room is counted apart from seats
+    standing: Option<Counts>,
+    // seats sold and available in limited fare buckets, by bucket
+    fares: BTreeMap<String, Counts>,
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy, serde::Serialize, serde::Deserialize)]
+pub struct Counts {
+    pub reserved: usize,
+    pub total: usize,
+}
+
+impl Counts {
+    pub fn free(&self) -> usize {
+        self.total.saturating_sub(self.reserved)
+    }
+
+    pub fn ratio(&self) -> f64 {
+        if self.total == 0 {
+            return 0.0;
+        }
+        self.reserved as f64 / self.total as f64
+    }
+}
+
+impl Occupancy {
+    /// Count reserved and total per coach for seats or other resources.
+    pub fn count<'a, R: Reservable + 'a>(items: impl IntoIterator<Item = &'a R>) -> Self {
+        let mut occupancy = Occupancy::default();
+        for item in items {
+            occupancy.add_seat(item.coach(), item.booking_reference().is_some());
+        }
+        occupancy
+    }
+
+    pub fn add_seat(&mut self, coach: &str, reserved: bool) {
+        let coach = self.coaches.entry(coach.to_string()).or_default();
+        coach.total += 1;
+        self.train.total += 1;
+        if reserved {
+            coach.reserved += 1;
+            self.train.reserved += 1;
+        }
+    }
+
+    /// Count places in a free-seating coach, which has no individual seats.
+    pub fn add_places(&mut self, coach: &str, capacity: usize, reserved: usize) {
+        let coach = self.coaches.entry(coach.to_string()).or_default();
+        coach.total += capacity;
+        coach.reserved += reserved;
+        self.train.total += capacity;
+        self.train.reserved += reserved;
+    }
+
+    pub fn set_standing(&mut self, capacity: usize, reserved: usize) {
+        self.standing = Some(Counts {
+            reserved,
+            total: capacity,
+        });
+    }
+
+    pub fn reserve_standing(&mut self, places: usize) {
+        if let Some(standing) = &mut self.standing {
+            standing.reserved += places;
+        }
+    }
+
+    pub fn release_standing(&mut self, places: usize) {
+        if let Some(standing) = &mut self.standing {
+            standing.reserved -= places;
+        }
+    }
+
+    pub fn set_fare(&mut self, fare: &str, total: usize, sold: usize) {
+        self.fares.insert(
+            fare.to_string(),
+            Counts {
+                reserved: sold,
+                total,
+            },
+        );
+    }
+
+    pub fn fares(&self) -> impl Iterator<Item = (&str, Counts)> {
+        self.fares
+            .iter()
+            .map(|(fare, counts)| (fare.as_str(), *counts))
+    }
+
+    pub fn standing(&self) -> Option<Counts> {
+        self.standing
+    }
+
+    pub fn reserve(&mut self, coach: &str) {
+        if let Some(coach) = self.coaches.get_mut(coach) {
+            coach.reserved += 1;
+            self.train.reserved += 1;
+        }
+    }
+
+    pub fn release(&mut self, coach: &str) {
+        if let Some(coach) = self.coaches.get_mut(coach) {
+            coach.reserved -= 1;
+            self.train.reserved -= 1;
+        }
+    }
+
+    /// Stop counting a free seat, for instance because it's blocked.
+    pub fn remove_seat(&mut self, coach: &str) {
+        if let Some(coach) = self.coaches.get_mut(coach) {
+            coach.total -= 1;
+            self.train.total -= 1;
+        }
+    }
+
+    pub fn reset(&mut self) {
+        for coach in self.coaches.values_mut() {
+            coach.reserved = 0;
+        }
+        self.train.reserved = 0;
+        if let Some(standing) = &mut self.standing {
+            standing.reserved = 0;
+        }
+        for fare in self.fares.values_mut() {
+            fare.reserved = 0;
+        }
+    }
+
+    /// Only the coaches that match, with the train totals counted over them.
+    pub fn filter_coaches(&self, keep: impl Fn(&str) -> bool) -> Occupancy {
+        let mut occupancy = Occupancy::default();
+        for (coach, counts) in self.coaches() {
+            if keep(coach) {
+                occupancy.add_places(coach, counts.total, counts.reserved);
+            }
+        }
+        occupancy
+    }
+
+    pub fn train(&self) -> Counts {
+        self.train
+    }
+
+    pub fn coaches(&self) -> impl Iterator<Item = (&str, Counts)> {
+        self.coaches
+            .iter()
+            .map(|(coach, counts)| (coach.as_str(), *counts))
+    }
+
+    #[cfg(test)]
+    pub fn coach(&self, coach: &str) -> Option<Counts> {
+        self.coaches.get(coach).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_seats() {
+        let mut occupancy = Occupancy::default();
+        occupancy.add_seat("A", false);
+        occupancy.add_seat("A", true);
+        occupancy.add_seat("B", false);
+        assert_eq!(
+            occupancy.train(),
+            Counts {
+                reserved: 1,
+                total: 3
+            }
+        );
+        assert_eq!(
+            occupancy.coach("A"),
+            Some(Counts {
+                reserved: 1,
+                total: 2
+            })
+        );
+        assert_eq!(occupancy.coach("B").unwrap().free(), 1);
+        assert_eq!(occupancy.coach("C"), None);
+    }
+
+    #[test]
+    fn test_reserve_and_reset() {
+        let mut occupancy = Occupancy::default();
+        occupancy.add_seat("A", false);
+        occupancy.add_seat("A", false);
+        occupancy.reserve("A");
+        assert_eq!(occupancy.train().ratio(), 0.5);
+        occupancy.reset();
+        assert_eq!(occupancy.train().reserved, 0);
+        assert_eq!(occupancy.coach("A").unwrap().reserved, 0);
+    }
+
+    #[test]
+    fn test_remove_seat() {
+        let mut occupancy = Occupancy::default();
+        occupancy.add_seat("A", false);
+        occupancy.add_seat("A", true);
+        occupancy.remove_seat("A");
+        assert_eq!(
+            occupancy.train(),
+            Counts {
+                reserved: 1,
+                total: 1
+            }
+        );
+        assert_eq!(occupancy.coach("A").unwrap().free(), 0);
+    }
+
+    #[test]
+    fn test_filter_coaches() {
+        let mut occupancy = Occupancy::default();
+        occupancy.add_seat("A", true);
+        occupancy.add_seat("B", false);
+        occupancy.add_seat("B", true);
+        occupancy.set_standing(10, 0);
+        let filtered = occupancy.filter_coaches(|coach| coach == "B");
+        assert_eq!(
+            filtered.train(),
+            Counts {
+                reserved: 1,
+                total: 2
+            }
+        );
+        assert_eq!(filtered.coach("A"), None);
+        assert_eq!(filtered.standing(), None);
+    }
+}
@@ -0,0 +1,60 @@
+//! Extending the service without changing it. When the crate is used as a
+//! library, plugins are registered on the state before [`crate::rest::serve`]
+//! builds the router:
+//!
+//! ```ignore
+//! let state = AppState::new().with_plugin(MyPlugin);
+//! serve(state, 8080).await;
+//! ```
+
+use std::sync::Arc;
+
+use axum::Router;
+
+use crate::rules::Rules;
+
+pub trait ServicePlugin: Send + Sync {
+    /// A short name for the plugin, used in logs.
+    fn name(&self) -> &'static str;
+
+    /// Routes to serve next to the built-in ones, with their own state. For
+    /// tenants and sandboxes they're served under their prefix too.
+    fn routes(&self) -> Router {
+        Router::new()
+    }
+
+    /// Add reservation rules, which are checked after the built-in ones. This
+    /// is called again whenever the policies change.
+    fn rules(&self, _rules: &mut Rules) {}
+}
+
+/// The registered plugins, in the order they were registered.
+#[derive(Clone, Default)]
+pub struct Plugins(Vec<Arc<dyn ServicePlugin>>);
+
+impl std::fmt::Debug for Plugins {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_list()
+            .entries(self.0.iter().map(|plugin| plugin.name()))
+            .finish()
+    }
+}
+
+impl Plugins {
+    pub fn add(&mut self, plugin: impl ServicePlugin + 'static) {
+        tracing::info!(plugin = plugin.name(), "plugin registered");
+        self.0.push(Arc::new(plugin));
+    }
+
+    pub fn routes(&self) -> Router {
+        self.0.iter().fold(Router::new(), |router, plugin| {
+            router.merge(plugin.routes())
+        })
+    }
+
+    pub fn add_rules(&self, rules: &mut Rules) {
+        for plugin in &self.0 {
+            plugin.rules(rules);
+        }
+    }
+}
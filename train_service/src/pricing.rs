@@ -0,0 +1,134 @@
+//! Working out the prices to quote from the fare buckets. The strategy is
+//! picked at startup, so pricing can follow how full a train is, or not.
+
+use std::str::FromStr;
+
+use crate::train::Train;
+
+pub trait PricingStrategy: Send + Sync {
+    /// The price to quote for a seat on a train, given the price of its
+    /// fare bucket. Prices are in cents.
+    fn price(&self, train: &Train, price: u64) -> u64;
+}
+
+/// Which pricing strategy to use.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Pricing {
+    /// Quote the prices of the fare buckets as they are.
+    Fixed,
+    /// Raise prices as trains fill up.
+    #[default]
+    Occupancy,
+}
+
+pub struct FixedPricing;
+
+impl PricingStrategy for FixedPricing {
+    fn price(&self, _train: &Train, price: u64) -> u64 {
+        price
+    }
+}
+
+/// Prices go up by a percentage once a train is above an occupancy.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Surcharge {
+    pub occupancy: f64,
+    pub percent: u64,
+}
+
+impl FromStr for Surcharge {
+    type Err = String;
+
+    /// Parse `OCCUPANCY:PERCENT`, like `0.8:25`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("expected OCCUPANCY:PERCENT, like 0.8:25, not {}", s);
+        let (occupancy, percent) = s.split_once(':').ok_or_else(invalid)?;
+        let occupancy: f64 = occupancy.parse().map_err(|_| invalid())?;
+        let percent = percent.parse().map_err(|_| invalid())?;
+        if !(0.0..=1.0).contains(&occupancy) {
+            return Err(format!(
+                "occupancy must be between 0 and 1, not {}",
+                occupancy
+            ));
+        }
+        Ok(Surcharge { occupancy, percent })
+    }
+}
+
+/// Raise prices by the surcharge for the highest occupancy a train is at.
+pub struct OccupancyPricing(Vec<Surcharge>);
+
+impl OccupancyPricing {
+    pub fn new(mut surcharges: Vec<Surcharge>) -> Self {
+        surcharges.sort_by(|a, b| a.occupancy.total_cmp(&b.occupancy));
+        OccupancyPricing(surcharges)
+    }
+
+    /// 10% more from half full, 25% more from 80% full.
+    pub fn default_surcharges() -> Vec<Surcharge> {
+        vec![
+            Surcharge {
+                occupancy: 0.5,
+                percent: 10,
+            },
+            Surcharge {
+                occupancy: 0.8,
+                percent: 25,
+            },
+        ]
+    }
+}
+
+impl Default for OccupancyPricing {
+    fn default() -> Self {
+        OccupancyPricing::new(OccupancyPricing::default_surcharges())
+    }
+}
+
+impl PricingStrategy for OccupancyPricing {
+    fn price(&self, train: &Train, price: u64) -> u64 {
+        let occupancy = train.occupancy().train().ratio();
+        let percent = self
+            .0
+            .iter()
+            .rev()
+            .find(|surcharge| occupancy >= surcharge.occupancy)
+            .map_or(0, |surcharge| surcharge.percent);
+        price * (100 + percent) / 100
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::TrainBuilder;
+
+    // a train with ten seats, of which `reserved` are reserved
+    fn train(reserved: usize) -> Train {
+        TrainBuilder::coaches(1)
+            .reserved(reserved as f64 / 10.0)
+            .build()
+    }
+
+    #[test]
+    fn test_occupancy_pricing() {
+        let pricing = OccupancyPricing::default();
+        assert_eq!(pricing.price(&train(4), 2000), 2000);
+        assert_eq!(pricing.price(&train(5), 2000), 2200);
+        assert_eq!(pricing.price(&train(9), 2000), 2500);
+        assert_eq!(FixedPricing.price(&train(9), 2000), 2000);
+    }
+
+    #[test]
+    fn test_parse_surcharge() {
+        assert_eq!(
+            "0.8:25".parse(),
+            Ok(Surcharge {
+                occupancy: 0.8,
+                percent: 25
+            })
+        );
+        assert!("0.8".parse::<Surcharge>().is_err());
+        assert!("1.5:10".parse::<Surcharge>().is_err());
+    }
+}
@@ -0,0 +1,227 @@
+//! Copies of trains for requests that only read them, so they don't have to
+//! wait for the lock on the whole state. Polling a train then doesn't queue
+//! up behind reservations.
+//!
+//! A copy is withdrawn as soon as its train may change, and made again the
+//! next time the train is read. It's out of date too once a hold on its train
+//! expires, even if nothing withdrew it yet. Readers that got hold of a copy
+//! before then keep it; they see the train as it was when they asked. Each
+//! copy is one version of its train, so its JSON only needs to be made once,
+//! however often the train is polled until it changes.
+//!
+//! Huge trains aren't kept as JSON: their seats are serialized a chunk at a
+//! time while the response is sent, so memory use stays flat however many
+//! of them are read at once.
+
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::io::Write;
+use std::sync::{Arc, Mutex, OnceLock};
+
+use axum::body::{Body, Bytes};
+
+use crate::lock::LockExt;
+use crate::response::{SeatResponse, TrainDetailsResponse, TrainResponse};
+use crate::train::{SeatId, Train, TrainId};
+
+// trains with more seats than this are streamed
+const STREAM_SEATS: usize = 10_000;
+// how many seats are serialized at a time when streaming
+const SEAT_CHUNK: usize = 1_000;
+
+#[derive(Debug, Default)]
+pub struct PublishedTrains {
+    // only held to look up or swap a copy, never while a train is changed
+    trains: Mutex<HashMap<TrainId, Arc<PublishedTrain>>>,
+}
+
+/// A train as it was when it was published.
+#[derive(Debug)]
+pub struct PublishedTrain {
+    train: Train,
+    // when the first hold in the copy expires
+    next_hold_expiry: Option<u64>,
+    json: OnceLock<Bytes>,
+    // the seat ids in the order they're streamed
+    seat_ids: OnceLock<Vec<SeatId>>,
+}
+
+impl PublishedTrain {
+    pub fn train(&self) -> &Train {
+        &self.train
+    }
+
+    /// Whether the copy still shows the train as it is at `now`, so no hold
+    /// in it has expired.
+    pub fn is_current(&self, now: u64) -> bool {
+        self.next_hold_expiry.is_none_or(|expires| now < expires)
+    }
+
+    /// The train as served by `GET /train/<train_id>`: streamed for huge
+    /// trains, and otherwise the JSON made the first time it's asked for.
+    pub fn body(self: Arc<Self>) -> Body {
+        if self.train.seats().count() > STREAM_SEATS {
+            Body::from_stream(futures_util::stream::iter(
+                self.json_chunks().map(Ok::<_, Infallible>),
+            ))
+        } else {
+            Body::from(self.json())
+        }
+    }
+
+    fn json(&self) -> Bytes {
+        self.json
+            .get_or_init(|| {
+                serde_json::to_vec(&TrainResponse::from(&self.train))
+                    .expect("trains serialize to JSON")
+                    .into()
+            })
+            .clone()
+    }
+
+    // the same JSON as `json`, made a few seats at a time as it's needed
+    fn json_chunks(self: Arc<Self>) -> impl Iterator<Item = Bytes> + Send + 'static {
+        let chunks = self.seat_ids().len().div_ceil(SEAT_CHUNK);
+        let train = self.clone();
+        std::iter::once(Bytes::from_static(b"{\"seats\":{"))
+            .chain((0..chunks).map(move |chunk| train.seats_chunk(chunk)))
+            .chain(std::iter::once_with(move || self.details()))
+    }
+
+    fn seat_ids(&self) -> &[SeatId] {
+        self.seat_ids.get_or_init(|| {
+            let mut seat_ids = self
+                .train
+                .seats()
+                .map(|(seat_id, _)| seat_id.clone())
+                .collect::<Vec<_>>();
+            seat_ids.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+            seat_ids
+        })
+    }
+
+    fn seats_chunk(&self, chunk: usize) -> Bytes {
+        let mut json = Vec::new();
+        let start = chunk * SEAT_CHUNK;
+        let seat_ids = &self.seat_ids()[start..(start + SEAT_CHUNK).min(self.seat_ids().len())];
+        for (i, seat_id) in seat_ids.iter().enumerate() {
+            if start + i > 0 {
+                json.push(b',');
+            }
+            let seat = self.train.get(seat_id).expect("published seats exist");
+            serde_json::to_writer(&mut json, seat_id.as_str()).expect("seat ids serialize to JSON");
+            json.push(b':');
+            serde_json::to_writer(&mut json, &SeatResponse::new(&self.train, seat))
+                .expect("seats serialize to JSON");
+        }
+        json.into()
+    }
+
+    // closes the seats, and adds the rest of the train
+    fn details(&self) -> Bytes {
+        let details = serde_json::to_vec(&TrainDetailsResponse::from(&self.train))
+            .expect("trains serialize to JSON");
+        let mut json = b"}".to_vec();
+        match details.strip_prefix(b"{") {
+            Some(b"}") | None => json.push(b'}'),
+            Some(fields) => {
+                json.push(b',');
+                json.write_all(fields)
+                    .expect("writing to a Vec doesn't fail");
+            }
+        }
+        json.into()
+    }
+}
+
+impl PublishedTrains {
+    pub fn get(&self, train_id: &TrainId) -> Option<Arc<PublishedTrain>> {
+        self.trains.lock_or_recover().get(train_id).cloned()
+    }
+
+    /// Copy a train for readers, replacing any older copy.
+    pub fn publish(&self, train_id: &TrainId, train: &Train) -> Arc<PublishedTrain> {
+        let train = Arc::new(PublishedTrain {
+            train: train.clone(),
+            next_hold_expiry: train.next_hold_expiry(),
+            json: OnceLock::new(),
+            seat_ids: OnceLock::new(),
+        });
+        self.trains
+            .lock_or_recover()
+            .insert(train_id.clone(), train.clone());
+        train
+    }
+
+    pub fn withdraw(&self, train_id: &TrainId) {
+        self.trains.lock_or_recover().remove(train_id);
+    }
+
+    pub fn withdraw_all(&self) {
+        self.trains.lock_or_recover().clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::TrainBuilder;
+
+    #[test]
+    fn test_publish() {
+        let published = PublishedTrains::default();
+        let train_id = TrainId::new("local_1000");
+        let train = TrainBuilder::coaches(1).seats_per_coach(2).build();
+        assert!(published.get(&train_id).is_none());
+
+        let copy = published.publish(&train_id, &train);
+        assert_eq!(*copy.train(), train);
+        assert!(Arc::ptr_eq(&published.get(&train_id).unwrap(), &copy));
+
+        published.withdraw(&train_id);
+        assert!(published.get(&train_id).is_none());
+        // readers keep the copy they have
+        assert_eq!(*copy.train(), train);
+    }
+
+    #[test]
+    fn test_json_is_made_once() {
+        let published = PublishedTrains::default();
+        let train_id = TrainId::new("local_1000");
+        let train = TrainBuilder::coaches(1)
+            .seats_per_coach(1)
+            .reserve("1A", "abc")
+            .build();
+        let copy = published.publish(&train_id, &train);
+        let json = copy.json();
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&json).unwrap(),
+            serde_json::to_value(TrainResponse::from(&train)).unwrap()
+        );
+        // the same bytes, not serialized again
+        assert_eq!(copy.json().as_ptr(), json.as_ptr());
+    }
+
+    #[test]
+    fn test_json_chunks() {
+        let published = PublishedTrains::default();
+        let train_id = TrainId::new("local_1000");
+        let trains = [
+            TrainBuilder::coaches(3)
+                .seats_per_coach(SEAT_CHUNK)
+                .reserve("12B", "abc")
+                .build(),
+            // more than seats
+            TrainBuilder::coaches(1)
+                .seats_per_coach(1)
+                .build()
+                .with_departure(Some(1798761600)),
+            Train::new(HashMap::new()),
+        ];
+        for train in trains {
+            let copy = published.publish(&train_id, &train);
+            let chunks = copy.clone().json_chunks().collect::<Vec<_>>();
+            assert_eq!(chunks.concat(), copy.json());
+        }
+    }
+}
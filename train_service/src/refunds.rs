@@ -0,0 +1,116 @@
+//! Refunds for cancelled bookings. How much is refunded depends on how close
+//! to departure the booking is cancelled, and close enough to departure
+//! bookings can't be cancelled at all.
+
+use std::collections::HashMap;
+
+use crate::booking_reference::BookingReference;
+use crate::train::{SeatId, TrainId};
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct RefundPolicy {
+    /// Cancelling at least this many hours before departure refunds
+    /// everything.
+    pub cutoff_hours: u64,
+    /// The percentage refunded when cancelling later than that.
+    pub late_percent: u64,
+    /// Bookings can't be cancelled once departure is this many minutes away.
+    pub cancellation_cutoff_minutes: Option<u64>,
+}
+
+impl Default for RefundPolicy {
+    fn default() -> Self {
+        RefundPolicy {
+            cutoff_hours: 24,
+            late_percent: 50,
+            cancellation_cutoff_minutes: None,
+        }
+    }
+}
+
+impl RefundPolicy {
+    /// Whether a booking on a train that departs at `departure` can still be
+    /// cancelled at `now`.
+    pub fn can_cancel(&self, departure: Option<u64>, now: u64) -> bool {
+        match (departure, self.cancellation_cutoff_minutes) {
+            (Some(departure), Some(cutoff)) => {
+                now.saturating_add(cutoff.saturating_mul(60)) <= departure
+            }
+            _ => true,
+        }
+    }
+
+    /// The percentage to refund when cancelling at `now`, for a train that
+    /// departs at `departure`; both in seconds since the Unix epoch. Trains
+    /// without a departure time are always refunded in full.
+    pub fn percent(&self, departure: Option<u64>, now: u64) -> u64 {
+        match departure {
+            Some(departure) if now + self.cutoff_hours * 3600 > departure => self.late_percent,
+            _ => 100,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Refund {
+    pub train_id: TrainId,
+    pub booking_reference: BookingReference,
+    pub seats: Vec<SeatId>,
+    /// Places without a seat number, in free-seating coaches or standing.
+    #[serde(default)]
+    pub places: usize,
+    /// The ids of resources, like bicycle places.
+    #[serde(default)]
+    pub resources: Vec<String>,
+    /// What was paid for the seats, in cents.
+    pub paid: u64,
+    pub percent: u64,
+    pub amount: u64,
+    /// When the booking was cancelled, in seconds since the Unix epoch.
+    pub cancelled_at: u64,
+}
+
+/// The refunds that were made, by booking reference.
+#[derive(Debug, Default)]
+pub struct Refunds(HashMap<BookingReference, Vec<Refund>>);
+
+impl Refunds {
+    pub fn add(&mut self, refund: Refund) {
+        self.0
+            .entry(refund.booking_reference.clone())
+            .or_default()
+            .push(refund);
+    }
+
+    pub fn get(&self, booking_reference: &BookingReference) -> &[Refund] {
+        self.0
+            .get(booking_reference)
+            .map_or(&[], |refunds| refunds.as_slice())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percent() {
+        let policy = RefundPolicy::default();
+        let departure = 100 * 3600;
+        assert_eq!(policy.percent(Some(departure), 76 * 3600), 100);
+        assert_eq!(policy.percent(Some(departure), 76 * 3600 + 1), 50);
+        assert_eq!(policy.percent(None, 76 * 3600 + 1), 100);
+    }
+
+    #[test]
+    fn test_can_cancel() {
+        let policy = RefundPolicy {
+            cancellation_cutoff_minutes: Some(30),
+            ..RefundPolicy::default()
+        };
+        assert!(policy.can_cancel(Some(3600), 1800));
+        assert!(!policy.can_cancel(Some(3600), 1801));
+        assert!(policy.can_cancel(None, 1801));
+        assert!(RefundPolicy::default().can_cancel(Some(3600), 7200));
+    }
+}
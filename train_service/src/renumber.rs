@@ -0,0 +1,140 @@
+//! Renaming seats, like when the seats of a coach are renumbered. Reservations
+//! move along with their seats.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::train::{Error, SeatId, Train};
+
+/// New seat ids, by old seat id. Seats that aren't in it keep their ids.
+#[derive(Debug, Default, PartialEq, Eq, Clone, serde::Deserialize)]
+pub struct SeatMapping(HashMap<SeatId, SeatId>);
+
+impl SeatMapping {
+    pub fn get(&self, seat_id: &SeatId) -> Option<&SeatId> {
+        self.0.get(seat_id)
+    }
+
+    /// Check that the mapping renames seats of the train, and that no two
+    /// seats end up with the same id.
+    pub fn validate(&self, train: &Train) -> Result<(), Error> {
+        let invalid = |message: String| Err(Error::InvalidSeatMapping(message));
+        let unknown = sorted(
+            self.0
+                .keys()
+                .filter(|&seat_id| train.get(seat_id).is_none()),
+        );
+        if !unknown.is_empty() {
+            return invalid(format!("seats {} do not exist", unknown.join(", ")));
+        }
+        let mut targets = HashSet::new();
+        let duplicates = sorted(self.0.values().filter(|&seat_id| !targets.insert(seat_id)));
+        if !duplicates.is_empty() {
+            return invalid(format!(
+                "seats {} are the new id of more than one seat",
+                duplicates.join(", ")
+            ));
+        }
+        // a seat can take the id of another seat only if that one is renamed too
+        let taken = sorted(
+            self.0
+                .values()
+                .filter(|&seat_id| train.get(seat_id).is_some() && !self.0.contains_key(seat_id)),
+        );
+        if !taken.is_empty() {
+            return invalid(format!("seats {} already exist", taken.join(", ")));
+        }
+        Ok(())
+    }
+}
+
+fn sorted<'a>(seat_ids: impl Iterator<Item = &'a SeatId>) -> Vec<&'a str> {
+    let mut seat_ids: Vec<&str> = seat_ids.map(SeatId::as_str).collect();
+    seat_ids.sort();
+    seat_ids
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::booking_reference::BookingReference;
+    use crate::fixtures::TrainBuilder;
+
+    fn train() -> Train {
+        TrainBuilder::coaches(1)
+            .seats_per_coach(2)
+            .reserve("1A", "abc")
+            .build()
+    }
+
+    fn mapping(pairs: &[(&str, &str)]) -> SeatMapping {
+        SeatMapping(
+            pairs
+                .iter()
+                .map(|(from, to)| (SeatId::new(from), SeatId::new(to)))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_validate() {
+        let train = train();
+        assert_eq!(mapping(&[("1A", "11A")]).validate(&train), Ok(()));
+        // swapping seats is fine
+        assert_eq!(
+            mapping(&[("1A", "2A"), ("2A", "1A")]).validate(&train),
+            Ok(())
+        );
+        assert_eq!(
+            mapping(&[("3A", "13A")]).validate(&train),
+            Err(Error::InvalidSeatMapping(
+                "seats 3A do not exist".to_string()
+            ))
+        );
+        assert_eq!(
+            mapping(&[("1A", "11A"), ("2A", "11A")]).validate(&train),
+            Err(Error::InvalidSeatMapping(
+                "seats 11A are the new id of more than one seat".to_string()
+            ))
+        );
+        assert_eq!(
+            mapping(&[("1A", "2A")]).validate(&train),
+            Err(Error::InvalidSeatMapping(
+                "seats 2A already exist".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_renumber_moves_reservations() {
+        let mut train = train();
+        train
+            .renumber(&mapping(&[("1A", "1B"), ("2A", "2B")]))
+            .unwrap();
+        let seat = train.get(&SeatId::new("1B")).unwrap();
+        assert_eq!((seat.seat_number(), seat.coach()), ("1", "B"));
+        assert_eq!(
+            seat.booking_reference(),
+            Some(&BookingReference::new("abc"))
+        );
+        assert!(train.get(&SeatId::new("1A")).is_none());
+        assert_eq!(train.booked_seats(&BookingReference::new("abc")), 1);
+        assert_eq!(train.occupancy().coach("B").unwrap().reserved, 1);
+    }
+
+    #[test]
+    fn test_renumber_moves_neighbours() {
+        let mut train: Train = serde_json::from_str(
+            r#"{"seats": {
+                "1A": {"seat_number": "1", "coach": "A", "neighbours": ["2A"]},
+                "2A": {"seat_number": "2", "coach": "A"}
+            }}"#,
+        )
+        .unwrap();
+        train.renumber(&mapping(&[("2A", "12A")])).unwrap();
+        let seat = train.get(&SeatId::new("1A")).unwrap();
+        assert_eq!(seat.neighbours(), [SeatId::new("12A")]);
+        assert!(train
+            .adjacency()
+            .are_adjacent(&SeatId::new("1A"), &SeatId::new("12A")));
+    }
+}
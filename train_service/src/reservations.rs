@@ -0,0 +1,214 @@
+//! Reservations as things of their own, with an id the service hands out,
+//! rather than only the booking references on seats. A reservation remembers
+//! its seats even after it's cancelled.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use crate::booking_reference::BookingReference;
+use crate::train::{self, SeatId, TrainId};
+
+#[derive(
+    Debug, PartialEq, Eq, Clone, Copy, PartialOrd, Ord, Hash, serde::Serialize, serde::Deserialize,
+)]
+#[serde(transparent)]
+pub struct ReservationId(u64);
+
+impl fmt::Display for ReservationId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReservationStatus {
+    Confirmed,
+    /// Cancelled by the passenger, or when the train was reset.
+    Cancelled,
+}
+
+/// A reservation of seats on a train, with times in seconds since the Unix
+/// epoch.
+#[derive(Debug, PartialEq, Eq, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Reservation {
+    pub id: ReservationId,
+    pub train_id: TrainId,
+    pub booking_reference: BookingReference,
+    pub seats: Vec<SeatId>,
+    pub status: ReservationStatus,
+    pub created_at: u64,
+    pub updated_at: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct Reservations {
+    last_id: u64,
+    reservations: BTreeMap<ReservationId, Reservation>,
+    // the reservations of each booking on each train
+    by_booking: HashMap<(TrainId, BookingReference), Vec<ReservationId>>,
+}
+
+impl Reservations {
+    /// Record seats that were just reserved, returning the new reservation's
+    /// id.
+    pub fn add(
+        &mut self,
+        train_id: &TrainId,
+        reservation: &train::Reservation,
+        now: u64,
+    ) -> ReservationId {
+        self.last_id += 1;
+        let id = ReservationId(self.last_id);
+        self.reservations.insert(
+            id,
+            Reservation {
+                id,
+                train_id: train_id.clone(),
+                booking_reference: reservation.booking_reference.clone(),
+                seats: reservation.seats.clone(),
+                status: ReservationStatus::Confirmed,
+                created_at: now,
+                updated_at: now,
+            },
+        );
+        self.by_booking
+            .entry((train_id.clone(), reservation.booking_reference.clone()))
+            .or_default()
+            .push(id);
+        id
+    }
+
+    pub fn get(&self, id: ReservationId) -> Option<&Reservation> {
+        self.reservations.get(&id)
+    }
+
+    // the confirmed reservations of a booking on a train
+    fn confirmed(
+        &mut self,
+        train_id: &TrainId,
+        booking_reference: &BookingReference,
+    ) -> impl Iterator<Item = &mut Reservation> {
+        let ids = self
+            .by_booking
+            .get(&(train_id.clone(), booking_reference.clone()))
+            .map_or(&[][..], Vec::as_slice);
+        self.reservations.values_mut().filter(move |reservation| {
+            ids.contains(&reservation.id) && reservation.status == ReservationStatus::Confirmed
+        })
+    }
+
+    /// The trains a booking has confirmed reservations on, by train id.
+    pub fn trains(&self, booking_reference: &BookingReference) -> Vec<TrainId> {
+        let mut train_ids = self
+            .by_booking
+            .iter()
+            .filter(|((_, reference), ids)| {
+                reference == booking_reference
+                    && ids
+                        .iter()
+                        .any(|id| self.reservations[id].status == ReservationStatus::Confirmed)
+            })
+            .map(|((train_id, _), _)| train_id.clone())
+            .collect::<Vec<_>>();
+        train_ids.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+        train_ids
+    }
+
+    /// A booking on a train was cancelled.
+    pub fn cancel(&mut self, train_id: &TrainId, booking_reference: &BookingReference, now: u64) {
+        for reservation in self.confirmed(train_id, booking_reference) {
+            reservation.status = ReservationStatus::Cancelled;
+            reservation.updated_at = now;
+        }
+    }
+
+    /// Seats of a booking moved, like in an upgrade.
+    pub fn moved(
+        &mut self,
+        train_id: &TrainId,
+        booking_reference: &BookingReference,
+        moves: &[(SeatId, SeatId)],
+        now: u64,
+    ) {
+        for reservation in self.confirmed(train_id, booking_reference) {
+            for seat_id in &mut reservation.seats {
+                if let Some((_, to)) = moves.iter().find(|(from, _)| from == seat_id) {
+                    *seat_id = to.clone();
+                    reservation.updated_at = now;
+                }
+            }
+        }
+    }
+
+    /// The seats of a reservation changed.
+    pub fn change_seats(&mut self, id: ReservationId, seats: Vec<SeatId>, now: u64) {
+        if let Some(reservation) = self.reservations.get_mut(&id) {
+            reservation.seats = seats;
+            reservation.updated_at = now;
+        }
+    }
+
+    /// All reservations on a train were cancelled, like when it's reset.
+    pub fn cancel_all(&mut self, train_id: &TrainId, now: u64) {
+        for reservation in self.reservations.values_mut().filter(|reservation| {
+            &reservation.train_id == train_id && reservation.status == ReservationStatus::Confirmed
+        }) {
+            reservation.status = ReservationStatus::Cancelled;
+            reservation.updated_at = now;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reservation(seats: &[&str], booking_reference: &str) -> train::Reservation {
+        train::Reservation {
+            seats: seats.iter().map(|seat_id| SeatId::new(seat_id)).collect(),
+            booking_reference: BookingReference::new(booking_reference),
+            accessible: false,
+        }
+    }
+
+    #[test]
+    fn test_reservations() {
+        let mut reservations = Reservations::default();
+        let train_id = TrainId::new("t");
+        let abc = BookingReference::new("abc");
+        let first = reservations.add(&train_id, &reservation(&["1A"], "abc"), 10);
+        let second = reservations.add(&train_id, &reservation(&["2A", "3A"], "abc"), 20);
+        let other = reservations.add(&train_id, &reservation(&["4A"], "def"), 20);
+        assert_ne!(first, second);
+        assert_eq!(reservations.get(first).unwrap().created_at, 10);
+
+        reservations.moved(
+            &train_id,
+            &abc,
+            &[(SeatId::new("2A"), SeatId::new("1F"))],
+            30,
+        );
+        let moved = reservations.get(second).unwrap();
+        assert_eq!(moved.seats, vec![SeatId::new("1F"), SeatId::new("3A")]);
+        assert_eq!(moved.updated_at, 30);
+        assert_eq!(reservations.get(first).unwrap().updated_at, 10);
+
+        assert_eq!(reservations.trains(&abc), [TrainId::new("t")]);
+        reservations.cancel(&train_id, &abc, 40);
+        assert!(reservations.trains(&abc).is_empty());
+        assert_eq!(
+            reservations.get(first).unwrap().status,
+            ReservationStatus::Cancelled
+        );
+        assert_eq!(
+            reservations.get(other).unwrap().status,
+            ReservationStatus::Confirmed
+        );
+        reservations.cancel_all(&train_id, 50);
+        assert_eq!(reservations.get(other).unwrap().updated_at, 50);
+        // cancelled reservations stay as they were
+        assert_eq!(reservations.get(first).unwrap().updated_at, 40);
+        assert!(reservations.get(ReservationId(99)).is_none());
+    }
+}
@@ -0,0 +1,130 @@
+//! Things besides seats that passengers can reserve, like a place for a
+//! bicycle or room on a luggage rack. Like seats, these belong to a coach and
+//! are held by a booking reference.
+
+use std::fmt::Display;
+
+use crate::booking_reference::BookingReference;
+use crate::train::Seat;
+
+/// Something in a coach that can be reserved with a booking reference.
+pub trait Reservable {
+    fn coach(&self) -> &str;
+
+    fn booking_reference(&self) -> Option<&BookingReference>;
+
+    /// Whether it can be reserved.
+    fn is_free(&self) -> bool {
+        self.booking_reference().is_none()
+    }
+}
+
+impl Reservable for Seat {
+    fn coach(&self) -> &str {
+        Seat::coach(self)
+    }
+
+    fn booking_reference(&self) -> Option<&BookingReference> {
+        Seat::booking_reference(self)
+    }
+
+    fn is_free(&self) -> bool {
+        Seat::is_free(self)
+    }
+}
+
+#[derive(
+    Debug,
+    PartialEq,
+    Eq,
+    Clone,
+    Copy,
+    Hash,
+    serde::Serialize,
+    serde::Deserialize,
+    schemars::JsonSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum ResourceKind {
+    Bicycle,
+    Luggage,
+}
+
+impl ResourceKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ResourceKind::Bicycle => "bicycle",
+            ResourceKind::Luggage => "luggage",
+        }
+    }
+}
+
+impl Display for ResourceKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Resource {
+    kind: ResourceKind,
+    coach: String,
+    #[serde(default)]
+    booking_reference: Option<BookingReference>,
+}
+
+impl Resource {
+    #[cfg(test)]
+    pub fn new(kind: ResourceKind, coach: String) -> Self {
+        Resource {
+            kind,
+            coach,
+            booking_reference: None,
+        }
+    }
+
+    pub fn kind(&self) -> ResourceKind {
+        self.kind
+    }
+
+    pub fn set_booking_reference(&mut self, booking_reference: Option<BookingReference>) {
+        self.booking_reference = booking_reference;
+    }
+}
+
+impl Reservable for Resource {
+    fn coach(&self) -> &str {
+        &self.coach
+    }
+
+    fn booking_reference(&self) -> Option<&BookingReference> {
+        self.booking_reference.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deserialize() {
+        let resource: Resource =
+            serde_json::from_str(r#"{"kind": "bicycle", "coach": "A"}"#).unwrap();
+        assert_eq!(
+            resource,
+            Resource::new(ResourceKind::Bicycle, "A".to_string())
+        );
+        assert!(resource.is_free());
+        assert!(serde_json::from_str::<Resource>(r#"{"kind": "piano", "coach": "A"}"#).is_err());
+    }
+
+    #[test]
+    fn test_seat_is_reservable() {
+        let seat = Seat::new("1".to_string(), "A".to_string())
+            .with_booking_reference(Some(BookingReference::new("abc")));
+        let reservable: &dyn Reservable = &seat;
+        assert_eq!(reservable.coach(), "A");
+        assert!(!reservable.is_free());
+    }
+}
@@ -0,0 +1,767 @@
+//! The JSON documents the service sends back.
+//!
+//! These are kept separate from the domain types in `train` so that the wire
+//! format stays stable when the domain model changes. They borrow from the
+//! domain so that building a response doesn't copy the train.
+
+use std::collections::BTreeMap;
+
+use serde::ser::SerializeMap;
+
+use crate::allocate::PreferencesMet;
+use crate::booking_reference::BookingReference;
+use crate::fares::{FareBucket, SeatFare};
+use crate::fields::{Field, Fields};
+use crate::forecast::Projection;
+use crate::history::SeatEvent;
+use crate::layout::{Layout, Place};
+use crate::metrics::OccupancySample;
+use crate::occupancy::{Counts, Occupancy};
+use crate::pseudonyms::Pseudonyms;
+use crate::refunds::Refund;
+use crate::reservations::ReservationId;
+use crate::resource::{Reservable, Resource, ResourceKind};
+use crate::train::{Capacity, Class, Coach, MissingSeats, Seat, SeatId, SeatPosition, Train};
+
+/// A train, with its seats by seat id.
+#[derive(Debug, serde::Serialize, schemars::JsonSchema)]
+pub struct TrainResponse<'a> {
+    #[schemars(with = "BTreeMap<SeatId, SeatResponse<'a>>")]
+    seats: BTreeMap<&'a str, SeatResponse<'a>>,
+    #[serde(flatten)]
+    details: TrainDetailsResponse<'a>,
+}
+
+/// Everything about a train except its seats.
+#[derive(Debug, serde::Serialize, schemars::JsonSchema)]
+pub struct TrainDetailsResponse<'a> {
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    coaches: BTreeMap<&'a str, CoachResponse>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    standing: Option<CapacityResponse>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    resources: BTreeMap<&'a str, ResourceResponse<'a>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    fares: Vec<FareResponse<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    departure: Option<u64>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    archived: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    bookings: Vec<TrainBookingResponse<'a>>,
+}
+
+/// What a booking holds on a train besides its seats and resources, and what
+/// it paid for its seats, in cents.
+#[derive(Debug, serde::Serialize, schemars::JsonSchema)]
+pub struct TrainBookingResponse<'a> {
+    #[schemars(with = "String")]
+    booking_reference: ReferenceResponse<'a>,
+    /// Places without a seat number, by free-seating coach.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    places: BTreeMap<&'a str, usize>,
+    #[serde(skip_serializing_if = "is_zero")]
+    standing: usize,
+    #[serde(skip_serializing_if = "is_zero")]
+    paid: u64,
+}
+
+fn is_zero<T: Default + PartialEq>(value: &T) -> bool {
+    *value == T::default()
+}
+
+/// The ids of trains that were generated.
+#[cfg(feature = "fixtures")]
+#[derive(Debug, serde::Serialize)]
+pub struct GeneratedResponse {
+    pub trains: Vec<String>,
+}
+
+/// A new sandbox session, and where its routes are.
+#[derive(Debug, serde::Serialize)]
+pub struct SandboxResponse {
+    pub session: String,
+    pub prefix: String,
+    pub expires_after_seconds: u64,
+}
+
+/// Several trains at once, and the ids of those that don't exist or are
+/// archived.
+#[derive(Debug, serde::Serialize)]
+pub struct TrainsResponse<'a> {
+    pub trains: BTreeMap<&'a str, serde_json::Value>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub missing: Vec<&'a str>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub archived: Vec<&'a str>,
+}
+
+#[derive(Debug, serde::Serialize, schemars::JsonSchema)]
+pub struct FareResponse<'a> {
+    name: &'a str,
+    price: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seats_per_coach: Option<usize>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    sold: &'a BTreeMap<String, usize>,
+}
+
+/// Seats held for a booking, and until when.
+#[derive(Debug, serde::Serialize)]
+pub struct HoldResponse<'a> {
+    pub train_id: &'a str,
+    pub booking_reference: &'a str,
+    pub seats: Vec<&'a str>,
+    pub expires: u64,
+}
+
+/// Where the seats are in the coaches that have a layout, so they can be
+/// drawn.
+#[derive(Debug, serde::Serialize)]
+pub struct LayoutResponse<'a> {
+    pub train_id: &'a str,
+    pub coaches: BTreeMap<&'a str, CoachLayoutResponse<'a>>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct CoachLayoutResponse<'a> {
+    #[serde(flatten)]
+    layout: Layout,
+    seats: BTreeMap<&'a str, SeatPlaceResponse>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct SeatPlaceResponse {
+    #[serde(flatten)]
+    place: Place,
+    free: bool,
+}
+
+impl<'a> LayoutResponse<'a> {
+    pub fn new(train_id: &'a str, train: &'a Train) -> Self {
+        let mut coaches: BTreeMap<&str, CoachLayoutResponse> = train
+            .coaches()
+            .filter_map(|(id, coach)| {
+                coach.layout.map(|layout| {
+                    (
+                        id.as_str(),
+                        CoachLayoutResponse {
+                            layout,
+                            seats: BTreeMap::new(),
+                        },
+                    )
+                })
+            })
+            .collect();
+        for (seat_id, seat) in train.seats() {
+            if let Some(coach) = coaches.get_mut(seat.coach()) {
+                // seats that don't fit are refused when the train is loaded
+                if let Some(place) = coach.layout.place(seat.seat_number()) {
+                    let free = train.is_available(seat);
+                    coach
+                        .seats
+                        .insert(seat_id.as_str(), SeatPlaceResponse { place, free });
+                }
+            }
+        }
+        LayoutResponse { train_id, coaches }
+    }
+}
+
+/// The free seats closest to a seat, nearest first.
+#[derive(Debug, serde::Serialize)]
+pub struct NearestResponse<'a> {
+    pub train_id: &'a str,
+    pub seat_id: &'a str,
+    pub seats: Vec<NearSeatResponse<'a>>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct NearSeatResponse<'a> {
+    pub seat_id: &'a str,
+    // in seats; a coach further along counts as 100
+    pub distance: usize,
+}
+
+/// The latest reservations of a seat, oldest first.
+#[derive(Debug, serde::Serialize)]
+pub struct SeatHistoryResponse<'a> {
+    pub train_id: &'a str,
+    pub seat_id: &'a str,
+    pub history: Vec<SeatEvent>,
+}
+
+/// What was freed by cancelling a booking on all of its trains.
+#[derive(Debug, serde::Serialize)]
+pub struct BookingCancelledResponse<'a> {
+    pub booking_reference: &'a str,
+    /// The number of seats freed on all trains together.
+    pub seats: usize,
+    /// The places without a seat number freed on all trains together.
+    pub places: usize,
+    /// The number of resources, like bicycle places, freed on all trains
+    /// together.
+    pub resources: usize,
+    pub refunds: Vec<Refund>,
+}
+
+/// The occupancy of a train each time it changed, oldest first.
+#[derive(Debug, serde::Serialize)]
+pub struct OccupancyHistoryResponse<'a> {
+    pub train_id: &'a str,
+    pub samples: Vec<OccupancySample>,
+}
+
+/// When a train is expected to be 70% and completely full.
+#[derive(Debug, serde::Serialize)]
+pub struct ForecastResponse<'a> {
+    pub train_id: &'a str,
+    pub seventy_percent: Projection,
+    pub full: Projection,
+}
+
+/// A booking reference, or its pseudonym when data is anonymized.
+#[derive(Debug, Clone, Copy)]
+pub struct ReferenceResponse<'a> {
+    booking_reference: &'a str,
+    pseudonyms: Option<&'a Pseudonyms>,
+}
+
+impl<'a> From<&'a BookingReference> for ReferenceResponse<'a> {
+    fn from(booking_reference: &'a BookingReference) -> Self {
+        ReferenceResponse {
+            booking_reference: booking_reference.as_str(),
+            pseudonyms: None,
+        }
+    }
+}
+
+impl serde::Serialize for ReferenceResponse<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.pseudonyms {
+            Some(pseudonyms) => serializer.serialize_str(&pseudonyms.of(self.booking_reference)),
+            None => serializer.serialize_str(self.booking_reference),
+        }
+    }
+}
+
+// the schema attributes describe what the `Serialize` implementation below
+// leaves out
+#[derive(Debug, schemars::JsonSchema)]
+pub struct SeatResponse<'a> {
+    seat_number: &'a str,
+    coach: &'a str,
+    #[schemars(with = "Option<String>")]
+    booking_reference: Option<ReferenceResponse<'a>>,
+    // when a seat is only held for the booking, until when
+    #[schemars(skip_serializing_if = "Option::is_none")]
+    held_until: Option<u64>,
+    #[schemars(skip_serializing_if = "Option::is_none")]
+    position: Option<SeatPosition>,
+    // whether the seat is in a quiet coach
+    #[schemars(skip_serializing_if = "std::ops::Not::not")]
+    quiet: bool,
+    #[schemars(skip_serializing_if = "std::ops::Not::not")]
+    accessible: bool,
+    #[schemars(skip_serializing_if = "Option::is_none")]
+    blocked: Option<&'a str>,
+    #[schemars(skip_serializing_if = "<[SeatId]>::is_empty")]
+    neighbours: &'a [SeatId],
+    // the fields the client asked for, if it asked
+    #[schemars(skip)]
+    fields: Option<&'a Fields>,
+}
+
+impl<'a> SeatResponse<'a> {
+    /// A seat of a train, which knows whether its coach is quiet.
+    pub fn new(train: &Train, seat: &'a Seat) -> Self {
+        SeatResponse {
+            quiet: train.is_quiet(seat.coach()),
+            ..seat.into()
+        }
+    }
+
+    /// Only send these fields of the seat, if given.
+    pub fn only(self, fields: Option<&'a Fields>) -> Self {
+        SeatResponse { fields, ..self }
+    }
+
+    fn anonymized(&mut self, pseudonyms: &'a Pseudonyms) {
+        if let Some(reference) = &mut self.booking_reference {
+            reference.pseudonyms = Some(pseudonyms);
+        }
+    }
+}
+
+// fields that weren't asked for are left out here, so they're never
+// serialized at all
+impl serde::Serialize for SeatResponse<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let sends = |field| self.fields.is_none_or(|fields| fields.contains(field));
+        let mut map = serializer.serialize_map(None)?;
+        if sends(Field::SeatNumber) {
+            map.serialize_entry("seat_number", self.seat_number)?;
+        }
+        if sends(Field::Coach) {
+            map.serialize_entry("coach", self.coach)?;
+        }
+        if sends(Field::BookingReference) {
+            map.serialize_entry("booking_reference", &self.booking_reference)?;
+        }
+        if let Some(held_until) = self.held_until.filter(|_| sends(Field::HeldUntil)) {
+            map.serialize_entry("held_until", &held_until)?;
+        }
+        if let Some(position) = self.position.filter(|_| sends(Field::Position)) {
+            map.serialize_entry("position", &position)?;
+        }
+        if self.quiet && sends(Field::Quiet) {
+            map.serialize_entry("quiet", &true)?;
+        }
+        if self.accessible && sends(Field::Accessible) {
+            map.serialize_entry("accessible", &true)?;
+        }
+        if let Some(blocked) = self.blocked.filter(|_| sends(Field::Blocked)) {
+            map.serialize_entry("blocked", blocked)?;
+        }
+        if !self.neighbours.is_empty() && sends(Field::Neighbours) {
+            map.serialize_entry("neighbours", self.neighbours)?;
+        }
+        map.end()
+    }
+}
+
+#[derive(Debug, serde::Serialize, schemars::JsonSchema)]
+pub struct ResourceResponse<'a> {
+    kind: ResourceKind,
+    coach: &'a str,
+    #[schemars(with = "Option<String>")]
+    booking_reference: Option<ReferenceResponse<'a>>,
+}
+
+#[derive(Debug, serde::Serialize, schemars::JsonSchema)]
+pub struct CoachResponse {
+    quiet: bool,
+    closed: bool,
+    #[serde(skip_serializing_if = "Class::is_second")]
+    class: Class,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    free_seating: Option<CapacityResponse>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    layout: Option<Layout>,
+}
+
+#[derive(Debug, serde::Serialize, schemars::JsonSchema)]
+pub struct CapacityResponse {
+    capacity: usize,
+    reserved: usize,
+}
+
+/// The confirmation of places reserved in a free-seating coach, or of
+/// standing room when there's no coach.
+#[derive(Debug, serde::Serialize)]
+pub struct PlacesResponse<'a> {
+    pub train_id: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub coach: Option<&'a str>,
+    pub booking_reference: &'a str,
+    pub places: usize,
+    pub remaining: usize,
+}
+
+/// The resources, such as bicycle places, that were picked for a booking.
+#[derive(Debug, serde::Serialize)]
+pub struct ResourceBookingResponse<'a> {
+    pub train_id: &'a str,
+    pub booking_reference: &'a str,
+    pub kind: ResourceKind,
+    pub resources: Vec<&'a str>,
+}
+
+/// A trains file of the current version. The version comes first, so the
+/// trains can be read one at a time when the file is loaded.
+#[derive(Debug, serde::Serialize)]
+pub struct TrainsFileResponse<'a> {
+    pub version: u64,
+    pub trains: BTreeMap<&'a str, TrainResponse<'a>>,
+}
+
+/// The time of a simulated clock, in seconds since the Unix epoch.
+#[derive(Debug, serde::Serialize)]
+pub struct TimeResponse {
+    pub now: u64,
+}
+
+/// An error with a code that clients can match on.
+#[derive(Debug, serde::Serialize)]
+pub struct ErrorResponse {
+    pub error: &'static str,
+    pub message: String,
+}
+
+/// The seats a booking moved from, and the ones it moved to.
+#[derive(Debug, serde::Serialize)]
+pub struct UpgradeResponse<'a> {
+    pub train_id: &'a str,
+    pub booking_reference: &'a str,
+    pub seats: BTreeMap<&'a str, &'a str>,
+}
+
+/// Why an upgrade couldn't be made: the first-class seats that are missing.
+#[derive(Debug, serde::Serialize)]
+pub struct UpgradeErrorResponse<'a> {
+    pub error: &'static str,
+    pub message: String,
+    pub missing: &'a [MissingSeats],
+}
+
+/// Seats that are taken, with free seats in the same coaches to try instead.
+#[derive(Debug, serde::Serialize)]
+pub struct ConflictResponse<'a> {
+    pub error: &'static str,
+    pub message: String,
+    pub alternatives: Vec<&'a str>,
+}
+
+/// What seats would cost, and from which fare buckets.
+#[derive(Debug, serde::Serialize)]
+pub struct QuoteResponse<'a> {
+    pub train_id: &'a str,
+    pub seats: Vec<SeatFareResponse<'a>>,
+    /// What a voucher takes off, in cents.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub discount: Option<u64>,
+    pub total: u64,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct SeatFareResponse<'a> {
+    seat_id: &'a str,
+    fare: &'a str,
+    price: u64,
+}
+
+impl<'a> From<&'a SeatFare> for SeatFareResponse<'a> {
+    fn from(seat_fare: &'a SeatFare) -> Self {
+        SeatFareResponse {
+            seat_id: seat_fare.seat_id.as_str(),
+            fare: &seat_fare.fare,
+            price: seat_fare.price,
+        }
+    }
+}
+
+/// The seats that were picked for a reservation.
+#[derive(Debug, serde::Serialize)]
+pub struct BookingResponse<'a> {
+    pub train_id: &'a str,
+    pub booking_reference: &'a str,
+    pub seats: Vec<&'a str>,
+    pub preferences_met: &'a PreferencesMet,
+    // how far apart the seats are; see `Allocation::spread`
+    pub spread: usize,
+    pub reservation_id: ReservationId,
+    /// What the voucher took off, in cents, when one was given.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub discount: Option<u64>,
+}
+
+/// How one reservation of a batch went.
+#[derive(Debug, serde::Serialize)]
+pub struct BatchItemResponse<'a> {
+    pub train_id: &'a str,
+    pub booking_reference: &'a str,
+    pub status: BatchStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reservation_id: Option<ReservationId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchStatus {
+    Reserved,
+    Conflict,
+    NotFound,
+    Rejected,
+}
+
+#[derive(Debug, serde::Serialize, schemars::JsonSchema)]
+pub struct AvailabilityResponse<'a> {
+    reserved: usize,
+    total: usize,
+    coaches: BTreeMap<&'a str, CountsResponse>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    standing: Option<CountsResponse>,
+}
+
+#[derive(Debug, serde::Serialize, schemars::JsonSchema)]
+pub struct CountsResponse {
+    reserved: usize,
+    total: usize,
+}
+
+impl<'a> From<&'a Train> for TrainResponse<'a> {
+    fn from(train: &'a Train) -> Self {
+        TrainResponse::filtered(train, |_, _| true)
+    }
+}
+
+impl<'a> TrainResponse<'a> {
+    /// A train with only the seats that match.
+    pub fn filtered(train: &'a Train, matches: impl Fn(&Train, &Seat) -> bool) -> Self {
+        TrainResponse {
+            seats: train
+                .seats()
+                .filter(|(_, seat)| matches(train, seat))
+                .map(|(seat_id, seat)| (seat_id.as_str(), SeatResponse::new(train, seat)))
+                .collect(),
+            details: train.into(),
+        }
+    }
+
+    /// Only send these fields of the seats, if given.
+    pub fn only(mut self, fields: Option<&'a Fields>) -> Self {
+        for seat in self.seats.values_mut() {
+            seat.fields = fields;
+        }
+        self
+    }
+
+    /// Send pseudonyms instead of booking references, if given.
+    pub fn anonymized(mut self, pseudonyms: Option<&'a Pseudonyms>) -> Self {
+        let Some(pseudonyms) = pseudonyms else {
+            return self;
+        };
+        for seat in self.seats.values_mut() {
+            seat.anonymized(pseudonyms);
+        }
+        for resource in self.details.resources.values_mut() {
+            if let Some(reference) = &mut resource.booking_reference {
+                reference.pseudonyms = Some(pseudonyms);
+            }
+        }
+        for booking in &mut self.details.bookings {
+            booking.booking_reference.pseudonyms = Some(pseudonyms);
+        }
+        self
+    }
+}
+
+impl<'a> From<&'a Train> for TrainDetailsResponse<'a> {
+    fn from(train: &'a Train) -> Self {
+        TrainDetailsResponse {
+            coaches: train
+                .coaches()
+                .map(|(coach, data)| (coach.as_str(), data.into()))
+                .collect(),
+            standing: train.standing().map(|&standing| standing.into()),
+            resources: train
+                .resources()
+                .map(|(id, resource)| (id.as_str(), resource.into()))
+                .collect(),
+            fares: train.fares().buckets().map(FareResponse::from).collect(),
+            departure: train.departure(),
+            archived: train.is_archived(),
+            bookings: bookings(train),
+        }
+    }
+}
+
+// the bookings with places or a payment, by booking reference
+fn bookings(train: &Train) -> Vec<TrainBookingResponse<'_>> {
+    fn booking<'a, 'b>(
+        bookings: &'b mut BTreeMap<&'a str, TrainBookingResponse<'a>>,
+        booking_reference: &'a BookingReference,
+    ) -> &'b mut TrainBookingResponse<'a> {
+        bookings
+            .entry(booking_reference.as_str())
+            .or_insert_with(|| TrainBookingResponse {
+                booking_reference: booking_reference.into(),
+                places: BTreeMap::new(),
+                standing: 0,
+                paid: 0,
+            })
+    }
+    let mut bookings = BTreeMap::new();
+    for (booking_reference, places) in train.places_by_booking() {
+        let booking = booking(&mut bookings, booking_reference);
+        booking.places = places
+            .coaches
+            .iter()
+            .map(|(coach, places)| (coach.as_str(), *places))
+            .collect();
+        booking.standing = places.standing;
+    }
+    for (booking_reference, paid) in train.paid_by_booking() {
+        booking(&mut bookings, booking_reference).paid = paid;
+    }
+    bookings.into_values().collect()
+}
+
+impl<'a> From<&'a FareBucket> for FareResponse<'a> {
+    fn from(bucket: &'a FareBucket) -> Self {
+        FareResponse {
+            name: &bucket.name,
+            price: bucket.price,
+            seats_per_coach: bucket.seats_per_coach,
+            sold: &bucket.sold,
+        }
+    }
+}
+
+impl<'a> From<&'a Resource> for ResourceResponse<'a> {
+    fn from(resource: &'a Resource) -> Self {
+        ResourceResponse {
+            kind: resource.kind(),
+            coach: resource.coach(),
+            booking_reference: resource.booking_reference().map(ReferenceResponse::from),
+        }
+    }
+}
+
+impl From<&Coach> for CoachResponse {
+    fn from(coach: &Coach) -> Self {
+        CoachResponse {
+            quiet: coach.quiet,
+            closed: coach.closed,
+            class: coach.class,
+            free_seating: coach.free_seating.map(CapacityResponse::from),
+            layout: coach.layout,
+        }
+    }
+}
+
+impl From<Capacity> for CapacityResponse {
+    fn from(capacity: Capacity) -> Self {
+        CapacityResponse {
+            capacity: capacity.capacity,
+            reserved: capacity.reserved,
+        }
+    }
+}
+
+impl<'a> From<&'a Seat> for SeatResponse<'a> {
+    fn from(seat: &'a Seat) -> Self {
+        SeatResponse {
+            seat_number: seat.seat_number(),
+            coach: seat.coach(),
+            booking_reference: seat.booking_reference().map(ReferenceResponse::from),
+            held_until: seat.held_until(),
+            position: seat.position(),
+            quiet: false,
+            accessible: seat.is_accessible(),
+            blocked: seat.blocked(),
+            neighbours: seat.neighbours(),
+            fields: None,
+        }
+    }
+}
+
+impl<'a> From<&'a Occupancy> for AvailabilityResponse<'a> {
+    fn from(occupancy: &'a Occupancy) -> Self {
+        let train = occupancy.train();
+        AvailabilityResponse {
+            reserved: train.reserved,
+            total: train.total,
+            coaches: occupancy
+                .coaches()
+                .map(|(coach, counts)| (coach, counts.into()))
+                .collect(),
+            standing: occupancy.standing().map(CountsResponse::from),
+        }
+    }
+}
+
+impl From<Counts> for CountsResponse {
+    fn from(counts: Counts) -> Self {
+        CountsResponse {
+            reserved: counts.reserved,
+            total: counts.total,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    // these tests pin the wire format; if they fail, clients break
+
+    fn train() -> Train {
+        serde_json::from_value(json!({
+            "seats": {
+                "1A": { "seat_number": "1", "coach": "A", "booking_reference": "abc" },
+                "2A": { "seat_number": "2", "coach": "A", "booking_reference": null },
+                "1B": { "seat_number": "1", "coach": "B", "booking_reference": null },
+            }
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_train_response_format() {
+        let train = train();
+        let value = serde_json::to_value(TrainResponse::from(&train)).unwrap();
+        assert_eq!(
+            value,
+            json!({
+                "seats": {
+                    "1A": { "seat_number": "1", "coach": "A", "booking_reference": "abc" },
+                    "2A": { "seat_number": "2", "coach": "A", "booking_reference": null },
+                    "1B": { "seat_number": "1", "coach": "B", "booking_reference": null },
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn test_train_response_format_optional_fields() {
+        let train: Train = serde_json::from_value(json!({
+            "seats": {
+                "1A": { "seat_number": "1", "coach": "A", "booking_reference": null, "position": "window", "accessible": true, "blocked": "crew" },
+            },
+            "coaches": {
+                "A": { "quiet": true },
+                "F": { "free_seating": { "capacity": 40, "reserved": 3 } },
+            },
+        }))
+        .unwrap();
+        let value = serde_json::to_value(TrainResponse::from(&train)).unwrap();
+        assert_eq!(
+            value,
+            json!({
+                "seats": {
+                    "1A": { "seat_number": "1", "coach": "A", "booking_reference": null, "position": "window", "quiet": true, "accessible": true, "blocked": "crew" },
+                },
+                "coaches": {
+                    "A": { "quiet": true, "closed": false },
+                    "F": {
+                        "quiet": false,
+                        "closed": false,
+                        "free_seating": { "capacity": 40, "reserved": 3 },
+                    },
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn test_availability_response_format() {
+        let train = train();
+        let value = serde_json::to_value(AvailabilityResponse::from(train.occupancy())).unwrap();
+        assert_eq!(
+            value,
+            json!({
+                "reserved": 1,
+                "total": 3,
+                "coaches": {
+                    "A": { "reserved": 1, "total": 2 },
+                    "B": { "reserved": 0, "total": 1 },
+                }
+            })
+        );
+    }
+}
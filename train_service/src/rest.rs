@@ -1,17 +1,124 @@
-use std::borrow::{Borrow, BorrowMut};
+use std::borrow::BorrowMut;
+use std::collections::{BTreeMap, HashSet};
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use axum::extract;
-use axum::http::StatusCode;
+use axum::http::{header, HeaderMap, StatusCode};
 use axum::response::{IntoResponse, Response};
-use axum::routing::{get, post};
+use axum::routing::{any, get, patch, post, put};
+#[cfg(feature = "fixtures")]
+use rand::rngs::StdRng;
+#[cfg(feature = "fixtures")]
+use rand::SeedableRng;
+use tower::ServiceExt;
 
-use crate::booking_reference::BookingReferenceService;
-use crate::train::{Error, Reservation, SeatId, TrainDataService, TrainId};
+use crate::alerts::NearFullAlert;
+use crate::allocate::{self, Preferences, Strategy};
+use crate::api_keys::{Allowance, ApiKey, ApiKeys, Usage};
+use crate::booking_reference::{BookingReference, BookingReferenceService};
+use crate::clock::{Clock, SimulatedClock, SystemClock};
+use crate::deprecations::Deprecations;
+use crate::extract::Json;
+use crate::fields::Fields;
+use crate::filter::SeatFilter;
+use crate::flags::{Flag, Flags};
+use crate::forecast;
+#[cfg(feature = "fixtures")]
+use crate::generate::Scenario;
+use crate::history::SeatHistory;
+use crate::http_client::HttpClient;
+use crate::json_api::{self, Format};
+use crate::listen::{self, Listen};
+use crate::lock::LockExt;
+use crate::metrics::Metrics;
+use crate::middleware::{self, AccessLogFormat};
+use crate::notify::{Event, LogNotifier, Notification, Notifier};
+use crate::plugin::{Plugins, ServicePlugin};
+use crate::pricing::{OccupancyPricing, PricingStrategy};
+use crate::pseudonyms::Pseudonyms;
+use crate::published::PublishedTrains;
+use crate::refunds::{Refund, RefundPolicy, Refunds};
+use crate::renumber::SeatMapping;
+use crate::reservations::{ReservationId, ReservationStatus, Reservations};
+use crate::resource::ResourceKind;
+#[cfg(feature = "fixtures")]
+use crate::response::GeneratedResponse;
+use crate::response::{
+    AvailabilityResponse, BatchItemResponse, BatchStatus, BookingCancelledResponse,
+    BookingResponse, ConflictResponse, ErrorResponse, ForecastResponse, HoldResponse,
+    LayoutResponse, NearSeatResponse, NearestResponse, OccupancyHistoryResponse, PlacesResponse,
+    QuoteResponse, ResourceBookingResponse, SandboxResponse, SeatHistoryResponse, SeatResponse,
+    TimeResponse, TrainResponse, TrainsResponse, UpgradeErrorResponse, UpgradeResponse,
+};
+use crate::rules::{Policies, Rules};
+use crate::sandbox::Sandboxes;
+use crate::schedule::ResetSchedule;
+use crate::schema;
+use crate::snapshot::{Export, Import};
+use crate::spec::TrainSpec;
+use crate::stack::{Backpressure, Queue, Stack};
+use crate::store::{TrainCache, TrainStore};
+use crate::templates::Templates;
+use crate::tenants::{Tenant, TenantLimits, TenantUsage};
+use crate::ticket_office::TicketOffice;
+use crate::train::{
+    Error, Reservation, SeatId, SeatPatch, Train, TrainDataService, TrainId, TrainsData,
+};
+use crate::vouchers::{Voucher, Vouchers};
 
 pub struct AppState {
     booking_reference_service: BookingReferenceService,
     train_data_service: TrainDataService,
+    templates: Templates,
+    request_timeout: Duration,
+    access_log: Option<AccessLogFormat>,
+    // the optional middleware layers
+    stack: Stack,
+    // whether JSON:API documents are sent unless plain JSON is asked for
+    json_api: bool,
+    // whether responses are wrapped in an envelope unless clients ask not to
+    envelope: bool,
+    // for booking references in anonymized exports
+    pseudonyms: Pseudonyms,
+    policies: Policies,
+    flags: Flags,
+    rules: Rules,
+    plugins: Plugins,
+    pricing: Arc<dyn PricingStrategy>,
+    api_keys: Option<Arc<ApiKeys>>,
+    deprecations: Option<Arc<Deprecations>>,
+    usage: Usage,
+    vouchers: Vouchers,
+    refund_policy: RefundPolicy,
+    refunds: Refunds,
+    seat_history: SeatHistory,
+    reservations: Reservations,
+    notifier: Arc<dyn Notifier>,
+    // the fraction of reserved seats at which a train counts as nearly full
+    near_full_alert: Option<f64>,
+    clock: Arc<dyn Clock>,
+    // set in demo mode, when time can be moved forward on request
+    simulated_clock: Option<SimulatedClock>,
+    metrics: Arc<Metrics>,
+    // other sets of trains, served under /tenants/<name>
+    tenants: BTreeMap<String, AppState>,
+    tenant_limits: BTreeMap<String, TenantLimits>,
+    // the tenant these trains belong to, if any
+    tenant: Option<Tenant>,
+    reset_schedule: Option<ResetSchedule>,
+    // how long unused sandbox sessions last, when there are sandboxes
+    sandbox_expiry: Option<Duration>,
+    // how many sandbox sessions there can be at a time
+    max_sandboxes: usize,
+    // whether scheduled resets start booking references over too
+    reset_booking_references: bool,
+}
+
+impl Default for AppState {
+    fn default() -> Self {
+        AppState::new()
+    }
 }
 
 impl AppState {
@@ -21,300 +128,6285 @@ impl AppState {
         AppState {
             booking_reference_service: BookingReferenceService::new(0),
             train_data_service: TrainDataService::new(trains),
+            templates: Templates::bundled(),
+            request_timeout: Duration::from_secs(5),
+            access_log: None,
+            stack: Stack::default(),
+            json_api: false,
+            envelope: false,
+            pseudonyms: Pseudonyms::default(),
+            policies: Policies::default(),
+            flags: Flags::default(),
+            rules: Rules::default(),
+            plugins: Plugins::default(),
+            pricing: Arc::new(OccupancyPricing::default()),
+            api_keys: None,
+            deprecations: None,
+            usage: Usage::default(),
+            vouchers: Vouchers::default(),
+            refund_policy: RefundPolicy::default(),
+            refunds: Refunds::default(),
+            seat_history: SeatHistory::default(),
+            reservations: Reservations::default(),
+            notifier: Arc::new(LogNotifier),
+            near_full_alert: None,
+            clock: Arc::new(SystemClock),
+            simulated_clock: None,
+            metrics: Arc::new(Metrics::default()),
+            tenants: BTreeMap::new(),
+            tenant_limits: BTreeMap::new(),
+            tenant: None,
+            reset_schedule: None,
+            sandbox_expiry: None,
+            max_sandboxes: 0,
+            reset_booking_references: false,
+        }
+    }
+
+    pub fn with_trains(self, trains: TrainsData) -> AppState {
+        AppState {
+            train_data_service: TrainDataService::new(trains).with_clock(self.clock.clone()),
+            ..self
+        }
+    }
+
+    /// Load trains from a store when they're first used.
+    pub fn with_store(self, store: Box<dyn TrainStore>) -> AppState {
+        AppState {
+            train_data_service: self.train_data_service.with_store(store),
+            ..self
+        }
+    }
+
+    /// Keep at most this many trains from the store loaded.
+    pub fn with_max_loaded_trains(self, capacity: usize) -> AppState {
+        let cache = TrainCache::new(capacity, self.metrics.cache());
+        AppState {
+            train_data_service: self.train_data_service.with_cache(cache),
+            ..self
+        }
+    }
+
+    /// Let clients start sandbox sessions, with their own copy of the trains,
+    /// which expire when they haven't been used for a while. No more than
+    /// `max_sessions` are kept at a time.
+    pub fn with_sandboxes(self, expiry: Duration, max_sessions: usize) -> AppState {
+        AppState {
+            sandbox_expiry: Some(expiry),
+            max_sandboxes: max_sessions,
+            ..self
+        }
+    }
+
+    // a fresh state for a sandbox, with the trains and configuration this
+    // one started with; trains in the store are copied too, and the clock is
+    // shared, so moving time forward moves it in sandboxes as well
+    fn sandbox_state(&mut self) -> impl Fn() -> AppState + Send + Sync + 'static {
+        if let Err(err) = self.train_data_service.load_all() {
+            tracing::warn!(?err, "could not load trains for sandboxes");
+        }
+        let trains = self.train_data_service.trains().clone();
+        let templates = self.templates.clone();
+        let policies = self.policies.clone();
+        let flags = self.flags.clone();
+        let plugins = self.plugins.clone();
+        let pricing = self.pricing.clone();
+        let refund_policy = self.refund_policy;
+        let notifier = self.notifier.clone();
+        let near_full_alert = self.near_full_alert;
+        let simulated_clock = self.simulated_clock.clone();
+        move || {
+            let state = AppState {
+                plugins: plugins.clone(),
+                pricing: pricing.clone(),
+                notifier: notifier.clone(),
+                near_full_alert,
+                ..AppState::new()
+            }
+            .with_trains(trains.clone())
+            .with_templates(templates.clone())
+            .with_policies(policies.clone())
+            .with_flags(flags.clone())
+            .with_refund_policy(refund_policy);
+            match &simulated_clock {
+                Some(clock) => state.with_simulated_clock(clock.clone()),
+                None => state,
+            }
+        }
+    }
+
+    /// Reset all trains on a schedule, and booking references too if asked.
+    pub fn with_reset_schedule(
+        self,
+        reset_schedule: ResetSchedule,
+        reset_booking_references: bool,
+    ) -> AppState {
+        AppState {
+            reset_schedule: Some(reset_schedule),
+            reset_booking_references,
+            ..self
+        }
+    }
+
+    // reset all trains but the archived ones, which can't be changed
+    fn reset_all(&mut self) {
+        let train_ids = match self.train_data_service.train_ids() {
+            Ok(train_ids) => train_ids,
+            Err(err) => {
+                tracing::warn!(?err, "could not reset trains");
+                return;
+            }
+        };
+        let now = self.clock.now();
+        for train_id in train_ids {
+            match self.train_data_service.train_mut(&train_id) {
+                Ok(train) => {
+                    train.reset();
+                    self.seat_history.released_all(&train_id, now);
+                    self.reservations.cancel_all(&train_id, now);
+                }
+                Err(Error::TrainArchived(_)) => {}
+                Err(err) => {
+                    tracing::warn!(train_id = train_id.as_str(), ?err, "could not reset train")
+                }
+            }
+        }
+        if self.reset_booking_references {
+            self.booking_reference_service.reset();
+        }
+        self.record_all_occupancy();
+        tracing::info!("reset all trains");
+    }
+
+    pub fn with_templates(self, templates: Templates) -> AppState {
+        AppState { templates, ..self }
+    }
+
+    // reserve seats on behalf of a client, returning the new reservation, the
+    // discount of the voucher if there is one, and what's left of the
+    // client's quota
+    fn reserve(
+        &mut self,
+        train_id: &TrainId,
+        api_key: Option<&ApiKey>,
+        reservation: &Reservation,
+        voucher: Option<&str>,
+    ) -> Result<(ReservationId, Option<u64>, Option<Allowance>), Error> {
+        // seats held for the booking were counted against the quotas already
+        let seats = reservation.seats.len()
+            - self
+                .train_data_service
+                .train_mut(train_id)?
+                .held_for(reservation);
+        if reservation.seats.is_empty() {
+            return Err(Error::ReservationWithoutSeats);
+        }
+        self.check_quotas(api_key, seats)?;
+        let discount = voucher
+            .map(|code| self.vouchers.check(code, self.clock.now()))
+            .transpose()?;
+        let booking_reference = &reservation.booking_reference;
+        let train = self.train_data_service.train_mut(train_id)?;
+        let paid = train.paid(booking_reference);
+        // the booking is charged what the seats are quoted at, with the
+        // discount taken off
+        let price = quoted_price(&*self.pricing, train, &reservation.seats);
+        let discount = discount.map(|discount| discount.amount(price));
+        train.reserve(reservation, &self.rules)?;
+        train.set_paid(booking_reference, paid + price - discount.unwrap_or(0));
+        let now = self.clock.now();
+        self.metrics
+            .record_occupancy(train_id, train.occupancy(), now);
+        for seat_id in &reservation.seats {
+            self.seat_history
+                .reserved(train_id, seat_id, &reservation.booking_reference, now);
+        }
+        let id = self.reservations.add(train_id, reservation, now);
+        let allowance = self.record_quotas(api_key, seats);
+        self.metrics.record_reservation(reservation.seats.len());
+        // only now that the reservation went through is the voucher used
+        if let Some(code) = voucher {
+            self.vouchers.redeem(code);
+        }
+        Ok((id, discount, allowance))
+    }
+
+    // reserve places without a seat number for a booking, in a free-seating
+    // coach or standing when there's no coach, returning how many are left
+    fn reserve_places(
+        &mut self,
+        train_id: &TrainId,
+        coach: Option<&str>,
+        api_key: Option<&ApiKey>,
+        request: &PlacesRequest,
+    ) -> Result<(usize, Option<Allowance>), Error> {
+        self.train_data_service.train_mut(train_id)?;
+        self.check_quotas(api_key, request.places)?;
+        let train = self.train_data_service.train_mut(train_id)?;
+        let remaining = match coach {
+            Some(coach) => train.reserve_places(
+                coach,
+                request.places,
+                &request.booking_reference,
+                &self.rules,
+            )?,
+            None => {
+                train.reserve_standing(request.places, &request.booking_reference, &self.rules)?
+            }
+        };
+        self.metrics
+            .record_occupancy(train_id, train.occupancy(), self.clock.now());
+        let allowance = self.record_quotas(api_key, request.places);
+        self.metrics.record_reservation(request.places);
+        Ok((remaining, allowance))
+    }
+
+    // refuse seats over the quota of the client's key or of the tenant
+    fn check_quotas(&self, api_key: Option<&ApiKey>, seats: usize) -> Result<(), Error> {
+        if let Some(api_key) = api_key {
+            self.usage.check(api_key, seats)?;
+        }
+        if let Some(tenant) = &self.tenant {
+            tenant.check_seats(seats)?;
+        }
+        Ok(())
+    }
+
+    // count reserved seats against the quotas, returning what the key has left
+    fn record_quotas(&mut self, api_key: Option<&ApiKey>, seats: usize) -> Option<Allowance> {
+        if let Some(tenant) = &self.tenant {
+            tenant.record_seats(seats);
+        }
+        api_key.and_then(|api_key| self.usage.record(api_key, seats))
+    }
+
+    // release the seats of a booking on a train and refund it
+    fn cancel_booking(
+        &mut self,
+        train_id: &TrainId,
+        booking_reference: &BookingReference,
+    ) -> Result<Refund, Error> {
+        let now = self.clock.now();
+        let train = self.train_data_service.train_mut(train_id)?;
+        let cancellation = train.cancel(booking_reference)?;
+        for seat_id in &cancellation.seats {
+            self.seat_history.released(train_id, seat_id, now);
+        }
+        self.reservations.cancel(train_id, booking_reference, now);
+        self.metrics
+            .record_occupancy(train_id, train.occupancy(), now);
+        let percent = self.refund_policy.percent(train.departure(), now);
+        let refund = Refund {
+            train_id: train_id.clone(),
+            booking_reference: booking_reference.clone(),
+            seats: cancellation.seats,
+            places: cancellation.places,
+            resources: cancellation.resources,
+            paid: cancellation.paid,
+            percent,
+            amount: cancellation.paid * percent / 100,
+            cancelled_at: now,
+        };
+        self.refunds.add(refund.clone());
+        self.notifier
+            .notify(&Notification::new(Event::Refund(refund.clone())));
+        Ok(refund)
+    }
+
+    // free the seats of the holds that ran out, on all trains
+    fn expire_holds(&mut self) {
+        if !self.train_data_service.expire_all_holds().is_empty() {
+            self.record_all_occupancy();
+        }
+    }
+
+    fn record_all_occupancy(&self) {
+        self.metrics.clear_occupancy();
+        let now = self.clock.now();
+        for (train_id, train) in self
+            .train_data_service
+            .trains()
+            .iter()
+            .filter(|(_, train)| !train.is_archived())
+        {
+            self.metrics
+                .record_occupancy(train_id, train.occupancy(), now);
+        }
+    }
+
+    pub fn with_request_timeout(self, request_timeout: Duration) -> AppState {
+        AppState {
+            request_timeout,
+            ..self
+        }
+    }
+
+    pub fn with_access_log(self, access_log: Option<AccessLogFormat>) -> AppState {
+        AppState { access_log, ..self }
+    }
+
+    pub fn with_stack(self, stack: Stack) -> AppState {
+        AppState { stack, ..self }
+    }
+
+    pub fn with_json_api(self, json_api: bool) -> AppState {
+        AppState { json_api, ..self }
+    }
+
+    pub fn with_envelope(self, envelope: bool) -> AppState {
+        AppState { envelope, ..self }
+    }
+
+    pub fn with_near_full_alert(self, near_full_alert: Option<f64>) -> AppState {
+        AppState {
+            near_full_alert,
+            ..self
+        }
+    }
+
+    pub fn with_api_keys(self, api_keys: Option<ApiKeys>) -> AppState {
+        AppState {
+            api_keys: api_keys.map(Arc::new),
+            ..self
+        }
+    }
+
+    pub fn with_deprecations(self, deprecations: Option<Deprecations>) -> AppState {
+        AppState {
+            deprecations: deprecations.map(Arc::new),
+            ..self
+        }
+    }
+
+    pub fn with_flags(self, flags: Flags) -> AppState {
+        AppState { flags, ..self }
+    }
+
+    pub fn with_policies(self, policies: Policies) -> AppState {
+        let mut state = AppState { policies, ..self };
+        state.rules = state.rules();
+        state
+    }
+
+    /// Serve the routes of a plugin, and check its reservation rules.
+    pub fn with_plugin(mut self, plugin: impl ServicePlugin + 'static) -> AppState {
+        self.plugins.add(plugin);
+        self.rules = self.rules();
+        self
+    }
+
+    // the rules of the policies, followed by those of plugins
+    fn rules(&self) -> Rules {
+        let mut rules = self.policies.rules();
+        self.plugins.add_rules(&mut rules);
+        rules
+    }
+
+    pub fn with_pricing(self, pricing: Box<dyn PricingStrategy>) -> AppState {
+        AppState {
+            pricing: Arc::from(pricing),
+            ..self
+        }
+    }
+
+    pub fn with_refund_policy(self, refund_policy: RefundPolicy) -> AppState {
+        AppState {
+            refund_policy,
+            ..self
         }
     }
+
+    /// Use a clock that only moves with `POST /admin/time/advance`.
+    pub fn with_simulated_clock(self, clock: SimulatedClock) -> AppState {
+        let shared: Arc<dyn Clock> = Arc::new(clock.clone());
+        AppState {
+            train_data_service: self.train_data_service.with_clock(shared.clone()),
+            clock: shared,
+            simulated_clock: Some(clock),
+            ..self
+        }
+    }
+
+    /// Serve the trains of another state under `/tenants/<name>`, apart
+    /// from these. Tenants have their own booking references too.
+    pub fn with_tenant(mut self, name: String, tenant: AppState) -> AppState {
+        self.tenants.insert(name, tenant);
+        self
+    }
+
+    /// Limit what tenants may do, by tenant name.
+    pub fn with_tenant_limits(self, tenant_limits: BTreeMap<String, TenantLimits>) -> AppState {
+        AppState {
+            tenant_limits,
+            ..self
+        }
+    }
+
+    #[cfg(test)]
+    pub fn with_notifier(self, notifier: impl Notifier + 'static) -> AppState {
+        AppState {
+            notifier: Arc::new(notifier),
+            ..self
+        }
+    }
+}
+
+pub async fn serve(state: AppState, port: u16) {
+    serve_on(state, &Listen::port(port)).await
 }
 
-pub async fn serve(state: AppState) {
-    let app = app(state);
+/// Serve on a port, a Unix domain socket, or both.
+pub async fn serve_on(state: AppState, listen: &Listen) {
+    listen::serve(app(state), listen).await.unwrap();
+}
+
+/// Serve the ticket office too, so everything runs in one process. It calls
+/// the service through the same port, like it would from a process of its
+/// own, so `listen` needs a port.
+pub async fn serve_all_in_one(state: AppState, listen: &Listen) {
+    let port = listen.port.expect("the ticket office needs a port");
+    let router = all_in_one(state, format!("http://127.0.0.1:{}", port));
+    listen::serve(router, listen).await.unwrap();
+}
+
+/// The service with the ticket office under `/ticket_office`, where it uses
+/// the service at `url`.
+pub fn all_in_one(state: AppState, url: String) -> axum::Router {
+    let office = TicketOffice::new(HttpClient::new(state.request_timeout), url);
+    let office = axum::Router::new().nest("/ticket_office", office.routes());
+    app_with(state, office)
+}
 
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:8081").await.unwrap();
-    println!("Listening on port 8081");
-    axum::serve(listener, app).await.unwrap();
+/// The service with all its middleware, for serving it some other way than
+/// [`serve`] does.
+pub fn app(state: AppState) -> axum::Router {
+    app_with(state, axum::Router::new())
 }
 
-fn app(state: AppState) -> axum::Router {
+// the service with more routes, which go through the same middleware
+fn app_with(mut state: AppState, extra: axum::Router) -> axum::Router {
+    let stack = state.stack.clone();
+    let request_timeout = stack
+        .timeout_seconds
+        .map(Duration::from_secs)
+        .unwrap_or(state.request_timeout);
+    let access_log = state.access_log;
+    let json_api = state.json_api;
+    let envelope = state.envelope;
+    let api_keys = state.api_keys.clone();
+    let deprecations = state.deprecations.clone();
+    let metrics = state.metrics.clone();
+    let simulated_clock = state.simulated_clock.clone();
+    let tenants = std::mem::take(&mut state.tenants);
+    let tenant_usage = Arc::new(TenantUsage::new(tenants.keys().map(|name| {
+        let limits = state.tenant_limits.get(name).cloned().unwrap_or_default();
+        (name.clone(), limits)
+    })));
+    let sandboxes = state.sandbox_expiry.map(|expiry| {
+        let sandbox_state = state.sandbox_state();
+        Arc::new(Sandboxes::new(expiry, state.max_sandboxes, move || {
+            routes(Arc::new(Mutex::new(sandbox_state())))
+        }))
+    });
     let state = Arc::new(Mutex::new(state));
-    axum::Router::new()
+    let router = axum::Router::new()
         .route("/", get(root))
+        .route("/metrics", get(metrics_handler).with_state(metrics.clone()))
+        .route("/stats", get(stats).with_state(metrics.clone()))
+        .route("/schema", get(schemas))
+        .route("/schema/:name", get(schema))
+        .route(
+            "/admin/tenants",
+            get(admin_tenants).with_state(tenant_usage.clone()),
+        )
+        .merge(queued_routes(state.clone(), stack.backpressure));
+    let mut states = vec![state];
+    let mut tenant_metrics = BTreeMap::new();
+    let router = tenants
+        .into_iter()
+        .fold(router, |router, (name, mut tenant)| {
+            tenant.tenant = Some(Tenant::new(name.clone(), tenant_usage.clone()));
+            tenant_metrics.insert(name.clone(), tenant.metrics.clone());
+            let tenant = Arc::new(Mutex::new(tenant));
+            states.push(tenant.clone());
+            let routes = queued_routes(tenant, stack.backpressure);
+            router.nest(&format!("/tenants/{}", name), routes)
+        });
+    metrics.set_tenants(tenant_metrics);
+    let router = match simulated_clock {
+        Some(clock) => router.route(
+            "/admin/time/advance",
+            post(admin_time_advance).with_state(TimeControl { clock, states }),
+        ),
+        None => router,
+    };
+    let router = match sandboxes {
+        Some(sandboxes) => router
+            .route(
+                "/sandbox",
+                post(sandbox_create).with_state(sandboxes.clone()),
+            )
+            .route(
+                "/sandbox/:session/*path",
+                any(sandbox_request).with_state(sandboxes),
+            ),
+        None => router,
+    };
+    let router = middleware::with_fallback(router.merge(extra));
+    let router = middleware::with_json_bodies(router);
+    let router = middleware::with_tenants(router, tenant_usage);
+    let router = match api_keys {
+        Some(api_keys) => middleware::with_api_keys(router, api_keys),
+        None => router,
+    };
+    let router = if json_api {
+        middleware::with_default_accept(router, json_api::MEDIA_TYPE)
+    } else {
+        router
+    };
+    let router = match deprecations {
+        Some(deprecations) => middleware::with_deprecations(router, deprecations),
+        None => router,
+    };
+    let router = match stack.chaos {
+        Some(chaos) => middleware::with_chaos(router, chaos),
+        None => router,
+    };
+    // inside panic recovery, which handles requests in a task of their own
+    let router = middleware::with_correlation_id(router);
+    let router = middleware::with_panic_recovery(router);
+    let router = middleware::with_timeout(router, request_timeout);
+    let router = match stack.load_shed {
+        Some(load_shed) => middleware::with_load_shed(router, load_shed),
+        None => router,
+    };
+    let router = match stack.rate_limit {
+        Some(limit) => middleware::with_rate_limit(router, limit),
+        None => router,
+    };
+    let router = middleware::with_metrics(router, metrics);
+    let router = match access_log {
+        Some(format) => middleware::with_access_log(router, format, |line| println!("{}", line)),
+        None => router,
+    };
+    let router = middleware::with_envelope(router, envelope);
+    let router = match stack.compression {
+        Some(compression) => middleware::with_compression(router, compression),
+        None => router,
+    };
+    let router = match stack.cors {
+        Some(cors) => middleware::with_cors(router, cors),
+        None => router,
+    };
+    middleware::with_tracing(router)
+}
+
+// the routes of a state, with requests for them turned away when too many
+// wait for its lock
+fn queued_routes(state: Arc<Mutex<AppState>>, backpressure: Option<Backpressure>) -> axum::Router {
+    let metrics = state.lock_or_recover().metrics.clone();
+    let router = routes(state);
+    match backpressure {
+        Some(backpressure) => {
+            let queue = Arc::new(Queue::new(backpressure));
+            metrics.set_queue(queue.clone());
+            middleware::with_backpressure(router, queue)
+        }
+        None => router,
+    }
+}
+
+// the routes that work on the trains of a state
+fn routes(state: Arc<Mutex<AppState>>) -> axum::Router {
+    let (reset_schedule, published, clock, plugin_routes) = {
+        let state = state.lock_or_recover();
+        state.record_all_occupancy();
+        // after the first occupancy is recorded, so trains that start out
+        // nearly full don't set off alerts
+        if let Some(threshold) = state.near_full_alert {
+            let alert = NearFullAlert::new(threshold, state.notifier.clone());
+            state.metrics.set_near_full_alert(alert);
+        }
+        (
+            state.reset_schedule.clone(),
+            state.train_data_service.published(),
+            state.clock.clone(),
+            state.plugins.routes(),
+        )
+    };
+    if let Some(reset_schedule) = reset_schedule {
+        tokio::spawn(reset_on_schedule(state.clone(), reset_schedule));
+    }
+    let router = axum::Router::new()
         .route(
             "/booking_reference",
             post(booking_reference).with_state(state.clone()),
         )
-        .route("/train/:train_id", get(train).with_state(state.clone()))
+        .route("/reserve", post(reserve).with_state(state.clone()))
+        .route(
+            "/reservations/batch",
+            post(reservations_batch).with_state(state.clone()),
+        )
+        .route(
+            "/train/:train_id",
+            get(train).with_state((state.clone(), published, clock)),
+        )
+        .route("/trains", get(trains).with_state(state.clone()))
+        .route(
+            "/train/:train_id/seat/:seat_id",
+            get(train_seat).with_state(state.clone()),
+        )
+        .route(
+            "/train/:train_id/seat/:seat_id/history",
+            get(train_seat_history).with_state(state.clone()),
+        )
+        .route(
+            "/train/:train_id/availability",
+            get(train_availability).with_state(state.clone()),
+        )
+        .route(
+            "/train/:train_id/occupancy_history",
+            get(train_occupancy_history).with_state(state.clone()),
+        )
+        .route(
+            "/train/:train_id/forecast",
+            get(train_forecast).with_state(state.clone()),
+        )
+        .route(
+            "/train/:train_id/nearest",
+            get(train_nearest).with_state(state.clone()),
+        )
+        .route(
+            "/train/:train_id/layout",
+            get(train_layout).with_state(state.clone()),
+        )
         .route(
             "/train/:train_id/reserve",
             post(train_reserve).with_state(state.clone()),
         )
+        .route(
+            "/reservation/:id",
+            get(reservation).with_state(state.clone()),
+        )
+        .route(
+            "/reservation/:id/seats",
+            put(reservation_seats).with_state(state.clone()),
+        )
+        .route(
+            "/train/:train_id/hold",
+            post(train_hold).with_state(state.clone()),
+        )
+        .route(
+            "/train/:train_id/coach/:coach/reserve",
+            post(coach_reserve).with_state(state.clone()),
+        )
+        .route(
+            "/train/:train_id/reserve_standing",
+            post(reserve_standing).with_state(state.clone()),
+        )
+        .route(
+            "/train/:train_id/resources/:kind/availability",
+            get(resource_availability).with_state(state.clone()),
+        )
+        .route(
+            "/train/:train_id/resources/:kind/reserve",
+            post(resource_reserve).with_state(state.clone()),
+        )
+        .route(
+            "/train/:train_id/cancel",
+            post(train_cancel).with_state(state.clone()),
+        )
+        .route(
+            "/refunds/:booking_reference",
+            get(refunds).with_state(state.clone()),
+        )
+        .route(
+            "/train/:train_id/quote",
+            post(train_quote).with_state(state.clone()),
+        )
+        .route(
+            "/train/:train_id/upgrade",
+            post(train_upgrade).with_state(state.clone()),
+        )
         .route(
             "/train/:train_id/reset",
             post(train_reset).with_state(state.clone()),
         )
+        .route("/admin/export", get(admin_export).with_state(state.clone()))
+        .route(
+            "/admin/import",
+            post(admin_import).with_state(state.clone()),
+        )
+        .route(
+            "/admin/policies",
+            get(admin_policies)
+                .put(admin_policies_update)
+                .with_state(state.clone()),
+        )
+        .route(
+            "/admin/flags",
+            get(admin_flags)
+                .put(admin_flags_update)
+                .with_state(state.clone()),
+        )
+        .route(
+            "/admin/vouchers",
+            get(admin_vouchers).with_state(state.clone()),
+        )
+        .route(
+            "/admin/vouchers/:code",
+            put(admin_voucher_update)
+                .delete(admin_voucher_delete)
+                .with_state(state.clone()),
+        )
+        .route(
+            "/admin/train/:train_id",
+            post(admin_train_create).with_state(state.clone()),
+        )
+        .route(
+            "/admin/train/:train_id/clone",
+            post(admin_train_clone).with_state(state.clone()),
+        )
+        .route(
+            "/admin/train/:train_id/renumber",
+            post(admin_train_renumber).with_state(state.clone()),
+        )
+        .route(
+            "/admin/train/:train_id/archive",
+            post(admin_train_archive).with_state(state.clone()),
+        )
+        .route(
+            "/admin/cancel/:booking_reference",
+            post(admin_cancel_booking).with_state(state.clone()),
+        )
+        .route(
+            "/admin/train/:train_id/restore",
+            post(admin_train_restore).with_state(state.clone()),
+        )
+        .route(
+            "/admin/train/:train_id/seat/:seat_id",
+            patch(admin_seat_patch).with_state(state.clone()),
+        )
+        .route(
+            "/admin/train/:train_id/seat/:seat_id/block",
+            post(admin_seat_block).with_state(state.clone()),
+        )
+        .route(
+            "/admin/train/:train_id/seat/:seat_id/unblock",
+            post(admin_seat_unblock).with_state(state.clone()),
+        )
+        .route(
+            "/admin/train/:train_id/coach/:coach/close",
+            post(admin_coach_close).with_state(state.clone()),
+        )
+        .route(
+            "/admin/train/:train_id/coach/:coach/open",
+            post(admin_coach_open).with_state(state.clone()),
+        )
+        .route(
+            "/admin/train/:train_id/from_template/:template",
+            post(admin_train_from_template).with_state(state.clone()),
+        );
+    #[cfg(feature = "fixtures")]
+    let router = router.route("/admin/generate", post(admin_generate).with_state(state));
+    router.merge(plugin_routes)
+}
+
+async fn reset_on_schedule(state: Arc<Mutex<AppState>>, schedule: ResetSchedule) {
+    loop {
+        // the service's clock, so a simulated time is followed too
+        let now = state.lock_or_recover().clock.now();
+        tokio::time::sleep(schedule.until_next(now)).await;
+        state.lock_or_recover().reset_all();
+    }
+}
+
+async fn sandbox_create(extract::State(sandboxes): extract::State<Arc<Sandboxes>>) -> Response {
+    let session = match sandboxes.create() {
+        Ok(session) => session,
+        Err(retry_after) => {
+            return (
+                StatusCode::SERVICE_UNAVAILABLE,
+                [(
+                    header::RETRY_AFTER,
+                    retry_after.as_secs_f64().ceil().max(1.0).to_string(),
+                )],
+                axum::Json(ErrorResponse {
+                    error: "too_many_sandboxes",
+                    message: "There are too many sandbox sessions, try again later".to_string(),
+                }),
+            )
+                .into_response();
+        }
+    };
+    let response = SandboxResponse {
+        prefix: format!("/sandbox/{}", session),
+        session,
+        expires_after_seconds: sandboxes.expiry().as_secs(),
+    };
+    (StatusCode::CREATED, axum::Json(response)).into_response()
+}
+
+// hand the request to the routes of the session, without the session prefix
+async fn sandbox_request(
+    extract::State(sandboxes): extract::State<Arc<Sandboxes>>,
+    extract::Path((session, _)): extract::Path<(String, String)>,
+    request: extract::Request,
+) -> Response {
+    let Some(router) = sandboxes.get(&session) else {
+        return (
+            StatusCode::NOT_FOUND,
+            axum::Json(ErrorResponse {
+                error: "sandbox_not_found",
+                message: format!("Sandbox {} does not exist or has expired", session),
+            }),
+        )
+            .into_response();
+    };
+    let (mut parts, body) = request.into_parts();
+    let prefix = format!("/sandbox/{}", session);
+    let path = parts.uri.path().strip_prefix(&prefix).unwrap_or("/");
+    let path_and_query = match parts.uri.query() {
+        Some(query) => format!("{}?{}", path, query),
+        None => path.to_string(),
+    };
+    // a new request, as the extensions of this one have the path parameters
+    // of the sandbox route; only the ones middleware added are kept
+    let mut forwarded = extract::Request::new(body);
+    *forwarded.method_mut() = parts.method;
+    *forwarded.uri_mut() = path_and_query
+        .parse()
+        .expect("a path from a valid URI is a valid URI");
+    *forwarded.version_mut() = parts.version;
+    *forwarded.headers_mut() = parts.headers;
+    if let Some(api_key) = parts.extensions.remove::<ApiKey>() {
+        forwarded.extensions_mut().insert(api_key);
+    }
+    if let Some(original_uri) = parts.extensions.remove::<extract::OriginalUri>() {
+        forwarded.extensions_mut().insert(original_uri);
+    }
+    match router.oneshot(forwarded).await {
+        Ok(response) => response,
+        Err(infallible) => match infallible {},
+    }
 }
 
 async fn root() -> &'static str {
     "Train service"
 }
 
+async fn metrics_handler(extract::State(metrics): extract::State<Arc<Metrics>>) -> Response {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        metrics.render(),
+    )
+        .into_response()
+}
+
+async fn stats(extract::State(metrics): extract::State<Arc<Metrics>>) -> impl IntoResponse {
+    axum::Json(metrics.stats())
+}
+
+async fn schemas() -> impl IntoResponse {
+    axum::Json(schema::schema_names())
+}
+
+async fn schema(extract::Path(name): extract::Path<String>) -> Response {
+    match schema::schema(&name) {
+        Some(schema) => axum::Json(schema).into_response(),
+        None => (
+            StatusCode::NOT_FOUND,
+            axum::Json(ErrorResponse {
+                error: "schema_not_found",
+                message: format!("Schema {} does not exist", name),
+            }),
+        )
+            .into_response(),
+    }
+}
+
 async fn booking_reference(
     extract::State(state): extract::State<Arc<Mutex<AppState>>>,
 ) -> impl IntoResponse {
     let reference = state
-        .lock()
-        .unwrap()
+        .lock_or_recover()
         .borrow_mut()
         .booking_reference_service
         .booking_reference();
-    axum::Json(reference)
+    (axum::Extension(reference.clone()), axum::Json(reference))
 }
 
+// what reading a train needs: the state to publish it from, the published
+// copies, and the clock that says whether a copy is still current
+type PublishedState = (Arc<Mutex<AppState>>, Arc<PublishedTrains>, Arc<dyn Clock>);
+
+// served from the published copy when there is one that's still current, so
+// reading doesn't wait for changes to other trains, and the train isn't
+// serialized over and over
 async fn train(
     extract::Path(train_id): extract::Path<TrainId>,
-    extract::State(state): extract::State<Arc<Mutex<AppState>>>,
-) -> Result<impl IntoResponse, Error> {
-    let train = state
-        .lock()
-        .unwrap()
-        .borrow()
-        .train_data_service
-        .train(&train_id)?
-        .clone();
-    Ok(axum::Json(train))
+    extract::Query(filter): extract::Query<SeatFilter>,
+    extract::Query(params): extract::Query<FieldsParams>,
+    format: Format,
+    extract::State((state, published, clock)): extract::State<PublishedState>,
+) -> Result<Response, Error> {
+    let current = published
+        .get(&train_id)
+        .filter(|train| train.is_current(clock.now()));
+    let train = match current {
+        Some(train) => train,
+        None => {
+            // publish while holding the lock, so nothing changes in between
+            let mut state = state.lock_or_recover();
+            let train = state.train_data_service.train(&train_id)?;
+            published.publish(&train_id, train)
+        }
+    };
+    let fields = params.fields()?;
+    if format == Format::JsonApi {
+        let matches = filter.compile()?;
+        let document = json_api::train(&train_id, train.train(), matches, fields.as_ref());
+        return Ok(document.into_response());
+    }
+    if !filter.is_empty() || fields.is_some() {
+        let matches = filter.compile()?;
+        let response = TrainResponse::filtered(train.train(), matches).only(fields.as_ref());
+        return Ok(axum::Json(response).into_response());
+    }
+    Ok(([(header::CONTENT_TYPE, "application/json")], train.body()).into_response())
 }
 
-async fn train_reserve(
-    extract::Path(train_id): extract::Path<TrainId>,
+async fn train_seat(
+    extract::Path((train_id, seat_id)): extract::Path<(TrainId, SeatId)>,
+    extract::Query(params): extract::Query<FieldsParams>,
+    format: Format,
     extract::State(state): extract::State<Arc<Mutex<AppState>>>,
-    extract::Json(reservation): extract::Json<Reservation>,
-) -> Result<impl IntoResponse, Error> {
-    let mut state = state.lock().unwrap();
-    let train = state.borrow_mut().train_data_service.train_mut(&train_id)?;
-    train.reserve(&reservation)?;
-    Ok(axum::Json(train.clone()))
+) -> Result<Response, Error> {
+    let fields = params.fields()?;
+    let mut state = state.lock_or_recover();
+    let train = state.borrow_mut().train_data_service.train(&train_id)?;
+    let seat = train
+        .get(&seat_id)
+        .ok_or_else(|| Error::SeatDoesNotExist(train_id.clone(), seat_id.clone()))?;
+    if format == Format::JsonApi {
+        let document = json_api::seat(&train_id, &seat_id, train, seat, fields.as_ref());
+        return Ok(document.into_response());
+    }
+    Ok(axum::Json(SeatResponse::new(train, seat).only(fields.as_ref())).into_response())
 }
 
-async fn train_reset(
+#[derive(serde::Deserialize)]
+struct FieldsParams {
+    // comma separated seat fields
+    fields: Option<String>,
+}
+
+impl FieldsParams {
+    fn fields(&self) -> Result<Option<Fields>, Error> {
+        self.fields.as_deref().map(str::parse).transpose()
+    }
+}
+
+async fn reservation(
+    extract::Path(id): extract::Path<ReservationId>,
+    format: Format,
+    extract::State(state): extract::State<Arc<Mutex<AppState>>>,
+) -> Result<Response, Error> {
+    let state = state.lock_or_recover();
+    let reservation = state
+        .reservations
+        .get(id)
+        .ok_or(Error::ReservationDoesNotExist(id))?;
+    if format == Format::JsonApi {
+        return Ok(json_api::reservation(reservation).into_response());
+    }
+    Ok(axum::Json(reservation).into_response())
+}
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SeatsChange {
+    // all seats the reservation should have
+    seats: Vec<SeatId>,
+    #[serde(default)]
+    accessible: bool,
+}
+
+async fn reservation_seats(
+    extract::Path(id): extract::Path<ReservationId>,
+    extract::State(state): extract::State<Arc<Mutex<AppState>>>,
+    api_key: Option<extract::Extension<ApiKey>>,
+    Json(change): Json<SeatsChange>,
+) -> Result<Response, Error> {
+    let mut state = state.lock_or_recover();
+    let state = &mut *state;
+    let reservation = state
+        .reservations
+        .get(id)
+        .ok_or(Error::ReservationDoesNotExist(id))?;
+    if reservation.status == ReservationStatus::Cancelled {
+        return Err(Error::ReservationCancelled(id));
+    }
+    let mut seats = change.seats;
+    let mut seen = HashSet::new();
+    seats.retain(|seat_id| seen.insert(seat_id.clone()));
+    if seats.is_empty() {
+        return Err(Error::ReservationWithoutSeats);
+    }
+    let remove: Vec<SeatId> = reservation
+        .seats
+        .iter()
+        .filter(|seat_id| !seats.contains(seat_id))
+        .cloned()
+        .collect();
+    let add = Reservation {
+        seats: seats
+            .iter()
+            .filter(|seat_id| !reservation.seats.contains(seat_id))
+            .cloned()
+            .collect(),
+        booking_reference: reservation.booking_reference.clone(),
+        accessible: change.accessible,
+    };
+    let train_id = reservation.train_id.clone();
+    // only the seats that are added count against the quotas
+    let api_key = api_key.map(|extract::Extension(api_key)| api_key);
+    state.check_quotas(api_key.as_ref(), add.seats.len())?;
+    let train = state.train_data_service.train_mut(&train_id)?;
+    train.change_seats(&remove, &add, &state.rules)?;
+    state
+        .metrics
+        .record_occupancy(&train_id, train.occupancy(), state.clock.now());
+    let now = state.clock.now();
+    for seat_id in &remove {
+        state.seat_history.released(&train_id, seat_id, now);
+    }
+    for seat_id in &add.seats {
+        state
+            .seat_history
+            .reserved(&train_id, seat_id, &add.booking_reference, now);
+    }
+    state.reservations.change_seats(id, seats, now);
+    let allowance = state.record_quotas(api_key.as_ref(), add.seats.len());
+    let reservation = state.reservations.get(id).unwrap();
+    Ok((
+        allowance.map(allowance_headers).unwrap_or_default(),
+        axum::Extension(add.booking_reference.clone()),
+        axum::Json(reservation),
+    )
+        .into_response())
+}
+
+async fn train_seat_history(
+    extract::Path((train_id, seat_id)): extract::Path<(TrainId, SeatId)>,
+    extract::State(state): extract::State<Arc<Mutex<AppState>>>,
+) -> Result<Response, Error> {
+    let mut state = state.lock_or_recover();
+    let state = &mut *state;
+    let train = state.train_data_service.train(&train_id)?;
+    if train.get(&seat_id).is_none() {
+        return Err(Error::SeatDoesNotExist(train_id, seat_id));
+    }
+    let response = SeatHistoryResponse {
+        train_id: train_id.as_str(),
+        seat_id: seat_id.as_str(),
+        history: state.seat_history.get(&train_id, &seat_id),
+    };
+    Ok(axum::Json(response).into_response())
+}
+
+#[derive(serde::Deserialize)]
+struct TrainsParams {
+    // comma separated
+    ids: String,
+}
+
+async fn trains(
+    extract::Query(params): extract::Query<TrainsParams>,
+    extract::State(state): extract::State<Arc<Mutex<AppState>>>,
+) -> Result<Response, Error> {
+    let mut state = state.lock_or_recover();
+    let mut response = TrainsResponse {
+        trains: BTreeMap::new(),
+        missing: Vec::new(),
+        archived: Vec::new(),
+    };
+    for train_id in params.ids.split(',').filter(|id| !id.is_empty()) {
+        // serialized one by one, as loading a train may evict another
+        match state.train_data_service.train(&TrainId::new(train_id)) {
+            // archived trains are left out, like they are from the listing
+            Ok(train) if train.is_archived() => response.archived.push(train_id),
+            Ok(train) => {
+                let train = serde_json::to_value(TrainResponse::from(train))
+                    .expect("trains serialize to JSON");
+                response.trains.insert(train_id, train);
+            }
+            Err(Error::TrainDoesNotExist(_)) => response.missing.push(train_id),
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(axum::Json(response).into_response())
+}
+
+#[derive(serde::Deserialize)]
+struct AvailabilityParams {
+    // only count quiet coaches, or only the others
+    quiet: Option<bool>,
+}
+
+async fn train_availability(
+    extract::Path(train_id): extract::Path<TrainId>,
+    extract::Query(params): extract::Query<AvailabilityParams>,
+    extract::State(state): extract::State<Arc<Mutex<AppState>>>,
+) -> Result<Response, Error> {
+    let mut state = state.lock_or_recover();
+    let train = state.borrow_mut().train_data_service.train(&train_id)?;
+    let response = match params.quiet {
+        Some(quiet) => {
+            let occupancy = train
+                .occupancy()
+                .filter_coaches(|coach| train.is_quiet(coach) == quiet);
+            axum::Json(AvailabilityResponse::from(&occupancy)).into_response()
+        }
+        None => axum::Json(AvailabilityResponse::from(train.occupancy())).into_response(),
+    };
+    Ok(response)
+}
+
+async fn train_occupancy_history(
+    extract::Path(train_id): extract::Path<TrainId>,
+    extract::State(state): extract::State<Arc<Mutex<AppState>>>,
+) -> Result<Response, Error> {
+    let mut state = state.lock_or_recover();
+    state.train_data_service.train(&train_id)?;
+    let response = OccupancyHistoryResponse {
+        train_id: train_id.as_str(),
+        samples: state.metrics.occupancy_history(&train_id),
+    };
+    Ok(axum::Json(response).into_response())
+}
+
+async fn train_forecast(
+    extract::Path(train_id): extract::Path<TrainId>,
+    extract::State(state): extract::State<Arc<Mutex<AppState>>>,
+) -> Result<Response, Error> {
+    let mut state = state.lock_or_recover();
+    state.train_data_service.train(&train_id)?;
+    let samples = state.metrics.occupancy_history(&train_id);
+    let response = ForecastResponse {
+        train_id: train_id.as_str(),
+        seventy_percent: forecast::project(&samples, 0.7),
+        full: forecast::project(&samples, 1.0),
+    };
+    Ok(axum::Json(response).into_response())
+}
+
+#[derive(serde::Deserialize)]
+struct NearestParams {
+    seat: SeatId,
+    #[serde(default = "default_nearest_count")]
+    count: usize,
+    #[serde(default)]
+    accessible: bool,
+}
+
+fn default_nearest_count() -> usize {
+    1
+}
+
+async fn train_nearest(
+    extract::Path(train_id): extract::Path<TrainId>,
+    extract::Query(params): extract::Query<NearestParams>,
+    extract::State(state): extract::State<Arc<Mutex<AppState>>>,
+) -> Result<Response, Error> {
+    let mut state = state.lock_or_recover();
+    let train = state.train_data_service.train(&train_id)?;
+    let Some(seats) = allocate::nearest(train, &params.seat, params.count, params.accessible)
+    else {
+        return Err(Error::SeatDoesNotExist(train_id, params.seat));
+    };
+    let response = NearestResponse {
+        train_id: train_id.as_str(),
+        seat_id: params.seat.as_str(),
+        seats: seats
+            .into_iter()
+            .map(|(seat_id, distance)| NearSeatResponse {
+                seat_id: seat_id.as_str(),
+                distance,
+            })
+            .collect(),
+    };
+    Ok(axum::Json(response).into_response())
+}
+
+async fn train_layout(
+    extract::Path(train_id): extract::Path<TrainId>,
+    extract::State(state): extract::State<Arc<Mutex<AppState>>>,
+) -> Result<Response, Error> {
+    let mut state = state.lock_or_recover();
+    let train = state.train_data_service.train(&train_id)?;
+    Ok(axum::Json(LayoutResponse::new(train_id.as_str(), train)).into_response())
+}
+
+async fn resource_availability(
+    extract::Path((train_id, kind)): extract::Path<(TrainId, ResourceKind)>,
+    extract::State(state): extract::State<Arc<Mutex<AppState>>>,
+) -> Result<Response, Error> {
+    let mut state = state.lock_or_recover();
+    let train = state.borrow_mut().train_data_service.train(&train_id)?;
+    let occupancy = train.resource_occupancy(kind);
+    Ok(axum::Json(AvailabilityResponse::from(&occupancy)).into_response())
+}
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ResourceRequest {
+    count: usize,
+    booking_reference: BookingReference,
+}
+
+async fn resource_reserve(
+    extract::Path((train_id, kind)): extract::Path<(TrainId, ResourceKind)>,
+    extract::State(state): extract::State<Arc<Mutex<AppState>>>,
+    Json(request): Json<ResourceRequest>,
+) -> Result<Response, Error> {
+    let mut state = state.lock_or_recover();
+    let train = state.borrow_mut().train_data_service.train_mut(&train_id)?;
+    let resources = train.reserve_resources(kind, request.count, &request.booking_reference)?;
+    let response = ResourceBookingResponse {
+        train_id: train_id.as_str(),
+        booking_reference: request.booking_reference.as_str(),
+        kind,
+        resources: resources.iter().map(String::as_str).collect(),
+    };
+    Ok((
+        axum::Extension(request.booking_reference.clone()),
+        axum::Json(response),
+    )
+        .into_response())
+}
+
+async fn train_reserve(
+    extract::Path(train_id): extract::Path<TrainId>,
+    extract::State(state): extract::State<Arc<Mutex<AppState>>>,
+    api_key: Option<extract::Extension<ApiKey>>,
+    Json(reservation): Json<Reservation>,
+) -> Result<Response, Error> {
+    let mut state = state.lock_or_recover();
+    let api_key = api_key.map(|extract::Extension(api_key)| api_key);
+    let (id, _, allowance) = state.reserve(&train_id, api_key.as_ref(), &reservation, None)?;
+    let train = state.train_data_service.train(&train_id)?;
+    Ok((
+        [("x-reservation-id", id.to_string())],
+        allowance.map(allowance_headers).unwrap_or_default(),
+        axum::Extension(reservation.booking_reference),
+        axum::Json(TrainResponse::from(train)),
+    )
+        .into_response())
+}
+
+// how long seats are held unless asked otherwise
+const DEFAULT_HOLD_SECONDS: u64 = 300;
+// and the longest they can be held, a day
+const MAX_HOLD_SECONDS: u64 = 24 * 60 * 60;
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct HoldRequest {
+    seats: Vec<SeatId>,
+    booking_reference: BookingReference,
+    #[serde(default)]
+    accessible: bool,
+    seconds: Option<u64>,
+}
+
+async fn train_hold(
+    extract::Path(train_id): extract::Path<TrainId>,
+    extract::State(state): extract::State<Arc<Mutex<AppState>>>,
+    api_key: Option<extract::Extension<ApiKey>>,
+    Json(request): Json<HoldRequest>,
+) -> Result<Response, Error> {
+    let mut state = state.lock_or_recover();
+    let state = &mut *state;
+    let api_key = api_key.map(|extract::Extension(api_key)| api_key);
+    state.flags.check(Flag::Holds)?;
+    let now = state.clock.now();
+    let seconds = request.seconds.unwrap_or(DEFAULT_HOLD_SECONDS);
+    if seconds > MAX_HOLD_SECONDS {
+        return Err(Error::HoldTooLong(MAX_HOLD_SECONDS));
+    }
+    let expires = now.saturating_add(seconds);
+    let reservation = Reservation {
+        seats: request.seats,
+        booking_reference: request.booking_reference,
+        accessible: request.accessible,
+    };
+    // held seats count against the quotas, so reserving them later doesn't
+    let seats = reservation.seats.len()
+        - state
+            .train_data_service
+            .train_mut(&train_id)?
+            .held_for(&reservation);
+    state.check_quotas(api_key.as_ref(), seats)?;
+    let train = state.train_data_service.train_mut(&train_id)?;
+    train.hold(&reservation, expires, &state.rules)?;
+    state
+        .metrics
+        .record_occupancy(&train_id, train.occupancy(), now);
+    let allowance = state.record_quotas(api_key.as_ref(), seats);
+    let response = HoldResponse {
+        train_id: train_id.as_str(),
+        booking_reference: reservation.booking_reference.as_str(),
+        seats: reservation.seats.iter().map(SeatId::as_str).collect(),
+        expires,
+    };
+    Ok((
+        allowance.map(allowance_headers).unwrap_or_default(),
+        axum::Extension(reservation.booking_reference.clone()),
+        axum::Json(response),
+    )
+        .into_response())
+}
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PlacesRequest {
+    places: usize,
+    booking_reference: BookingReference,
+}
+
+async fn coach_reserve(
+    extract::Path((train_id, coach)): extract::Path<(TrainId, String)>,
+    extract::State(state): extract::State<Arc<Mutex<AppState>>>,
+    api_key: Option<extract::Extension<ApiKey>>,
+    Json(request): Json<PlacesRequest>,
+) -> Result<Response, Error> {
+    places_response(&state, &train_id, Some(&coach), api_key, request)
+}
+
+async fn reserve_standing(
     extract::Path(train_id): extract::Path<TrainId>,
     extract::State(state): extract::State<Arc<Mutex<AppState>>>,
+    api_key: Option<extract::Extension<ApiKey>>,
+    Json(request): Json<PlacesRequest>,
+) -> Result<Response, Error> {
+    places_response(&state, &train_id, None, api_key, request)
+}
+
+fn places_response(
+    state: &Mutex<AppState>,
+    train_id: &TrainId,
+    coach: Option<&str>,
+    api_key: Option<extract::Extension<ApiKey>>,
+    request: PlacesRequest,
+) -> Result<Response, Error> {
+    let api_key = api_key.map(|extract::Extension(api_key)| api_key);
+    let (remaining, allowance) =
+        state
+            .lock_or_recover()
+            .reserve_places(train_id, coach, api_key.as_ref(), &request)?;
+    let response = PlacesResponse {
+        train_id: train_id.as_str(),
+        coach,
+        booking_reference: request.booking_reference.as_str(),
+        places: request.places,
+        remaining,
+    };
+    Ok((
+        allowance.map(allowance_headers).unwrap_or_default(),
+        axum::Extension(request.booking_reference.clone()),
+        axum::Json(response),
+    )
+        .into_response())
+}
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CancelRequest {
+    booking_reference: BookingReference,
+}
+
+async fn train_cancel(
+    extract::Path(train_id): extract::Path<TrainId>,
+    extract::State(state): extract::State<Arc<Mutex<AppState>>>,
+    Json(request): Json<CancelRequest>,
+) -> Result<Response, Error> {
+    let mut state = state.lock_or_recover();
+    let departure = state.train_data_service.train_mut(&train_id)?.departure();
+    if !state.refund_policy.can_cancel(departure, state.clock.now()) {
+        return Err(Error::CancellationClosed(train_id));
+    }
+    let refund = state.cancel_booking(&train_id, &request.booking_reference)?;
+    Ok((
+        axum::Extension(request.booking_reference),
+        axum::Json(refund),
+    )
+        .into_response())
+}
+
+async fn refunds(
+    extract::Path(booking_reference): extract::Path<BookingReference>,
+    extract::State(state): extract::State<Arc<Mutex<AppState>>>,
 ) -> impl IntoResponse {
-    let mut state = state.lock().unwrap();
-    let train = state
-        .borrow_mut()
-        .train_data_service
-        .train_mut(&train_id)
-        .unwrap();
-    train.reset();
-    axum::Json(train.clone())
+    let state = state.lock_or_recover();
+    axum::Json(state.refunds.get(&booking_reference).to_vec())
 }
 
-impl IntoResponse for Error {
-    fn into_response(self) -> Response {
-        match self {
-            Error::TrainDoesNotExist(train_id) => (
-                StatusCode::NOT_FOUND,
-                format!("Train {} does not exist", train_id),
-            )
-                .into_response(),
-            Error::SeatsAlreadyReserved(seats) => (
-                StatusCode::BAD_REQUEST,
-                format!("Seats [{}] are already reserved", format_seat_ids(&seats)),
-            )
-                .into_response(),
-            Error::SeatsDoNotExist(seats) => (
-                StatusCode::BAD_REQUEST,
-                format!("Seats [{}] do not exist", format_seat_ids(&seats)),
-            )
-                .into_response(),
-        }
+// what seats cost as they're quoted, with the pricing applied; like when
+// they're sold, seats without a fare cost nothing
+fn quoted_price(pricing: &dyn PricingStrategy, train: &Train, seat_ids: &[SeatId]) -> u64 {
+    let seats = seat_ids
+        .iter()
+        .filter_map(|seat_id| train.get(seat_id).map(|seat| (seat_id, seat.coach())));
+    train
+        .fares()
+        .quote(seats)
+        .into_iter()
+        .filter_map(|(_, bucket)| bucket)
+        .map(|bucket| pricing.price(train, bucket.price))
+        .sum()
+}
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct QuoteRequest {
+    seats: Vec<SeatId>,
+    #[serde(default)]
+    voucher: Option<String>,
+}
+
+async fn train_quote(
+    extract::Path(train_id): extract::Path<TrainId>,
+    extract::State(state): extract::State<Arc<Mutex<AppState>>>,
+    Json(request): Json<QuoteRequest>,
+) -> Result<Response, Error> {
+    let mut state = state.lock_or_recover();
+    let state = &mut *state;
+    let train = state.train_data_service.train(&train_id)?;
+    let mut quote = train.quote(&request.seats)?;
+    for seat_fare in &mut quote {
+        seat_fare.price = state.pricing.price(train, seat_fare.price);
     }
+    let total = quote.iter().map(|seat_fare| seat_fare.price).sum();
+    let discount = match &request.voucher {
+        Some(code) => Some(state.vouchers.check(code, state.clock.now())?.amount(total)),
+        None => None,
+    };
+    let response = QuoteResponse {
+        train_id: train_id.as_str(),
+        seats: quote.iter().map(Into::into).collect(),
+        discount,
+        total: total - discount.unwrap_or(0),
+    };
+    Ok(axum::Json(response).into_response())
 }
 
-fn format_seat_ids(seats: &[SeatId]) -> String {
-    seats
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct UpgradeRequest {
+    booking_reference: BookingReference,
+}
+
+async fn train_upgrade(
+    extract::Path(train_id): extract::Path<TrainId>,
+    extract::State(state): extract::State<Arc<Mutex<AppState>>>,
+    Json(request): Json<UpgradeRequest>,
+) -> Result<Response, Error> {
+    let mut state = state.lock_or_recover();
+    let state = &mut *state;
+    let train = state.train_data_service.train_mut(&train_id)?;
+    let moves = train.upgrade(&request.booking_reference)?;
+    let now = state.clock.now();
+    for (from, to) in &moves {
+        state.seat_history.released(&train_id, from, now);
+        state
+            .seat_history
+            .reserved(&train_id, to, &request.booking_reference, now);
+    }
+    state
+        .reservations
+        .moved(&train_id, &request.booking_reference, &moves, now);
+    state
+        .metrics
+        .record_occupancy(&train_id, train.occupancy(), state.clock.now());
+    let response = UpgradeResponse {
+        train_id: train_id.as_str(),
+        booking_reference: request.booking_reference.as_str(),
+        seats: moves
+            .iter()
+            .map(|(from, to)| (from.as_str(), to.as_str()))
+            .collect(),
+    };
+    Ok((
+        axum::Extension(request.booking_reference.clone()),
+        axum::Json(response),
+    )
+        .into_response())
+}
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BookingRequest {
+    train_id: TrainId,
+    seat_count: usize,
+    #[serde(default)]
+    preferences: Preferences,
+    #[serde(default)]
+    strategy: Strategy,
+    #[serde(default)]
+    voucher: Option<String>,
+}
+
+async fn reserve(
+    extract::State(state): extract::State<Arc<Mutex<AppState>>>,
+    api_key: Option<extract::Extension<ApiKey>>,
+    Json(request): Json<BookingRequest>,
+) -> Result<Response, Error> {
+    let mut state = state.lock_or_recover();
+    state.flags.check(Flag::AutoAllocation)?;
+    let train = state.train_data_service.train(&request.train_id)?;
+    let allocation = allocate::allocate(
+        train,
+        request.seat_count,
+        &request.preferences,
+        request.strategy,
+    )?;
+    // the booking reference is only taken once the seats are reserved, so
+    // requests that are turned away don't use one up
+    let reservation = Reservation {
+        seats: allocation.seats,
+        booking_reference: state.booking_reference_service.next(),
+        accessible: request.preferences.accessible,
+    };
+    let api_key = api_key.map(|extract::Extension(api_key)| api_key);
+    let (reservation_id, discount, allowance) = state.reserve(
+        &request.train_id,
+        api_key.as_ref(),
+        &reservation,
+        request.voucher.as_deref(),
+    )?;
+    state.booking_reference_service.booking_reference();
+    let response = BookingResponse {
+        train_id: request.train_id.as_str(),
+        booking_reference: reservation.booking_reference.as_str(),
+        seats: reservation.seats.iter().map(SeatId::as_str).collect(),
+        preferences_met: &allocation.preferences_met,
+        spread: allocation.spread,
+        reservation_id,
+        discount,
+    };
+    Ok((
+        allowance.map(allowance_headers).unwrap_or_default(),
+        axum::Extension(reservation.booking_reference.clone()),
+        axum::Json(response),
+    )
+        .into_response())
+}
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BatchRequest {
+    reservations: Vec<BatchReservation>,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BatchReservation {
+    train_id: TrainId,
+    seats: Vec<SeatId>,
+    booking_reference: BookingReference,
+    #[serde(default)]
+    accessible: bool,
+}
+
+// make each reservation on its own, so one that fails doesn't stop the others
+async fn reservations_batch(
+    extract::State(state): extract::State<Arc<Mutex<AppState>>>,
+    api_key: Option<extract::Extension<ApiKey>>,
+    Json(request): Json<BatchRequest>,
+) -> Response {
+    let api_key = api_key.map(|extract::Extension(api_key)| api_key);
+    let results: Vec<Result<ReservationId, Error>> = {
+        let mut state = state.lock_or_recover();
+        request
+            .reservations
+            .iter()
+            .map(|item| {
+                let reservation = Reservation {
+                    seats: item.seats.clone(),
+                    booking_reference: item.booking_reference.clone(),
+                    accessible: item.accessible,
+                };
+                state
+                    .reserve(&item.train_id, api_key.as_ref(), &reservation, None)
+                    .map(|(id, _, _)| id)
+            })
+            .collect()
+    };
+    let items = request
+        .reservations
         .iter()
-        .map(|seat_id| seat_id.to_string())
-        .collect::<Vec<String>>()
-        .join(", ")
+        .zip(results)
+        .map(|(item, result)| {
+            let (status, reservation_id, message) = match result {
+                Ok(id) => (BatchStatus::Reserved, Some(id), None),
+                Err(err) => (batch_status(&err), None, Some(err.message())),
+            };
+            BatchItemResponse {
+                train_id: item.train_id.as_str(),
+                booking_reference: item.booking_reference.as_str(),
+                status,
+                reservation_id,
+                message,
+            }
+        })
+        .collect::<Vec<_>>();
+    axum::Json(items).into_response()
 }
 
-#[cfg(test)]
-mod tests {
-    use axum_test::{TestServer, TestServerConfig};
+fn batch_status(err: &Error) -> BatchStatus {
+    match err {
+        Error::TrainDoesNotExist(_) | Error::SeatsDoNotExist(_) | Error::SeatDoesNotExist(_, _) => {
+            BatchStatus::NotFound
+        }
+        Error::SeatsAlreadyReserved(_, _)
+        | Error::SeatsBlocked(_)
+        | Error::SeatsAccessible(_)
+        | Error::TrainArchived(_)
+        | Error::CoachesClosed(_) => BatchStatus::Conflict,
+        _ => BatchStatus::Rejected,
+    }
+}
 
-    use crate::{
-        booking_reference::BookingReference,
-        train::{SeatId, Train, TrainId, TrainsData},
+fn allowance_headers(allowance: Allowance) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert("x-quota-limit", allowance.limit.into());
+    headers.insert("x-quota-remaining", allowance.remaining.into());
+    headers
+}
+
+async fn train_reset(
+    extract::Path(train_id): extract::Path<TrainId>,
+    extract::State(state): extract::State<Arc<Mutex<AppState>>>,
+) -> Result<Response, Error> {
+    let mut state = state.lock_or_recover();
+    let state = &mut *state;
+    let train = state.train_data_service.train_mut(&train_id)?;
+    train.reset();
+    let now = state.clock.now();
+    state.seat_history.released_all(&train_id, now);
+    state.reservations.cancel_all(&train_id, now);
+    state
+        .metrics
+        .record_occupancy(&train_id, train.occupancy(), state.clock.now());
+    Ok(axum::Json(TrainResponse::from(&*train)).into_response())
+}
+
+async fn admin_train_create(
+    extract::Path(train_id): extract::Path<TrainId>,
+    extract::State(state): extract::State<Arc<Mutex<AppState>>>,
+    Json(spec): Json<TrainSpec>,
+) -> Result<Response, Error> {
+    let train = spec.build()?;
+    let mut state = state.lock_or_recover();
+    let state = &mut *state;
+    state
+        .train_data_service
+        .add_train(train_id.clone(), train)?;
+    let train = state.train_data_service.train(&train_id)?;
+    state
+        .metrics
+        .record_occupancy(&train_id, train.occupancy(), state.clock.now());
+    Ok((StatusCode::CREATED, axum::Json(TrainResponse::from(train))).into_response())
+}
+
+#[cfg(feature = "fixtures")]
+async fn admin_generate(
+    extract::Query(scenario): extract::Query<Scenario>,
+    extract::State(state): extract::State<Arc<Mutex<AppState>>>,
+) -> Result<Response, Error> {
+    scenario.validate()?;
+    let mut rng = match scenario.seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
     };
+    let mut state = state.lock_or_recover();
+    let state = &mut *state;
+    let existing: HashSet<TrainId> = state.train_data_service.train_ids()?.into_iter().collect();
+    let train_ids = (1..)
+        .map(|number| TrainId::new(format!("generated_{}", number)))
+        .filter(|train_id| !existing.contains(train_id));
+    let mut generated = Vec::with_capacity(scenario.trains);
+    for train_id in train_ids.take(scenario.trains) {
+        let booking_references = &mut state.booking_reference_service;
+        let train = scenario.train(&mut rng, || booking_references.booking_reference())?;
+        state
+            .metrics
+            .record_occupancy(&train_id, train.occupancy(), state.clock.now());
+        state
+            .train_data_service
+            .add_train(train_id.clone(), train)?;
+        generated.push(train_id.to_string());
+    }
+    let response = GeneratedResponse { trains: generated };
+    Ok((StatusCode::CREATED, axum::Json(response)).into_response())
+}
 
-    // based around https://github.com/tokio-rs/axum/blob/main/examples/testing/src/main.rs
-    use super::*;
+async fn admin_train_from_template(
+    extract::Path((train_id, template)): extract::Path<(TrainId, String)>,
+    extract::State(state): extract::State<Arc<Mutex<AppState>>>,
+) -> Result<Response, Error> {
+    let mut state = state.lock_or_recover();
+    let state = &mut *state;
+    let train = state.templates.get(&template)?.build()?;
+    state
+        .train_data_service
+        .add_train(train_id.clone(), train)?;
+    let train = state.train_data_service.train(&train_id)?;
+    state
+        .metrics
+        .record_occupancy(&train_id, train.occupancy(), state.clock.now());
+    Ok((StatusCode::CREATED, axum::Json(TrainResponse::from(train))).into_response())
+}
 
-    fn new_test_app() -> TestServer {
-        let app = app(AppState::new());
-        let config = TestServerConfig::builder()
-            .expect_success_by_default()
-            .mock_transport()
-            .build();
+async fn admin_train_archive(
+    extract::Path(train_id): extract::Path<TrainId>,
+    extract::State(state): extract::State<Arc<Mutex<AppState>>>,
+) -> Result<Response, Error> {
+    set_archived(&state, &train_id, true)
+}
 
-        TestServer::new_with_config(app, config).unwrap()
+async fn admin_train_restore(
+    extract::Path(train_id): extract::Path<TrainId>,
+    extract::State(state): extract::State<Arc<Mutex<AppState>>>,
+) -> Result<Response, Error> {
+    set_archived(&state, &train_id, false)
+}
+
+// cancel a booking on every train it has seats on, or on none of them
+async fn admin_cancel_booking(
+    extract::Path(booking_reference): extract::Path<BookingReference>,
+    extract::State(state): extract::State<Arc<Mutex<AppState>>>,
+) -> Result<Response, Error> {
+    let mut state = state.lock_or_recover();
+    // places without a seat number and resources have no reservation, so
+    // every train is looked at, including those only in the store; all are
+    // checked before anything is cancelled, so it's all or nothing
+    let mut train_ids = Vec::new();
+    for train_id in state.train_data_service.train_ids()? {
+        let train = state.train_data_service.train(&train_id)?;
+        if train.has_booking(&booking_reference) {
+            if train.is_archived() {
+                return Err(Error::TrainArchived(train_id));
+            }
+            train_ids.push(train_id);
+        }
+    }
+    train_ids.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+    if train_ids.is_empty() {
+        return Err(Error::BookingDoesNotExist(booking_reference));
     }
+    let refunds = train_ids
+        .iter()
+        .map(|train_id| state.cancel_booking(train_id, &booking_reference))
+        .collect::<Result<Vec<_>, _>>()?;
+    let response = BookingCancelledResponse {
+        booking_reference: booking_reference.as_str(),
+        seats: refunds.iter().map(|refund| refund.seats.len()).sum(),
+        places: refunds.iter().map(|refund| refund.places).sum(),
+        resources: refunds.iter().map(|refund| refund.resources.len()).sum(),
+        refunds,
+    };
+    Ok((
+        axum::Extension(booking_reference.clone()),
+        axum::Json(response),
+    )
+        .into_response())
+}
 
-    fn new_test_app_failing() -> TestServer {
-        let app = app(AppState::new());
-        let config = TestServerConfig::builder().mock_transport().build();
-        TestServer::new_with_config(app, config).unwrap()
+fn set_archived(
+    state: &Mutex<AppState>,
+    train_id: &TrainId,
+    archived: bool,
+) -> Result<Response, Error> {
+    let mut state = state.lock_or_recover();
+    let state = &mut *state;
+    state.train_data_service.set_archived(train_id, archived)?;
+    // archived trains are left out of the metrics
+    state.record_all_occupancy();
+    let train = state.train_data_service.train(train_id)?;
+    Ok(axum::Json(TrainResponse::from(train)).into_response())
+}
+
+#[derive(serde::Deserialize)]
+struct CloneParams {
+    new_id: TrainId,
+}
+
+async fn admin_train_clone(
+    extract::Path(train_id): extract::Path<TrainId>,
+    extract::Query(params): extract::Query<CloneParams>,
+    extract::State(state): extract::State<Arc<Mutex<AppState>>>,
+) -> Result<Response, Error> {
+    let mut state = state.lock_or_recover();
+    let state = &mut *state;
+    let train = state.train_data_service.train(&train_id)?.copy_structure();
+    state
+        .train_data_service
+        .add_train(params.new_id.clone(), train)?;
+    let train = state.train_data_service.train(&params.new_id)?;
+    state
+        .metrics
+        .record_occupancy(&params.new_id, train.occupancy(), state.clock.now());
+    Ok((StatusCode::CREATED, axum::Json(TrainResponse::from(train))).into_response())
+}
+
+async fn admin_train_renumber(
+    extract::Path(train_id): extract::Path<TrainId>,
+    extract::State(state): extract::State<Arc<Mutex<AppState>>>,
+    Json(mapping): Json<SeatMapping>,
+) -> Result<Response, Error> {
+    let mut state = state.lock_or_recover();
+    let state = &mut *state;
+    let train = state.train_data_service.train_mut(&train_id)?;
+    train.renumber(&mapping)?;
+    state
+        .metrics
+        .record_occupancy(&train_id, train.occupancy(), state.clock.now());
+    Ok(axum::Json(TrainResponse::from(&*train)).into_response())
+}
+
+async fn admin_seat_patch(
+    extract::Path((train_id, seat_id)): extract::Path<(TrainId, SeatId)>,
+    extract::State(state): extract::State<Arc<Mutex<AppState>>>,
+    Json(patch): Json<SeatPatch>,
+) -> Result<Response, Error> {
+    let mut state = state.lock_or_recover();
+    let state = &mut *state;
+    let train = state.train_data_service.train_mut(&train_id)?;
+    train.patch_seat(&seat_id, &patch)?;
+    state
+        .metrics
+        .record_occupancy(&train_id, train.occupancy(), state.clock.now());
+    let seat = train.get(&seat_id).expect("patched seat exists");
+    Ok(axum::Json(SeatResponse::new(train, seat)).into_response())
+}
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BlockRequest {
+    reason: String,
+}
+
+async fn admin_seat_block(
+    extract::Path((train_id, seat_id)): extract::Path<(TrainId, SeatId)>,
+    extract::State(state): extract::State<Arc<Mutex<AppState>>>,
+    Json(request): Json<BlockRequest>,
+) -> Result<Response, Error> {
+    let mut state = state.lock_or_recover();
+    let state = &mut *state;
+    let train = state.train_data_service.train_mut(&train_id)?;
+    train.block(&seat_id, request.reason)?;
+    state
+        .metrics
+        .record_occupancy(&train_id, train.occupancy(), state.clock.now());
+    Ok(axum::Json(TrainResponse::from(&*train)).into_response())
+}
+
+async fn admin_seat_unblock(
+    extract::Path((train_id, seat_id)): extract::Path<(TrainId, SeatId)>,
+    extract::State(state): extract::State<Arc<Mutex<AppState>>>,
+) -> Result<Response, Error> {
+    let mut state = state.lock_or_recover();
+    let state = &mut *state;
+    let train = state.train_data_service.train_mut(&train_id)?;
+    train.unblock(&seat_id)?;
+    state
+        .metrics
+        .record_occupancy(&train_id, train.occupancy(), state.clock.now());
+    Ok(axum::Json(TrainResponse::from(&*train)).into_response())
+}
+
+async fn admin_coach_close(
+    extract::Path((train_id, coach)): extract::Path<(TrainId, String)>,
+    extract::State(state): extract::State<Arc<Mutex<AppState>>>,
+) -> Result<Response, Error> {
+    set_coach_closed(&state, &train_id, &coach, true)
+}
+
+async fn admin_coach_open(
+    extract::Path((train_id, coach)): extract::Path<(TrainId, String)>,
+    extract::State(state): extract::State<Arc<Mutex<AppState>>>,
+) -> Result<Response, Error> {
+    set_coach_closed(&state, &train_id, &coach, false)
+}
+
+fn set_coach_closed(
+    state: &Mutex<AppState>,
+    train_id: &TrainId,
+    coach: &str,
+    closed: bool,
+) -> Result<Response, Error> {
+    let mut state = state.lock_or_recover();
+    let state = &mut *state;
+    let train = state.train_data_service.train_mut(train_id)?;
+    train.set_closed(coach, closed)?;
+    state
+        .metrics
+        .record_occupancy(train_id, train.occupancy(), state.clock.now());
+    Ok(axum::Json(TrainResponse::from(&*train)).into_response())
+}
+
+#[derive(serde::Deserialize)]
+struct ExportParams {
+    // for sharing: pseudonyms instead of booking references
+    #[serde(default)]
+    anonymize: bool,
+}
+
+async fn admin_export(
+    extract::Query(params): extract::Query<ExportParams>,
+    extract::State(state): extract::State<Arc<Mutex<AppState>>>,
+) -> Result<Response, Error> {
+    let mut state = state.lock_or_recover();
+    let state = state.borrow_mut();
+    state.train_data_service.load_all()?;
+    Ok(axum::Json(Export::new(
+        &state.booking_reference_service,
+        &state.train_data_service,
+        &state.vouchers,
+        params.anonymize.then_some(&state.pseudonyms),
+    ))
+    .into_response())
+}
+
+#[derive(serde::Deserialize)]
+struct ImportParams {
+    #[serde(default)]
+    dry_run: bool,
+}
+
+async fn admin_import(
+    extract::Query(params): extract::Query<ImportParams>,
+    extract::State(state): extract::State<Arc<Mutex<AppState>>>,
+    Json(import): Json<Import>,
+) -> Result<impl IntoResponse, Error> {
+    let summary = import.validate(params.dry_run)?;
+    if !params.dry_run {
+        let (counter, trains, vouchers) = import.into_parts();
+        // replace everything at once, so nobody sees a half-restored state
+        let mut state = state.lock_or_recover();
+        let state = state.borrow_mut();
+        // the seats of the trains that are replaced are gone, like after a
+        // reset, and so are what was reserved and refunded on them
+        let now = state.clock.now();
+        for train_id in state.train_data_service.train_ids()? {
+            state.seat_history.released_all(&train_id, now);
+            state.reservations.cancel_all(&train_id, now);
+        }
+        state.refunds = Refunds::default();
+        state.usage = Usage::default();
+        state.booking_reference_service = BookingReferenceService::new(counter);
+        state.train_data_service.replace(trains);
+        state.vouchers = vouchers;
+        state.record_all_occupancy();
+    }
+    Ok(axum::Json(summary))
+}
+
+async fn admin_policies(extract::State(state): extract::State<Arc<Mutex<AppState>>>) -> Response {
+    axum::Json(&state.lock_or_recover().policies).into_response()
+}
+
+async fn admin_policies_update(
+    extract::State(state): extract::State<Arc<Mutex<AppState>>>,
+    Json(policies): Json<Policies>,
+) -> Result<impl IntoResponse, Error> {
+    policies.validate().map_err(Error::InvalidPolicy)?;
+    let mut state = state.lock_or_recover();
+    state.policies = policies.clone();
+    state.rules = state.rules();
+    Ok(axum::Json(policies))
+}
+
+async fn admin_flags(extract::State(state): extract::State<Arc<Mutex<AppState>>>) -> Response {
+    axum::Json(&state.lock_or_recover().flags).into_response()
+}
+
+async fn admin_flags_update(
+    extract::State(state): extract::State<Arc<Mutex<AppState>>>,
+    Json(flags): Json<Flags>,
+) -> impl IntoResponse {
+    state.lock_or_recover().flags = flags.clone();
+    axum::Json(flags)
+}
+
+async fn admin_vouchers(
+    extract::State(state): extract::State<Arc<Mutex<AppState>>>,
+) -> impl IntoResponse {
+    let state = state.lock_or_recover();
+    axum::Json(state.vouchers.clone())
+}
+
+async fn admin_voucher_update(
+    extract::Path(code): extract::Path<String>,
+    extract::State(state): extract::State<Arc<Mutex<AppState>>>,
+    Json(voucher): Json<Voucher>,
+) -> Result<impl IntoResponse, Error> {
+    let mut state = state.lock_or_recover();
+    state.vouchers.insert(code, voucher.clone())?;
+    Ok(axum::Json(voucher))
+}
+
+async fn admin_voucher_delete(
+    extract::Path(code): extract::Path<String>,
+    extract::State(state): extract::State<Arc<Mutex<AppState>>>,
+) -> Result<impl IntoResponse, Error> {
+    let mut state = state.lock_or_recover();
+    state.vouchers.remove(&code)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn admin_tenants(
+    extract::State(usage): extract::State<Arc<TenantUsage>>,
+) -> impl IntoResponse {
+    axum::Json(usage.stats())
+}
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AdvanceRequest {
+    seconds: u64,
+}
+
+// the simulated clock, and the states of all tenants, which share it
+#[derive(Clone)]
+struct TimeControl {
+    clock: SimulatedClock,
+    states: Vec<Arc<Mutex<AppState>>>,
+}
+
+// holds that run out as time moves on are expired right away, so the
+// occupancy metrics count their seats as free too
+async fn admin_time_advance(
+    extract::State(time): extract::State<TimeControl>,
+    Json(request): Json<AdvanceRequest>,
+) -> Result<Response, Error> {
+    let now = time
+        .clock
+        .advance(request.seconds)
+        .ok_or(Error::ClockOverflow(request.seconds))?;
+    for state in &time.states {
+        state.lock_or_recover().expire_holds();
+    }
+    Ok(axum::Json(TimeResponse { now }).into_response())
+}
+
+impl Error {
+    /// What went wrong, as it's told to clients.
+    pub fn message(&self) -> String {
+        match self {
+            Error::TrainDoesNotExist(train_id) => format!("Train {} does not exist", train_id),
+            Error::TrainArchived(train_id) => format!("Train {} is archived", train_id),
+            Error::TrainAlreadyExists(train_id) => format!("Train {} already exists", train_id),
+            Error::InvalidTrainSpec(message) => format!("Invalid train spec: {}", message),
+            Error::TemplateDoesNotExist(template) => {
+                format!("Template {} does not exist", template)
+            }
+            Error::StoreFailed(message) => format!("Could not load train: {}", message),
+            Error::InvalidSeatMapping(message) => format!("Invalid seat mapping: {}", message),
+            Error::InvalidSeatFilter(message) => format!("Invalid seat filter: {}", message),
+            Error::InvalidFields(message) => format!("Invalid fields: {}", message),
+            Error::InvalidImport(message) => format!("Invalid import: {}", message),
+            Error::InvalidPolicy(message) => format!("Invalid policy: {}", message),
+            Error::SeatsAlreadyReserved(seats, _) => {
+                format!("Seats [{}] are already reserved", format_seat_ids(seats))
+            }
+            Error::SeatsDoNotExist(seats) => {
+                format!("Seats [{}] do not exist", format_seat_ids(seats))
+            }
+            Error::HoldTooLong(max_seconds) => {
+                format!("Seats can be held for at most {} seconds", max_seconds)
+            }
+            Error::ClockOverflow(seconds) => {
+                format!("The clock can't be advanced by {} seconds", seconds)
+            }
+            Error::DuplicateSeats(seats) => format!(
+                "Seats [{}] are asked for more than once",
+                format_seat_ids(seats)
+            ),
+            Error::SeatDoesNotExist(train_id, seat_id) => {
+                format!("Seat {} does not exist on train {}", seat_id, train_id)
+            }
+            Error::ReservationDoesNotExist(id) => format!("Reservation {} does not exist", id),
+            Error::ReservationCancelled(id) => format!("Reservation {} was cancelled", id),
+            Error::ReservationWithoutSeats => {
+                "A reservation needs at least one seat; cancel the booking to release all seats"
+                    .to_string()
+            }
+            Error::SeatsAccessible(seats) => format!(
+                "Seats [{}] are kept for passengers who need accessible seats",
+                format_seat_ids(seats)
+            ),
+            Error::SeatsBlocked(seats) => format!("Seats [{}] are blocked", format_seat_ids(seats)),
+            Error::NotFreeSeating(coach) => format!("Coach {} does not have free seating", coach),
+            Error::NotEnoughPlaces(coach, remaining) => format!(
+                "Not enough places left in coach {}: {} remaining",
+                coach, remaining
+            ),
+            Error::NoStandingRoom => "This train has no standing room".to_string(),
+            Error::NotEnoughStandingRoom(remaining) => {
+                format!("Not enough standing room left: {} remaining", remaining)
+            }
+            Error::NotEnoughResources(kind, available) => {
+                format!("Not enough {} space left: {} available", kind, available)
+            }
+            Error::BookingDoesNotExist(booking_reference) => format!(
+                "Booking {} does not exist on this train",
+                booking_reference.as_str()
+            ),
+            Error::NothingToUpgrade(booking_reference) => format!(
+                "Booking {} has no second-class seats",
+                booking_reference.as_str()
+            ),
+            Error::UpgradeUnavailable(_) => {
+                "Not enough equivalent seats are free in first class".to_string()
+            }
+            Error::NoFareAvailable(seats) => {
+                format!("No fares available for seats [{}]", format_seat_ids(seats))
+            }
+            Error::CancellationClosed(train_id) => {
+                format!("Train {} departs too soon to cancel bookings", train_id)
+            }
+            Error::InvalidVoucher(code, reason) => format!("Voucher {} {}", code, reason),
+            Error::VoucherDoesNotExist(code) => format!("Voucher {} does not exist", code),
+            Error::CoachDoesNotExist(coach) => format!("Coach {} does not exist", coach),
+            Error::CoachesClosed(coaches) => format!("Coaches [{}] are closed", coaches.join(", ")),
+            Error::NotEnoughFreeSeats(seat_count) => {
+                format!("There are not {} free seats on this train", seat_count)
+            }
+            Error::RuleViolated(_, message) => message.clone(),
+            Error::ReservationNotAllowed(name) => format!("{} may not reserve seats", name),
+            Error::FeatureOff(flag) => format!("The {} feature is switched off", flag.name()),
+            Error::QuotaExceeded(allowance) => format!(
+                "Seat quota exceeded: {} of {} seats remaining",
+                allowance.remaining, allowance.limit
+            ),
+        }
+    }
+
+    // the status of the response for an error, and its error code
+    fn status(&self) -> (StatusCode, &'static str) {
+        match self {
+            Error::TrainDoesNotExist(..) => (StatusCode::NOT_FOUND, "train_not_found"),
+            Error::TrainArchived(..) => (StatusCode::CONFLICT, "train_archived"),
+            Error::TrainAlreadyExists(..) => (StatusCode::CONFLICT, "train_already_exists"),
+            Error::InvalidTrainSpec(..) => (StatusCode::UNPROCESSABLE_ENTITY, "invalid_train_spec"),
+            Error::TemplateDoesNotExist(..) => (StatusCode::NOT_FOUND, "template_not_found"),
+            Error::StoreFailed(..) => (StatusCode::INTERNAL_SERVER_ERROR, "store_failed"),
+            Error::InvalidSeatMapping(..) => {
+                (StatusCode::UNPROCESSABLE_ENTITY, "invalid_seat_mapping")
+            }
+            Error::InvalidSeatFilter(..) => (StatusCode::BAD_REQUEST, "invalid_seat_filter"),
+            Error::InvalidFields(..) => (StatusCode::BAD_REQUEST, "invalid_fields"),
+            Error::InvalidImport(..) => (StatusCode::UNPROCESSABLE_ENTITY, "invalid_import"),
+            Error::InvalidPolicy(..) => (StatusCode::UNPROCESSABLE_ENTITY, "invalid_policy"),
+            Error::SeatsAlreadyReserved(..) => (StatusCode::CONFLICT, "seats_already_reserved"),
+            Error::SeatsDoNotExist(..) => (StatusCode::BAD_REQUEST, "seats_not_found"),
+            Error::HoldTooLong(..) => (StatusCode::BAD_REQUEST, "hold_too_long"),
+            Error::ClockOverflow(..) => (StatusCode::BAD_REQUEST, "clock_overflow"),
+            Error::DuplicateSeats(..) => (StatusCode::BAD_REQUEST, "duplicate_seats"),
+            Error::SeatDoesNotExist(..) => (StatusCode::NOT_FOUND, "seat_not_found"),
+            Error::ReservationDoesNotExist(..) => (StatusCode::NOT_FOUND, "reservation_not_found"),
+            Error::ReservationCancelled(..) => (StatusCode::CONFLICT, "reservation_cancelled"),
+            Error::ReservationWithoutSeats => {
+                (StatusCode::BAD_REQUEST, "reservation_without_seats")
+            }
+            Error::SeatsAccessible(..) => (StatusCode::BAD_REQUEST, "seats_accessible"),
+            Error::SeatsBlocked(..) => (StatusCode::BAD_REQUEST, "seats_blocked"),
+            Error::NotFreeSeating(..) => (StatusCode::BAD_REQUEST, "not_free_seating"),
+            Error::NotEnoughPlaces(..) => (StatusCode::CONFLICT, "not_enough_places"),
+            Error::NoStandingRoom => (StatusCode::BAD_REQUEST, "no_standing_room"),
+            Error::NotEnoughStandingRoom(..) => (StatusCode::CONFLICT, "not_enough_standing_room"),
+            Error::NotEnoughResources(..) => (StatusCode::CONFLICT, "not_enough_resources"),
+            Error::BookingDoesNotExist(..) => (StatusCode::NOT_FOUND, "booking_not_found"),
+            Error::NothingToUpgrade(..) => (StatusCode::BAD_REQUEST, "nothing_to_upgrade"),
+            Error::UpgradeUnavailable(..) => (StatusCode::CONFLICT, "upgrade_unavailable"),
+            Error::NoFareAvailable(..) => (StatusCode::CONFLICT, "no_fare_available"),
+            Error::CancellationClosed(..) => (StatusCode::CONFLICT, "cancellation_closed"),
+            Error::InvalidVoucher(..) => (StatusCode::BAD_REQUEST, "invalid_voucher"),
+            Error::VoucherDoesNotExist(..) => (StatusCode::NOT_FOUND, "voucher_not_found"),
+            Error::CoachDoesNotExist(..) => (StatusCode::NOT_FOUND, "coach_not_found"),
+            Error::CoachesClosed(..) => (StatusCode::BAD_REQUEST, "coaches_closed"),
+            Error::NotEnoughFreeSeats(..) => (StatusCode::CONFLICT, "not_enough_free_seats"),
+            Error::RuleViolated(..) => (StatusCode::BAD_REQUEST, "rule_violated"),
+            Error::ReservationNotAllowed(..) => (StatusCode::FORBIDDEN, "reservation_not_allowed"),
+            Error::FeatureOff(..) => (StatusCode::NOT_FOUND, "feature_off"),
+            Error::QuotaExceeded(..) => (StatusCode::TOO_MANY_REQUESTS, "seat_quota_exceeded"),
+        }
+    }
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        let (status, error) = self.status();
+        let message = self.message();
+        match self {
+            Error::SeatsAlreadyReserved(_, alternatives) => (
+                status,
+                axum::Json(ConflictResponse {
+                    error,
+                    message,
+                    alternatives: alternatives.iter().map(SeatId::as_str).collect(),
+                }),
+            )
+                .into_response(),
+            Error::UpgradeUnavailable(missing) => (
+                status,
+                axum::Json(UpgradeErrorResponse {
+                    error,
+                    message,
+                    missing: &missing,
+                }),
+            )
+                .into_response(),
+            Error::QuotaExceeded(allowance) => (
+                status,
+                allowance_headers(allowance),
+                axum::Json(ErrorResponse { error, message }),
+            )
+                .into_response(),
+            _ => (status, axum::Json(ErrorResponse { error, message })).into_response(),
+        }
+    }
+}
+
+fn format_seat_ids(seats: &[SeatId]) -> String {
+    seats
+        .iter()
+        .map(|seat_id| seat_id.to_string())
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use axum_test::{TestServer, TestServerConfig};
+
+    use crate::{
+        alerts::NearlyFull,
+        api_keys::ApiKeys,
+        booking_reference::BookingReference,
+        clock::SimulatedClock,
+        extract::BodyError,
+        metrics::{Stats, TrainStats},
+        pricing::FixedPricing,
+        snapshot::ImportSummary,
+        store::DirectoryStore,
+        train::{SeatId, Train, TrainId, TrainsData},
+    };
+
+    // based around https://github.com/tokio-rs/axum/blob/main/examples/testing/src/main.rs
+    use super::*;
+
+    fn new_test_app() -> TestServer {
+        let app = app(AppState::new());
+        let config = TestServerConfig::builder()
+            .expect_success_by_default()
+            .mock_transport()
+            .build();
+
+        TestServer::new_with_config(app, config).unwrap()
+    }
+
+    fn new_test_app_failing() -> TestServer {
+        let app = app(AppState::new());
+        let config = TestServerConfig::builder().mock_transport().build();
+        TestServer::new_with_config(app, config).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_booking_reference() {
+        let server = new_test_app();
+
+        let response = server
+            .post("/booking_reference")
+            .await
+            .json::<BookingReference>();
+
+        assert_eq!(response, BookingReference::new("1"));
+    }
+
+    #[tokio::test]
+    async fn test_schema() {
+        let server = new_test_app();
+
+        let names = server.get("/schema").await.json::<Vec<String>>();
+        assert!(names.contains(&"train".to_string()));
+
+        let schema = server
+            .get("/schema/train")
+            .await
+            .json::<serde_json::Value>();
+        assert_eq!(schema["title"], "train");
+    }
+
+    #[tokio::test]
+    async fn test_schema_does_not_exist() {
+        let server = new_test_app_failing();
+
+        let response = server.get("/schema/does_not_exist").await;
+
+        assert_eq!(response.status_code(), 404);
+        assert_eq!(
+            response.json::<serde_json::Value>(),
+            serde_json::json!({
+                "error": "schema_not_found",
+                "message": "Schema does_not_exist does not exist",
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_train_local_1000_get() {
+        let server = new_test_app();
+
+        let train = server.get("/train/local_1000").await.json::<Train>();
+
+        let trains_str = include_str!("trains.json");
+        let trains: TrainsData = serde_json::from_str(trains_str).unwrap();
+        let local_2000 = trains.get(&TrainId::new("local_1000")).unwrap();
+
+        assert_eq!(&train, local_2000);
+    }
+
+    #[tokio::test]
+    async fn test_train_does_not_exist() {
+        let server = new_test_app_failing();
+
+        let response = server.get("/train/does_not_exist").await;
+
+        assert_eq!(response.status_code(), 404);
+        assert_eq!(
+            response.json::<serde_json::Value>(),
+            serde_json::json!({
+                "error": "train_not_found",
+                "message": "Train does_not_exist does not exist",
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reserve() {
+        let server = new_test_app();
+
+        let train = server
+            .post("/train/local_1000/reserve")
+            .json(&Reservation {
+                seats: vec![SeatId::new("1A")],
+                booking_reference: BookingReference::new("123456"),
+                accessible: false,
+            })
+            .await
+            .json::<Train>();
+
+        assert_eq!(
+            train
+                .get(&SeatId::new("1A"))
+                .unwrap()
+                .booking_reference()
+                .unwrap(),
+            &BookingReference::new("123456")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_availability() {
+        let server = new_test_app();
+
+        server
+            .post("/train/local_1000/reserve")
+            .json(&Reservation {
+                seats: vec![SeatId::new("1A"), SeatId::new("2A")],
+                booking_reference: BookingReference::new("123456"),
+                accessible: false,
+            })
+            .await;
+
+        let response = server.get("/train/local_1000/availability").await;
+        let occupancy = response.json::<serde_json::Value>();
+        assert_eq!(occupancy["reserved"], 2);
+        assert_eq!(occupancy["total"], 16);
+        assert_eq!(occupancy["coaches"]["A"]["reserved"], 2);
+        assert_eq!(occupancy["coaches"]["A"]["total"], 4);
+    }
+
+    #[tokio::test]
+    async fn test_reserve_seat_does_not_exist() {
+        let server = new_test_app_failing();
+
+        let response = server
+            .post("/train/local_1000/reserve")
+            .json(&Reservation {
+                seats: vec![SeatId::new("99Z")],
+                booking_reference: BookingReference::new("123456"),
+                accessible: false,
+            })
+            .await;
+
+        assert_eq!(response.status_code(), 400);
+        assert_eq!(
+            response.json::<serde_json::Value>()["message"],
+            "Seats [99Z] do not exist"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reserve_duplicate_seats() {
+        let server = new_test_app_failing();
+
+        let response = server
+            .post("/train/local_1000/reserve")
+            .json(&serde_json::json!({
+                "seats": ["1A", "1A"],
+                "booking_reference": "123456",
+            }))
+            .await;
+
+        assert_eq!(response.status_code(), 400);
+        assert_eq!(
+            response.json::<serde_json::Value>()["message"],
+            "Seats [1A] are asked for more than once"
+        );
+        let occupancy = server
+            .get("/train/local_1000/availability")
+            .await
+            .json::<serde_json::Value>();
+        assert_eq!(occupancy["reserved"], 0);
+        let response = server.get("/reservation/1").await;
+        assert_eq!(response.status_code(), 404);
+    }
+
+    #[tokio::test]
+    async fn test_reserve_without_seats() {
+        let server = new_test_app_failing();
+
+        let response = server
+            .post("/train/local_1000/reserve")
+            .json(&serde_json::json!({
+                "seats": [],
+                "booking_reference": "123456",
+            }))
+            .await;
+
+        assert_eq!(response.status_code(), 400);
+        assert_eq!(
+            response.json::<serde_json::Value>()["message"],
+            "A reservation needs at least one seat; cancel the booking to release all seats"
+        );
+        // no reservation was made
+        let response = server.get("/reservation/1").await;
+        assert_eq!(response.status_code(), 404);
+    }
+
+    #[tokio::test]
+    async fn test_reserve_seat_invalid_id() {
+        let server = new_test_app_failing();
+
+        let response = server
+            .post("/train/local_1000/reserve")
+            .json(&serde_json::json!({
+                "seats": ["does_not_exist"],
+                "booking_reference": "123456",
+            }))
+            .await;
+
+        assert_eq!(response.status_code(), 422);
+        let body_error = response.json::<BodyError>();
+        assert_eq!(body_error.path, Some("seats[0]".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_reserve_unknown_field() {
+        let server = new_test_app_failing();
+
+        let response = server
+            .post("/train/local_1000/reserve")
+            .json(&serde_json::json!({
+                "seats": ["1A"],
+                "booking_reference": "123456",
+                "seat_count": 1,
+            }))
+            .await;
+
+        assert_eq!(response.status_code(), 422);
+        let body_error = response.json::<BodyError>();
+        assert!(body_error.message.starts_with("unknown field `seat_count`"));
+        assert_eq!(body_error.line, Some(1));
+    }
+
+    #[tokio::test]
+    async fn test_reserve_wrong_type() {
+        let server = new_test_app_failing();
+
+        let response = server
+            .post("/train/local_1000/reserve")
+            .json(&serde_json::json!({
+                "seats": "1A",
+                "booking_reference": "123456",
+            }))
+            .await;
+
+        assert_eq!(response.status_code(), 422);
+        let body_error = response.json::<BodyError>();
+        assert_eq!(body_error.path, Some("seats".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_reserve_seat_already_reserved() {
+        let server = new_test_app_failing();
+
+        // make a reservation
+        server
+            .post("/train/local_1000/reserve")
+            .json(&Reservation {
+                seats: vec![SeatId::new("1A")],
+                booking_reference: BookingReference::new("123456"),
+                accessible: false,
+            })
+            .await;
+
+        // try to reserve again
+        let response = server
+            .post("/train/local_1000/reserve")
+            .json(&Reservation {
+                seats: vec![SeatId::new("1A")],
+                booking_reference: BookingReference::new("123456"),
+                accessible: false,
+            })
+            .await;
+
+        assert_eq!(response.status_code(), 409);
+        assert_eq!(
+            response.json::<serde_json::Value>(),
+            serde_json::json!({
+                "error": "seats_already_reserved",
+                "message": "Seats [1A] are already reserved",
+                "alternatives": ["2A", "3A", "4A"],
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reserve_reset() {
+        let server = new_test_app();
+
+        // make a reservation
+        let train = server
+            .post("/train/local_1000/reserve")
+            .json(&Reservation {
+                seats: vec![SeatId::new("1A")],
+                booking_reference: BookingReference::new("first"),
+                accessible: false,
+            })
+            .await
+            .json::<Train>();
+
+        // the first reservation is there
+        assert_eq!(
+            train
+                .get(&SeatId::new("1A"))
+                .unwrap()
+                .booking_reference()
+                .unwrap(),
+            &BookingReference::new("first")
+        );
+
+        // reset the train
+        let train = server.post("/train/local_1000/reset").await.json::<Train>();
+
+        // the reservation is gone
+        assert_eq!(
+            train.get(&SeatId::new("1A")).unwrap().booking_reference(),
+            None
+        );
+
+        // try to reserve again
+        let train = server
+            .post("/train/local_1000/reserve")
+            .json(&Reservation {
+                seats: vec![SeatId::new("1A")],
+                booking_reference: BookingReference::new("second"),
+                accessible: false,
+            })
+            .await
+            .json::<Train>();
+
+        // the second reservation is there
+        assert_eq!(
+            train
+                .get(&SeatId::new("1A"))
+                .unwrap()
+                .booking_reference()
+                .unwrap(),
+            &BookingReference::new("second")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_admin_train_create() {
+        let server = new_test_app();
+
+        let response = server
+            .post("/admin/train/generated")
+            .json(&serde_json::json!({
+                "coaches": [{"id": "A", "seats": 20}, {"id": "B", "seats": 30}],
+            }))
+            .await;
+        assert_eq!(response.status_code(), 201);
+
+        let occupancy = server
+            .get("/train/generated/availability")
+            .await
+            .json::<serde_json::Value>();
+        assert_eq!(occupancy["total"], 50);
+        assert_eq!(occupancy["coaches"]["B"]["total"], 30);
+
+        let train = server.get("/train/generated").await.json::<Train>();
+        assert!(train.get(&SeatId::new("20A")).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_admin_train_create_already_exists() {
+        let server = new_test_app_failing();
+
+        let response = server
+            .post("/admin/train/local_1000")
+            .json(&serde_json::json!({ "coaches": [{"id": "A", "seats": 2}] }))
+            .await;
+
+        assert_eq!(response.status_code(), 409);
+        assert_eq!(
+            response.json::<serde_json::Value>()["message"],
+            "Train local_1000 already exists"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_admin_train_create_invalid_spec() {
+        let server = new_test_app_failing();
+
+        let response = server
+            .post("/admin/train/generated")
+            .json(&serde_json::json!({
+                "coaches": [{"id": "A", "seats": 2}, {"id": "A", "seats": 2}],
+            }))
+            .await;
+
+        assert_eq!(response.status_code(), 422);
+        assert_eq!(
+            response.json::<serde_json::Value>()["message"],
+            "Invalid train spec: coach A appears more than once"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_admin_train_from_template() {
+        let server = new_test_app();
+
+        let response = server
+            .post("/admin/train/session_1/from_template/regional_3_coach")
+            .await;
+        assert_eq!(response.status_code(), 201);
+
+        let occupancy = server
+            .get("/train/session_1/availability")
+            .await
+            .json::<serde_json::Value>();
+        assert_eq!(occupancy["total"], 120);
+    }
+
+    #[tokio::test]
+    async fn test_admin_train_from_template_does_not_exist() {
+        let server = new_test_app_failing();
+
+        let response = server
+            .post("/admin/train/session_1/from_template/unknown")
+            .await;
+
+        assert_eq!(response.status_code(), 404);
+        assert_eq!(
+            response.json::<serde_json::Value>()["message"],
+            "Template unknown does not exist"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_admin_export_anonymized() {
+        let server = new_test_app();
+        for (train_id, seats, booking_reference) in [
+            ("local_1000", vec!["1A", "2A"], "abc"),
+            ("express_2000", vec!["1A"], "def"),
+        ] {
+            server
+                .post(&format!("/train/{}/reserve", train_id))
+                .json(
+                    &serde_json::json!({ "seats": seats, "booking_reference": booking_reference }),
+                )
+                .await;
+        }
+
+        let export = || async {
+            server
+                .get("/admin/export")
+                .add_query_param("anonymize", true)
+                .await
+                .json::<serde_json::Value>()
+        };
+        let first = export().await;
+        let reference = |export: &serde_json::Value, train_id: &str, seat_id: &str| {
+            export["trains"][train_id]["seats"][seat_id]["booking_reference"].clone()
+        };
+        let abc = reference(&first, "local_1000", "1A");
+        assert!(abc.as_str().unwrap().starts_with("anon-"));
+        assert_eq!(reference(&first, "local_1000", "2A"), abc);
+        assert_ne!(reference(&first, "express_2000", "1A"), abc);
+        assert_eq!(
+            reference(&first, "local_1000", "3A"),
+            serde_json::Value::Null
+        );
+        assert!(!first.to_string().contains("\"abc\""));
+        // the same pseudonyms in the next export
+        assert_eq!(reference(&export().await, "local_1000", "1A"), abc);
+    }
+
+    #[tokio::test]
+    async fn test_admin_export_import() {
+        let server = new_test_app();
+
+        server
+            .post("/train/local_1000/reserve")
+            .json(&Reservation {
+                seats: vec![SeatId::new("1A")],
+                booking_reference: BookingReference::new("first"),
+                accessible: false,
+            })
+            .await;
+        server.post("/booking_reference").await;
+        let export = server
+            .get("/admin/export")
+            .await
+            .json::<serde_json::Value>();
+        assert_eq!(export["booking_reference_counter"], 1);
+        assert_eq!(
+            export["trains"]["local_1000"]["seats"]["1A"]["booking_reference"],
+            "first"
+        );
+
+        // change things after the export
+        server.post("/train/local_1000/reset").await;
+        server.post("/booking_reference").await;
+        server
+            .post("/train/local_1000/reserve")
+            .json(&serde_json::json!({ "seats": ["2A"], "booking_reference": "later" }))
+            .await
+            .assert_status_ok();
+        server
+            .post("/train/local_1000/reserve")
+            .json(&serde_json::json!({ "seats": ["3A"], "booking_reference": "refunded" }))
+            .await
+            .assert_status_ok();
+        server
+            .post("/train/local_1000/cancel")
+            .json(&serde_json::json!({ "booking_reference": "refunded" }))
+            .await
+            .assert_status_ok();
+
+        let summary = server
+            .post("/admin/import")
+            .json(&export)
+            .await
+            .json::<ImportSummary>();
+        assert_eq!(
+            summary,
+            ImportSummary {
+                dry_run: false,
+                trains: 2,
+                seats: 32
+            }
+        );
+
+        // the state is back to what it was at export time
+        let train = server.get("/train/local_1000").await.json::<Train>();
+        assert_eq!(
+            train.get(&SeatId::new("1A")).unwrap().booking_reference(),
+            Some(&BookingReference::new("first"))
+        );
+        let reference = server
+            .post("/booking_reference")
+            .await
+            .json::<BookingReference>();
+        assert_eq!(reference, BookingReference::new("2"));
+        // and what happened since is gone, like after a reset
+        let reservation = server
+            .get("/reservation/2")
+            .await
+            .json::<serde_json::Value>();
+        assert_eq!(reservation["status"], "cancelled");
+        let history = server
+            .get("/train/local_1000/seat/2A/history")
+            .await
+            .json::<serde_json::Value>();
+        assert!(!history["history"][0]["released_at"].is_null());
+        let refunds = server.get("/refunds/refunded").await.json::<Vec<Refund>>();
+        assert!(refunds.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_admin_import_dry_run() {
+        let server = new_test_app();
+
+        let export = server
+            .get("/admin/export")
+            .await
+            .json::<serde_json::Value>();
+        server
+            .post("/train/local_1000/reserve")
+            .json(&Reservation {
+                seats: vec![SeatId::new("1A")],
+                booking_reference: BookingReference::new("kept"),
+                accessible: false,
+            })
+            .await;
+
+        let summary = server
+            .post("/admin/import")
+            .add_query_param("dry_run", true)
+            .json(&export)
+            .await
+            .json::<ImportSummary>();
+        assert!(summary.dry_run);
+
+        // nothing was replaced
+        let train = server.get("/train/local_1000").await.json::<Train>();
+        assert_eq!(
+            train.get(&SeatId::new("1A")).unwrap().booking_reference(),
+            Some(&BookingReference::new("kept"))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_admin_import_invalid() {
+        let server = new_test_app_failing();
+
+        let response = server
+            .post("/admin/import")
+            .json(&serde_json::json!({
+                "version": 99,
+                "booking_reference_counter": 0,
+                "trains": {},
+            }))
+            .await;
+
+        assert_eq!(response.status_code(), 422);
+        assert_eq!(
+            response.json::<serde_json::Value>()["message"],
+            "Invalid import: unsupported version 99, expected 1"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_metrics() {
+        let server = new_test_app();
+
+        server.get("/train/local_1000").await;
+        server
+            .post("/train/local_1000/reserve")
+            .json(&Reservation {
+                seats: vec![SeatId::new("1A"), SeatId::new("2A")],
+                booking_reference: BookingReference::new("123456"),
+                accessible: false,
+            })
+            .await;
+
+        let metrics = server.get("/metrics").await.text();
+        assert!(metrics.contains(
+            "train_service_requests_total{method=\"GET\",route=\"/train/:train_id\",status=\"200\"} 1\n"
+        ));
+        assert!(metrics.contains(
+            "train_service_request_duration_seconds_count{method=\"POST\",route=\"/train/:train_id/reserve\"} 1\n"
+        ));
+        assert!(
+            metrics.contains("train_service_train_occupancy_ratio{train=\"local_1000\"} 0.125\n")
+        );
+        assert!(metrics.contains("train_service_train_occupancy_ratio{train=\"express_2000\"} 0\n"));
+    }
+
+    #[tokio::test]
+    async fn test_stats() {
+        let server = new_test_app();
+
+        server
+            .post("/train/local_1000/reserve")
+            .json(&Reservation {
+                seats: vec![SeatId::new("1A"), SeatId::new("2A")],
+                booking_reference: BookingReference::new("123456"),
+                accessible: false,
+            })
+            .await;
+        server.get("/train/does_not_exist").expect_failure().await;
+
+        let stats = server.get("/stats").await.json::<Stats>();
+        assert_eq!(stats.requests, 2);
+        assert_eq!(stats.errors, 1);
+        assert_eq!(stats.reservations, 1);
+        assert_eq!(stats.reserved_seats, 2);
+        assert_eq!(
+            stats.trains["local_1000"],
+            TrainStats {
+                reserved: 2,
+                total: 16,
+                occupancy: 0.125,
+                standing: None,
+                fares: BTreeMap::new(),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reserve_too_many_seats() {
+        let app = app(AppState::new().with_policies(Policies {
+            max_seats_per_booking: Some(2),
+            ..Policies::default()
+        }));
+        let server =
+            TestServer::new_with_config(app, TestServerConfig::builder().mock_transport().build())
+                .unwrap();
+        let reserve = |seat_ids: &[&str]| Reservation {
+            seats: seat_ids
+                .iter()
+                .map(|seat_id| SeatId::new(seat_id))
+                .collect(),
+            booking_reference: BookingReference::new("123456"),
+            accessible: false,
+        };
+
+        server
+            .post("/train/local_1000/reserve")
+            .json(&reserve(&["1A"]))
+            .expect_success()
+            .await;
+        let response = server
+            .post("/train/local_1000/reserve")
+            .json(&reserve(&["2A", "3A"]))
+            .expect_failure()
+            .await;
+        assert_eq!(response.status_code(), 400);
+        assert_eq!(
+            response.json::<serde_json::Value>()["message"],
+            "Booking reference 123456 may hold at most 2 seats on this train"
+        );
+        // the limit is per train
+        server
+            .post("/train/express_2000/reserve")
+            .json(&reserve(&["1A", "2A"]))
+            .expect_success()
+            .await;
+    }
+
+    #[tokio::test]
+    async fn test_admin_flags() {
+        let server = new_test_app();
+        let reserve = serde_json::json!({ "train_id": "local_1000", "seat_count": 1 });
+        let hold = serde_json::json!({ "seats": ["1A"], "booking_reference": "123456" });
+
+        let flags = server.get("/admin/flags").await.json::<Flags>();
+        assert_eq!(flags, Flags::default());
+
+        let response = server
+            .put("/admin/flags")
+            .json(&serde_json::json!({ "auto_allocation": false }))
+            .await;
+        assert_eq!(
+            response.json::<Flags>(),
+            Flags {
+                auto_allocation: false,
+                holds: true,
+            }
+        );
+        let response = server
+            .post("/reserve")
+            .json(&reserve)
+            .expect_failure()
+            .await;
+        assert_eq!(response.status_code(), 404);
+        assert_eq!(
+            response.json::<serde_json::Value>()["message"],
+            "The auto_allocation feature is switched off"
+        );
+        server
+            .post("/train/local_1000/hold")
+            .json(&hold)
+            .expect_success()
+            .await;
+
+        server
+            .put("/admin/flags")
+            .json(&serde_json::json!({ "holds": false }))
+            .await;
+        server
+            .post("/reserve")
+            .json(&reserve)
+            .expect_success()
+            .await;
+        let response = server
+            .post("/train/express_2000/hold")
+            .json(&hold)
+            .expect_failure()
+            .await;
+        assert_eq!(response.status_code(), 404);
+
+        let response = server
+            .put("/admin/flags")
+            .json(&serde_json::json!({ "webhooks": true }))
+            .expect_failure()
+            .await;
+        assert_eq!(response.status_code(), 422);
+    }
+
+    struct TestPlugin;
+
+    impl ServicePlugin for TestPlugin {
+        fn name(&self) -> &'static str {
+            "test"
+        }
+
+        fn routes(&self) -> axum::Router {
+            axum::Router::new().route("/hello", get(|| async { "Hello" }))
+        }
+
+        fn rules(&self, rules: &mut Rules) {
+            rules.add(NoSeat1A);
+        }
+    }
+
+    struct NoSeat1A;
+
+    impl crate::rules::ReservationRule for NoSeat1A {
+        fn name(&self) -> &'static str {
+            "no_seat_1a"
+        }
+
+        fn check(&self, _train: &Train, reservation: &Reservation) -> Result<(), String> {
+            if reservation.seats.contains(&SeatId::new("1A")) {
+                return Err("Seat 1A is kept free".to_string());
+            }
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_plugin() {
+        let app = app(AppState::new().with_plugin(TestPlugin));
+        let server =
+            TestServer::new_with_config(app, TestServerConfig::builder().mock_transport().build())
+                .unwrap();
+        assert_eq!(server.get("/hello").await.text(), "Hello");
+
+        let reservation = |seat_id| Reservation {
+            seats: vec![SeatId::new(seat_id)],
+            booking_reference: BookingReference::new("123456"),
+            accessible: false,
+        };
+        let response = server
+            .post("/train/local_1000/reserve")
+            .json(&reservation("1A"))
+            .await;
+        assert_eq!(response.status_code(), 400);
+        // the rules of plugins stay when the policies change
+        server
+            .put("/admin/policies")
+            .json(&Policies::default())
+            .await;
+        let response = server
+            .post("/train/local_1000/reserve")
+            .json(&reservation("1A"))
+            .await;
+        assert_eq!(response.status_code(), 400);
+        let response = server
+            .post("/train/local_1000/reserve")
+            .json(&reservation("2A"))
+            .await;
+        assert_eq!(response.status_code(), 200);
+    }
+
+    #[tokio::test]
+    async fn test_admin_policies() {
+        let server = new_test_app();
+
+        let policies = server.get("/admin/policies").await.json::<Policies>();
+        assert_eq!(policies, Policies::default());
+
+        let policies = Policies {
+            same_coach: true,
+            ..Policies::default()
+        };
+        let response = server.put("/admin/policies").json(&policies).await;
+        assert_eq!(response.json::<Policies>(), policies);
+        assert_eq!(
+            server.get("/admin/policies").await.json::<Policies>(),
+            policies
+        );
+
+        let response = server
+            .post("/train/local_1000/reserve")
+            .json(&Reservation {
+                seats: vec![SeatId::new("1A"), SeatId::new("1B")],
+                booking_reference: BookingReference::new("123456"),
+                accessible: false,
+            })
+            .expect_failure()
+            .await;
+        assert_eq!(response.status_code(), 400);
+        assert_eq!(
+            response.json::<serde_json::Value>()["message"],
+            "All seats of a reservation must be in the same coach"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_admin_policies_invalid() {
+        let server = new_test_app_failing();
+        let response = server
+            .put("/admin/policies")
+            .json(&serde_json::json!({ "max_occupancy": 70 }))
+            .await;
+        assert_eq!(response.status_code(), 422);
+        assert_eq!(
+            response.json::<serde_json::Value>()["message"],
+            "Invalid policy: max_occupancy must be between 0 and 1, not 70"
+        );
+        let response = server
+            .put("/admin/policies")
+            .json(&serde_json::json!({ "threshold": 0.7 }))
+            .await;
+        assert_eq!(response.status_code(), 422);
+    }
+
+    fn new_test_app_with_stack(stack: serde_json::Value) -> TestServer {
+        let stack: Stack = serde_json::from_value(stack).unwrap();
+        let app = app(AppState::new().with_stack(stack));
+        TestServer::new_with_config(app, TestServerConfig::builder().mock_transport().build())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_envelope_by_default() {
+        let app = app(AppState::new().with_envelope(true));
+        let server =
+            TestServer::new_with_config(app, TestServerConfig::builder().mock_transport().build())
+                .unwrap();
+
+        let response = server.get("/train/local_1000").await;
+        let envelope = response.json::<crate::envelope::Envelope>();
+        assert_eq!(envelope.error, None);
+        assert_eq!(
+            envelope.data.unwrap()["seats"]["1A"]["seat_number"],
+            serde_json::json!("1")
+        );
+
+        let response = server.get("/train/unknown").expect_failure().await;
+        assert_eq!(response.status_code(), 404);
+        let envelope = response.json::<crate::envelope::Envelope>();
+        assert_eq!(
+            response.header("x-correlation-id"),
+            envelope.meta.request_id
+        );
+        assert_eq!(
+            envelope.error,
+            Some(serde_json::json!({
+                "error": "train_not_found",
+                "message": "Train unknown does not exist",
+            }))
+        );
+        let response = server.get("/nowhere").expect_failure().await;
+        assert_eq!(
+            response.json::<crate::envelope::Envelope>().error.unwrap()["error"],
+            serde_json::json!("not_found")
+        );
+
+        let response = server
+            .get("/train/local_1000")
+            .add_header(
+                axum::http::HeaderName::from_static("x-envelope"),
+                axum::http::HeaderValue::from_static("false"),
+            )
+            .await;
+        assert!(response.json::<Train>().get(&SeatId::new("1A")).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_body_not_json() {
+        let server = new_test_app();
+
+        let response = server
+            .post("/train/local_1000/reserve")
+            .text(r#"{"seats": ["1A"], "booking_reference": "123456"}"#)
+            .expect_failure()
+            .await;
+        assert_eq!(response.status_code(), 415);
+        assert_eq!(
+            response.json::<BodyError>().message,
+            "Expected request with `Content-Type: application/json`, not `text/plain`"
+        );
+        // also where the body would be ignored
+        let response = server
+            .post("/booking_reference")
+            .text("please")
+            .expect_failure()
+            .await;
+        assert_eq!(response.status_code(), 415);
+        server.post("/booking_reference").expect_success().await;
+    }
+
+    #[tokio::test]
+    async fn test_middleware_stack() {
+        let server = new_test_app_with_stack(serde_json::json!({
+            "rate_limit": { "requests": 1, "per_seconds": 60 },
+            "cors": { "origins": ["*"] },
+        }));
+        let origin = || axum::http::HeaderValue::from_static("http://localhost:3000");
+        let response = server
+            .get("/train/local_1000")
+            .add_header(header::ORIGIN, origin())
+            .await;
+        assert_eq!(response.header("access-control-allow-origin"), "*");
+        // refused requests can be read by browsers too
+        let response = server
+            .get("/train/local_1000")
+            .add_header(header::ORIGIN, origin())
+            .expect_failure()
+            .await;
+        assert_eq!(response.status_code(), 429);
+        assert_eq!(response.header("access-control-allow-origin"), "*");
+    }
+
+    #[tokio::test]
+    async fn test_compression_stack() {
+        let server = new_test_app_with_stack(serde_json::json!({ "compression": {} }));
+        let response = server
+            .get("/train/local_1000")
+            .add_header(
+                header::ACCEPT_ENCODING,
+                axum::http::HeaderValue::from_static("gzip"),
+            )
+            .await;
+        assert_eq!(response.header("content-encoding"), "gzip");
+    }
+
+    #[tokio::test]
+    async fn test_backpressure_metrics() {
+        let server = new_test_app_with_stack(serde_json::json!({
+            "backpressure": { "max_queue": 10 },
+        }));
+        server.get("/train/local_1000").await;
+        let metrics = server.get("/metrics").await.text();
+        assert!(metrics.contains("train_service_queue_depth 0\n"));
+        assert!(metrics.contains("train_service_queue_rejected_total 0\n"));
+    }
+
+    #[tokio::test]
+    async fn test_deprecations() {
+        let deprecations: Deprecations = serde_json::from_value(serde_json::json!([
+            {
+                "route": "/train/:train_id",
+                "method": "GET",
+                "since": 1767225600,
+                "sunset": 1798761599,
+                "link": "https://example.com/migrating",
+            },
+            { "route": "/booking_reference", "since": 1767225600 },
+        ]))
+        .unwrap();
+        let app = app(AppState::new().with_deprecations(Some(deprecations)));
+        let server =
+            TestServer::new_with_config(app, TestServerConfig::builder().mock_transport().build())
+                .unwrap();
+
+        let response = server.get("/train/local_1000").await;
+        assert_eq!(response.status_code(), 200);
+        assert_eq!(response.header("deprecation"), "@1767225600");
+        assert_eq!(response.header("sunset"), "Thu, 31 Dec 2026 23:59:59 GMT");
+        assert_eq!(
+            response.header("link"),
+            "<https://example.com/migrating>; rel=\"deprecation\""
+        );
+
+        let response = server.post("/booking_reference").await;
+        assert_eq!(response.header("deprecation"), "@1767225600");
+        assert!(response.maybe_header("sunset").is_none());
+        assert!(response.maybe_header("link").is_none());
+
+        let response = server.get("/train/local_1000/seat/1A").await;
+        assert!(response.maybe_header("deprecation").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_api_key_quotas() {
+        let api_keys: ApiKeys = serde_json::from_value(serde_json::json!({
+            "agency-secret": { "name": "agency", "seat_quota": 3 },
+            "viewer-secret": { "name": "viewer", "seat_quota": 0 },
+        }))
+        .unwrap();
+        let app = app(AppState::new().with_api_keys(Some(api_keys)));
+        let server =
+            TestServer::new_with_config(app, TestServerConfig::builder().mock_transport().build())
+                .unwrap();
+        let reservation = |seat_ids: &[&str]| Reservation {
+            seats: seat_ids
+                .iter()
+                .map(|seat_id| SeatId::new(seat_id))
+                .collect(),
+            booking_reference: BookingReference::new("123456"),
+            accessible: false,
+        };
+
+        let response = server.get("/train/local_1000").await;
+        assert_eq!(response.status_code(), 401);
+        let response = server
+            .get("/train/local_1000")
+            .add_header("x-api-key".parse().unwrap(), "wrong".parse().unwrap())
+            .await;
+        assert_eq!(response.status_code(), 401);
+
+        let response = server
+            .post("/train/local_1000/reserve")
+            .add_header(
+                "x-api-key".parse().unwrap(),
+                "agency-secret".parse().unwrap(),
+            )
+            .json(&reservation(&["1A", "2A"]))
+            .await;
+        assert_eq!(response.status_code(), 200);
+        assert_eq!(response.header("x-quota-limit"), "3");
+        assert_eq!(response.header("x-quota-remaining"), "1");
+
+        let response = server
+            .post("/train/local_1000/reserve")
+            .add_header(
+                "x-api-key".parse().unwrap(),
+                "agency-secret".parse().unwrap(),
+            )
+            .json(&reservation(&["3A", "4A"]))
+            .await;
+        assert_eq!(response.status_code(), 429);
+        assert_eq!(response.header("x-quota-remaining"), "1");
+        assert_eq!(
+            response.json::<serde_json::Value>()["message"],
+            "Seat quota exceeded: 1 of 3 seats remaining"
+        );
+        // seats added to a reservation count too
+        let change_seats = |seats: serde_json::Value| {
+            server
+                .put("/reservation/1/seats")
+                .add_header(
+                    "x-api-key".parse().unwrap(),
+                    "agency-secret".parse().unwrap(),
+                )
+                .json(&serde_json::json!({ "seats": seats }))
+        };
+        let response = change_seats(serde_json::json!(["1A", "2A", "3A", "4A"])).await;
+        assert_eq!(response.status_code(), 429);
+        let response = change_seats(serde_json::json!(["2A", "3A"])).await;
+        assert_eq!(response.status_code(), 200);
+        assert_eq!(response.header("x-quota-remaining"), "0");
+
+        let response = server
+            .post("/train/local_1000/reserve")
+            .add_header(
+                "x-api-key".parse().unwrap(),
+                "viewer-secret".parse().unwrap(),
+            )
+            .json(&reservation(&["3A"]))
+            .await;
+        assert_eq!(response.status_code(), 403);
+        let response = server
+            .get("/train/local_1000")
+            .add_header(
+                "x-api-key".parse().unwrap(),
+                "viewer-secret".parse().unwrap(),
+            )
+            .await;
+        assert_eq!(response.status_code(), 200);
+    }
+
+    #[tokio::test]
+    async fn test_holds_count_against_quotas() {
+        let api_keys: ApiKeys = serde_json::from_value(serde_json::json!({
+            "agency-secret": { "name": "agency", "seat_quota": 3 },
+        }))
+        .unwrap();
+        let app = app(AppState::new().with_api_keys(Some(api_keys)));
+        let server =
+            TestServer::new_with_config(app, TestServerConfig::builder().mock_transport().build())
+                .unwrap();
+        let post = |path: &str, seats: &[&str]| {
+            server
+                .post(path)
+                .add_header(
+                    "x-api-key".parse().unwrap(),
+                    "agency-secret".parse().unwrap(),
+                )
+                .json(&serde_json::json!({ "seats": seats, "booking_reference": "abc" }))
+        };
+
+        let response = post("/train/local_1000/hold", &["1A", "2A"]).await;
+        response.assert_status_ok();
+        assert_eq!(response.header("x-quota-remaining"), "1");
+        // reserving the held seats doesn't count them again
+        let response = post("/train/local_1000/reserve", &["1A", "2A"]).await;
+        response.assert_status_ok();
+        assert_eq!(response.header("x-quota-remaining"), "1");
+        let response = post("/train/local_1000/hold", &["3A", "4A"]).await;
+        assert_eq!(response.status_code(), 429);
+    }
+
+    #[tokio::test]
+    async fn test_reserve_auto_allocated() {
+        let server = new_test_app();
+
+        let response = server
+            .post("/reserve")
+            .json(&serde_json::json!({
+                "train_id": "local_1000",
+                "seat_count": 2,
+                "preferences": { "near": "1B" },
+            }))
+            .await;
+        assert_eq!(
+            response.json::<serde_json::Value>(),
+            serde_json::json!({
+                "train_id": "local_1000",
+                "booking_reference": "1",
+                "seats": ["1B", "2B"],
+                "preferences_met": { "near": true },
+                "spread": 1,
+                "reservation_id": 1,
+            })
+        );
+
+        let train = server.get("/train/local_1000").await.json::<Train>();
+        assert_eq!(
+            train.get(&SeatId::new("2B")).unwrap().booking_reference(),
+            Some(&BookingReference::new("1"))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reserve_auto_allocated_not_enough_seats() {
+        let server = new_test_app_failing();
+        let response = server
+            .post("/reserve")
+            .json(&serde_json::json!({ "train_id": "local_1000", "seat_count": 17 }))
+            .await;
+        assert_eq!(response.status_code(), 409);
+        assert_eq!(
+            response.json::<serde_json::Value>()["message"],
+            "There are not 17 free seats on this train"
+        );
+        let response = server
+            .post("/reserve")
+            .json(&serde_json::json!({ "train_id": "does_not_exist", "seat_count": 1 }))
+            .await;
+        assert_eq!(response.status_code(), 404);
+        let response = server
+            .post("/reserve")
+            .json(&serde_json::json!({
+                "train_id": "local_1000",
+                "seat_count": 1,
+                "voucher": "UNKNOWN",
+            }))
+            .await;
+        assert_eq!(response.status_code(), 400);
+
+        // the requests that were turned away didn't use up booking references
+        let response = server
+            .post("/reserve")
+            .json(&serde_json::json!({ "train_id": "local_1000", "seat_count": 1 }))
+            .await;
+        assert_eq!(
+            response.json::<serde_json::Value>()["booking_reference"],
+            "1"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_admin_seat_block() {
+        let server = new_test_app();
+
+        let train = server
+            .post("/admin/train/local_1000/seat/1A/block")
+            .json(&serde_json::json!({ "reason": "crew" }))
+            .await
+            .json::<serde_json::Value>();
+        assert_eq!(train["seats"]["1A"]["blocked"], "crew");
+        let availability = server
+            .get("/train/local_1000/availability")
+            .await
+            .json::<serde_json::Value>();
+        assert_eq!(availability["total"], 15);
+
+        let response = server
+            .post("/train/local_1000/reserve")
+            .json(&Reservation {
+                seats: vec![SeatId::new("1A")],
+                booking_reference: BookingReference::new("123456"),
+                accessible: false,
+            })
+            .expect_failure()
+            .await;
+        assert_eq!(response.status_code(), 400);
+        assert_eq!(
+            response.json::<serde_json::Value>()["message"],
+            "Seats [1A] are blocked"
+        );
+
+        let train = server
+            .post("/admin/train/local_1000/seat/1A/unblock")
+            .await
+            .json::<serde_json::Value>();
+        assert!(train["seats"]["1A"].get("blocked").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_admin_coach_close() {
+        let server = new_test_app();
+
+        let train = server
+            .post("/admin/train/local_1000/coach/A/close")
+            .await
+            .json::<serde_json::Value>();
+        assert_eq!(
+            train["coaches"]["A"],
+            serde_json::json!({ "quiet": false, "closed": true })
+        );
+        let availability = server
+            .get("/train/local_1000/availability")
+            .await
+            .json::<serde_json::Value>();
+        assert_eq!(availability["total"], 12);
+
+        let response = server
+            .post("/train/local_1000/reserve")
+            .json(&Reservation {
+                seats: vec![SeatId::new("1A")],
+                booking_reference: BookingReference::new("123456"),
+                accessible: false,
+            })
+            .expect_failure()
+            .await;
+        assert_eq!(response.status_code(), 400);
+        assert_eq!(
+            response.json::<serde_json::Value>()["message"],
+            "Coaches [A] are closed"
+        );
+
+        let train = server
+            .post("/admin/train/local_1000/coach/A/open")
+            .await
+            .json::<serde_json::Value>();
+        assert!(train.get("coaches").is_none());
+
+        let response = server
+            .post("/admin/train/local_1000/coach/Z/close")
+            .expect_failure()
+            .await;
+        assert_eq!(response.status_code(), 404);
+    }
+
+    #[tokio::test]
+    async fn test_coach_reserve_free_seating() {
+        let trains: TrainsData = serde_json::from_value(serde_json::json!({
+            "commuter": {
+                "seats": {},
+                "coaches": { "F": { "free_seating": { "capacity": 3 } } },
+            },
+        }))
+        .unwrap();
+        let app = app(AppState::new().with_trains(trains));
+        let server =
+            TestServer::new_with_config(app, TestServerConfig::builder().mock_transport().build())
+                .unwrap();
+
+        let response = server
+            .post("/train/commuter/coach/F/reserve")
+            .json(&serde_json::json!({ "places": 2, "booking_reference": "abc" }))
+            .await;
+        assert_eq!(
+            response.json::<serde_json::Value>(),
+            serde_json::json!({
+                "train_id": "commuter",
+                "coach": "F",
+                "booking_reference": "abc",
+                "places": 2,
+                "remaining": 1,
+            })
+        );
+
+        let response = server
+            .post("/train/commuter/coach/F/reserve")
+            .json(&serde_json::json!({ "places": 2, "booking_reference": "def" }))
+            .await;
+        assert_eq!(response.status_code(), 409);
+        assert_eq!(
+            response.json::<serde_json::Value>()["message"],
+            "Not enough places left in coach F: 1 remaining"
+        );
+
+        let response = server
+            .post("/train/commuter/coach/A/reserve")
+            .json(&serde_json::json!({ "places": 1, "booking_reference": "def" }))
+            .await;
+        assert_eq!(response.status_code(), 400);
+
+        // the places are kept under the booking reference, and go with it
+        let response = server
+            .post("/train/commuter/cancel")
+            .json(&serde_json::json!({ "booking_reference": "abc" }))
+            .await;
+        response.assert_status_ok();
+        assert_eq!(response.json::<serde_json::Value>()["places"], 2);
+        server
+            .put("/admin/policies")
+            .json(&serde_json::json!({ "max_seats_per_booking": 1 }))
+            .await
+            .assert_status_ok();
+        let response = server
+            .post("/train/commuter/coach/F/reserve")
+            .json(&serde_json::json!({ "places": 2, "booking_reference": "def" }))
+            .await;
+        assert_eq!(
+            response.json::<serde_json::Value>()["error"],
+            "rule_violated"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reserve_standing() {
+        let trains: TrainsData = serde_json::from_value(serde_json::json!({
+            "commuter": {
+                "seats": {
+                    "1A": { "seat_number": "1", "coach": "A", "booking_reference": null },
+                },
+                "standing": { "capacity": 3 },
+            },
+        }))
+        .unwrap();
+        let app = app(AppState::new().with_trains(trains));
+        let server =
+            TestServer::new_with_config(app, TestServerConfig::builder().mock_transport().build())
+                .unwrap();
+
+        let response = server
+            .post("/train/commuter/reserve_standing")
+            .json(&serde_json::json!({ "places": 2, "booking_reference": "abc" }))
+            .await;
+        assert_eq!(
+            response.json::<serde_json::Value>(),
+            serde_json::json!({
+                "train_id": "commuter",
+                "booking_reference": "abc",
+                "places": 2,
+                "remaining": 1,
+            })
+        );
+
+        let response = server
+            .post("/train/commuter/reserve_standing")
+            .json(&serde_json::json!({ "places": 2, "booking_reference": "def" }))
+            .await;
+        assert_eq!(response.status_code(), 409);
+        assert_eq!(
+            response.json::<serde_json::Value>()["message"],
+            "Not enough standing room left: 1 remaining"
+        );
+
+        let response = server.get("/train/commuter/availability").await;
+        assert_eq!(
+            response.json::<serde_json::Value>(),
+            serde_json::json!({
+                "reserved": 0,
+                "total": 1,
+                "coaches": { "A": { "reserved": 0, "total": 1 } },
+                "standing": { "reserved": 2, "total": 3 },
+            })
+        );
+
+        let response = server.get("/stats").await;
+        assert_eq!(
+            response.json::<serde_json::Value>()["trains"]["commuter"]["standing"],
+            serde_json::json!({ "reserved": 2, "total": 3 })
+        );
+
+        let response = server.get("/train/commuter").await;
+        assert_eq!(
+            response.json::<serde_json::Value>()["standing"],
+            serde_json::json!({ "capacity": 3, "reserved": 2 })
+        );
+
+        let response = server
+            .post("/train/local_1000/reserve_standing")
+            .json(&serde_json::json!({ "places": 1, "booking_reference": "abc" }))
+            .await;
+        assert_eq!(response.status_code(), 404);
+
+        let response = server.post("/admin/cancel/abc").await;
+        response.assert_status_ok();
+        assert_eq!(response.json::<serde_json::Value>()["places"], 2);
+        let response = server.get("/train/commuter").await;
+        assert_eq!(
+            response.json::<serde_json::Value>()["standing"],
+            serde_json::json!({ "capacity": 3, "reserved": 0 })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_resources() {
+        let trains: TrainsData = serde_json::from_value(serde_json::json!({
+            "regional": {
+                "seats": {
+                    "1A": { "seat_number": "1", "coach": "A", "booking_reference": null },
+                },
+                "resources": {
+                    "bike1": { "kind": "bicycle", "coach": "A" },
+                    "rack1": { "kind": "luggage", "coach": "A" },
+                },
+            },
+        }))
+        .unwrap();
+        let app = app(AppState::new().with_trains(trains));
+        let server =
+            TestServer::new_with_config(app, TestServerConfig::builder().mock_transport().build())
+                .unwrap();
+
+        server
+            .post("/train/regional/reserve")
+            .json(&serde_json::json!({ "seats": ["1A"], "booking_reference": "abc" }))
+            .await
+            .assert_status_ok();
+        let response = server
+            .post("/train/regional/resources/bicycle/reserve")
+            .json(&serde_json::json!({ "count": 1, "booking_reference": "abc" }))
+            .await;
+        assert_eq!(
+            response.json::<serde_json::Value>(),
+            serde_json::json!({
+                "train_id": "regional",
+                "booking_reference": "abc",
+                "kind": "bicycle",
+                "resources": ["bike1"],
+            })
+        );
+
+        let response = server
+            .post("/train/regional/resources/bicycle/reserve")
+            .json(&serde_json::json!({ "count": 1, "booking_reference": "def" }))
+            .await;
+        assert_eq!(response.status_code(), 409);
+        assert_eq!(
+            response.json::<serde_json::Value>()["message"],
+            "Not enough bicycle space left: 0 available"
+        );
+
+        let response = server
+            .get("/train/regional/resources/luggage/availability")
+            .await;
+        assert_eq!(
+            response.json::<serde_json::Value>(),
+            serde_json::json!({
+                "reserved": 0,
+                "total": 1,
+                "coaches": { "A": { "reserved": 0, "total": 1 } },
+            })
+        );
+
+        let response = server.get("/train/regional").await;
+        assert_eq!(
+            response.json::<serde_json::Value>()["resources"]["bike1"],
+            serde_json::json!({ "kind": "bicycle", "coach": "A", "booking_reference": "abc" })
+        );
+
+        let response = server
+            .get("/train/regional/resources/piano/availability")
+            .await;
+        assert_eq!(response.status_code(), 400);
+
+        let response = server
+            .post("/train/regional/cancel")
+            .json(&serde_json::json!({ "booking_reference": "abc" }))
+            .await;
+        assert_eq!(
+            response.json::<serde_json::Value>()["resources"],
+            serde_json::json!(["bike1"])
+        );
+        let response = server
+            .get("/train/regional/resources/bicycle/availability")
+            .await;
+        assert_eq!(response.json::<serde_json::Value>()["reserved"], 0);
+
+        // a booking that only holds resources can be cancelled too
+        server
+            .post("/train/regional/resources/luggage/reserve")
+            .json(&serde_json::json!({ "count": 1, "booking_reference": "def" }))
+            .await
+            .assert_status_ok();
+        let response = server.post("/admin/cancel/def").await;
+        response.assert_status_ok();
+        assert_eq!(response.json::<serde_json::Value>()["resources"], 1);
+        let response = server
+            .get("/train/regional/resources/luggage/availability")
+            .await;
+        assert_eq!(response.json::<serde_json::Value>()["reserved"], 0);
+    }
+
+    #[tokio::test]
+    async fn test_availability_quiet() {
+        let trains: TrainsData = serde_json::from_value(serde_json::json!({
+            "regional": {
+                "seats": {
+                    "1A": { "seat_number": "1", "coach": "A", "booking_reference": null },
+                    "1B": { "seat_number": "1", "coach": "B", "booking_reference": "abc" },
+                    "2B": { "seat_number": "2", "coach": "B", "booking_reference": null },
+                },
+                "coaches": { "B": { "quiet": true } },
+            },
+        }))
+        .unwrap();
+        let app = app(AppState::new().with_trains(trains));
+        let server =
+            TestServer::new_with_config(app, TestServerConfig::builder().mock_transport().build())
+                .unwrap();
+
+        let response = server
+            .get("/train/regional/availability")
+            .add_query_param("quiet", true)
+            .await;
+        assert_eq!(
+            response.json::<serde_json::Value>(),
+            serde_json::json!({
+                "reserved": 1,
+                "total": 2,
+                "coaches": { "B": { "reserved": 1, "total": 2 } },
+            })
+        );
+        let response = server
+            .get("/train/regional/availability")
+            .add_query_param("quiet", false)
+            .await;
+        assert_eq!(response.json::<serde_json::Value>()["total"], 1);
+        let response = server.get("/train/regional/availability").await;
+        assert_eq!(response.json::<serde_json::Value>()["total"], 3);
+
+        let response = server.get("/train/regional").await;
+        let train = response.json::<serde_json::Value>();
+        assert_eq!(train["seats"]["1B"]["quiet"], true);
+        assert_eq!(train["seats"]["1A"].get("quiet"), None);
+    }
+
+    #[tokio::test]
+    async fn test_train_upgrade() {
+        let trains: TrainsData = serde_json::from_value(serde_json::json!({
+            "regional": {
+                "seats": {
+                    "1A": { "seat_number": "1", "coach": "A", "booking_reference": "abc", "position": "window" },
+                    "2A": { "seat_number": "2", "coach": "A", "booking_reference": "def", "position": "aisle" },
+                    "1F": { "seat_number": "1", "coach": "F", "booking_reference": null, "position": "window" },
+                },
+                "coaches": { "F": { "class": "first" } },
+            },
+        }))
+        .unwrap();
+        let app = app(AppState::new().with_trains(trains));
+        let server =
+            TestServer::new_with_config(app, TestServerConfig::builder().mock_transport().build())
+                .unwrap();
+
+        let response = server
+            .post("/train/regional/upgrade")
+            .json(&serde_json::json!({ "booking_reference": "def" }))
+            .await;
+        assert_eq!(response.status_code(), 409);
+        assert_eq!(
+            response.json::<serde_json::Value>(),
+            serde_json::json!({
+                "error": "upgrade_unavailable",
+                "message": "Not enough equivalent seats are free in first class",
+                "missing": [{ "position": "aisle", "accessible": false, "seats": 1 }],
+            })
+        );
+
+        let response = server
+            .post("/train/regional/upgrade")
+            .json(&serde_json::json!({ "booking_reference": "abc" }))
+            .await;
+        assert_eq!(
+            response.json::<serde_json::Value>(),
+            serde_json::json!({
+                "train_id": "regional",
+                "booking_reference": "abc",
+                "seats": { "1A": "1F" },
+            })
+        );
+
+        let response = server
+            .post("/train/regional/upgrade")
+            .json(&serde_json::json!({ "booking_reference": "xyz" }))
+            .await;
+        assert_eq!(response.status_code(), 404);
+    }
+
+    #[tokio::test]
+    async fn test_fares() {
+        let trains: TrainsData = serde_json::from_value(serde_json::json!({
+            "regional": {
+                "seats": {
+                    "1A": { "seat_number": "1", "coach": "A", "booking_reference": null },
+                    "2A": { "seat_number": "2", "coach": "A", "booking_reference": null },
+                    "1B": { "seat_number": "1", "coach": "B", "booking_reference": null },
+                },
+                "fares": [
+                    { "name": "standard", "price": 4500 },
+                    { "name": "saver", "price": 1900, "seats_per_coach": 1 },
+                ],
+            },
+        }))
+        .unwrap();
+        let app = app(AppState::new().with_trains(trains));
+        let server =
+            TestServer::new_with_config(app, TestServerConfig::builder().mock_transport().build())
+                .unwrap();
+
+        let response = server
+            .post("/train/regional/quote")
+            .json(&serde_json::json!({ "seats": ["1A", "2A"] }))
+            .await;
+        assert_eq!(
+            response.json::<serde_json::Value>(),
+            serde_json::json!({
+                "train_id": "regional",
+                "seats": [
+                    { "seat_id": "1A", "fare": "saver", "price": 1900 },
+                    { "seat_id": "2A", "fare": "standard", "price": 4500 },
+                ],
+                "total": 6400,
+            })
+        );
+
+        // reserving uses up the saver seat in coach A
+        server
+            .post("/train/regional/reserve")
+            .json(&serde_json::json!({ "seats": ["1A"], "booking_reference": "abc" }))
+            .await
+            .assert_status_ok();
+        let response = server
+            .post("/train/regional/quote")
+            .json(&serde_json::json!({ "seats": ["2A", "1B"] }))
+            .await;
+        assert_eq!(response.json::<serde_json::Value>()["total"], 6400);
+
+        let response = server.get("/stats").await;
+        assert_eq!(
+            response.json::<serde_json::Value>()["trains"]["regional"]["fares"],
+            serde_json::json!({ "saver": 1 })
+        );
+
+        let response = server.get("/train/regional").await;
+        assert_eq!(
+            response.json::<serde_json::Value>()["fares"][0],
+            serde_json::json!({
+                "name": "saver",
+                "price": 1900,
+                "seats_per_coach": 1,
+                "sold": { "A": 1 },
+            })
+        );
+
+        let response = server
+            .post("/train/regional/quote")
+            .json(&serde_json::json!({ "seats": ["9Z"] }))
+            .await;
+        assert_eq!(response.status_code(), 400);
+    }
+
+    #[tokio::test]
+    async fn test_quote_pricing() {
+        let trains: TrainsData = serde_json::from_value(serde_json::json!({
+            "regional": {
+                "seats": {
+                    "1A": { "seat_number": "1", "coach": "A", "booking_reference": "abc" },
+                    "2A": { "seat_number": "2", "coach": "A", "booking_reference": null },
+                },
+                "fares": [{ "name": "standard", "price": 4000 }],
+            },
+        }))
+        .unwrap();
+        let quote = |pricing: Box<dyn PricingStrategy>| {
+            let app = app(AppState::new()
+                .with_trains(trains.clone())
+                .with_pricing(pricing));
+            async move {
+                let server = TestServer::new_with_config(
+                    app,
+                    TestServerConfig::builder().mock_transport().build(),
+                )
+                .unwrap();
+                server
+                    .post("/train/regional/quote")
+                    .json(&serde_json::json!({ "seats": ["2A"] }))
+                    .await
+                    .json::<serde_json::Value>()["total"]
+                    .clone()
+            }
+        };
+        // the train is half full
+        assert_eq!(quote(Box::<OccupancyPricing>::default()).await, 4400);
+        assert_eq!(quote(Box::new(FixedPricing)).await, 4000);
+    }
+
+    #[tokio::test]
+    async fn test_vouchers() {
+        let trains: TrainsData = serde_json::from_value(serde_json::json!({
+            "regional": {
+                "seats": {
+                    "1A": { "seat_number": "1", "coach": "A", "booking_reference": null },
+                    "2A": { "seat_number": "2", "coach": "A", "booking_reference": null },
+                },
+                "fares": [{ "name": "standard", "price": 4000 }],
+            },
+        }))
+        .unwrap();
+        let clock = SimulatedClock::new(0);
+        let app = app(AppState::new()
+            .with_trains(trains)
+            .with_simulated_clock(clock.clone())
+            .with_pricing(Box::new(FixedPricing)));
+        let server =
+            TestServer::new_with_config(app, TestServerConfig::builder().mock_transport().build())
+                .unwrap();
+
+        let voucher = serde_json::json!({ "discount": { "fixed": 500 }, "max_uses": 1 });
+        server
+            .put("/admin/vouchers/WELCOME")
+            .json(&voucher)
+            .await
+            .assert_status_ok();
+        let response = server
+            .post("/train/regional/quote")
+            .json(&serde_json::json!({ "seats": ["1A"], "voucher": "WELCOME" }))
+            .await;
+        assert_eq!(
+            response.json::<serde_json::Value>(),
+            serde_json::json!({
+                "train_id": "regional",
+                "seats": [{ "seat_id": "1A", "fare": "standard", "price": 4000 }],
+                "discount": 500,
+                "total": 3500,
+            })
+        );
+
+        // booking with the voucher uses it up
+        let response = server
+            .post("/reserve")
+            .json(&serde_json::json!({
+                "train_id": "regional",
+                "seat_count": 1,
+                "voucher": "WELCOME",
+            }))
+            .await;
+        response.assert_status_ok();
+        let booking = response.json::<serde_json::Value>();
+        assert_eq!(booking["discount"], 500);
+        let response = server
+            .post("/reserve")
+            .json(&serde_json::json!({
+                "train_id": "regional",
+                "seat_count": 1,
+                "voucher": "WELCOME",
+            }))
+            .await;
+        assert_eq!(response.status_code(), 400);
+        assert_eq!(
+            response.json::<serde_json::Value>()["message"],
+            "Voucher WELCOME has been used up"
+        );
+        let response = server.get("/admin/vouchers").await;
+        assert_eq!(response.json::<serde_json::Value>()["WELCOME"]["uses"], 1);
+
+        // nothing was reserved with the used-up voucher
+        let response = server.get("/train/regional/availability").await;
+        assert_eq!(response.json::<serde_json::Value>()["reserved"], 1);
+
+        // what was paid is what's refunded, with the discount taken off
+        let response = server
+            .post("/train/regional/cancel")
+            .json(&serde_json::json!({ "booking_reference": booking["booking_reference"] }))
+            .await;
+        assert_eq!(response.json::<Refund>().paid, 3500);
+
+        // a voucher isn't used by a booking that fails
+        server
+            .put("/admin/vouchers/ONCE")
+            .json(&voucher)
+            .await
+            .assert_status_ok();
+        let response = server
+            .post("/reserve")
+            .json(&serde_json::json!({
+                "train_id": "regional",
+                "seat_count": 3,
+                "voucher": "ONCE",
+            }))
+            .await;
+        assert_eq!(response.status_code(), 409);
+        let response = server.get("/admin/vouchers").await;
+        assert_eq!(response.json::<serde_json::Value>()["ONCE"]["uses"], 0);
+
+        let response = server
+            .post("/train/regional/quote")
+            .json(&serde_json::json!({ "seats": ["2A"], "voucher": "UNKNOWN" }))
+            .await;
+        assert_eq!(
+            response.json::<serde_json::Value>()["message"],
+            "Voucher UNKNOWN does not exist"
+        );
+
+        server
+            .delete("/admin/vouchers/WELCOME")
+            .await
+            .assert_status(StatusCode::NO_CONTENT);
+        let response = server.delete("/admin/vouchers/WELCOME").await;
+        assert_eq!(response.status_code(), 404);
+
+        server
+            .put("/admin/vouchers/SUMMER")
+            .json(&serde_json::json!({ "discount": { "percent": 10 }, "expires_at": 5000 }))
+            .await
+            .assert_status_ok();
+        let response = server
+            .post("/train/regional/quote")
+            .json(&serde_json::json!({ "seats": ["2A"], "voucher": "SUMMER" }))
+            .await;
+        assert_eq!(response.json::<serde_json::Value>()["discount"], 400);
+        clock.set(5000);
+        let response = server
+            .post("/train/regional/quote")
+            .json(&serde_json::json!({ "seats": ["2A"], "voucher": "SUMMER" }))
+            .await;
+        assert_eq!(
+            response.json::<serde_json::Value>()["message"],
+            "Voucher SUMMER has expired"
+        );
+
+        let response = server
+            .put("/admin/vouchers/GREEDY")
+            .json(&serde_json::json!({ "discount": { "percent": 200 } }))
+            .await;
+        assert_eq!(response.status_code(), 400);
+    }
+
+    #[derive(Clone, Default)]
+    struct RecordingNotifier(Arc<Mutex<Vec<Event>>>);
+
+    impl Notifier for RecordingNotifier {
+        fn notify(&self, notification: &Notification) {
+            self.0.lock().unwrap().push(notification.event.clone());
+        }
+    }
+
+    #[derive(Default, Clone)]
+    struct CorrelationIds(Arc<Mutex<Vec<Option<String>>>>);
+
+    impl Notifier for CorrelationIds {
+        fn notify(&self, notification: &Notification) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(notification.correlation_id.clone());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_correlation_id() {
+        let notifier = CorrelationIds::default();
+        let app = app(AppState::new()
+            .with_near_full_alert(Some(0.05))
+            .with_notifier(notifier.clone()));
+        let server =
+            TestServer::new_with_config(app, TestServerConfig::builder().mock_transport().build())
+                .unwrap();
+        let response = server
+            .post("/train/express_2000/reserve")
+            .json(&serde_json::json!({ "seats": ["1A"], "booking_reference": "123456" }))
+            .add_header(
+                axum::http::HeaderName::from_static("x-correlation-id"),
+                axum::http::HeaderValue::from_static("booking-42"),
+            )
+            .await;
+        assert_eq!(response.header("x-correlation-id"), "booking-42");
+        let response = server
+            .post("/train/express_2000/cancel")
+            .json(&serde_json::json!({ "booking_reference": "123456" }))
+            .await;
+        let generated = response.header("x-correlation-id");
+
+        // the near-full alert, and the refund
+        assert_eq!(
+            *notifier.0.lock().unwrap(),
+            [
+                Some("booking-42".to_string()),
+                Some(generated.to_str().unwrap().to_string())
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_near_full_alert() {
+        let notifier = RecordingNotifier::default();
+        let app = app(AppState::new()
+            .with_near_full_alert(Some(0.25))
+            .with_notifier(notifier.clone()));
+        let server =
+            TestServer::new_with_config(app, TestServerConfig::builder().mock_transport().build())
+                .unwrap();
+        let reserve = |seats: &[&str], booking_reference: &str| {
+            server.post("/train/express_2000/reserve").json(
+                &serde_json::json!({ "seats": seats, "booking_reference": booking_reference }),
+            )
+        };
+
+        reserve(&["1A", "2A", "3A"], "abc").await.assert_status_ok();
+        assert!(notifier.0.lock().unwrap().is_empty());
+        reserve(&["4A"], "def").await.assert_status_ok();
+        assert_eq!(
+            *notifier.0.lock().unwrap(),
+            [Event::NearlyFull(NearlyFull {
+                train_id: TrainId::new("express_2000"),
+                reserved: 4,
+                total: 16,
+                remaining: 12,
+            })]
+        );
+        reserve(&["5A"], "ghi").await.assert_status_ok();
+        assert_eq!(notifier.0.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_admin_cancel_booking() {
+        let server = new_test_app();
+        let reserve = |train_id: &str, seats: &[&str], booking_reference: &str| {
+            server.post(&format!("/train/{}/reserve", train_id)).json(
+                &serde_json::json!({ "seats": seats, "booking_reference": booking_reference }),
+            )
+        };
+        reserve("express_2000", &["1A", "2A"], "abc").await;
+        reserve("local_1000", &["1A"], "abc").await;
+        reserve("express_2000", &["3A"], "def").await;
+
+        let response = server.post("/admin/cancel/abc").await;
+        let response = response.json::<serde_json::Value>();
+        assert_eq!(response["booking_reference"], "abc");
+        assert_eq!(response["seats"], 3);
+        let refunds = response["refunds"].as_array().unwrap();
+        assert_eq!(refunds[0]["train_id"], "express_2000");
+        assert_eq!(refunds[0]["seats"], serde_json::json!(["1A", "2A"]));
+        assert_eq!(refunds[1]["train_id"], "local_1000");
+        assert_eq!(refunds[1]["seats"], serde_json::json!(["1A"]));
+
+        // other bookings stay
+        let response = server.get("/train/express_2000/availability").await;
+        assert_eq!(response.json::<serde_json::Value>()["reserved"], 1);
+        let response = server.get("/train/local_1000/availability").await;
+        assert_eq!(response.json::<serde_json::Value>()["reserved"], 0);
+        let response = server.get("/reservation/1").await;
+        assert_eq!(response.json::<serde_json::Value>()["status"], "cancelled");
+
+        server
+            .post("/admin/cancel/abc")
+            .expect_failure()
+            .await
+            .assert_status_not_found();
+
+        // all or nothing: an archived train stops the whole sweep
+        reserve("express_2000", &["5A"], "ghi").await;
+        reserve("local_1000", &["5B"], "ghi").await;
+        server.post("/admin/train/local_1000/archive").await;
+        server.post("/admin/cancel/ghi").expect_failure().await;
+        let response = server.get("/train/express_2000/seat/5A").await;
+        assert_eq!(
+            response.json::<serde_json::Value>()["booking_reference"],
+            "ghi"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_refund_what_was_charged() {
+        let trains: TrainsData = serde_json::from_value(serde_json::json!({
+            "regional": {
+                "seats": {
+                    "1A": { "seat_number": "1", "coach": "A" },
+                    "2A": { "seat_number": "2", "coach": "A" },
+                },
+                "fares": [
+                    { "name": "saver", "price": 1000, "seats_per_coach": 1 },
+                    { "name": "standard", "price": 4000 },
+                ],
+            },
+        }))
+        .unwrap();
+        // 10% more from half full
+        let app = app(AppState::new().with_trains(trains));
+        let server =
+            TestServer::new_with_config(app, TestServerConfig::builder().mock_transport().build())
+                .unwrap();
+        let reserve = |seat_id: &str, booking_reference: &str| {
+            server.post("/train/regional/reserve").json(
+                &serde_json::json!({ "seats": [seat_id], "booking_reference": booking_reference }),
+            )
+        };
+        let cancel = |booking_reference: &str| {
+            server
+                .post("/train/regional/cancel")
+                .json(&serde_json::json!({ "booking_reference": booking_reference }))
+        };
+        reserve("1A", "abc").await.assert_status_ok();
+        let response = server
+            .post("/train/regional/quote")
+            .json(&serde_json::json!({ "seats": ["2A"] }))
+            .await;
+        assert_eq!(response.json::<serde_json::Value>()["total"], 4400);
+        reserve("2A", "def").await.assert_status_ok();
+        assert_eq!(cancel("def").await.json::<Refund>().paid, 4400);
+        assert_eq!(cancel("abc").await.json::<Refund>().paid, 1000);
+
+        // the cancelled seats were taken back into their buckets
+        let response = server.get("/train/regional").await;
+        assert_eq!(
+            response.json::<serde_json::Value>()["fares"],
+            serde_json::json!([
+                { "name": "saver", "price": 1000, "seats_per_coach": 1 },
+                { "name": "standard", "price": 4000 },
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cancel_refund() {
+        let seats = serde_json::json!({
+            "1A": { "seat_number": "1", "coach": "A", "booking_reference": null },
+            "2A": { "seat_number": "2", "coach": "A", "booking_reference": null },
+        });
+        let fares = serde_json::json!([{ "name": "standard", "price": 4000 }]);
+        let trains: TrainsData = serde_json::from_value(serde_json::json!({
+            "later": { "seats": seats, "fares": fares, "departure": 100_000 },
+            "departed": { "seats": seats, "fares": fares, "departure": 1000 },
+        }))
+        .unwrap();
+        let notifier = RecordingNotifier::default();
+        let app = app(AppState::new()
+            .with_trains(trains)
+            .with_simulated_clock(SimulatedClock::new(2000))
+            .with_notifier(notifier.clone()));
+        let server =
+            TestServer::new_with_config(app, TestServerConfig::builder().mock_transport().build())
+                .unwrap();
+
+        for train_id in ["later", "departed"] {
+            server
+                .post(&format!("/train/{}/reserve", train_id))
+                .json(&serde_json::json!({ "seats": ["1A", "2A"], "booking_reference": "abc" }))
+                .await
+                .assert_status_ok();
+        }
+        let response = server
+            .post("/train/later/cancel")
+            .json(&serde_json::json!({ "booking_reference": "abc" }))
+            .await;
+        let refund = response.json::<Refund>();
+        assert_eq!(refund.seats, vec![SeatId::new("1A"), SeatId::new("2A")]);
+        assert_eq!(
+            (refund.paid, refund.percent, refund.amount),
+            (8000, 100, 8000)
+        );
+
+        let response = server
+            .post("/train/departed/cancel")
+            .json(&serde_json::json!({ "booking_reference": "abc" }))
+            .await;
+        let refund = response.json::<Refund>();
+        assert_eq!(
+            (refund.paid, refund.percent, refund.amount),
+            (8000, 50, 4000)
+        );
+        assert_eq!(refund.cancelled_at, 2000);
+
+        let refunds = server.get("/refunds/abc").await.json::<Vec<Refund>>();
+        assert_eq!(refunds.len(), 2);
+        assert_eq!(refunds[1], refund);
+        assert_eq!(
+            *notifier.0.lock().unwrap().last().unwrap(),
+            Event::Refund(refund)
+        );
+
+        // the seats are free again, and the booking is gone
+        let response = server.get("/train/later/availability").await;
+        assert_eq!(response.json::<serde_json::Value>()["reserved"], 0);
+        let response = server
+            .post("/train/later/cancel")
+            .json(&serde_json::json!({ "booking_reference": "abc" }))
+            .await;
+        assert_eq!(response.status_code(), 404);
+    }
+
+    #[tokio::test]
+    async fn test_cancellation_cutoff() {
+        let trains: TrainsData = serde_json::from_value(serde_json::json!({
+            "departed": {
+                "seats": {
+                    "1A": { "seat_number": "1", "coach": "A", "booking_reference": "abc" },
+                },
+                "departure": 3600,
+            },
+        }))
+        .unwrap();
+        let clock = SimulatedClock::new(1800);
+        let app = app(AppState::new()
+            .with_trains(trains)
+            .with_simulated_clock(clock.clone())
+            .with_refund_policy(RefundPolicy {
+                cancellation_cutoff_minutes: Some(30),
+                ..RefundPolicy::default()
+            }));
+        let server =
+            TestServer::new_with_config(app, TestServerConfig::builder().mock_transport().build())
+                .unwrap();
+
+        // less than half an hour before departure is too late
+        clock.advance(1);
+        let response = server
+            .post("/train/departed/cancel")
+            .json(&serde_json::json!({ "booking_reference": "abc" }))
+            .await;
+        assert_eq!(response.status_code(), 409);
+        assert_eq!(
+            response.json::<serde_json::Value>(),
+            serde_json::json!({
+                "error": "cancellation_closed",
+                "message": "Train departed departs too soon to cancel bookings",
+            })
+        );
+        let response = server.get("/train/departed/availability").await;
+        assert_eq!(response.json::<serde_json::Value>()["reserved"], 1);
+
+        clock.set(1800);
+        server
+            .post("/train/departed/cancel")
+            .json(&serde_json::json!({ "booking_reference": "abc" }))
+            .await
+            .assert_status_ok();
+    }
+
+    #[tokio::test]
+    async fn test_advance_time() {
+        let trains: TrainsData = serde_json::from_value(serde_json::json!({
+            "departing": {
+                "seats": {
+                    "1A": { "seat_number": "1", "coach": "A", "booking_reference": "abc" },
+                },
+                "departure": 3600,
+            },
+        }))
+        .unwrap();
+        let app = app(AppState::new()
+            .with_trains(trains)
+            .with_simulated_clock(SimulatedClock::new(0))
+            .with_refund_policy(RefundPolicy {
+                cancellation_cutoff_minutes: Some(30),
+                ..RefundPolicy::default()
+            }));
+        let server =
+            TestServer::new_with_config(app, TestServerConfig::builder().mock_transport().build())
+                .unwrap();
+
+        let response = server
+            .post("/admin/time/advance")
+            .json(&serde_json::json!({ "seconds": 1801 }))
+            .await;
+        assert_eq!(
+            response.json::<serde_json::Value>(),
+            serde_json::json!({ "now": 1801 })
+        );
+        let response = server
+            .post("/admin/time/advance")
+            .json(&serde_json::json!({ "seconds": u64::MAX }))
+            .await;
+        assert_eq!(response.status_code(), 400);
+        assert_eq!(
+            response.json::<serde_json::Value>()["error"],
+            "clock_overflow"
+        );
+        let response = server
+            .post("/train/departing/cancel")
+            .json(&serde_json::json!({ "booking_reference": "abc" }))
+            .await;
+        assert_eq!(response.status_code(), 409);
+    }
+
+    #[tokio::test]
+    async fn test_advance_time_expires_holds() {
+        let clock = SimulatedClock::new(1000);
+        let app = app(AppState::new()
+            .with_simulated_clock(clock.clone())
+            .with_tenant(
+                "red".to_string(),
+                AppState::new().with_simulated_clock(clock),
+            ));
+        let server =
+            TestServer::new_with_config(app, TestServerConfig::builder().mock_transport().build())
+                .unwrap();
+        for prefix in ["", "/tenants/red"] {
+            server
+                .post(&format!("{}/train/express_2000/hold", prefix))
+                .json(&serde_json::json!({
+                    "seats": ["1A"],
+                    "booking_reference": "abc",
+                    "seconds": 60,
+                }))
+                .await
+                .assert_status_ok();
+        }
+        let stats = server.get("/stats").await.json::<serde_json::Value>();
+        assert_eq!(stats["trains"]["express_2000"]["reserved"], 1);
+
+        server
+            .post("/admin/time/advance")
+            .json(&serde_json::json!({ "seconds": 60 }))
+            .await
+            .assert_status_ok();
+        // freed before anything reads the train
+        let stats = server.get("/stats").await.json::<serde_json::Value>();
+        assert_eq!(stats["trains"]["express_2000"]["reserved"], 0);
+        for prefix in ["", "/tenants/red"] {
+            let train = server
+                .get(&format!("{}/train/express_2000", prefix))
+                .await
+                .json::<serde_json::Value>();
+            assert_eq!(
+                train["seats"]["1A"]["booking_reference"],
+                serde_json::Value::Null
+            );
+            assert_eq!(train["seats"]["1A"].get("held_until"), None);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_advance_time_without_simulated_clock() {
+        let server = TestServer::new_with_config(
+            app(AppState::new()),
+            TestServerConfig::builder().mock_transport().build(),
+        )
+        .unwrap();
+        let response = server
+            .post("/admin/time/advance")
+            .json(&serde_json::json!({ "seconds": 60 }))
+            .await;
+        assert_eq!(response.status_code(), 404);
+    }
+
+    #[tokio::test]
+    async fn test_tenants() {
+        let trains = || -> TrainsData {
+            serde_json::from_value(serde_json::json!({
+                "express_2000": {
+                    "seats": {
+                        "1A": { "seat_number": "1", "coach": "A", "booking_reference": null },
+                    },
+                },
+            }))
+            .unwrap()
+        };
+        let app = app(AppState::new()
+            .with_trains(trains())
+            .with_tenant("red".to_string(), AppState::new().with_trains(trains()))
+            .with_tenant("blue".to_string(), AppState::new().with_trains(trains())));
+        let server =
+            TestServer::new_with_config(app, TestServerConfig::builder().mock_transport().build())
+                .unwrap();
+
+        let red = server
+            .post("/tenants/red/booking_reference")
+            .await
+            .json::<BookingReference>();
+        let blue = server
+            .post("/tenants/blue/booking_reference")
+            .await
+            .json::<BookingReference>();
+        // each tenant has its own sequence
+        assert_eq!(red, blue);
+        assert_eq!(red, BookingReference::new("1"));
+
+        server
+            .post("/tenants/red/train/express_2000/reserve")
+            .json(&serde_json::json!({ "seats": ["1A"], "booking_reference": "abc" }))
+            .await
+            .assert_status_ok();
+        for (path, reserved) in [
+            ("/tenants/red/train/express_2000/availability", 1),
+            ("/tenants/blue/train/express_2000/availability", 0),
+            ("/train/express_2000/availability", 0),
+        ] {
+            let response = server.get(path).await;
+            assert_eq!(response.json::<serde_json::Value>()["reserved"], reserved);
+        }
+        let response = server.get("/tenants/green/train/express_2000").await;
+        assert_eq!(response.status_code(), 404);
+
+        // the metrics of tenants are labelled with their names
+        let metrics = server.get("/metrics").await.text();
+        assert!(metrics.contains("train_service_train_occupancy_ratio{train=\"express_2000\"} 0\n"));
+        assert!(metrics.contains(
+            "train_service_train_occupancy_ratio{tenant=\"red\",train=\"express_2000\"} 1\n"
+        ));
+        assert!(metrics.contains(
+            "train_service_coach_occupancy_ratio{tenant=\"blue\",train=\"express_2000\",coach=\"A\"} 0\n"
+        ));
+        let stats = server.get("/stats").await.json::<Stats>();
+        assert_eq!(stats.reservations, 0);
+        assert_eq!(stats.tenants["red"].reservations, 1);
+        assert_eq!(stats.tenants["red"].trains["express_2000"].reserved, 1);
+        assert_eq!(stats.tenants["blue"].trains["express_2000"].reserved, 0);
+    }
+
+    #[tokio::test]
+    async fn test_tenant_api_keys_and_quotas() {
+        let trains = || -> TrainsData {
+            serde_json::from_value(serde_json::json!({
+                "express_2000": {
+                    "seats": {
+                        "1A": { "seat_number": "1", "coach": "A", "booking_reference": null },
+                        "2A": { "seat_number": "2", "coach": "A", "booking_reference": null },
+                    },
+                },
+            }))
+            .unwrap()
+        };
+        let api_keys: ApiKeys = serde_json::from_value(serde_json::json!({
+            "red-secret": { "name": "red_team", "tenant": "red" },
+            "admin-secret": { "name": "admin" },
+        }))
+        .unwrap();
+        let tenant_limits = BTreeMap::from([(
+            "red".to_string(),
+            TenantLimits {
+                request_quota: Some(3),
+                seat_quota: Some(1),
+            },
+        )]);
+        let app = app(AppState::new()
+            .with_trains(trains())
+            .with_api_keys(Some(api_keys))
+            .with_tenant("red".to_string(), AppState::new().with_trains(trains()))
+            .with_tenant("blue".to_string(), AppState::new().with_trains(trains()))
+            .with_tenant_limits(tenant_limits));
+        let server =
+            TestServer::new_with_config(app, TestServerConfig::builder().mock_transport().build())
+                .unwrap();
+
+        // a key of a tenant only works for that tenant
+        for path in ["/tenants/blue/train/express_2000", "/train/express_2000"] {
+            let response = server
+                .get(path)
+                .add_header("x-api-key".parse().unwrap(), "red-secret".parse().unwrap())
+                .await;
+            assert_eq!(response.status_code(), 403);
+        }
+
+        let reserve = |seats: serde_json::Value| {
+            server
+                .post("/tenants/red/train/express_2000/reserve")
+                .add_header("x-api-key".parse().unwrap(), "red-secret".parse().unwrap())
+                .json(&serde_json::json!({ "seats": seats, "booking_reference": "abc" }))
+        };
+        reserve(serde_json::json!(["1A"])).await.assert_status_ok();
+        // out of seats
+        let response = reserve(serde_json::json!(["2A"])).await;
+        assert_eq!(response.status_code(), 429);
+        server
+            .get("/tenants/red/train/express_2000")
+            .add_header("x-api-key".parse().unwrap(), "red-secret".parse().unwrap())
+            .await
+            .assert_status_ok();
+        // out of requests
+        let response = reserve(serde_json::json!(["2A"])).await;
+        assert_eq!(response.status_code(), 429);
+        assert_eq!(
+            response.json::<serde_json::Value>()["error"],
+            "request_quota_exceeded"
+        );
+
+        let response = server
+            .get("/admin/tenants")
+            .add_header(
+                "x-api-key".parse().unwrap(),
+                "admin-secret".parse().unwrap(),
+            )
+            .await;
+        assert_eq!(
+            response.json::<serde_json::Value>(),
+            serde_json::json!({
+                "blue": { "requests": 0, "seats": 0 },
+                "red": { "requests": 3, "seats": 1, "request_quota": 3, "seat_quota": 1 },
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_archive_and_restore_train() {
+        let server = new_test_app_failing();
+        let reserve = || {
+            server
+                .post("/train/express_2000/reserve")
+                .json(&serde_json::json!({ "seats": ["1A"], "booking_reference": "abc" }))
+        };
+
+        let response = server.post("/admin/train/express_2000/archive").await;
+        assert_eq!(response.json::<serde_json::Value>()["archived"], true);
+        let response = reserve().await;
+        assert_eq!(response.status_code(), 409);
+        assert_eq!(
+            response.json::<serde_json::Value>()["message"],
+            "Train express_2000 is archived"
+        );
+        let stats = server.get("/stats").await.json::<Stats>();
+        assert!(!stats.trains.contains_key("express_2000"));
+
+        let response = server.post("/admin/train/express_2000/restore").await;
+        assert!(response
+            .json::<serde_json::Value>()
+            .get("archived")
+            .is_none());
+        reserve().await.assert_status_ok();
+        let stats = server.get("/stats").await.json::<Stats>();
+        assert_eq!(stats.trains["express_2000"].reserved, 1);
+
+        let response = server.post("/admin/train/unknown/archive").await;
+        assert_eq!(response.status_code(), 404);
+    }
+
+    #[tokio::test]
+    async fn test_clone_train() {
+        let server = new_test_app_failing();
+        server
+            .post("/train/express_2000/reserve")
+            .json(&serde_json::json!({ "seats": ["1A"], "booking_reference": "abc" }))
+            .await
+            .assert_status_ok();
+
+        let response = server
+            .post("/admin/train/express_2000/clone")
+            .add_query_param("new_id", "express_2000_red")
+            .await;
+        assert_eq!(response.status_code(), 201);
+        let clone = response.json::<serde_json::Value>();
+        assert_eq!(
+            clone["seats"]["1A"]["booking_reference"],
+            serde_json::Value::Null
+        );
+        let original = server
+            .get("/train/express_2000")
+            .await
+            .json::<serde_json::Value>();
+        assert_eq!(
+            clone["seats"].as_object().unwrap().len(),
+            original["seats"].as_object().unwrap().len()
+        );
+        assert_eq!(original["seats"]["1A"]["booking_reference"], "abc");
+
+        // the clone is independent of the original
+        server
+            .post("/train/express_2000_red/reserve")
+            .json(&serde_json::json!({ "seats": ["2A"], "booking_reference": "def" }))
+            .await
+            .assert_status_ok();
+        let response = server.get("/train/express_2000/availability").await;
+        assert_eq!(response.json::<serde_json::Value>()["reserved"], 1);
+
+        let response = server
+            .post("/admin/train/express_2000/clone")
+            .add_query_param("new_id", "express_2000_red")
+            .await;
+        assert_eq!(response.status_code(), 409);
+        let response = server
+            .post("/admin/train/unknown/clone")
+            .add_query_param("new_id", "other")
+            .await;
+        assert_eq!(response.status_code(), 404);
+    }
+
+    #[tokio::test]
+    async fn test_renumber_seats() {
+        let server = new_test_app_failing();
+        server
+            .post("/train/express_2000/reserve")
+            .json(&serde_json::json!({ "seats": ["1A"], "booking_reference": "abc" }))
+            .await
+            .assert_status_ok();
+
+        let response = server
+            .post("/admin/train/express_2000/renumber")
+            .json(&serde_json::json!({ "1A": "101A" }))
+            .await;
+        let train = response.json::<serde_json::Value>();
+        assert_eq!(train["seats"]["101A"]["booking_reference"], "abc");
+        assert_eq!(train["seats"]["101A"]["seat_number"], "101");
+        assert!(train["seats"].get("1A").is_none());
+
+        let response = server
+            .post("/admin/train/express_2000/renumber")
+            .json(&serde_json::json!({ "101A": "2A" }))
+            .await;
+        assert_eq!(response.status_code(), 422);
+        assert_eq!(
+            response.json::<serde_json::Value>()["message"],
+            "Invalid seat mapping: seats 2A already exist"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_trains_from_store() {
+        let dir =
+            std::env::temp_dir().join(format!("train_service_rest_store_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let train = r#"{"seats": {"1A": {"coach": "A", "seat_number": "1"}}}"#;
+        std::fs::write(dir.join("stored_1.json"), train).unwrap();
+        std::fs::write(dir.join("stored_2.json"), train).unwrap();
+        let app = app(AppState::new()
+            .with_trains(TrainsData::from_trains(Default::default()))
+            .with_store(Box::new(DirectoryStore::new(dir.clone()))));
+        let server =
+            TestServer::new_with_config(app, TestServerConfig::builder().mock_transport().build())
+                .unwrap();
+
+        server
+            .post("/train/stored_1/reserve")
+            .json(&serde_json::json!({ "seats": ["1A"], "booking_reference": "abc" }))
+            .await
+            .assert_status_ok();
+        // the loaded train is kept
+        let response = server.get("/train/stored_1").await;
+        assert_eq!(
+            response.json::<serde_json::Value>()["seats"]["1A"]["booking_reference"],
+            "abc"
+        );
+        let response = server.get("/train/stored_3").await;
+        assert_eq!(response.status_code(), 404);
+
+        // exports have all trains, also those that weren't used yet
+        let export = server
+            .get("/admin/export")
+            .await
+            .json::<serde_json::Value>();
+        let trains = export["trains"].as_object().unwrap();
+        assert_eq!(
+            trains.keys().collect::<Vec<_>>(),
+            vec!["stored_1", "stored_2"]
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_trains_cache() {
+        let dir =
+            std::env::temp_dir().join(format!("train_service_rest_cache_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let train = r#"{"seats": {"1A": {"coach": "A", "seat_number": "1"}}}"#;
+        std::fs::write(dir.join("stored_1.json"), train).unwrap();
+        std::fs::write(dir.join("stored_2.json"), train).unwrap();
+        let app = app(AppState::new()
+            .with_trains(TrainsData::from_trains(Default::default()))
+            .with_store(Box::new(DirectoryStore::new(dir.clone())))
+            .with_max_loaded_trains(1));
+        let server =
+            TestServer::new_with_config(app, TestServerConfig::builder().mock_transport().build())
+                .unwrap();
+
+        server
+            .post("/train/stored_1/reserve")
+            .json(&serde_json::json!({ "seats": ["1A"], "booking_reference": "abc" }))
+            .await
+            .assert_status_ok();
+        // using another train drops the first, which is saved as it changed
+        server.get("/train/stored_2").await.assert_status_ok();
+        let saved = std::fs::read_to_string(dir.join("stored_1.json")).unwrap();
+        assert!(saved.contains("\"booking_reference\": \"abc\""));
+        let response = server.get("/train/stored_1").await;
+        assert_eq!(
+            response.json::<serde_json::Value>()["seats"]["1A"]["booking_reference"],
+            "abc"
+        );
+
+        let stats = server.get("/stats").await.json::<serde_json::Value>();
+        assert_eq!(
+            stats["cache"],
+            serde_json::json!({ "capacity": 1, "trains": 1, "evictions": 2 })
+        );
+        let metrics = server.get("/metrics").await.text();
+        assert!(metrics.contains("train_service_cache_evictions_total 2\n"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_trains_cache_keeps_bookings() {
+        let dir = std::env::temp_dir().join(format!(
+            "train_service_rest_cache_bookings_{}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let train = r#"{"seats": {}, "coaches": {"F": {"free_seating": {"capacity": 10}}}}"#;
+        std::fs::write(dir.join("t1.json"), train).unwrap();
+        std::fs::write(dir.join("t2.json"), train).unwrap();
+        let app = app(AppState::new()
+            .with_trains(TrainsData::from_trains(Default::default()))
+            .with_store(Box::new(DirectoryStore::new(dir.clone())))
+            .with_max_loaded_trains(1));
+        let server =
+            TestServer::new_with_config(app, TestServerConfig::builder().mock_transport().build())
+                .unwrap();
+
+        server
+            .post("/train/t1/coach/F/reserve")
+            .json(&serde_json::json!({ "places": 3, "booking_reference": "abc" }))
+            .await
+            .assert_status_ok();
+        // t1 is saved and dropped, and loaded again for the cancellation
+        server.get("/train/t2").await.assert_status_ok();
+        let response = server
+            .post("/train/t1/cancel")
+            .json(&serde_json::json!({ "booking_reference": "abc" }))
+            .await;
+        response.assert_status_ok();
+        assert_eq!(response.json::<serde_json::Value>()["places"], 3);
+        let response = server.get("/train/t1").await;
+        assert_eq!(
+            response.json::<serde_json::Value>()["coaches"]["F"]["free_seating"],
+            serde_json::json!({ "capacity": 10, "reserved": 0 })
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_admin_cancel_booking_in_store() {
+        let dir = std::env::temp_dir().join(format!(
+            "train_service_rest_admin_cancel_{}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let train = r#"{"seats": {}, "coaches": {"F": {"free_seating": {"capacity": 10}}}}"#;
+        for train_id in ["t1", "t2", "t3"] {
+            std::fs::write(dir.join(format!("{}.json", train_id)), train).unwrap();
+        }
+        let app = app(AppState::new()
+            .with_trains(TrainsData::from_trains(Default::default()))
+            .with_store(Box::new(DirectoryStore::new(dir.clone())))
+            .with_max_loaded_trains(1));
+        let server =
+            TestServer::new_with_config(app, TestServerConfig::builder().mock_transport().build())
+                .unwrap();
+        for train_id in ["t1", "t2"] {
+            server
+                .post(&format!("/train/{}/coach/F/reserve", train_id))
+                .json(&serde_json::json!({ "places": 2, "booking_reference": "abc" }))
+                .await
+                .assert_status_ok();
+        }
+        // only t3 is loaded, the booking is on trains in the store
+        server.get("/train/t3").await.assert_status_ok();
+
+        let response = server.post("/admin/cancel/abc").await;
+        response.assert_status_ok();
+        let response = response.json::<serde_json::Value>();
+        assert_eq!(response["places"], 4);
+        assert_eq!(response["refunds"][0]["train_id"], "t1");
+        assert_eq!(response["refunds"][1]["train_id"], "t2");
+        for train_id in ["t1", "t2"] {
+            let response = server.get(&format!("/train/{}", train_id)).await;
+            assert_eq!(
+                response.json::<serde_json::Value>()["coaches"]["F"]["free_seating"]["reserved"],
+                0
+            );
+        }
+
+        // an archived train in the store stops the whole sweep
+        for train_id in ["t1", "t2"] {
+            server
+                .post(&format!("/train/{}/coach/F/reserve", train_id))
+                .json(&serde_json::json!({ "places": 2, "booking_reference": "def" }))
+                .await
+                .assert_status_ok();
+        }
+        server
+            .post("/admin/train/t2/archive")
+            .await
+            .assert_status_ok();
+        server.get("/train/t3").await.assert_status_ok();
+        server.post("/admin/cancel/def").expect_failure().await;
+        let response = server.get("/train/t1").await;
+        assert_eq!(
+            response.json::<serde_json::Value>()["coaches"]["F"]["free_seating"]["reserved"],
+            2
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_reservations_batch() {
+        let server = new_test_app_failing();
+        let response = server
+            .post("/reservations/batch")
+            .json(&serde_json::json!({ "reservations": [
+                { "train_id": "express_2000", "seats": ["1A"], "booking_reference": "abc" },
+                { "train_id": "express_2000", "seats": ["1A"], "booking_reference": "def" },
+                { "train_id": "unknown", "seats": ["1A"], "booking_reference": "ghi" },
+                { "train_id": "local_1000", "seats": ["1A"], "booking_reference": "jkl" },
+            ] }))
+            .await;
+        response.assert_status_ok();
+        assert_eq!(
+            response.json::<serde_json::Value>(),
+            serde_json::json!([
+                {
+                    "train_id": "express_2000",
+                    "booking_reference": "abc",
+                    "status": "reserved",
+                    "reservation_id": 1,
+                },
+                {
+                    "train_id": "express_2000",
+                    "booking_reference": "def",
+                    "status": "conflict",
+                    "message": "Seats [1A] are already reserved",
+                },
+                {
+                    "train_id": "unknown",
+                    "booking_reference": "ghi",
+                    "status": "not_found",
+                    "message": "Train unknown does not exist",
+                },
+                {
+                    "train_id": "local_1000",
+                    "booking_reference": "jkl",
+                    "status": "reserved",
+                    "reservation_id": 2,
+                },
+            ])
+        );
+        let response = server.get("/train/local_1000").await;
+        assert_eq!(
+            response.json::<serde_json::Value>()["seats"]["1A"]["booking_reference"],
+            "jkl"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_trains() {
+        let server = new_test_app();
+        let response = server
+            .get("/trains")
+            .add_query_param("ids", "express_2000,unknown,local_1000")
+            .await;
+        let trains = response.json::<serde_json::Value>();
+        assert_eq!(
+            trains["trains"]
+                .as_object()
+                .unwrap()
+                .keys()
+                .collect::<Vec<_>>(),
+            vec!["express_2000", "local_1000"]
+        );
+        assert_eq!(
+            trains["trains"]["local_1000"],
+            server
+                .get("/train/local_1000")
+                .await
+                .json::<serde_json::Value>()
+        );
+        assert_eq!(trains["missing"], serde_json::json!(["unknown"]));
+
+        server.post("/admin/train/local_1000/archive").await;
+        let response = server
+            .get("/trains")
+            .add_query_param("ids", "express_2000,local_1000")
+            .await;
+        let trains = response.json::<serde_json::Value>();
+        assert!(trains["trains"].get("local_1000").is_none());
+        assert_eq!(trains["archived"], serde_json::json!(["local_1000"]));
+    }
+
+    #[tokio::test]
+    async fn test_train_seat() {
+        let server = new_test_app_failing();
+        let response = server.get("/train/express_2000/seat/1A").await;
+        response.assert_status_ok();
+        assert_eq!(
+            response.json::<serde_json::Value>(),
+            server
+                .get("/train/express_2000")
+                .await
+                .json::<serde_json::Value>()["seats"]["1A"]
+        );
+
+        let response = server.get("/train/express_2000/seat/99Z").await;
+        assert_eq!(response.status_code(), 404);
+        assert_eq!(
+            response.json::<serde_json::Value>(),
+            serde_json::json!({
+                "error": "seat_not_found",
+                "message": "Seat 99Z does not exist on train express_2000",
+            })
+        );
+        let response = server.get("/train/unknown/seat/1A").await;
+        assert_eq!(response.status_code(), 404);
+    }
+
+    #[tokio::test]
+    async fn test_train_nearest() {
+        let server = new_test_app();
+        server
+            .post("/train/local_1000/reserve")
+            .json(&serde_json::json!({
+                "seats": ["3C"],
+                "booking_reference": "abc",
+            }))
+            .await
+            .assert_status_ok();
+
+        let response = server
+            .get("/train/local_1000/nearest")
+            .add_query_param("seat", "4C")
+            .add_query_param("count", 3)
+            .await;
+        assert_eq!(
+            response.json::<serde_json::Value>(),
+            serde_json::json!({
+                "train_id": "local_1000",
+                "seat_id": "4C",
+                "seats": [
+                    { "seat_id": "2C", "distance": 2 },
+                    { "seat_id": "1C", "distance": 3 },
+                    { "seat_id": "4B", "distance": 100 },
+                ],
+            })
+        );
+
+        let response = server
+            .get("/train/local_1000/nearest")
+            .add_query_param("seat", "1A")
+            .await;
+        assert_eq!(
+            response.json::<serde_json::Value>()["seats"],
+            serde_json::json!([{ "seat_id": "2A", "distance": 1 }])
+        );
+
+        server
+            .get("/train/local_1000/nearest")
+            .add_query_param("seat", "9Z")
+            .expect_failure()
+            .await
+            .assert_status(StatusCode::NOT_FOUND);
+        server
+            .get("/train/local_1000/nearest")
+            .expect_failure()
+            .await
+            .assert_status(StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_train_layout() {
+        let server = new_test_app();
+        server
+            .post("/admin/train/generated")
+            .json(&serde_json::json!({
+                "coaches": [
+                    {"id": "A", "seats": 3, "layout": {"rows": 1, "columns": 4, "aisle_after": 2}},
+                    {"id": "B", "seats": 2},
+                ],
+            }))
+            .await;
+        server
+            .post("/train/generated/reserve")
+            .json(&serde_json::json!({
+                "seats": ["2A"],
+                "booking_reference": "abc",
+            }))
+            .await;
+
+        let response = server.get("/train/generated/layout").await;
+        assert_eq!(
+            response.json::<serde_json::Value>(),
+            serde_json::json!({
+                "train_id": "generated",
+                "coaches": {
+                    "A": {
+                        "rows": 1,
+                        "columns": 4,
+                        "aisle_after": 2,
+                        "seats": {
+                            "1A": { "row": 1, "column": 1, "free": true },
+                            "2A": { "row": 1, "column": 2, "free": false },
+                            "3A": { "row": 1, "column": 3, "free": true },
+                        },
+                    },
+                },
+            })
+        );
+        let train = server
+            .get("/train/generated")
+            .await
+            .json::<serde_json::Value>();
+        assert_eq!(
+            train["coaches"]["A"]["layout"],
+            serde_json::json!({ "rows": 1, "columns": 4, "aisle_after": 2 })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_train_occupancy_history() {
+        let clock = SimulatedClock::new(1000);
+        let app = app(AppState::new().with_simulated_clock(clock.clone()));
+        let server =
+            TestServer::new_with_config(app, TestServerConfig::builder().mock_transport().build())
+                .unwrap();
+        clock.advance(60);
+        server
+            .post("/train/express_2000/reserve")
+            .json(&serde_json::json!({ "seats": ["1A", "2A"], "booking_reference": "abc" }))
+            .await
+            .assert_status_ok();
+        clock.advance(60);
+        server
+            .post("/train/express_2000/cancel")
+            .json(&serde_json::json!({ "booking_reference": "abc" }))
+            .await
+            .assert_status_ok();
+
+        let response = server.get("/train/express_2000/occupancy_history").await;
+        assert_eq!(
+            response.json::<serde_json::Value>(),
+            serde_json::json!({
+                "train_id": "express_2000",
+                "samples": [
+                    { "at": 1000, "reserved": 0, "total": 16 },
+                    { "at": 1060, "reserved": 2, "total": 16 },
+                    { "at": 1120, "reserved": 0, "total": 16 },
+                ],
+            })
+        );
+
+        server
+            .get("/train/unknown/occupancy_history")
+            .await
+            .assert_status_not_found();
+    }
+
+    #[tokio::test]
+    async fn test_train_forecast() {
+        let clock = SimulatedClock::new(1000);
+        let app = app(AppState::new().with_simulated_clock(clock.clone()));
+        let server =
+            TestServer::new_with_config(app, TestServerConfig::builder().mock_transport().build())
+                .unwrap();
+        let response = server.get("/train/express_2000/forecast").await;
+        assert_eq!(
+            response.json::<serde_json::Value>(),
+            serde_json::json!({
+                "train_id": "express_2000",
+                "seventy_percent": "not expected",
+                "full": "not expected",
+            })
+        );
+
+        // two seats a minute
+        for seats in [["1A", "2A"], ["3A", "4A"]] {
+            clock.advance(60);
+            server
+                .post("/train/express_2000/reserve")
+                .json(&serde_json::json!({ "seats": seats, "booking_reference": "abc" }))
+                .await
+                .assert_status_ok();
+        }
+        let response = server.get("/train/express_2000/forecast").await;
+        assert_eq!(
+            response.json::<serde_json::Value>(),
+            serde_json::json!({
+                "train_id": "express_2000",
+                // 11.2 seats at 1/30 seat a second, from 4 at 1120
+                "seventy_percent": 1336,
+                "full": 1480,
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_train_filter() {
+        let server = new_test_app();
+        server
+            .post("/train/express_2000/reserve")
+            .json(&serde_json::json!({
+                "seats": ["1A"],
+                "booking_reference": "abc",
+            }))
+            .await;
+
+        let seat_ids = |response: axum_test::TestResponse| {
+            let mut seat_ids = response.json::<serde_json::Value>()["seats"]
+                .as_object()
+                .unwrap()
+                .keys()
+                .cloned()
+                .collect::<Vec<_>>();
+            seat_ids.sort();
+            seat_ids
+        };
+        let response = server
+            .get("/train/express_2000")
+            .add_query_param("free", true)
+            .add_query_param("coach", "A")
+            .await;
+        assert_eq!(
+            seat_ids(response),
+            ["2A", "3A", "4A", "5A", "6A", "7A", "8A"]
+        );
+        let response = server
+            .get("/train/express_2000")
+            .add_query_param("free", false)
+            .await;
+        assert_eq!(seat_ids(response), ["1A"]);
+        let response = server
+            .get("/train/express_2000")
+            .add_query_param("coach", "B")
+            .add_query_param("class", "first")
+            .await;
+        assert_eq!(seat_ids(response), Vec::<String>::new());
+
+        let response = server
+            .get("/train/express_2000")
+            .add_query_param("attr", "sunroof")
+            .expect_failure()
+            .await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        assert_eq!(
+            response.json::<serde_json::Value>()["message"],
+            "Invalid seat filter: unknown attribute sunroof"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_train_fields() {
+        let server = new_test_app();
+        server
+            .post("/train/express_2000/reserve")
+            .json(&serde_json::json!({
+                "seats": ["1A"],
+                "booking_reference": "abc",
+            }))
+            .await;
+
+        let response = server
+            .get("/train/express_2000")
+            .add_query_param("fields", "seat_number,booking_reference")
+            .await
+            .json::<serde_json::Value>();
+        assert_eq!(
+            response["seats"]["1A"],
+            serde_json::json!({ "seat_number": "1", "booking_reference": "abc" })
+        );
+        assert_eq!(
+            response["seats"]["2B"],
+            serde_json::json!({ "seat_number": "2", "booking_reference": null })
+        );
+
+        // together with a filter
+        let response = server
+            .get("/train/express_2000")
+            .add_query_param("free", false)
+            .add_query_param("fields", "coach")
+            .await;
+        assert_eq!(
+            response.json::<serde_json::Value>()["seats"],
+            serde_json::json!({ "1A": { "coach": "A" } })
+        );
+
+        let response = server
+            .get("/train/express_2000/seat/1A")
+            .add_query_param("fields", "booking_reference")
+            .await;
+        assert_eq!(
+            response.json::<serde_json::Value>(),
+            serde_json::json!({ "booking_reference": "abc" })
+        );
+
+        let response = server
+            .get("/train/express_2000")
+            .add_query_param("fields", "colour")
+            .expect_failure()
+            .await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        assert_eq!(
+            response.json::<serde_json::Value>()["message"],
+            "Invalid fields: unknown field colour"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_json_api() {
+        let clock = SimulatedClock::new(1000);
+        let app = app(AppState::new().with_simulated_clock(clock));
+        let server =
+            TestServer::new_with_config(app, TestServerConfig::builder().mock_transport().build())
+                .unwrap();
+        server
+            .post("/train/express_2000/reserve")
+            .json(&serde_json::json!({ "seats": ["1A", "2A"], "booking_reference": "abc" }))
+            .await
+            .assert_status_ok();
+        let accept = axum::http::HeaderValue::from_static(json_api::MEDIA_TYPE);
+
+        let response = server
+            .get("/train/express_2000")
+            .add_header(header::ACCEPT, accept.clone())
+            .add_query_param("free", false)
+            .add_query_param("fields", "booking_reference")
+            .await;
+        assert_eq!(response.header(header::CONTENT_TYPE), json_api::MEDIA_TYPE);
+        assert_eq!(
+            response.json::<serde_json::Value>(),
+            serde_json::json!({
+                "data": {
+                    "type": "trains",
+                    "id": "express_2000",
+                    "attributes": {},
+                    "relationships": {
+                        "seats": {
+                            "data": [
+                                { "type": "seats", "id": "express_2000/1A" },
+                                { "type": "seats", "id": "express_2000/2A" },
+                            ],
+                        },
+                    },
+                },
+                "included": [
+                    {
+                        "type": "seats",
+                        "id": "express_2000/1A",
+                        "attributes": { "booking_reference": "abc" },
+                        "relationships": {
+                            "train": { "data": { "type": "trains", "id": "express_2000" } },
+                        },
+                    },
+                    {
+                        "type": "seats",
+                        "id": "express_2000/2A",
+                        "attributes": { "booking_reference": "abc" },
+                        "relationships": {
+                            "train": { "data": { "type": "trains", "id": "express_2000" } },
+                        },
+                    },
+                ],
+            })
+        );
+
+        let response = server
+            .get("/train/express_2000/seat/3A")
+            .add_header(header::ACCEPT, accept.clone())
+            .await;
+        assert_eq!(
+            response.json::<serde_json::Value>()["data"]["attributes"],
+            serde_json::json!({ "seat_number": "3", "coach": "A", "booking_reference": null })
+        );
+
+        let response = server
+            .get("/reservation/1")
+            .add_header(header::ACCEPT, accept)
+            .await;
+        assert_eq!(
+            response.json::<serde_json::Value>(),
+            serde_json::json!({
+                "data": {
+                    "type": "reservations",
+                    "id": "1",
+                    "attributes": {
+                        "booking_reference": "abc",
+                        "status": "confirmed",
+                        "created_at": 1000,
+                        "updated_at": 1000,
+                    },
+                    "relationships": {
+                        "train": { "data": { "type": "trains", "id": "express_2000" } },
+                        "seats": {
+                            "data": [
+                                { "type": "seats", "id": "express_2000/1A" },
+                                { "type": "seats", "id": "express_2000/2A" },
+                            ],
+                        },
+                    },
+                },
+            })
+        );
+
+        // without asking, it's plain JSON
+        let response = server.get("/reservation/1").await;
+        assert_eq!(response.json::<serde_json::Value>()["id"], 1);
+    }
+
+    #[tokio::test]
+    async fn test_json_api_by_default() {
+        let app = app(AppState::new().with_json_api(true));
+        let server =
+            TestServer::new_with_config(app, TestServerConfig::builder().mock_transport().build())
+                .unwrap();
+
+        let response = server.get("/train/express_2000/seat/1A").await;
+        assert_eq!(response.header(header::CONTENT_TYPE), json_api::MEDIA_TYPE);
+        assert_eq!(
+            response.json::<serde_json::Value>()["data"]["id"],
+            "express_2000/1A"
+        );
+
+        // clients can still ask for plain JSON
+        let response = server
+            .get("/train/express_2000/seat/1A")
+            .add_header(
+                header::ACCEPT,
+                axum::http::HeaderValue::from_static("application/json"),
+            )
+            .await;
+        assert_eq!(response.json::<serde_json::Value>()["seat_number"], "1");
     }
 
     #[tokio::test]
-    async fn test_booking_reference() {
+    async fn test_reserve_strategy() {
         let server = new_test_app();
 
         let response = server
-            .post("/booking_reference")
+            .post("/reserve")
+            .json(&serde_json::json!({
+                "train_id": "local_1000",
+                "seat_count": 3,
+                "strategy": "preferences",
+            }))
             .await
-            .json::<BookingReference>();
+            .json::<serde_json::Value>();
+        assert_eq!(response["seats"], serde_json::json!(["1A", "2A", "3A"]));
+        assert_eq!(response["spread"], 4);
 
-        assert_eq!(response, BookingReference::new("1"));
+        server
+            .post("/reserve")
+            .json(&serde_json::json!({
+                "train_id": "local_1000",
+                "seat_count": 3,
+                "strategy": "random",
+            }))
+            .expect_failure()
+            .await
+            .assert_status(StatusCode::UNPROCESSABLE_ENTITY);
     }
 
     #[tokio::test]
-    async fn test_train_local_1000_get() {
+    async fn test_train_reads_latest_changes() {
         let server = new_test_app();
+        let booking_reference = || async {
+            let train = server
+                .get("/train/express_2000")
+                .await
+                .json::<serde_json::Value>();
+            train["seats"]["1A"]["booking_reference"].clone()
+        };
+        assert_eq!(booking_reference().await, serde_json::Value::Null);
+        let response = server.get("/train/express_2000").await;
+        assert_eq!(response.header("content-type"), "application/json");
 
-        let train = server.get("/train/local_1000").await.json::<Train>();
+        server
+            .post("/train/express_2000/reserve")
+            .json(&serde_json::json!({
+                "seats": ["1A"],
+                "booking_reference": "abc",
+            }))
+            .await;
+        assert_eq!(booking_reference().await, "abc");
 
-        let trains_str = include_str!("trains.json");
-        let trains: TrainsData = serde_json::from_str(trains_str).unwrap();
-        let local_2000 = trains.get(&TrainId::new("local_1000")).unwrap();
+        let export = server
+            .get("/admin/export")
+            .await
+            .json::<serde_json::Value>();
+        server
+            .post("/train/express_2000/cancel")
+            .json(&serde_json::json!({ "booking_reference": "abc" }))
+            .await;
+        assert_eq!(booking_reference().await, serde_json::Value::Null);
+        // importing replaces all trains
+        server.post("/admin/import").json(&export).await;
+        assert_eq!(booking_reference().await, "abc");
+    }
 
-        assert_eq!(&train, local_2000);
+    #[tokio::test]
+    async fn test_huge_train_is_streamed() {
+        let server = new_test_app();
+        server
+            .post("/admin/train/huge")
+            .json(&serde_json::json!({
+                "coaches": [{"id": "A", "seats": 6000}, {"id": "B", "seats": 6000}],
+            }))
+            .await;
+        server
+            .post("/train/huge/reserve")
+            .json(&serde_json::json!({
+                "seats": ["5999B"],
+                "booking_reference": "abc",
+            }))
+            .await;
+
+        let response = server.get("/train/huge").await;
+        assert_eq!(response.header("content-type"), "application/json");
+        let train = response.json::<Train>();
+        assert_eq!(train.seats().count(), 12000);
+        assert_eq!(
+            train
+                .get(&SeatId::new("5999B"))
+                .unwrap()
+                .booking_reference(),
+            Some(&BookingReference::new("abc"))
+        );
     }
 
     #[tokio::test]
-    async fn test_train_does_not_exist() {
-        let server = new_test_app_failing();
+    async fn test_train_seat_history() {
+        let clock = SimulatedClock::new(1000);
+        let app = app(AppState::new().with_simulated_clock(clock.clone()));
+        let server =
+            TestServer::new_with_config(app, TestServerConfig::builder().mock_transport().build())
+                .unwrap();
+        let reserve = |booking_reference: &'static str| {
+            server
+                .post("/train/express_2000/reserve")
+                .json(&serde_json::json!({
+                    "seats": ["1A"],
+                    "booking_reference": booking_reference,
+                }))
+        };
+        reserve("abc").await.assert_status_ok();
+        clock.advance(60);
+        server
+            .post("/train/express_2000/cancel")
+            .json(&serde_json::json!({ "booking_reference": "abc" }))
+            .await
+            .assert_status_ok();
+        clock.advance(60);
+        reserve("def").await.assert_status_ok();
 
-        let response = server.get("/train/does_not_exist").await.status_code();
+        let response = server.get("/train/express_2000/seat/1A/history").await;
+        response.assert_status_ok();
+        assert_eq!(
+            response.json::<serde_json::Value>(),
+            serde_json::json!({
+                "train_id": "express_2000",
+                "seat_id": "1A",
+                "history": [
+                    { "booking_reference": "abc", "reserved_at": 1000, "released_at": 1060 },
+                    { "booking_reference": "def", "reserved_at": 1120 },
+                ],
+            })
+        );
 
-        assert_eq!(response, 404);
+        clock.advance(60);
+        server
+            .post("/train/express_2000/reset")
+            .await
+            .assert_status_ok();
+        let response = server.get("/train/express_2000/seat/1A/history").await;
+        assert_eq!(
+            response.json::<serde_json::Value>()["history"][1]["released_at"],
+            1180
+        );
+        let response = server.get("/train/express_2000/seat/2A/history").await;
+        assert_eq!(
+            response.json::<serde_json::Value>()["history"],
+            serde_json::json!([])
+        );
+        let response = server.get("/train/express_2000/seat/99Z/history").await;
+        assert_eq!(response.status_code(), 404);
     }
 
     #[tokio::test]
-    async fn test_reserve() {
-        let server = new_test_app();
+    async fn test_reservation() {
+        let clock = SimulatedClock::new(1000);
+        let app = app(AppState::new().with_simulated_clock(clock.clone()));
+        let server =
+            TestServer::new_with_config(app, TestServerConfig::builder().mock_transport().build())
+                .unwrap();
+        let response = server
+            .post("/train/express_2000/reserve")
+            .json(&serde_json::json!({ "seats": ["1A", "2A"], "booking_reference": "abc" }))
+            .await;
+        response.assert_status_ok();
+        let id = response.header("x-reservation-id");
+        assert_eq!(id, "1");
 
-        let train = server
-            .post("/train/local_1000/reserve")
-            .json(&Reservation {
-                seats: vec![SeatId::new("1A")],
-                booking_reference: BookingReference::new("123456"),
+        let response = server.get("/reservation/1").await;
+        response.assert_status_ok();
+        assert_eq!(
+            response.json::<serde_json::Value>(),
+            serde_json::json!({
+                "id": 1,
+                "train_id": "express_2000",
+                "booking_reference": "abc",
+                "seats": ["1A", "2A"],
+                "status": "confirmed",
+                "created_at": 1000,
+                "updated_at": 1000,
             })
+        );
+
+        clock.advance(60);
+        server
+            .post("/train/express_2000/cancel")
+            .json(&serde_json::json!({ "booking_reference": "abc" }))
             .await
-            .json::<Train>();
+            .assert_status_ok();
+        let reservation = server
+            .get("/reservation/1")
+            .await
+            .json::<serde_json::Value>();
+        assert_eq!(reservation["status"], "cancelled");
+        assert_eq!(reservation["updated_at"], 1060);
+        assert_eq!(reservation["seats"], serde_json::json!(["1A", "2A"]));
 
+        let response = server.get("/reservation/2").await;
+        assert_eq!(response.status_code(), 404);
         assert_eq!(
-            train
-                .get(&SeatId::new("1A"))
-                .unwrap()
-                .booking_reference()
-                .unwrap(),
-            &BookingReference::new("123456")
+            response.json::<serde_json::Value>(),
+            serde_json::json!({
+                "error": "reservation_not_found",
+                "message": "Reservation 2 does not exist",
+            })
         );
     }
 
     #[tokio::test]
-    async fn test_reserve_seat_does_not_exist() {
+    async fn test_change_reservation_seats() {
         let server = new_test_app_failing();
+        server
+            .post("/train/express_2000/reserve")
+            .json(&serde_json::json!({ "seats": ["1A", "2A"], "booking_reference": "abc" }))
+            .await
+            .assert_status_ok();
+        server
+            .post("/train/express_2000/reserve")
+            .json(&serde_json::json!({ "seats": ["4A"], "booking_reference": "def" }))
+            .await
+            .assert_status_ok();
 
         let response = server
-            .post("/train/local_1000/reserve")
-            .json(&Reservation {
-                seats: vec![SeatId::new("does_not_exist")],
-                booking_reference: BookingReference::new("123456"),
-            })
+            .put("/reservation/1/seats")
+            .json(&serde_json::json!({ "seats": ["2A", "4A"] }))
+            .await;
+        assert_eq!(
+            response.json::<serde_json::Value>()["alternatives"],
+            serde_json::json!(["3A", "5A", "6A", "7A", "8A"])
+        );
+        let response = server
+            .put("/reservation/1/seats")
+            .json(&serde_json::json!({ "seats": ["2A", "3A"] }))
             .await;
+        response.assert_status_ok();
+        assert_eq!(
+            response.json::<serde_json::Value>()["seats"],
+            serde_json::json!(["2A", "3A"])
+        );
+        let seats = server
+            .get("/train/express_2000")
+            .await
+            .json::<serde_json::Value>()["seats"]
+            .clone();
+        assert_eq!(seats["1A"]["booking_reference"], serde_json::Value::Null);
+        assert_eq!(seats["3A"]["booking_reference"], "abc");
 
+        let response = server
+            .put("/reservation/1/seats")
+            .json(&serde_json::json!({ "seats": [] }))
+            .await;
         assert_eq!(response.status_code(), 400);
-        assert_eq!(response.text(), "Seats [does_not_exist] do not exist");
+        server
+            .post("/train/express_2000/cancel")
+            .json(&serde_json::json!({ "booking_reference": "abc" }))
+            .await
+            .assert_status_ok();
+        let response = server
+            .put("/reservation/1/seats")
+            .json(&serde_json::json!({ "seats": ["1A"] }))
+            .await;
+        assert_eq!(response.status_code(), 409);
+        assert_eq!(
+            response.json::<serde_json::Value>()["error"],
+            "reservation_cancelled"
+        );
+        let response = server
+            .put("/reservation/9/seats")
+            .json(&serde_json::json!({ "seats": ["1A"] }))
+            .await;
+        assert_eq!(response.status_code(), 404);
     }
 
     #[tokio::test]
-    async fn test_reserve_seat_already_reserved() {
-        let server = new_test_app_failing();
+    async fn test_hold_seats() {
+        let clock = SimulatedClock::new(1000);
+        let app = app(AppState::new().with_simulated_clock(clock.clone()));
+        let server =
+            TestServer::new_with_config(app, TestServerConfig::builder().mock_transport().build())
+                .unwrap();
+        let response = server
+            .post("/train/express_2000/hold")
+            .json(&serde_json::json!({
+                "seats": ["1A", "2A"],
+                "booking_reference": "abc",
+                "seconds": 60,
+            }))
+            .await;
+        response.assert_status_ok();
+        assert_eq!(
+            response.json::<serde_json::Value>(),
+            serde_json::json!({
+                "train_id": "express_2000",
+                "booking_reference": "abc",
+                "seats": ["1A", "2A"],
+                "expires": 1060,
+            })
+        );
+        let seat = server
+            .get("/train/express_2000/seat/1A")
+            .await
+            .json::<serde_json::Value>();
+        assert_eq!(seat["held_until"], 1060);
 
-        // make a reservation
+        let reserve = |booking_reference: &'static str| {
+            server
+                .post("/train/express_2000/reserve")
+                .json(&serde_json::json!({
+                    "seats": ["1A"],
+                    "booking_reference": booking_reference,
+                }))
+        };
+        let response = reserve("def").await;
+        assert_eq!(response.status_code(), 409);
+        assert_eq!(
+            response.json::<serde_json::Value>()["message"],
+            "Seats [1A] are already reserved"
+        );
+        reserve("abc").await.assert_status_ok();
+        let seat = server
+            .get("/train/express_2000/seat/1A")
+            .await
+            .json::<serde_json::Value>();
+        assert_eq!(seat["booking_reference"], "abc");
+        assert_eq!(seat.get("held_until"), None);
+
+        let response = server
+            .post("/train/express_2000/hold")
+            .json(&serde_json::json!({
+                "seats": ["3A"],
+                "booking_reference": "abc",
+                "seconds": u64::MAX,
+            }))
+            .await;
+        assert_eq!(response.status_code(), 400);
+        assert_eq!(
+            response.json::<serde_json::Value>()["message"],
+            "Seats can be held for at most 86400 seconds"
+        );
+
+        // the hold on 2A runs out
+        clock.advance(60);
         server
-            .post("/train/local_1000/reserve")
-            .json(&Reservation {
-                seats: vec![SeatId::new("1A")],
-                booking_reference: BookingReference::new("123456"),
-            })
+            .post("/train/express_2000/reserve")
+            .json(&serde_json::json!({ "seats": ["2A"], "booking_reference": "def" }))
+            .await
+            .assert_status_ok();
+    }
+
+    #[tokio::test]
+    async fn test_expired_holds_are_free() {
+        let clock = SimulatedClock::new(1000);
+        let app = app(AppState::new().with_simulated_clock(clock.clone()));
+        let server =
+            TestServer::new_with_config(app, TestServerConfig::builder().mock_transport().build())
+                .unwrap();
+        let train = server
+            .get("/train/express_2000")
+            .await
+            .json::<serde_json::Value>();
+        let seats: Vec<&String> = train["seats"].as_object().unwrap().keys().collect();
+        server
+            .post("/train/express_2000/hold")
+            .json(&serde_json::json!({
+                "seats": seats,
+                "booking_reference": "abc",
+                "seconds": 60,
+            }))
+            .await
+            .assert_status_ok();
+        // published while the holds last
+        let train = server
+            .get("/train/express_2000")
+            .await
+            .json::<serde_json::Value>();
+        assert_eq!(train["seats"]["1A"]["held_until"], 1060);
+
+        clock.advance(120);
+        let train = server
+            .get("/train/express_2000")
+            .await
+            .json::<serde_json::Value>();
+        assert_eq!(
+            train["seats"]["1A"]["booking_reference"],
+            serde_json::Value::Null
+        );
+        assert_eq!(train["seats"]["1A"].get("held_until"), None);
+        let seat = server
+            .get("/train/express_2000/seat/2A")
+            .await
+            .json::<serde_json::Value>();
+        assert_eq!(seat.get("held_until"), None);
+        let availability = server
+            .get("/train/express_2000/availability")
+            .await
+            .json::<serde_json::Value>();
+        assert_eq!(availability["reserved"], 0);
+        server
+            .post("/reserve")
+            .json(&serde_json::json!({ "train_id": "express_2000", "seat_count": 2 }))
+            .await
+            .assert_status_ok();
+    }
+
+    #[tokio::test]
+    async fn test_patch_seat() {
+        let server = new_test_app_failing();
+        let response = server
+            .patch("/admin/train/express_2000/seat/1A")
+            .json(&serde_json::json!({ "accessible": true, "blocked": "crew" }))
+            .await;
+        response.assert_status_ok();
+        let seat = response.json::<serde_json::Value>();
+        assert_eq!(seat["accessible"], true);
+        assert_eq!(seat["blocked"], "crew");
+        let response = server
+            .patch("/admin/train/express_2000/seat/1A")
+            .json(&serde_json::json!({ "blocked": null }))
             .await;
+        let seat = response.json::<serde_json::Value>();
+        assert_eq!(seat["accessible"], true);
+        assert_eq!(seat.get("blocked"), None);
 
-        // try to reserve again
+        server
+            .post("/train/express_2000/reserve")
+            .json(&serde_json::json!({ "seats": ["2A"], "booking_reference": "abc" }))
+            .await
+            .assert_status_ok();
         let response = server
-            .post("/train/local_1000/reserve")
-            .json(&Reservation {
-                seats: vec![SeatId::new("1A")],
-                booking_reference: BookingReference::new("123456"),
+            .patch("/admin/train/express_2000/seat/2A")
+            .json(&serde_json::json!({ "blocked": "crew" }))
+            .await;
+        assert_eq!(response.status_code(), 409);
+        assert_eq!(
+            response.json::<serde_json::Value>(),
+            serde_json::json!({
+                "error": "seats_already_reserved",
+                "message": "Seats [2A] are already reserved",
+                "alternatives": [],
             })
+        );
+        let response = server
+            .patch("/admin/train/express_2000/seat/2A")
+            .json(&serde_json::json!({ "booking_reference": null }))
             .await;
+        assert_eq!(response.status_code(), 422);
+    }
 
-        assert_eq!(response.status_code(), 400);
-        assert_eq!(response.text(), "Seats [1A] are already reserved");
+    #[tokio::test]
+    async fn test_not_found_and_method_not_allowed() {
+        let server = new_test_app_failing();
+        let response = server.get("/nowhere").await;
+        assert_eq!(response.status_code(), 404);
+        assert_eq!(
+            response.json::<serde_json::Value>(),
+            serde_json::json!({
+                "error": "not_found",
+                "message": "There is nothing at /nowhere",
+            })
+        );
+
+        let response = server.get("/train/express_2000/reserve").await;
+        assert_eq!(response.status_code(), 405);
+        assert_eq!(response.header("allow"), "POST");
+        assert_eq!(
+            response.json::<serde_json::Value>()["error"],
+            "method_not_allowed"
+        );
     }
 
     #[tokio::test]
-    async fn test_reserve_reset() {
-        let server = new_test_app();
+    async fn test_recovers_from_panic_holding_lock() {
+        let state = Arc::new(Mutex::new(AppState::new()));
+        let router = axum::Router::new()
+            // nothing is published, so reading the train takes the lock
+            .route(
+                "/train/:train_id",
+                get(train).with_state((
+                    state.clone(),
+                    Arc::default(),
+                    Arc::new(SystemClock) as Arc<dyn Clock>,
+                )),
+            )
+            .route(
+                "/panic",
+                get(
+                    |extract::State(state): extract::State<Arc<Mutex<AppState>>>| async move {
+                        let _state = state.lock_or_recover();
+                        if true {
+                            panic!("panicking while holding the lock");
+                        }
+                        "unreachable"
+                    },
+                )
+                .with_state(state.clone()),
+            );
+        let server = TestServer::new(middleware::with_panic_recovery(router)).unwrap();
 
-        // make a reservation
-        let train = server
-            .post("/train/local_1000/reserve")
-            .json(&Reservation {
-                seats: vec![SeatId::new("1A")],
-                booking_reference: BookingReference::new("first"),
-            })
+        let response = server.get("/panic").expect_failure().await;
+        assert_eq!(response.status_code(), 500);
+        assert!(state.is_poisoned());
+        server.get("/train/express_2000").await.assert_status_ok();
+    }
+
+    #[tokio::test]
+    async fn test_reset_on_schedule() {
+        let app = app(AppState::new()
+            .with_reset_schedule(ResetSchedule::Every(Duration::from_millis(50)), true));
+        let server =
+            TestServer::new_with_config(app, TestServerConfig::builder().mock_transport().build())
+                .unwrap();
+        let booking_reference = server.post("/booking_reference").await.text();
+        server
+            .post("/train/express_2000/reserve")
+            .json(&serde_json::json!({ "seats": ["1A"], "booking_reference": "abc" }))
             .await
-            .json::<Train>();
+            .assert_status_ok();
 
-        // the first reservation is there
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let response = server.get("/train/express_2000").await;
         assert_eq!(
-            train
-                .get(&SeatId::new("1A"))
-                .unwrap()
-                .booking_reference()
-                .unwrap(),
-            &BookingReference::new("first")
+            response.json::<serde_json::Value>()["seats"]["1A"]["booking_reference"],
+            serde_json::Value::Null
+        );
+        assert_eq!(
+            server.post("/booking_reference").await.text(),
+            booking_reference
         );
+    }
 
-        // reset the train
-        let train = server.post("/train/local_1000/reset").await.json::<Train>();
+    #[tokio::test]
+    async fn test_reset_on_schedule_follows_clock() {
+        // a second before midnight, by the service's clock
+        let app = app(AppState::new()
+            .with_simulated_clock(SimulatedClock::new(86399))
+            .with_reset_schedule(ResetSchedule::At(vec!["00:00".parse().unwrap()]), false));
+        let server =
+            TestServer::new_with_config(app, TestServerConfig::builder().mock_transport().build())
+                .unwrap();
+        server
+            .post("/train/express_2000/reserve")
+            .json(&serde_json::json!({ "seats": ["1A"], "booking_reference": "abc" }))
+            .await
+            .assert_status_ok();
 
-        // the reservation is gone
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        let response = server.get("/train/express_2000").await;
         assert_eq!(
-            train.get(&SeatId::new("1A")).unwrap().booking_reference(),
-            None
+            response.json::<serde_json::Value>()["seats"]["1A"]["booking_reference"],
+            serde_json::Value::Null
         );
+    }
 
-        // try to reserve again
-        let train = server
-            .post("/train/local_1000/reserve")
-            .json(&Reservation {
-                seats: vec![SeatId::new("1A")],
-                booking_reference: BookingReference::new("second"),
-            })
+    #[tokio::test]
+    async fn test_sandboxes() {
+        let app = app(AppState::new().with_sandboxes(Duration::from_secs(60), 2));
+        let server =
+            TestServer::new_with_config(app, TestServerConfig::builder().mock_transport().build())
+                .unwrap();
+        let response = server.post("/sandbox").await;
+        assert_eq!(response.status_code(), 201);
+        let prefix = response.json::<serde_json::Value>()["prefix"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        let other = server.post("/sandbox").await.json::<serde_json::Value>()["prefix"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        // no more than two at a time
+        let response = server.post("/sandbox").await;
+        assert_eq!(response.status_code(), 503);
+        assert_eq!(
+            response.json::<serde_json::Value>()["error"],
+            "too_many_sandboxes"
+        );
+        assert_eq!(response.header("retry-after"), "60");
+
+        server
+            .post(&format!("{}/train/express_2000/reserve", prefix))
+            .json(&serde_json::json!({ "seats": ["1A"], "booking_reference": "abc" }))
             .await
-            .json::<Train>();
+            .assert_status_ok();
+        let booked = |response: axum_test::TestResponse| {
+            response.json::<serde_json::Value>()["seats"]["1A"]["booking_reference"].clone()
+        };
+        assert_eq!(
+            booked(server.get(&format!("{}/train/express_2000", prefix)).await),
+            "abc"
+        );
+        // other sandboxes, and the service itself, don't see it
+        assert_eq!(
+            booked(server.get(&format!("{}/train/express_2000", other)).await),
+            serde_json::Value::Null
+        );
+        assert_eq!(
+            booked(server.get("/train/express_2000").await),
+            serde_json::Value::Null
+        );
+        let response = server
+            .get(&format!("{}/train/express_2000/availability", prefix))
+            .add_query_param("quiet", "false")
+            .await;
+        response.assert_status_ok();
 
-        // the second reservation is there
+        let response = server.get("/sandbox/unknown/train/express_2000").await;
+        assert_eq!(response.status_code(), 404);
         assert_eq!(
-            train
-                .get(&SeatId::new("1A"))
-                .unwrap()
-                .booking_reference()
-                .unwrap(),
-            &BookingReference::new("second")
+            response.json::<serde_json::Value>()["error"],
+            "sandbox_not_found"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sandboxes_copy_configuration() {
+        let dir = std::env::temp_dir().join(format!(
+            "train_service_rest_sandbox_config_{}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let train = serde_json::json!({
+            "seats": {
+                "1A": { "seat_number": "1", "coach": "A", "booking_reference": "abc" },
+                "2A": { "seat_number": "2", "coach": "A", "booking_reference": null },
+            },
+            "fares": [{ "name": "standard", "price": 4000 }],
+        });
+        std::fs::write(dir.join("regional.json"), train.to_string()).unwrap();
+        let app = app(AppState::new()
+            .with_trains(TrainsData::from_trains(Default::default()))
+            .with_store(Box::new(DirectoryStore::new(dir.clone())))
+            .with_pricing(Box::new(FixedPricing))
+            .with_simulated_clock(SimulatedClock::new(0))
+            .with_sandboxes(Duration::from_secs(60), 1));
+        let server =
+            TestServer::new_with_config(app, TestServerConfig::builder().mock_transport().build())
+                .unwrap();
+        let prefix = server.post("/sandbox").await.json::<serde_json::Value>()["prefix"]
+            .as_str()
+            .unwrap()
+            .to_string();
+
+        // the train was never loaded, but the sandbox has it, priced the same
+        let response = server
+            .post(&format!("{}/train/regional/quote", prefix))
+            .json(&serde_json::json!({ "seats": ["2A"] }))
+            .await;
+        assert_eq!(response.json::<serde_json::Value>()["total"], 4000);
+
+        // moving the clock lets holds in sandboxes run out too
+        server
+            .post(&format!("{}/train/regional/hold", prefix))
+            .json(&serde_json::json!({
+                "seats": ["2A"],
+                "booking_reference": "def",
+                "seconds": 60,
+            }))
+            .await
+            .assert_status_ok();
+        server
+            .post("/admin/time/advance")
+            .json(&serde_json::json!({ "seconds": 61 }))
+            .await
+            .assert_status_ok();
+        let response = server
+            .get(&format!("{}/train/regional/seat/2A", prefix))
+            .await;
+        assert_eq!(
+            response.json::<serde_json::Value>()["held_until"],
+            serde_json::Value::Null
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "fixtures")]
+    #[tokio::test]
+    async fn test_generate_trains() {
+        let server = new_test_app_failing();
+        let response = server
+            .post("/admin/generate")
+            .add_query_param("trains", 2)
+            .add_query_param("coaches", 2)
+            .add_query_param("seats", 10)
+            .add_query_param("occupancy", 0.5)
+            .add_query_param("seed", 7)
+            .await;
+        assert_eq!(response.status_code(), 201);
+        assert_eq!(
+            response.json::<serde_json::Value>(),
+            serde_json::json!({ "trains": ["generated_1", "generated_2"] })
+        );
+        let availability = server
+            .get("/train/generated_2/availability")
+            .await
+            .json::<serde_json::Value>();
+        assert_eq!(availability["reserved"], 10);
+        assert_eq!(availability["total"], 20);
+
+        // new trains don't replace the ones there are
+        let response = server.post("/admin/generate").await;
+        assert_eq!(
+            response.json::<serde_json::Value>(),
+            serde_json::json!({ "trains": ["generated_3"] })
         );
+        let response = server
+            .post("/admin/generate")
+            .add_query_param("occupancy", 2)
+            .await;
+        assert_eq!(response.status_code(), 422);
     }
 }
@@ -0,0 +1,329 @@
+//! Business rules that are checked before a reservation is made.
+//!
+//! Each rule looks at the train and the reservation and can refuse it. The
+//! rules that apply are registered at startup, so new ones can be added by
+//! implementing [`ReservationRule`] without touching the reservation code.
+
+use crate::booking_reference::BookingReference;
+use crate::train::{Error, Reservation, Train};
+
+pub trait ReservationRule: Send + Sync {
+    /// A short name for the rule, used in error reports.
+    fn name(&self) -> &'static str;
+
+    /// Check a reservation or hold that is otherwise valid: all its seats
+    /// exist and are free, or held for the same booking. Return an
+    /// explanation if the rule refuses it.
+    fn check(&self, train: &Train, reservation: &Reservation) -> Result<(), String>;
+
+    /// Check places without a seat number: in a free-seating coach, or
+    /// standing when there's no coach. Rules about particular seats don't
+    /// apply to them.
+    fn check_places(
+        &self,
+        _train: &Train,
+        _booking_reference: &BookingReference,
+        _coach: Option<&str>,
+        _places: usize,
+    ) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// The rules to check, in order; the first one to refuse a reservation wins.
+#[derive(Default)]
+pub struct Rules(Vec<Box<dyn ReservationRule>>);
+
+impl Rules {
+    pub fn add(&mut self, rule: impl ReservationRule + 'static) {
+        self.0.push(Box::new(rule));
+    }
+
+    pub fn check(&self, train: &Train, reservation: &Reservation) -> Result<(), Error> {
+        for rule in &self.0 {
+            rule.check(train, reservation)
+                .map_err(|message| Error::RuleViolated(rule.name(), message))?;
+        }
+        Ok(())
+    }
+
+    pub fn check_places(
+        &self,
+        train: &Train,
+        booking_reference: &BookingReference,
+        coach: Option<&str>,
+        places: usize,
+    ) -> Result<(), Error> {
+        for rule in &self.0 {
+            rule.check_places(train, booking_reference, coach, places)
+                .map_err(|message| Error::RuleViolated(rule.name(), message))?;
+        }
+        Ok(())
+    }
+}
+
+/// The parameters of the built-in rules, which say which of them apply.
+#[derive(Debug, Default, PartialEq, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Policies {
+    pub max_seats_per_booking: Option<usize>,
+    pub max_occupancy: Option<f64>,
+    pub same_coach: bool,
+}
+
+impl Policies {
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(max) = self.max_occupancy {
+            if !(0.0..=1.0).contains(&max) {
+                return Err(format!(
+                    "max_occupancy must be between 0 and 1, not {}",
+                    max
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn rules(&self) -> Rules {
+        let mut rules = Rules::default();
+        if let Some(max) = self.max_seats_per_booking {
+            rules.add(MaxSeatsPerBooking(max));
+        }
+        if let Some(max) = self.max_occupancy {
+            rules.add(MaxOccupancy(max));
+        }
+        if self.same_coach {
+            rules.add(SameCoach);
+        }
+        rules
+    }
+}
+
+/// A booking reference may hold at most this many seats on a train,
+/// counting places without a seat number too.
+pub struct MaxSeatsPerBooking(pub usize);
+
+impl MaxSeatsPerBooking {
+    fn check_count(
+        &self,
+        train: &Train,
+        booking_reference: &BookingReference,
+        count: usize,
+    ) -> Result<(), String> {
+        let held = train.booked_seats(booking_reference)
+            + train.held_seats(booking_reference)
+            + train.booked_places(booking_reference);
+        if held + count > self.0 {
+            return Err(format!(
+                "Booking reference {} may hold at most {} seats on this train",
+                booking_reference.as_str(),
+                self.0
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl ReservationRule for MaxSeatsPerBooking {
+    fn name(&self) -> &'static str {
+        "max_seats_per_booking"
+    }
+
+    // seats held for the booking already count as its seats
+    fn check(&self, train: &Train, reservation: &Reservation) -> Result<(), String> {
+        self.check_count(
+            train,
+            &reservation.booking_reference,
+            reservation.seats.len() - train.held_for(reservation),
+        )
+    }
+
+    fn check_places(
+        &self,
+        train: &Train,
+        booking_reference: &BookingReference,
+        _coach: Option<&str>,
+        places: usize,
+    ) -> Result<(), String> {
+        self.check_count(train, booking_reference, places)
+    }
+}
+
+/// No reservation may take the train above this fraction of reserved seats.
+pub struct MaxOccupancy(pub f64);
+
+impl MaxOccupancy {
+    fn check_count(&self, train: &Train, count: usize) -> Result<(), String> {
+        let counts = train.occupancy().train();
+        let reserved = counts.reserved + count;
+        if reserved as f64 > counts.total as f64 * self.0 {
+            return Err(format!(
+                "Reserving {} seats would take this train above {}% occupancy",
+                count,
+                self.0 * 100.0
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl ReservationRule for MaxOccupancy {
+    fn name(&self) -> &'static str {
+        "max_occupancy"
+    }
+
+    // held seats are part of the occupancy already
+    fn check(&self, train: &Train, reservation: &Reservation) -> Result<(), String> {
+        self.check_count(train, reservation.seats.len() - train.held_for(reservation))
+    }
+
+    // standing room isn't part of the occupancy
+    fn check_places(
+        &self,
+        train: &Train,
+        _booking_reference: &BookingReference,
+        coach: Option<&str>,
+        places: usize,
+    ) -> Result<(), String> {
+        match coach {
+            Some(_) => self.check_count(train, places),
+            None => Ok(()),
+        }
+    }
+}
+
+/// All seats of a reservation must be in the same coach.
+pub struct SameCoach;
+
+impl ReservationRule for SameCoach {
+    fn name(&self) -> &'static str {
+        "same_coach"
+    }
+
+    fn check(&self, train: &Train, reservation: &Reservation) -> Result<(), String> {
+        let mut coaches = reservation
+            .seats
+            .iter()
+            .filter_map(|seat_id| train.get(seat_id))
+            .map(|seat| seat.coach());
+        if let Some(first) = coaches.next() {
+            if coaches.any(|coach| coach != first) {
+                return Err("All seats of a reservation must be in the same coach".to_string());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::booking_reference::BookingReference;
+    use crate::fixtures::TrainBuilder;
+    use crate::train::SeatId;
+
+    // a train with seats 1 to 5 in coaches A and B, with 1A reserved
+    fn train() -> Train {
+        TrainBuilder::coaches(2)
+            .seats_per_coach(5)
+            .reserve("1A", "existing")
+            .build()
+    }
+
+    fn reservation(seats: &[&str], booking_reference: &str) -> Reservation {
+        Reservation {
+            seats: seats.iter().map(|seat_id| SeatId::new(seat_id)).collect(),
+            booking_reference: BookingReference::new(booking_reference),
+            accessible: false,
+        }
+    }
+
+    #[test]
+    fn test_max_seats_per_booking() {
+        let rule = MaxSeatsPerBooking(2);
+        assert!(rule
+            .check(&train(), &reservation(&["2A"], "existing"))
+            .is_ok());
+        assert!(rule
+            .check(&train(), &reservation(&["2A", "3A"], "existing"))
+            .is_err());
+        assert!(rule
+            .check(&train(), &reservation(&["2A", "3A"], "new"))
+            .is_ok());
+    }
+
+    #[test]
+    fn test_max_occupancy() {
+        let rule = MaxOccupancy(0.7);
+        assert!(rule
+            .check(
+                &train(),
+                &reservation(&["2A", "3A", "4A", "5A", "1B", "2B"], "new")
+            )
+            .is_ok());
+        assert_eq!(
+            rule.check(
+                &train(),
+                &reservation(&["2A", "3A", "4A", "5A", "1B", "2B", "3B"], "new")
+            ),
+            Err("Reserving 7 seats would take this train above 70% occupancy".to_string())
+        );
+    }
+
+    #[test]
+    fn test_same_coach() {
+        assert!(SameCoach
+            .check(&train(), &reservation(&["2A", "3A"], "new"))
+            .is_ok());
+        assert!(SameCoach
+            .check(&train(), &reservation(&["2A", "2B"], "new"))
+            .is_err());
+    }
+
+    #[test]
+    fn test_rules_first_violation_wins() {
+        let mut rules = Rules::default();
+        assert_eq!(
+            rules.check(&train(), &reservation(&["2A", "2B"], "new")),
+            Ok(())
+        );
+        rules.add(SameCoach);
+        rules.add(MaxSeatsPerBooking(1));
+        let result = rules.check(&train(), &reservation(&["2A", "2B"], "new"));
+        assert!(matches!(result, Err(Error::RuleViolated("same_coach", _))));
+    }
+
+    #[test]
+    fn test_policies_rules() {
+        let policies = Policies {
+            max_seats_per_booking: Some(1),
+            ..Policies::default()
+        };
+        let result = policies
+            .rules()
+            .check(&train(), &reservation(&["2A", "2B"], "new"));
+        assert!(matches!(
+            result,
+            Err(Error::RuleViolated("max_seats_per_booking", _))
+        ));
+        assert_eq!(
+            Policies::default()
+                .rules()
+                .check(&train(), &reservation(&["2A", "2B"], "new")),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_policies_validate() {
+        assert_eq!(Policies::default().validate(), Ok(()));
+        let policies = Policies {
+            max_occupancy: Some(1.5),
+            ..Policies::default()
+        };
+        assert_eq!(
+            policies.validate(),
+            Err("max_occupancy must be between 0 and 1, not 1.5".to_string())
+        );
+    }
+}
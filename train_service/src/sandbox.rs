@@ -0,0 +1,114 @@
+//! Throwaway sessions, each with its own copy of the trains, so many people
+//! can try the service at the same time without getting in each other's way.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use axum::Router;
+
+use crate::lock::LockExt;
+
+pub struct Sandboxes {
+    // sessions that aren't used for this long are dropped
+    expiry: Duration,
+    // no more sessions than this are kept at a time
+    max_sessions: usize,
+    // the routes of a new session, with fresh state
+    create: Box<dyn Fn() -> Router + Send + Sync>,
+    sessions: Mutex<HashMap<String, Session>>,
+}
+
+struct Session {
+    router: Router,
+    last_used: Instant,
+}
+
+impl Sandboxes {
+    pub fn new(
+        expiry: Duration,
+        max_sessions: usize,
+        create: impl Fn() -> Router + Send + Sync + 'static,
+    ) -> Self {
+        Sandboxes {
+            expiry,
+            max_sessions,
+            create: Box::new(create),
+            sessions: Mutex::default(),
+        }
+    }
+
+    pub fn expiry(&self) -> Duration {
+        self.expiry
+    }
+
+    /// Start a new session, returning its id. When there are as many
+    /// sessions as allowed, this returns how long until the least recently
+    /// used one expires instead.
+    pub fn create(&self) -> Result<String, Duration> {
+        let mut sessions = self.sessions.lock_or_recover();
+        self.drop_expired(&mut sessions);
+        if sessions.len() >= self.max_sessions {
+            let retry_after = sessions
+                .values()
+                .map(|session| self.expiry.saturating_sub(session.last_used.elapsed()))
+                .min()
+                .unwrap_or_default();
+            return Err(retry_after);
+        }
+        let router = (self.create)();
+        let session_id = loop {
+            let session_id = format!("{:016x}", rand::random::<u64>());
+            if !sessions.contains_key(&session_id) {
+                break session_id;
+            }
+        };
+        sessions.insert(
+            session_id.clone(),
+            Session {
+                router,
+                last_used: Instant::now(),
+            },
+        );
+        Ok(session_id)
+    }
+
+    /// The routes of a session, unless it expired.
+    pub fn get(&self, session_id: &str) -> Option<Router> {
+        let mut sessions = self.sessions.lock_or_recover();
+        self.drop_expired(&mut sessions);
+        let session = sessions.get_mut(session_id)?;
+        session.last_used = Instant::now();
+        Some(session.router.clone())
+    }
+
+    fn drop_expired(&self, sessions: &mut HashMap<String, Session>) {
+        sessions.retain(|_, session| session.last_used.elapsed() < self.expiry);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sessions_expire() {
+        let sandboxes = Sandboxes::new(Duration::from_millis(50), 10, Router::new);
+        let session_id = sandboxes.create().unwrap();
+        assert!(sandboxes.get(&session_id).is_some());
+        assert!(sandboxes.get("unknown").is_none());
+        std::thread::sleep(Duration::from_millis(100));
+        assert!(sandboxes.get(&session_id).is_none());
+    }
+
+    #[test]
+    fn test_max_sessions() {
+        let sandboxes = Sandboxes::new(Duration::from_millis(50), 1, Router::new);
+        sandboxes.create().unwrap();
+        let retry_after = sandboxes.create().unwrap_err();
+        assert!(retry_after <= Duration::from_millis(50));
+        // an expired session makes room for a new one
+        std::thread::sleep(Duration::from_millis(100));
+        assert!(sandboxes.create().is_ok());
+    }
+}
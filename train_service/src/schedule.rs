@@ -0,0 +1,106 @@
+//! Resetting all trains on a schedule, so a long-running demo cleans itself up
+//! between sessions.
+
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+use std::time::Duration;
+
+const DAY: u64 = 24 * 60 * 60;
+
+/// When to reset the trains.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ResetSchedule {
+    Every(Duration),
+    // every day at these times
+    At(Vec<TimeOfDay>),
+}
+
+/// A time of day in UTC, given as `HH:MM`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, PartialOrd, Ord)]
+pub struct TimeOfDay {
+    hour: u64,
+    minute: u64,
+}
+
+impl TimeOfDay {
+    fn seconds(&self) -> u64 {
+        (self.hour * 60 + self.minute) * 60
+    }
+}
+
+impl FromStr for TimeOfDay {
+    type Err = String;
+
+    /// Parse `HH:MM`, like `03:30`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("expected a time as HH:MM, like 03:30, not {}", s);
+        let (hour, minute) = s.split_once(':').ok_or_else(invalid)?;
+        let hour = hour.parse().map_err(|_| invalid())?;
+        let minute = minute.parse().map_err(|_| invalid())?;
+        if hour > 23 || minute > 59 {
+            return Err(invalid());
+        }
+        Ok(TimeOfDay { hour, minute })
+    }
+}
+
+impl Display for TimeOfDay {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02}:{:02}", self.hour, self.minute)
+    }
+}
+
+impl ResetSchedule {
+    /// How long to wait for the next reset, at `now` in seconds since the
+    /// epoch.
+    pub fn until_next(&self, now: u64) -> Duration {
+        match self {
+            ResetSchedule::Every(interval) => *interval,
+            ResetSchedule::At(times) => {
+                let today = now % DAY;
+                let wait = times
+                    .iter()
+                    .map(|time| (time.seconds() + DAY - today - 1) % DAY + 1)
+                    .min()
+                    .unwrap_or(DAY);
+                Duration::from_secs(wait)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_time_of_day() {
+        let time: TimeOfDay = "03:30".parse().unwrap();
+        assert_eq!(time.to_string(), "03:30");
+        assert!("24:00".parse::<TimeOfDay>().is_err());
+        assert!("3".parse::<TimeOfDay>().is_err());
+    }
+
+    #[test]
+    fn test_until_next() {
+        let every = ResetSchedule::Every(Duration::from_secs(60));
+        assert_eq!(every.until_next(12345), Duration::from_secs(60));
+
+        let at = ResetSchedule::At(vec!["03:00".parse().unwrap(), "15:00".parse().unwrap()]);
+        let day = 20_000 * DAY;
+        assert_eq!(at.until_next(day), Duration::from_secs(3 * 3600));
+        assert_eq!(
+            at.until_next(day + 4 * 3600),
+            Duration::from_secs(11 * 3600)
+        );
+        // right at a reset time, the next one is later
+        assert_eq!(
+            at.until_next(day + 15 * 3600),
+            Duration::from_secs(12 * 3600)
+        );
+        assert_eq!(
+            at.until_next(day + 16 * 3600),
+            Duration::from_secs(11 * 3600)
+        );
+    }
+}
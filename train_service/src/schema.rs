@@ -0,0 +1,141 @@
+//! JSON Schemas for the request and response bodies of the service.
+//!
+//! Kata teams can fetch these to validate their payloads, or to generate
+//! types in the language they're working in.
+
+use schemars::generate::SchemaSettings;
+use schemars::JsonSchema;
+use serde_json::Value;
+
+use crate::booking_reference::BookingReference;
+use crate::response::{
+    AvailabilityResponse, CoachResponse, CountsResponse, ResourceResponse, SeatResponse,
+    TrainResponse,
+};
+use crate::train::Reservation;
+
+// request bodies are described as the service reads them, responses as it
+// writes them: a field that's left out when empty isn't required
+fn document<T: JsonSchema>(name: &'static str, settings: SchemaSettings) -> (&'static str, Value) {
+    let mut schema = settings.into_generator().into_root_schema_for::<T>();
+    schema.insert("title".to_string(), Value::from(name));
+    (name, schema.to_value())
+}
+
+fn request<T: JsonSchema>(name: &'static str) -> (&'static str, Value) {
+    document::<T>(name, SchemaSettings::draft2020_12().for_deserialize())
+}
+
+fn response<T: JsonSchema>(name: &'static str) -> (&'static str, Value) {
+    document::<T>(name, SchemaSettings::draft2020_12().for_serialize())
+}
+
+fn documents() -> [(&'static str, Value); 8] {
+    [
+        request::<BookingReference>("booking_reference"),
+        request::<Reservation>("reservation"),
+        response::<SeatResponse>("seat"),
+        response::<CoachResponse>("coach"),
+        response::<ResourceResponse>("resource"),
+        response::<TrainResponse>("train"),
+        response::<CountsResponse>("counts"),
+        response::<AvailabilityResponse>("availability"),
+    ]
+}
+
+pub fn schema_names() -> Vec<&'static str> {
+    documents().into_iter().map(|(name, _)| name).collect()
+}
+
+pub fn schema(name: &str) -> Option<Value> {
+    documents()
+        .into_iter()
+        .find(|(schema_name, _)| *schema_name == name)
+        .map(|(_, schema)| schema)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    // the schemas follow serde's attributes, but the seats are serialized by hand
+    fn assert_properties_match(schema: &Value, value: &Value) {
+        let properties = schema["properties"].as_object().unwrap();
+        let fields = value.as_object().unwrap();
+        let mut property_names = properties.keys().collect::<Vec<_>>();
+        let mut field_names = fields.keys().collect::<Vec<_>>();
+        property_names.sort();
+        field_names.sort();
+        assert_eq!(property_names, field_names);
+    }
+
+    #[test]
+    fn test_reservation_schema_matches_serialization() {
+        let reservation: Reservation = serde_json::from_value(
+            json!({ "seats": ["1A"], "booking_reference": "abc", "accessible": true }),
+        )
+        .unwrap();
+        let value = serde_json::to_value(&reservation).unwrap();
+        assert_properties_match(&schema("reservation").unwrap(), &value);
+    }
+
+    #[test]
+    fn test_train_schema_matches_serialization() {
+        let train: crate::train::Train = serde_json::from_value(json!({
+            "seats": {
+                "1A": { "seat_number": "1", "coach": "A", "booking_reference": null, "position": "aisle", "accessible": true, "blocked": "crew", "neighbours": ["2A"] },
+                "2A": { "seat_number": "2", "coach": "A", "booking_reference": "abc", "held_until": 100 },
+            },
+            "coaches": { "A": { "quiet": true, "class": "first", "free_seating": { "capacity": 10, "reserved": 2 }, "layout": { "rows": 1, "columns": 2 } } },
+            "standing": { "capacity": 20, "reserved": 1 },
+            "resources": { "bike1": { "kind": "bicycle", "coach": "A" } },
+            "fares": [{ "name": "saver", "price": 1900 }],
+            "departure": 1798761600,
+            "archived": true,
+            "bookings": [{ "booking_reference": "def", "places": { "A": 2 }, "standing": 1, "paid": 3800 }],
+        }))
+        .unwrap();
+        let value = serde_json::to_value(TrainResponse::from(&train)).unwrap();
+        assert_properties_match(&schema("train").unwrap(), &value);
+        let booking = &schema("train").unwrap()["$defs"]["TrainBookingResponse"];
+        assert_properties_match(booking, &value["bookings"][0]);
+        // a seat is either held or blocked, so together they have every field
+        let mut seat = value["seats"]["1A"].clone();
+        seat["held_until"] = value["seats"]["2A"]["held_until"].clone();
+        assert_properties_match(&schema("seat").unwrap(), &seat);
+        assert_properties_match(&schema("coach").unwrap(), &value["coaches"]["A"]);
+        assert_properties_match(&schema("resource").unwrap(), &value["resources"]["bike1"]);
+
+        let value = serde_json::to_value(AvailabilityResponse::from(train.occupancy())).unwrap();
+        assert_properties_match(&schema("availability").unwrap(), &value);
+        assert_properties_match(&schema("counts").unwrap(), &value["coaches"]["A"]);
+    }
+
+    #[test]
+    fn test_schema_lookup() {
+        let schema = schema("train").unwrap();
+        assert_eq!(schema["title"], "train");
+        assert_eq!(
+            schema["$schema"],
+            "https://json-schema.org/draft/2020-12/schema"
+        );
+        assert!(super::schema("unknown").is_none());
+        assert!(schema_names().contains(&"reservation"));
+    }
+
+    #[test]
+    fn test_seat_schema() {
+        let schema = schema("seat").unwrap();
+        assert_eq!(
+            schema["required"],
+            json!(["seat_number", "coach", "booking_reference"])
+        );
+        assert_eq!(
+            schema["$defs"]["SeatId"]["pattern"],
+            "^[0-9]+[A-Za-z][A-Za-z0-9]*$"
+        );
+        assert!(schema["properties"].get("fields").is_none());
+    }
+}
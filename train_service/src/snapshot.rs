@@ -0,0 +1,174 @@
+use std::collections::BTreeMap;
+
+use crate::booking_reference::BookingReferenceService;
+use crate::pseudonyms::Pseudonyms;
+use crate::response::TrainResponse;
+use crate::train::{Error, SeatId, TrainDataService, TrainsData};
+use crate::vouchers::Vouchers;
+
+pub const FORMAT_VERSION: u32 = 1;
+
+/// The complete state of the service, as exported.
+#[derive(Debug, serde::Serialize)]
+pub struct Export<'a> {
+    version: u32,
+    booking_reference_counter: u64,
+    trains: BTreeMap<&'a str, TrainResponse<'a>>,
+    #[serde(skip_serializing_if = "Vouchers::is_empty")]
+    vouchers: &'a Vouchers,
+}
+
+impl<'a> Export<'a> {
+    /// The state, with pseudonyms for booking references if given.
+    pub fn new(
+        booking_reference_service: &BookingReferenceService,
+        train_data_service: &'a TrainDataService,
+        vouchers: &'a Vouchers,
+        pseudonyms: Option<&'a Pseudonyms>,
+    ) -> Self {
+        Export {
+            version: FORMAT_VERSION,
+            booking_reference_counter: booking_reference_service.counter(),
+            trains: train_data_service
+                .trains()
+                .iter()
+                .map(|(train_id, train)| {
+                    let train = TrainResponse::from(train).anonymized(pseudonyms);
+                    (train_id.as_str(), train)
+                })
+                .collect(),
+            vouchers,
+        }
+    }
+}
+
+/// A previously exported state, to be restored.
+#[derive(Debug, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Import {
+    version: u32,
+    booking_reference_counter: u64,
+    trains: TrainsData,
+    #[serde(default)]
+    vouchers: Vouchers,
+}
+
+#[derive(Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ImportSummary {
+    pub dry_run: bool,
+    pub trains: usize,
+    pub seats: usize,
+}
+
+impl Import {
+    pub fn validate(&self, dry_run: bool) -> Result<ImportSummary, Error> {
+        if self.version != FORMAT_VERSION {
+            return Err(Error::InvalidImport(format!(
+                "unsupported version {}, expected {}",
+                self.version, FORMAT_VERSION
+            )));
+        }
+        let mut seats = 0;
+        for (train_id, train) in self.trains.iter() {
+            for (seat_id, seat) in train.seats() {
+                let expected = SeatId::from_parts(seat.seat_number(), seat.coach());
+                if expected.as_ref() != Ok(seat_id) {
+                    return Err(Error::InvalidImport(format!(
+                        "seat {} of train {} has seat number {} and coach {}",
+                        seat_id,
+                        train_id,
+                        seat.seat_number(),
+                        seat.coach()
+                    )));
+                }
+                seats += 1;
+            }
+        }
+        Ok(ImportSummary {
+            dry_run,
+            trains: self.trains.iter().count(),
+            seats,
+        })
+    }
+
+    pub fn into_parts(self) -> (u64, TrainsData, Vouchers) {
+        (self.booking_reference_counter, self.trains, self.vouchers)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn import(value: serde_json::Value) -> Import {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn test_export_import_round_trip() {
+        let trains: TrainsData = serde_json::from_str(include_str!("trains.json")).unwrap();
+        let train_data_service = TrainDataService::new(trains.clone());
+        let booking_reference_service = BookingReferenceService::new(42);
+        let mut vouchers = Vouchers::default();
+        vouchers
+            .insert(
+                "SPRING".to_string(),
+                serde_json::from_value(json!({ "discount": { "percent": 10 } })).unwrap(),
+            )
+            .unwrap();
+        let export = Export::new(
+            &booking_reference_service,
+            &train_data_service,
+            &vouchers,
+            None,
+        );
+
+        let imported = import(serde_json::to_value(&export).unwrap());
+        let summary = imported.validate(false).unwrap();
+        assert_eq!(
+            summary,
+            ImportSummary {
+                dry_run: false,
+                trains: 2,
+                seats: 32
+            }
+        );
+        assert_eq!(imported.into_parts(), (42, trains, vouchers));
+    }
+
+    #[test]
+    fn test_import_wrong_version() {
+        let imported = import(json!({
+            "version": 99,
+            "booking_reference_counter": 0,
+            "trains": {},
+        }));
+        assert_eq!(
+            imported.validate(false),
+            Err(Error::InvalidImport(
+                "unsupported version 99, expected 1".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_import_inconsistent_seat() {
+        let imported = import(json!({
+            "version": 1,
+            "booking_reference_counter": 0,
+            "trains": {
+                "t": { "seats": {
+                    "1A": { "seat_number": "2", "coach": "A", "booking_reference": null },
+                } },
+            },
+        }));
+        assert_eq!(
+            imported.validate(true),
+            Err(Error::InvalidImport(
+                "seat 1A of train t has seat number 2 and coach A".to_string()
+            ))
+        );
+    }
+}
@@ -0,0 +1,148 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::layout::Layout;
+use crate::train::{Coach, Error, Seat, SeatId, Train};
+
+/// A compact description of a train: which coaches it has, and how many
+/// seats are in each. Seats are numbered from 1 within each coach.
+///
+/// In JSON this looks like
+/// `{"coaches": [{"id": "A", "seats": 20}, {"id": "B", "seats": 30}]}`.
+#[derive(Debug, Default, PartialEq, Eq, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TrainSpec {
+    pub coaches: Vec<CoachSpec>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CoachSpec {
+    pub id: String,
+    pub seats: usize,
+    /// How the seats are laid out; they have to fit.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub layout: Option<Layout>,
+}
+
+impl TrainSpec {
+    #[cfg(test)]
+    pub fn coach<S: Into<String>>(mut self, id: S, seats: usize) -> Self {
+        self.coaches.push(CoachSpec {
+            id: id.into(),
+            seats,
+            layout: None,
+        });
+        self
+    }
+
+    pub fn build(&self) -> Result<Train, Error> {
+        let _span = tracing::info_span!("build_train", coaches = self.coaches.len()).entered();
+        let mut coach_ids = HashSet::new();
+        let mut seats = HashMap::new();
+        let mut coaches = HashMap::new();
+        for coach in &self.coaches {
+            if !coach_ids.insert(&coach.id) {
+                return Err(Error::InvalidTrainSpec(format!(
+                    "coach {} appears more than once",
+                    coach.id
+                )));
+            }
+            if let Some(layout) = coach.layout {
+                let invalid = |message: &str| {
+                    Error::InvalidTrainSpec(format!("coach {}: {}", coach.id, message))
+                };
+                layout.validate().map_err(|message| invalid(&message))?;
+                if coach.seats > layout.rows * layout.columns {
+                    return Err(invalid("the seats don't fit in the layout"));
+                }
+                coaches.insert(
+                    coach.id.clone(),
+                    Coach {
+                        layout: Some(layout),
+                        ..Coach::default()
+                    },
+                );
+            }
+            for seat_number in 1..=coach.seats {
+                let seat_number = seat_number.to_string();
+                let seat_id = SeatId::from_parts(&seat_number, &coach.id)
+                    .map_err(|_| Error::InvalidTrainSpec(format!("invalid coach {}", coach.id)))?;
+                seats.insert(seat_id, Seat::new(seat_number, coach.id.clone()));
+            }
+        }
+        Ok(Train::new(seats).with_coaches(coaches))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build() {
+        let train = TrainSpec::default()
+            .coach("A", 2)
+            .coach("B", 3)
+            .build()
+            .unwrap();
+        assert_eq!(train.occupancy().train().total, 5);
+        let seat = train.get(&SeatId::new("3B")).unwrap();
+        assert_eq!(seat.seat_number(), "3");
+        assert_eq!(seat.coach(), "B");
+        assert_eq!(seat.booking_reference(), None);
+        assert!(train.get(&SeatId::new("3A")).is_none());
+    }
+
+    #[test]
+    fn test_build_from_json() {
+        let spec: TrainSpec = serde_json::from_str(
+            r#"{"coaches": [{"id": "A", "seats": 20}, {"id": "B", "seats": 30}]}"#,
+        )
+        .unwrap();
+        let train = spec.build().unwrap();
+        assert_eq!(train.occupancy().train().total, 50);
+    }
+
+    #[test]
+    fn test_build_duplicate_coach() {
+        let result = TrainSpec::default().coach("A", 2).coach("A", 3).build();
+        assert_eq!(
+            result,
+            Err(Error::InvalidTrainSpec(
+                "coach A appears more than once".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_build_invalid_coach() {
+        let result = TrainSpec::default().coach("1", 2).build();
+        assert_eq!(
+            result,
+            Err(Error::InvalidTrainSpec("invalid coach 1".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_build_with_layout() {
+        let spec: TrainSpec = serde_json::from_str(
+            r#"{"coaches": [{"id": "A", "seats": 6, "layout": {"rows": 2, "columns": 3, "aisle_after": 1}}]}"#,
+        )
+        .unwrap();
+        let train = spec.build().unwrap();
+        let adjacency = train.adjacency();
+        assert!(adjacency.are_adjacent(&SeatId::new("2A"), &SeatId::new("3A")));
+        assert!(!adjacency.are_adjacent(&SeatId::new("1A"), &SeatId::new("2A")));
+
+        let spec: TrainSpec = serde_json::from_str(
+            r#"{"coaches": [{"id": "A", "seats": 7, "layout": {"rows": 2, "columns": 3}}]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            spec.build(),
+            Err(Error::InvalidTrainSpec(
+                "coach A: the seats don't fit in the layout".to_string()
+            ))
+        );
+    }
+}
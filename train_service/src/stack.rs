@@ -0,0 +1,332 @@
+//! The optional middleware layers, switched on and configured in a file, so
+//! a facilitator can pick the layers for a session without changing code.
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::lock::LockExt;
+
+/// The layers to run. Those left out don't run.
+#[derive(Debug, Default, PartialEq, Clone, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Stack {
+    /// Give up on requests after this many seconds, instead of after the
+    /// `--request-timeout`.
+    pub timeout_seconds: Option<u64>,
+    pub rate_limit: Option<RateLimit>,
+    pub load_shed: Option<LoadShed>,
+    pub backpressure: Option<Backpressure>,
+    pub cors: Option<Cors>,
+    pub chaos: Option<Chaos>,
+    pub compression: Option<Compression>,
+}
+
+/// Refuse requests once there were this many in a period, for all clients
+/// together.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimit {
+    pub requests: u64,
+    pub per_seconds: u64,
+}
+
+/// Refuse requests while this many are being handled already, so the
+/// requests that are taken stay fast when there are too many.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LoadShed {
+    pub max_in_flight: usize,
+    /// How many seconds clients are told to wait before trying again.
+    #[serde(default = "LoadShed::default_retry_after_seconds")]
+    pub retry_after_seconds: u64,
+}
+
+impl LoadShed {
+    fn default_retry_after_seconds() -> u64 {
+        1
+    }
+}
+
+/// Turn requests for the trains away while too many wait for them. All
+/// trains of a tenant are behind one lock, so requests for them are handled
+/// one at a time, and those waiting make up a queue.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Backpressure {
+    pub max_queue: usize,
+}
+
+/// Let browsers on other origins call the service.
+#[derive(Debug, PartialEq, Eq, Clone, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Cors {
+    /// The origins that may, like `http://localhost:3000`, or `*` for any.
+    pub origins: Vec<String>,
+}
+
+impl Cors {
+    /// What to send as `Access-Control-Allow-Origin` to this origin, if it's
+    /// allowed.
+    pub fn allow_origin<'a>(&self, origin: &'a str) -> Option<&'a str> {
+        if self.origins.iter().any(|allowed| allowed == "*") {
+            Some("*")
+        } else {
+            self.origins
+                .iter()
+                .any(|allowed| allowed == origin)
+                .then_some(origin)
+        }
+    }
+}
+
+/// Compress responses with gzip for clients that accept it.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Compression {
+    /// Responses of this many bytes or fewer are sent as they are.
+    pub min_size: u16,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Compression { min_size: 32 }
+    }
+}
+
+/// Make things go wrong on purpose, to practice with clients that cope.
+#[derive(Debug, Default, PartialEq, Clone, Copy, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Chaos {
+    /// The fraction of requests that fail with a 503 response.
+    pub failure_rate: f64,
+    /// How long to wait before handling each request, in milliseconds.
+    pub delay_ms: u64,
+}
+
+impl Stack {
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(rate_limit) = self.rate_limit {
+            if rate_limit.per_seconds == 0 {
+                return Err("rate_limit.per_seconds must be more than 0".to_string());
+            }
+        }
+        if let Some(load_shed) = self.load_shed {
+            if load_shed.max_in_flight == 0 {
+                return Err("load_shed.max_in_flight must be more than 0".to_string());
+            }
+        }
+        if let Some(backpressure) = self.backpressure {
+            if backpressure.max_queue == 0 {
+                return Err("backpressure.max_queue must be more than 0".to_string());
+            }
+        }
+        if let Some(chaos) = self.chaos {
+            if !(0.0..=1.0).contains(&chaos.failure_rate) {
+                return Err(format!(
+                    "chaos.failure_rate must be between 0 and 1, not {}",
+                    chaos.failure_rate
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Counts requests in fixed periods, starting a new period once the last
+/// one is over.
+#[derive(Debug)]
+pub struct RateLimiter {
+    limit: RateLimit,
+    // when the period started, and the requests in it so far; it starts with
+    // the first request
+    period: Mutex<Option<(Instant, u64)>>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: RateLimit) -> Self {
+        RateLimiter {
+            limit,
+            period: Mutex::new(None),
+        }
+    }
+
+    /// Count a request at `now`. If it's over the limit, return how long
+    /// until the next period starts.
+    pub fn check(&self, now: Instant) -> Result<(), Duration> {
+        let length = Duration::from_secs(self.limit.per_seconds);
+        let mut period = self.period.lock_or_recover();
+        let (start, requests) = period.get_or_insert((now, 0));
+        if now.duration_since(*start) >= length {
+            *start = now;
+            *requests = 0;
+        }
+        if *requests >= self.limit.requests {
+            return Err(length.saturating_sub(now.duration_since(*start)));
+        }
+        *requests += 1;
+        Ok(())
+    }
+}
+
+// how long requests are guessed to take until one finished
+const FIRST_SERVICE_TIME: Duration = Duration::from_millis(10);
+
+/// The requests waiting for the trains or being handled, and how long each
+/// takes to handle, to tell clients that are turned away when to come back.
+#[derive(Debug)]
+pub struct Queue {
+    max_queue: usize,
+    inner: Mutex<QueueState>,
+}
+
+#[derive(Debug, Default)]
+struct QueueState {
+    depth: usize,
+    // a moving average
+    service_time: Option<Duration>,
+    rejected: u64,
+}
+
+/// A request's place in the queue, which it leaves when this is dropped.
+#[derive(Debug)]
+pub struct Place {
+    queue: Arc<Queue>,
+    position: usize,
+    entered: Instant,
+}
+
+impl Drop for Place {
+    fn drop(&mut self) {
+        self.queue.leave(self.position, self.entered.elapsed());
+    }
+}
+
+impl Queue {
+    pub fn new(backpressure: Backpressure) -> Self {
+        Queue {
+            max_queue: backpressure.max_queue,
+            inner: Mutex::default(),
+        }
+    }
+
+    /// Join the queue, or if it's full, learn how long it takes to handle
+    /// the requests in it.
+    pub fn enter(self: &Arc<Self>) -> Result<Place, Duration> {
+        let mut inner = self.inner.lock_or_recover();
+        if inner.depth >= self.max_queue {
+            inner.rejected += 1;
+            let service_time = inner.service_time.unwrap_or(FIRST_SERVICE_TIME);
+            return Err(service_time.saturating_mul(inner.depth as u32));
+        }
+        inner.depth += 1;
+        Ok(Place {
+            queue: self.clone(),
+            position: inner.depth,
+            entered: Instant::now(),
+        })
+    }
+
+    // a request that was handled after waiting for those in front of it
+    fn leave(&self, position: usize, took: Duration) {
+        let mut inner = self.inner.lock_or_recover();
+        inner.depth -= 1;
+        let sample = took / position as u32;
+        inner.service_time = Some(match inner.service_time {
+            Some(average) => average.mul_f64(0.8) + sample.mul_f64(0.2),
+            None => sample,
+        });
+    }
+
+    pub fn depth(&self) -> usize {
+        self.inner.lock_or_recover().depth
+    }
+
+    /// How many requests were turned away.
+    pub fn rejected(&self) -> u64 {
+        self.inner.lock_or_recover().rejected
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limiter() {
+        let limiter = RateLimiter::new(RateLimit {
+            requests: 2,
+            per_seconds: 10,
+        });
+        let start = Instant::now();
+        assert_eq!(limiter.check(start), Ok(()));
+        assert_eq!(limiter.check(start + Duration::from_secs(1)), Ok(()));
+        assert_eq!(
+            limiter.check(start + Duration::from_secs(4)),
+            Err(Duration::from_secs(6))
+        );
+        assert_eq!(limiter.check(start + Duration::from_secs(10)), Ok(()));
+    }
+
+    #[test]
+    fn test_queue() {
+        let queue = Arc::new(Queue::new(Backpressure { max_queue: 2 }));
+        let first = queue.enter().unwrap();
+        let second = queue.enter().unwrap();
+        assert_eq!(queue.depth(), 2);
+        // guessed before any request was handled
+        assert_eq!(queue.enter().unwrap_err(), Duration::from_millis(20));
+        assert_eq!(queue.rejected(), 1);
+
+        drop(first);
+        drop(second);
+        assert_eq!(queue.depth(), 0);
+
+        // requests were handled in 100ms each
+        let queue = Arc::new(Queue {
+            max_queue: 2,
+            inner: Mutex::new(QueueState {
+                service_time: Some(Duration::from_millis(100)),
+                ..QueueState::default()
+            }),
+        });
+        let _first = queue.enter().unwrap();
+        let _second = queue.enter().unwrap();
+        assert_eq!(queue.enter().unwrap_err(), Duration::from_millis(200));
+    }
+
+    #[test]
+    fn test_cors() {
+        let cors = Cors {
+            origins: vec!["http://localhost:3000".to_string()],
+        };
+        assert_eq!(
+            cors.allow_origin("http://localhost:3000"),
+            Some("http://localhost:3000")
+        );
+        assert_eq!(cors.allow_origin("http://example.com"), None);
+        let any = Cors {
+            origins: vec!["*".to_string()],
+        };
+        assert_eq!(any.allow_origin("http://example.com"), Some("*"));
+    }
+
+    #[test]
+    fn test_validate() {
+        let stack: Stack = serde_json::from_value(serde_json::json!({
+            "rate_limit": { "requests": 10, "per_seconds": 1 },
+            "load_shed": { "max_in_flight": 100 },
+            "chaos": { "failure_rate": 0.1 },
+        }))
+        .unwrap();
+        assert_eq!(stack.validate(), Ok(()));
+        assert_eq!(stack.load_shed.unwrap().retry_after_seconds, 1);
+        let stack = Stack {
+            chaos: Some(Chaos {
+                failure_rate: 2.0,
+                delay_ms: 0,
+            }),
+            ..Stack::default()
+        };
+        assert!(stack.validate().is_err());
+    }
+}
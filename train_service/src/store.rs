@@ -0,0 +1,240 @@
+//! Where trains come from when they aren't all loaded at startup. Trains in
+//! a store are loaded the first time they're used.
+
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::load::{self, LoadError};
+use crate::metrics::CacheMetrics;
+use crate::response::TrainResponse;
+use crate::train::{Train, TrainId};
+
+pub trait TrainStore: Send {
+    /// The ids of all trains in the store.
+    fn train_ids(&self) -> Result<Vec<TrainId>, LoadError>;
+
+    /// A train, if the store has it.
+    fn fetch(&self, train_id: &TrainId) -> Result<Option<Train>, LoadError>;
+
+    /// Store a train, replacing what the store had for it.
+    fn save(&self, train_id: &TrainId, train: &Train) -> Result<(), LoadError>;
+}
+
+/// A directory with a JSON file for each train, named after the train, like
+/// `express_2000.json`.
+pub struct DirectoryStore {
+    dir: PathBuf,
+}
+
+impl DirectoryStore {
+    pub fn new(dir: PathBuf) -> Self {
+        DirectoryStore { dir }
+    }
+
+    // only plain names, so a train id can't point outside the directory
+    fn path(&self, train_id: &TrainId) -> Option<PathBuf> {
+        let name = train_id.as_str();
+        let valid = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        valid.then(|| self.dir.join(format!("{}.json", name)))
+    }
+}
+
+impl TrainStore for DirectoryStore {
+    fn train_ids(&self) -> Result<Vec<TrainId>, LoadError> {
+        let entries =
+            std::fs::read_dir(&self.dir).map_err(|err| LoadError::Io(self.dir.clone(), err))?;
+        let mut train_ids = Vec::new();
+        for entry in entries {
+            let path = entry
+                .map_err(|err| LoadError::Io(self.dir.clone(), err))?
+                .path();
+            if path
+                .extension()
+                .is_some_and(|extension| extension == "json")
+            {
+                if let Some(stem) = path.file_stem().and_then(|stem| stem.to_str()) {
+                    train_ids.push(TrainId::new(stem));
+                }
+            }
+        }
+        Ok(train_ids)
+    }
+
+    fn fetch(&self, train_id: &TrainId) -> Result<Option<Train>, LoadError> {
+        match self.path(train_id) {
+            Some(path) if path.is_file() => load::load_json(&path).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    fn save(&self, train_id: &TrainId, train: &Train) -> Result<(), LoadError> {
+        let Some(path) = self.path(train_id) else {
+            return Err(LoadError::Write(
+                self.dir.join(train_id.as_str()),
+                std::io::Error::new(std::io::ErrorKind::InvalidInput, "not a valid file name"),
+            ));
+        };
+        let json = serde_json::to_string_pretty(&TrainResponse::from(train))
+            .map_err(|err| LoadError::Json(path.clone(), err))?;
+        // written next to it first, so a train is never half written
+        let partial = path.with_extension("json.partial");
+        std::fs::write(&partial, json)
+            .and_then(|_| std::fs::rename(&partial, &path))
+            .map_err(|err| LoadError::Write(path, err))
+    }
+}
+
+/// Which trains loaded from a store were used least recently, and which were
+/// changed since. When more trains are loaded than fit, the least recently
+/// used ones are dropped again, after saving them if they were changed.
+pub struct TrainCache {
+    capacity: usize,
+    // a use counter, so the least recently used train has the lowest value
+    uses: u64,
+    last_used: HashMap<TrainId, u64>,
+    dirty: HashSet<TrainId>,
+    metrics: Arc<CacheMetrics>,
+}
+
+impl TrainCache {
+    pub fn new(capacity: usize, metrics: Arc<CacheMetrics>) -> Self {
+        metrics.set_capacity(capacity);
+        TrainCache {
+            capacity,
+            uses: 0,
+            last_used: HashMap::new(),
+            dirty: HashSet::new(),
+            metrics,
+        }
+    }
+
+    /// A train was fetched from the store.
+    pub fn loaded(&mut self, train_id: &TrainId) {
+        self.touch(train_id);
+    }
+
+    /// A train was used. A train the cache hasn't seen isn't in the store
+    /// yet, so it counts as changed.
+    pub fn used(&mut self, train_id: &TrainId) {
+        if !self.last_used.contains_key(train_id) {
+            self.dirty.insert(train_id.clone());
+        }
+        self.touch(train_id);
+    }
+
+    /// A train was changed, so it has to be saved before it's dropped.
+    pub fn changed(&mut self, train_id: &TrainId) {
+        self.used(train_id);
+        self.dirty.insert(train_id.clone());
+    }
+
+    fn touch(&mut self, train_id: &TrainId) {
+        self.uses += 1;
+        self.last_used.insert(train_id.clone(), self.uses);
+    }
+
+    /// The train to drop next when more than `loaded` trains don't fit,
+    /// other than the one in use.
+    pub fn to_evict(&self, loaded: usize, in_use: &TrainId) -> Option<&TrainId> {
+        if loaded <= self.capacity {
+            return None;
+        }
+        self.last_used
+            .iter()
+            .filter(|(train_id, _)| *train_id != in_use)
+            .min_by_key(|(_, uses)| **uses)
+            .map(|(train_id, _)| train_id)
+    }
+
+    pub fn is_dirty(&self, train_id: &TrainId) -> bool {
+        self.dirty.contains(train_id)
+    }
+
+    pub fn evicted(&mut self, train_id: &TrainId) {
+        self.last_used.remove(train_id);
+        self.dirty.remove(train_id);
+        self.metrics.record_eviction();
+    }
+
+    pub fn set_loaded(&self, loaded: usize) {
+        self.metrics.set_trains(loaded);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::TrainBuilder;
+
+    #[test]
+    fn test_directory_store() {
+        let dir = std::env::temp_dir().join(format!("train_service_store_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("t.json"),
+            r#"{"seats": {"1A": {"coach": "A", "seat_number": "1"}}}"#,
+        )
+        .unwrap();
+        std::fs::write(dir.join("broken.json"), "{").unwrap();
+        std::fs::write(dir.join("notes.txt"), "not a train").unwrap();
+
+        let store = DirectoryStore::new(dir.clone());
+        let mut train_ids = store.train_ids().unwrap();
+        train_ids.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+        assert_eq!(train_ids, vec![TrainId::new("broken"), TrainId::new("t")]);
+        assert!(store.fetch(&TrainId::new("t")).unwrap().is_some());
+        assert!(store.fetch(&TrainId::new("unknown")).unwrap().is_none());
+        assert!(store.fetch(&TrainId::new("../t")).unwrap().is_none());
+        assert!(matches!(
+            store.fetch(&TrainId::new("broken")),
+            Err(LoadError::Json(_, _))
+        ));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_save_train() {
+        let dir =
+            std::env::temp_dir().join(format!("train_service_store_save_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let store = DirectoryStore::new(dir.clone());
+        let train = TrainBuilder::coaches(1).seats_per_coach(1).build();
+        store.save(&TrainId::new("t"), &train).unwrap();
+        assert_eq!(
+            store.fetch(&TrainId::new("t")).unwrap(),
+            Some(train.clone())
+        );
+        assert!(matches!(
+            store.save(&TrainId::new("../t"), &train),
+            Err(LoadError::Write(_, _))
+        ));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_cache_evicts_least_recently_used() {
+        let metrics = Arc::new(CacheMetrics::default());
+        let mut cache = TrainCache::new(2, metrics);
+        let (a, b, c) = (TrainId::new("a"), TrainId::new("b"), TrainId::new("c"));
+        cache.loaded(&a);
+        cache.loaded(&b);
+        assert_eq!(cache.to_evict(2, &b), None);
+        cache.used(&a);
+        cache.loaded(&c);
+        assert_eq!(cache.to_evict(3, &c), Some(&b));
+        assert!(!cache.is_dirty(&b));
+        cache.evicted(&b);
+        // the train in use is never evicted
+        assert_eq!(cache.to_evict(3, &a), Some(&c));
+        // trains that weren't loaded from the store need saving
+        let d = TrainId::new("d");
+        cache.used(&d);
+        assert!(cache.is_dirty(&d));
+    }
+}
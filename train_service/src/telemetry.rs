@@ -0,0 +1,383 @@
+//! The service's tracing subscriber: events are logged to standard error,
+//! and spans can be exported to an OpenTelemetry collector with
+//! `tracing-opentelemetry`, using OTLP over HTTP.
+//!
+//! This is configured with the standard OpenTelemetry environment variables:
+//! `OTEL_EXPORTER_OTLP_ENDPOINT` (or `OTEL_EXPORTER_OTLP_TRACES_ENDPOINT`) to
+//! say where to send spans, `OTEL_SERVICE_NAME` to name the service, and
+//! `OTEL_TRACES_EXPORTER=none` to switch export off. The exporter and the
+//! batch span processor pick up the other `OTEL_EXPORTER_OTLP_*` and
+//! `OTEL_BSP_*` variables, for headers, timeouts and queue sizes.
+//!
+//! Requests with a W3C trace context, in `traceparent` and `tracestate`
+//! headers, are traced as part of the trace of their caller, see
+//! [`continue_trace`], and [`trace_headers`] gives the headers to pass the
+//! trace on to services that are called in turn.
+
+use std::collections::HashMap;
+use std::fmt::Debug;
+
+use axum::http::HeaderMap;
+use opentelemetry::propagation::{Extractor, TextMapPropagator};
+use opentelemetry::trace::{TraceContextExt, TracerProvider as _};
+use opentelemetry::KeyValue;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::TracerProvider;
+use opentelemetry_sdk::{runtime, Resource};
+use tracing::field::{Field, Visit};
+use tracing::{Event, Subscriber};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::layer::{Context, SubscriberExt};
+use tracing_subscriber::Layer;
+
+use crate::middleware::logfmt_value;
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct TelemetryConfig {
+    pub service_name: String,
+}
+
+impl TelemetryConfig {
+    /// Whether to export spans, and under which service name. The exporter
+    /// reads where to send them from the environment itself.
+    pub fn from_env() -> Option<Self> {
+        Self::from_vars(|name| std::env::var(name).ok())
+    }
+
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> Option<Self> {
+        if var("OTEL_TRACES_EXPORTER").as_deref() == Some("none") {
+            return None;
+        }
+        // without an endpoint, the exporter would send spans to a collector
+        // on localhost that's most likely not there
+        var("OTEL_EXPORTER_OTLP_TRACES_ENDPOINT").or_else(|| var("OTEL_EXPORTER_OTLP_ENDPOINT"))?;
+        Some(TelemetryConfig {
+            service_name: var("OTEL_SERVICE_NAME").unwrap_or_else(|| "train_service".to_string()),
+        })
+    }
+}
+
+/// Install the global subscriber, logging events to standard error. With a
+/// `config`, spans are also sent to the collector in the background, so this
+/// has to run inside the Tokio runtime.
+pub fn init(config: Option<TelemetryConfig>) -> Result<(), String> {
+    let tracer_provider = config.map(tracer_provider).transpose()?;
+    let export = tracer_provider.as_ref().map(|provider| {
+        tracing_opentelemetry::layer().with_tracer(provider.tracer("train_service"))
+    });
+    let subscriber = tracing_subscriber::registry()
+        .with(LevelFilter::INFO)
+        .with(LogLayer::new(|line| eprintln!("{}", line)))
+        .with(export);
+    tracing::subscriber::set_global_default(subscriber)
+        .expect("a global tracing subscriber was already installed");
+    if let Some(provider) = tracer_provider {
+        opentelemetry::global::set_tracer_provider(provider);
+    }
+    Ok(())
+}
+
+fn tracer_provider(config: TelemetryConfig) -> Result<TracerProvider, String> {
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_http()
+        .build()
+        .map_err(|err| format!("cannot export spans: {}", err))?;
+    let resource = Resource::default().merge(&Resource::new([KeyValue::new(
+        "service.name",
+        config.service_name,
+    )]));
+    Ok(TracerProvider::builder()
+        .with_batch_exporter(exporter, runtime::Tokio)
+        .with_resource(resource)
+        .build())
+}
+
+/// Make `span` part of the trace of the caller, when the request has a
+/// `traceparent` header; its `tracestate` is kept with the trace. This has
+/// to happen before the span is entered.
+pub fn continue_trace(span: &tracing::Span, headers: &HeaderMap) {
+    let context = TraceContextPropagator::new().extract(&HeaderExtractor(headers));
+    if context.span().span_context().is_remote() {
+        span.set_parent(context);
+    }
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key)?.to_str().ok()
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|name| name.as_str()).collect()
+    }
+}
+
+/// The headers to pass the trace of the current span on to another service:
+/// `traceparent`, and `tracestate` if the trace has one. There are none when
+/// spans aren't exported.
+pub fn trace_headers() -> Vec<(&'static str, String)> {
+    let mut headers = HashMap::new();
+    TraceContextPropagator::new().inject_context(&tracing::Span::current().context(), &mut headers);
+    ["traceparent", "tracestate"]
+        .into_iter()
+        .filter_map(|name| Some((name, headers.remove(name)?)))
+        .filter(|(_, value)| !value.is_empty())
+        .collect()
+}
+
+/// Writes a line for each event.
+pub struct LogLayer<F>(F);
+
+impl<F: Fn(String) + Send + Sync + 'static> LogLayer<F> {
+    pub fn new(write: F) -> Self {
+        LogLayer(write)
+    }
+}
+
+impl<S: Subscriber, F: Fn(String) + Send + Sync + 'static> Layer<S> for LogLayer<F> {
+    fn on_event(&self, event: &Event<'_>, _context: Context<'_, S>) {
+        (self.0)(log_line(event));
+    }
+}
+
+// an event as a line like `WARN train_service::lock: message key=value`
+fn log_line(event: &Event<'_>) -> String {
+    let mut fields = Vec::new();
+    event.record(&mut FieldVisitor(&mut fields));
+    let metadata = event.metadata();
+    let mut line = format!("{:>5} {}:", metadata.level(), metadata.target());
+    for (key, value) in fields {
+        if key == "message" {
+            line.push_str(&format!(" {}", value));
+        } else {
+            line.push_str(&format!(" {}={}", key, logfmt_value(&value)));
+        }
+    }
+    line
+}
+
+struct FieldVisitor<'a>(&'a mut Vec<(&'static str, String)>);
+
+impl Visit for FieldVisitor<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.push((field.name(), value.to_string()));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        self.0.push((field.name(), format!("{:?}", value)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use futures_util::future::BoxFuture;
+    use opentelemetry::trace::{SpanId, TraceId};
+    use opentelemetry_sdk::export::trace::{ExportResult, SpanData, SpanExporter};
+
+    use super::*;
+
+    // keeps exported spans to look at
+    #[derive(Debug, Clone, Default)]
+    struct Exported(Arc<Mutex<Vec<SpanData>>>);
+
+    impl SpanExporter for Exported {
+        fn export(&mut self, batch: Vec<SpanData>) -> BoxFuture<'static, ExportResult> {
+            self.0.lock().unwrap().extend(batch);
+            Box::pin(async { Ok(()) })
+        }
+    }
+
+    fn exporting() -> (impl Subscriber + Send + Sync, Exported) {
+        let exported = Exported::default();
+        let provider = TracerProvider::builder()
+            .with_simple_exporter(exported.clone())
+            .build();
+        let subscriber = tracing_subscriber::registry()
+            .with(LevelFilter::INFO)
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+        (subscriber, exported)
+    }
+
+    fn exported(exported: &Exported, name: &str) -> SpanData {
+        exported
+            .0
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|span| span.name == name)
+            .unwrap_or_else(|| panic!("no span {}", name))
+            .clone()
+    }
+
+    fn attribute(span: &SpanData, key: &str) -> Option<String> {
+        span.attributes
+            .iter()
+            .find(|attribute| attribute.key.as_str() == key)
+            .map(|attribute| attribute.value.to_string())
+    }
+
+    #[test]
+    fn test_logs_events() {
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let logged = Arc::clone(&lines);
+        let subscriber = tracing_subscriber::registry()
+            .with(LevelFilter::INFO)
+            .with(LogLayer::new(move |line| logged.lock().unwrap().push(line)));
+        tracing::subscriber::with_default(subscriber, || {
+            let _span = tracing::info_span!("reserve").entered();
+            tracing::error!(
+                incident = "4f2a",
+                reason = "out of seats",
+                "handler panicked"
+            );
+            tracing::warn!(uses = 3, "deprecated route used");
+            tracing::debug!("not logged");
+        });
+        assert_eq!(
+            *lines.lock().unwrap(),
+            [
+                "ERROR train_service::telemetry::tests: handler panicked incident=4f2a reason=\"out of seats\"",
+                " WARN train_service::telemetry::tests: deprecated route used uses=3",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_config_from_vars() {
+        let vars = HashMap::from([("OTEL_EXPORTER_OTLP_ENDPOINT", "http://collector:4318")]);
+        let config = TelemetryConfig::from_vars(|name| vars.get(name).map(|v| v.to_string()));
+        assert_eq!(
+            config,
+            Some(TelemetryConfig {
+                service_name: "train_service".to_string(),
+            })
+        );
+
+        let vars = HashMap::from([
+            (
+                "OTEL_EXPORTER_OTLP_TRACES_ENDPOINT",
+                "https://localhost/traces",
+            ),
+            ("OTEL_SERVICE_NAME", "trains"),
+        ]);
+        let config = TelemetryConfig::from_vars(|name| vars.get(name).map(|v| v.to_string()));
+        assert_eq!(config.unwrap().service_name, "trains");
+
+        let vars = HashMap::from([
+            ("OTEL_EXPORTER_OTLP_ENDPOINT", "http://collector:4318"),
+            ("OTEL_TRACES_EXPORTER", "none"),
+        ]);
+        let config = TelemetryConfig::from_vars(|name| vars.get(name).map(|v| v.to_string()));
+        assert_eq!(config, None);
+
+        assert_eq!(TelemetryConfig::from_vars(|_| None), None);
+    }
+
+    #[test]
+    fn test_exports_nested_spans() {
+        let (subscriber, spans) = exporting();
+        tracing::subscriber::with_default(subscriber, || {
+            let _outer = tracing::info_span!("outer", train_id = "local_1000").entered();
+            let _inner = tracing::info_span!("inner", seats = 2).entered();
+            tracing::info!(reserved = true, "reserved seats");
+        });
+        let outer = exported(&spans, "outer");
+        let inner = exported(&spans, "inner");
+        assert_eq!(inner.span_context.trace_id(), outer.span_context.trace_id());
+        assert_eq!(inner.parent_span_id, outer.span_context.span_id());
+        assert_eq!(outer.parent_span_id, SpanId::INVALID);
+        assert_eq!(attribute(&outer, "train_id").as_deref(), Some("local_1000"));
+        assert_eq!(inner.events.len(), 1);
+    }
+
+    // which span is current goes along with the task, not the thread
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_exports_spans_across_await() {
+        use tracing::instrument::{Instrument, WithSubscriber};
+
+        let (subscriber, spans) = exporting();
+        let _default = tracing::subscriber::set_default(subscriber);
+        let request = tracing::info_span!("request");
+        tokio::spawn(
+            async {
+                for _ in 0..10 {
+                    tokio::task::yield_now().await;
+                }
+                tracing::info_span!("reserve").in_scope(|| {});
+            }
+            .instrument(request.clone())
+            .with_current_subscriber(),
+        )
+        .await
+        .unwrap();
+        drop(request);
+        let request = exported(&spans, "request");
+        let reserve = exported(&spans, "reserve");
+        assert_eq!(reserve.parent_span_id, request.span_context.span_id());
+    }
+
+    #[test]
+    fn test_continues_remote_trace() {
+        let traceparent = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        let mut headers = HeaderMap::new();
+        headers.insert("traceparent", traceparent.parse().unwrap());
+        headers.insert("tracestate", "vendor=abc".parse().unwrap());
+        let mut outbound = Vec::new();
+        let (subscriber, spans) = exporting();
+        tracing::subscriber::with_default(subscriber, || {
+            let request = tracing::info_span!("request");
+            continue_trace(&request, &headers);
+            let _request = request.enter();
+            let _reserve = tracing::info_span!("reserve").entered();
+            outbound = trace_headers();
+        });
+        let request = exported(&spans, "request");
+        let reserve = exported(&spans, "reserve");
+        let trace_id = TraceId::from_hex("4bf92f3577b34da6a3ce929d0e0e4736").unwrap();
+        assert_eq!(request.span_context.trace_id(), trace_id);
+        assert_eq!(
+            request.parent_span_id,
+            SpanId::from_hex("00f067aa0ba902b7").unwrap()
+        );
+        assert_eq!(request.span_context.trace_state().header(), "vendor=abc");
+        assert_eq!(reserve.span_context.trace_id(), trace_id);
+        assert_eq!(reserve.parent_span_id, request.span_context.span_id());
+        assert_eq!(
+            outbound,
+            vec![
+                (
+                    "traceparent",
+                    format!("00-{}-{}-01", trace_id, reserve.span_context.span_id())
+                ),
+                ("tracestate", "vendor=abc".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_invalid_remote_trace() {
+        let mut headers = HeaderMap::new();
+        headers.insert("traceparent", "nonsense".parse().unwrap());
+        let (subscriber, spans) = exporting();
+        tracing::subscriber::with_default(subscriber, || {
+            let request = tracing::info_span!("request");
+            continue_trace(&request, &headers);
+            let _request = request.enter();
+        });
+        assert_eq!(exported(&spans, "request").parent_span_id, SpanId::INVALID);
+    }
+
+    #[test]
+    fn test_no_trace_headers_without_export() {
+        let subscriber = tracing_subscriber::registry();
+        tracing::subscriber::with_default(subscriber, || {
+            let _request = tracing::info_span!("request").entered();
+            assert_eq!(trace_headers(), vec![]);
+        });
+    }
+}
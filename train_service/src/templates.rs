@@ -0,0 +1,45 @@
+use std::collections::HashMap;
+
+use crate::spec::TrainSpec;
+use crate::train::Error;
+
+/// Named train specifications that new trains can be created from.
+#[derive(Debug, Default, PartialEq, Eq, Clone, serde::Deserialize)]
+pub struct Templates(HashMap<String, TrainSpec>);
+
+impl Templates {
+    pub fn bundled() -> Self {
+        let templates_str = include_str!("templates.json");
+        serde_json::from_str(templates_str).unwrap()
+    }
+
+    pub fn get(&self, name: &str) -> Result<&TrainSpec, Error> {
+        self.0
+            .get(name)
+            .ok_or_else(|| Error::TemplateDoesNotExist(name.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bundled_templates_build() {
+        let templates = Templates::bundled();
+        for spec in templates.0.values() {
+            spec.build().unwrap();
+        }
+        let train = templates.get("intercity_8_coach").unwrap().build().unwrap();
+        assert_eq!(train.occupancy().coaches().count(), 8);
+    }
+
+    #[test]
+    fn test_template_does_not_exist() {
+        let templates = Templates::default();
+        assert_eq!(
+            templates.get("unknown"),
+            Err(Error::TemplateDoesNotExist("unknown".to_string()))
+        );
+    }
+}
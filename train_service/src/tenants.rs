@@ -0,0 +1,166 @@
+//! Limits on what tenants may do, and how much they've done. A tenant is a
+//! separate set of trains served under `/tenants/<name>`.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use crate::api_keys::Allowance;
+use crate::lock::LockExt;
+use crate::train::Error;
+
+#[derive(Debug, Default, PartialEq, Eq, Clone, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TenantLimits {
+    /// The most requests the tenant may make; unlimited if not given.
+    #[serde(default)]
+    pub request_quota: Option<u64>,
+    /// The most seats the tenant may reserve; unlimited if not given.
+    #[serde(default)]
+    pub seat_quota: Option<usize>,
+}
+
+/// How much a tenant has used, and its limits.
+#[derive(Debug, Default, PartialEq, Eq, Clone, serde::Serialize, serde::Deserialize)]
+pub struct TenantStats {
+    pub requests: u64,
+    pub seats: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_quota: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seat_quota: Option<usize>,
+}
+
+/// The usage of all tenants, shared between the tenants and the middleware
+/// that counts their requests.
+#[derive(Debug, Default)]
+pub struct TenantUsage(Mutex<BTreeMap<String, TenantStats>>);
+
+impl TenantUsage {
+    pub fn new(limits: impl IntoIterator<Item = (String, TenantLimits)>) -> Self {
+        let stats = limits
+            .into_iter()
+            .map(|(name, limits)| {
+                let stats = TenantStats {
+                    request_quota: limits.request_quota,
+                    seat_quota: limits.seat_quota,
+                    ..TenantStats::default()
+                };
+                (name, stats)
+            })
+            .collect();
+        TenantUsage(Mutex::new(stats))
+    }
+
+    pub fn contains(&self, tenant: &str) -> bool {
+        self.0.lock_or_recover().contains_key(tenant)
+    }
+
+    /// Count a request of a tenant, unless it's out of requests.
+    pub fn record_request(&self, tenant: &str) -> Result<(), Allowance> {
+        let mut stats = self.0.lock_or_recover();
+        let Some(stats) = stats.get_mut(tenant) else {
+            return Ok(());
+        };
+        if let Some(limit) = stats.request_quota {
+            if stats.requests >= limit {
+                return Err(Allowance {
+                    limit: limit as usize,
+                    remaining: 0,
+                });
+            }
+        }
+        stats.requests += 1;
+        Ok(())
+    }
+
+    pub fn stats(&self) -> BTreeMap<String, TenantStats> {
+        self.0.lock_or_recover().clone()
+    }
+}
+
+/// The tenant a set of trains belongs to.
+#[derive(Debug, Clone)]
+pub struct Tenant {
+    name: String,
+    usage: Arc<TenantUsage>,
+}
+
+impl Tenant {
+    pub fn new(name: String, usage: Arc<TenantUsage>) -> Self {
+        Tenant { name, usage }
+    }
+
+    /// Check whether the tenant may reserve this many more seats.
+    pub fn check_seats(&self, seats: usize) -> Result<(), Error> {
+        let stats = self.usage.0.lock_or_recover();
+        let Some(stats) = stats.get(&self.name) else {
+            return Ok(());
+        };
+        if let Some(limit) = stats.seat_quota {
+            let remaining = limit.saturating_sub(stats.seats);
+            if seats > remaining {
+                return Err(Error::QuotaExceeded(Allowance { limit, remaining }));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn record_seats(&self, seats: usize) {
+        if let Some(stats) = self.usage.0.lock_or_recover().get_mut(&self.name) {
+            stats.seats += seats;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usage() -> Arc<TenantUsage> {
+        Arc::new(TenantUsage::new([
+            (
+                "red".to_string(),
+                TenantLimits {
+                    request_quota: Some(2),
+                    seat_quota: Some(3),
+                },
+            ),
+            ("blue".to_string(), TenantLimits::default()),
+        ]))
+    }
+
+    #[test]
+    fn test_request_quota() {
+        let usage = usage();
+        assert_eq!(usage.record_request("red"), Ok(()));
+        assert_eq!(usage.record_request("red"), Ok(()));
+        assert_eq!(
+            usage.record_request("red"),
+            Err(Allowance {
+                limit: 2,
+                remaining: 0
+            })
+        );
+        assert_eq!(usage.record_request("blue"), Ok(()));
+        assert_eq!(usage.stats()["red"].requests, 2);
+        assert_eq!(usage.stats()["blue"].requests, 1);
+    }
+
+    #[test]
+    fn test_seat_quota() {
+        let usage = usage();
+        let red = Tenant::new("red".to_string(), usage.clone());
+        assert_eq!(red.check_seats(3), Ok(()));
+        red.record_seats(2);
+        assert_eq!(
+            red.check_seats(2),
+            Err(Error::QuotaExceeded(Allowance {
+                limit: 3,
+                remaining: 1
+            }))
+        );
+        let blue = Tenant::new("blue".to_string(), usage.clone());
+        assert_eq!(blue.check_seats(100), Ok(()));
+        assert_eq!(usage.stats()["red"].seats, 2);
+    }
+}
@@ -0,0 +1,346 @@
+//! The other side of the kata: the ticket office participants are asked to
+//! write, which reserves seats through the train service's HTTP API. This is
+//! the reference solution, for facilitators to demo and to compare solutions
+//! with; the `ticket_office` binary runs it.
+//!
+//! Seats are picked by the business rules in the kata's README: no more than
+//! 70% of the train may be reserved, all seats of a booking are in the same
+//! coach, and a coach that stays at or under 70% is preferred.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use axum::extract::{Json, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::post;
+use axum::Router;
+
+use crate::http_client::{ClientError, HttpClient};
+
+// how often to start over when another booking takes the seats first
+const ATTEMPTS: usize = 3;
+
+/// A train as the train service sends it, with only what's needed here.
+#[derive(Debug, serde::Deserialize)]
+pub struct TrainSeats {
+    pub seats: BTreeMap<String, Seat>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct Seat {
+    pub coach: String,
+    pub seat_number: String,
+    pub booking_reference: Option<String>,
+    pub held_until: Option<u64>,
+    pub blocked: Option<String>,
+}
+
+impl Seat {
+    fn is_free(&self) -> bool {
+        self.booking_reference.as_deref().unwrap_or("").is_empty()
+            && self.held_until.is_none()
+            && self.blocked.is_none()
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct BookingRequest {
+    pub train_id: String,
+    pub seat_count: usize,
+}
+
+/// What the ticket office answers. Without seats to reserve, there's no
+/// booking reference and the seats are empty.
+#[derive(Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Booking {
+    pub train_id: String,
+    pub booking_reference: Option<String>,
+    pub seats: Vec<String>,
+}
+
+// whether `reserved` out of `total` is at most 70%
+fn within_limit(reserved: usize, total: usize) -> bool {
+    reserved * 10 <= total * 7
+}
+
+/// The seats to reserve for `seat_count` passengers, if the rules allow it.
+pub fn choose_seats(train: &TrainSeats, seat_count: usize) -> Option<Vec<String>> {
+    if seat_count == 0 {
+        return None;
+    }
+    let reserved = train.seats.values().filter(|seat| !seat.is_free()).count();
+    if !within_limit(reserved + seat_count, train.seats.len()) {
+        return None;
+    }
+    let mut coaches: BTreeMap<&str, Vec<(&String, &Seat)>> = BTreeMap::new();
+    for (seat_id, seat) in &train.seats {
+        coaches
+            .entry(&seat.coach)
+            .or_default()
+            .push((seat_id, seat));
+    }
+    let candidates = coaches.values().filter_map(|seats| {
+        let mut free: Vec<_> = seats.iter().filter(|(_, seat)| seat.is_free()).collect();
+        if free.len() < seat_count {
+            return None;
+        }
+        // by seat number, so 2A comes before 10A
+        free.sort_by_key(|(_, seat)| {
+            (
+                seat.seat_number.parse::<u32>().ok(),
+                seat.seat_number.clone(),
+            )
+        });
+        let reserved = seats.len() - free.len();
+        let seat_ids = free
+            .iter()
+            .take(seat_count)
+            .map(|(seat_id, _)| seat_id.to_string())
+            .collect::<Vec<_>>();
+        Some((within_limit(reserved + seat_count, seats.len()), seat_ids))
+    });
+    let mut fallback = None;
+    for (within_limit, seat_ids) in candidates {
+        if within_limit {
+            return Some(seat_ids);
+        }
+        fallback.get_or_insert(seat_ids);
+    }
+    fallback
+}
+
+/// Books seats through the train service at `url`.
+#[derive(Debug, Clone)]
+pub struct TicketOffice {
+    client: HttpClient,
+    url: String,
+    // bookings are made one at a time, so they don't take each other's seats
+    booking: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl TicketOffice {
+    pub fn new(client: HttpClient, url: impl Into<String>) -> Self {
+        TicketOffice {
+            client,
+            url: url.into().trim_end_matches('/').to_string(),
+            booking: Arc::default(),
+        }
+    }
+
+    /// Reserve seats on a train. A booking reference is only asked for once
+    /// there are seats to reserve with it; when another booking takes them
+    /// first, this starts over with the same reference.
+    pub async fn book(&self, train_id: &str, seat_count: usize) -> Result<Booking, ClientError> {
+        let _booking = self.booking.lock().await;
+        let mut booking_reference: Option<String> = None;
+        for attempt in 1..=ATTEMPTS {
+            let train: TrainSeats = self
+                .client
+                .get_json(&format!("{}/train/{}", self.url, train_id))
+                .await?;
+            let Some(seats) = choose_seats(&train, seat_count) else {
+                break;
+            };
+            let reference = match &booking_reference {
+                Some(reference) => reference,
+                None => booking_reference.insert(
+                    self.client
+                        .post_json(&format!("{}/booking_reference", self.url), &())
+                        .await?,
+                ),
+            };
+            let result = self
+                .client
+                .post_json::<_, serde_json::Value>(
+                    &format!("{}/train/{}/reserve", self.url, train_id),
+                    &serde_json::json!({ "seats": seats, "booking_reference": reference }),
+                )
+                .await;
+            match result {
+                Ok(_) => {
+                    return Ok(Booking {
+                        train_id: train_id.to_string(),
+                        booking_reference: Some(reference.clone()),
+                        seats,
+                    })
+                }
+                Err(ClientError::Status(StatusCode::CONFLICT, _)) if attempt < ATTEMPTS => {
+                    tracing::info!(train_id, attempt, "seats taken meanwhile, trying again");
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(Booking {
+            train_id: train_id.to_string(),
+            booking_reference: None,
+            seats: Vec::new(),
+        })
+    }
+
+    // the same office, calling the train service with this API key
+    fn with_api_key(&self, api_key: &str) -> TicketOffice {
+        TicketOffice {
+            client: self.client.clone().with_api_key(api_key),
+            ..self.clone()
+        }
+    }
+
+    /// The ticket office as a service, with the kata's `POST /reserve`. The
+    /// `X-API-Key` header of a request is passed on to the train service, so
+    /// a booking counts against the quota of whoever asked for it.
+    pub fn routes(self) -> Router {
+        Router::new()
+            .route("/reserve", post(reserve))
+            .with_state(Arc::new(self))
+    }
+}
+
+async fn reserve(
+    State(office): State<Arc<TicketOffice>>,
+    headers: HeaderMap,
+    Json(request): Json<BookingRequest>,
+) -> Response {
+    let api_key = headers
+        .get("x-api-key")
+        .and_then(|value| value.to_str().ok());
+    let office = match api_key {
+        Some(api_key) => Arc::new(office.with_api_key(api_key)),
+        None => office,
+    };
+    match office.book(&request.train_id, request.seat_count).await {
+        Ok(booking) => Json(booking).into_response(),
+        // like an unknown train, passing on the error of the train service
+        Err(ClientError::Status(status, body)) if status.is_client_error() => {
+            match serde_json::from_str::<serde_json::Value>(&body) {
+                Ok(error) => (status, Json(error)).into_response(),
+                Err(_) => (status, body).into_response(),
+            }
+        }
+        Err(err) => (
+            StatusCode::BAD_GATEWAY,
+            format!("The train service failed: {}", err),
+        )
+            .into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::rest::{self, AppState};
+
+    use super::*;
+
+    fn train(seats: &[(&str, bool)]) -> TrainSeats {
+        let seats = seats
+            .iter()
+            .map(|(seat_id, reserved)| {
+                let (number, coach) = seat_id.split_at(seat_id.len() - 1);
+                let seat = Seat {
+                    coach: coach.to_string(),
+                    seat_number: number.to_string(),
+                    booking_reference: reserved.then(|| "75bcd15".to_string()),
+                    held_until: None,
+                    blocked: None,
+                };
+                (seat_id.to_string(), seat)
+            })
+            .collect();
+        TrainSeats { seats }
+    }
+
+    #[test]
+    fn test_choose_seats() {
+        let empty = train(&[
+            ("1A", false),
+            ("2A", false),
+            ("10A", false),
+            ("1B", false),
+            ("2B", false),
+            ("10B", false),
+        ]);
+        assert_eq!(
+            choose_seats(&empty, 2),
+            Some(vec!["1A".to_string(), "2A".to_string()])
+        );
+        // over 70% of the train
+        assert_eq!(choose_seats(&empty, 5), None);
+        assert_eq!(choose_seats(&empty, 0), None);
+
+        // coach A would go over 70%, B doesn't
+        let some = train(&[
+            ("1A", true),
+            ("2A", false),
+            ("10A", false),
+            ("1B", false),
+            ("2B", false),
+            ("10B", false),
+        ]);
+        assert_eq!(
+            choose_seats(&some, 2),
+            Some(vec!["1B".to_string(), "2B".to_string()])
+        );
+        // but a coach can when there's no other way
+        assert_eq!(
+            choose_seats(&some, 3),
+            Some(vec!["1B".to_string(), "2B".to_string(), "10B".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_seat_from_service() {
+        let train: TrainSeats = serde_json::from_value(serde_json::json!({
+            "seats": {
+                "1A": { "seat_number": "1", "coach": "A", "booking_reference": "" },
+                "2A": { "seat_number": "2", "coach": "A", "booking_reference": null },
+                "3A": { "seat_number": "3", "coach": "A", "booking_reference": null,
+                        "blocked": "broken" },
+            }
+        }))
+        .unwrap();
+        assert!(train.seats["1A"].is_free());
+        assert!(train.seats["2A"].is_free());
+        assert!(!train.seats["3A"].is_free());
+    }
+
+    #[tokio::test]
+    async fn test_book() {
+        let listener = tokio::net::TcpListener::bind(("127.0.0.1", 0))
+            .await
+            .unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, rest::app(AppState::new())).await });
+        let office = TicketOffice::new(
+            HttpClient::new(Duration::from_secs(5)),
+            format!("http://{}/", address),
+        );
+
+        let booking = office.book("local_1000", 2).await.unwrap();
+        assert_eq!(booking.seats, ["1A", "2A"]);
+        assert!(booking.booking_reference.is_some());
+        // coach A would go over 70% with another two
+        let booking = office.book("local_1000", 2).await.unwrap();
+        assert_eq!(booking.seats, ["1B", "2B"]);
+        // every coach would, but it has to go somewhere
+        let booking = office.book("local_1000", 4).await.unwrap();
+        assert_eq!(booking.seats, ["3B", "4B", "5B", "6B"]);
+        // the train would go over 70%
+        let booking = office.book("local_1000", 4).await.unwrap();
+        assert_eq!(
+            booking,
+            Booking {
+                train_id: "local_1000".to_string(),
+                booking_reference: None,
+                seats: Vec::new(),
+            }
+        );
+
+        let result = office.book("does_not_exist", 1).await;
+        assert!(matches!(
+            result,
+            Err(ClientError::Status(StatusCode::NOT_FOUND, _))
+        ));
+    }
+}
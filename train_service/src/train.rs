@@ -1,18 +1,39 @@
 use std::{
-    collections::HashMap,
+    borrow::Cow,
+    collections::{HashMap, HashSet},
     fmt::{self, Display, Formatter},
+    sync::Arc,
 };
 
+use crate::adjacency::Adjacency;
+use crate::api_keys::Allowance;
 use crate::booking_reference::BookingReference;
+use crate::clock::{Clock, SystemClock};
+use crate::fares::{FareBucket, Fares, SeatFare};
+use crate::flags::Flag;
+use crate::layout::Layout;
+use crate::occupancy::Occupancy;
+use crate::published::PublishedTrains;
+use crate::renumber::SeatMapping;
+use crate::reservations::ReservationId;
+use crate::resource::{Reservable, Resource, ResourceKind};
+use crate::rules::Rules;
+use crate::store::{TrainCache, TrainStore};
+
+// free seats suggested when the seats asked for are taken
+const MAX_ALTERNATIVES: usize = 10;
 
 #[derive(Debug, PartialEq, Eq, Clone, Hash, serde::Serialize, serde::Deserialize)]
-pub struct TrainId(String);
+pub struct TrainId(Arc<str>);
 
 impl TrainId {
-    #[cfg(test)]
-    pub fn new<S: Into<String>>(id: S) -> Self {
+    pub fn new<S: Into<Arc<str>>>(id: S) -> Self {
         Self(id.into())
     }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
 }
 
 impl Display for TrainId {
@@ -21,13 +42,58 @@ impl Display for TrainId {
     }
 }
 
+/// A seat id is a seat number followed by a coach, like `12A`.
 #[derive(Debug, PartialEq, Eq, Clone, Hash, serde::Serialize, serde::Deserialize)]
-pub struct SeatId(String);
+#[serde(try_from = "String")]
+pub struct SeatId(Arc<str>);
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct InvalidSeatId(String);
+
+impl Display for InvalidSeatId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid seat id `{}`, expected a seat number followed by a coach, like `1A`",
+            self.0
+        )
+    }
+}
 
 impl SeatId {
     #[cfg(test)]
-    pub fn new<S: Into<String>>(id: S) -> Self {
-        Self(id.into())
+    pub fn new(id: &str) -> Self {
+        Self::parse(id).unwrap()
+    }
+
+    pub fn parse(id: &str) -> Result<Self, InvalidSeatId> {
+        let coach_start = id
+            .find(|c: char| !c.is_ascii_digit())
+            .ok_or_else(|| InvalidSeatId(id.to_string()))?;
+        let (seat_number, coach) = id.split_at(coach_start);
+        Self::from_parts(seat_number, coach).map_err(|_| InvalidSeatId(id.to_string()))
+    }
+
+    pub fn from_parts(seat_number: &str, coach: &str) -> Result<Self, InvalidSeatId> {
+        let valid_seat_number =
+            !seat_number.is_empty() && seat_number.chars().all(|c| c.is_ascii_digit());
+        let valid_coach = coach.starts_with(|c: char| c.is_ascii_alphabetic())
+            && coach.chars().all(|c| c.is_ascii_alphanumeric());
+        if !valid_seat_number || !valid_coach {
+            return Err(InvalidSeatId(format!("{}{}", seat_number, coach)));
+        }
+        Ok(Self(format!("{}{}", seat_number, coach).into()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// The seat number and the coach.
+    pub fn split(&self) -> (&str, &str) {
+        // a valid seat id always has a coach after the seat number
+        let coach_start = self.0.find(|c: char| !c.is_ascii_digit()).unwrap();
+        self.0.split_at(coach_start)
     }
 }
 
@@ -37,12 +103,38 @@ impl Display for SeatId {
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+// the same rule as `from_parts`, so clients can check seat ids themselves
+impl schemars::JsonSchema for SeatId {
+    fn schema_name() -> Cow<'static, str> {
+        "SeatId".into()
+    }
+
+    fn json_schema(_generator: &mut schemars::SchemaGenerator) -> schemars::Schema {
+        schemars::json_schema!({ "type": "string", "pattern": "^[0-9]+[A-Za-z][A-Za-z0-9]*$" })
+    }
+}
+
+impl TryFrom<String> for SeatId {
+    type Error = InvalidSeatId;
+
+    fn try_from(id: String) -> Result<Self, Self::Error> {
+        Self::parse(&id)
+    }
+}
+
 pub struct TrainDataService {
     trains: TrainsData,
+    // trains that aren't loaded yet are fetched from here when they're used
+    store: Option<Box<dyn TrainStore>>,
+    // bounds how many trains from the store stay loaded
+    cache: Option<TrainCache>,
+    // copies of the trains for readers, withdrawn when a train may change
+    published: Arc<PublishedTrains>,
+    // when holds expire
+    clock: Arc<dyn Clock>,
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, PartialEq, Eq, Clone, serde::Deserialize)]
 pub struct TrainsData(HashMap<TrainId, Train>);
 
 impl TrainsData {
@@ -51,202 +143,2826 @@ impl TrainsData {
         TrainsData(HashMap::new())
     }
 
+    pub fn from_trains(trains: HashMap<TrainId, Train>) -> Self {
+        TrainsData(trains)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&TrainId, &Train)> {
+        self.0.iter()
+    }
+
     #[cfg(test)]
     pub fn get(&self, train_id: &TrainId) -> Option<&Train> {
         self.0.get(train_id)
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, PartialEq, Eq, Clone, serde::Deserialize)]
+#[serde(try_from = "TrainData")]
 pub struct Train {
     seats: HashMap<SeatId, Seat>,
+    // which seats are next to each other, derived from the seats
+    adjacency: Adjacency,
+    // coaches that have something special about them; others are plain
+    coaches: HashMap<String, Coach>,
+    standing: Option<Capacity>,
+    // bicycle places, luggage racks and the like, by id
+    resources: HashMap<String, Resource>,
+    fares: Fares,
+    // when the train leaves, in seconds since the Unix epoch
+    departure: Option<u64>,
+    // pulled out of service for now; it can't be changed until it's restored
+    archived: bool,
+    occupancy: Occupancy,
+    // the seats held by each booking reference
+    booked: HashMap<BookingReference, HashSet<SeatId>>,
+    // the places without a seat number held by each booking reference
+    booked_places: HashMap<BookingReference, BookedPlaces>,
+    // what each booking paid for its seats, in cents
+    paid: HashMap<BookingReference, u64>,
 }
 
-impl Train {
-    #[cfg(test)]
-    pub fn get(&self, seat_id: &SeatId) -> Option<&Seat> {
-        self.seats.get(seat_id)
-    }
+/// The places without a seat number of a booking.
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct BookedPlaces {
+    /// By free-seating coach.
+    pub coaches: HashMap<String, usize>,
+    pub standing: usize,
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, serde::Serialize, serde::Deserialize)]
-pub struct Seat {
-    seat_number: String,
-    coach: String,
-    booking_reference: Option<BookingReference>,
+impl BookedPlaces {
+    fn total(&self) -> usize {
+        self.coaches.values().sum::<usize>() + self.standing
+    }
 }
 
-impl Seat {
-    #[cfg(test)]
-    pub fn booking_reference(&self) -> Option<&BookingReference> {
-        self.booking_reference.as_ref()
-    }
+// the serialized form of a train; the occupancy index is derived from it
+#[derive(serde::Deserialize)]
+struct TrainData {
+    seats: HashMap<SeatId, Seat>,
+    #[serde(default)]
+    coaches: HashMap<String, Coach>,
+    #[serde(default)]
+    standing: Option<Capacity>,
+    #[serde(default)]
+    resources: HashMap<String, Resource>,
+    #[serde(default)]
+    fares: Vec<FareBucket>,
+    #[serde(default)]
+    departure: Option<u64>,
+    #[serde(default)]
+    archived: bool,
+    #[serde(default)]
+    bookings: Vec<BookingData>,
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, serde::Serialize, serde::Deserialize)]
-pub struct Reservation {
-    pub seats: Vec<SeatId>,
-    pub booking_reference: BookingReference,
+// what a booking holds besides its seats and resources, which keep their
+// booking reference themselves, and what it paid
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BookingData {
+    booking_reference: BookingReference,
+    #[serde(default)]
+    places: HashMap<String, usize>,
+    #[serde(default)]
+    standing: usize,
+    #[serde(default)]
+    paid: u64,
 }
 
-#[derive(Debug, PartialEq, Eq)]
-pub enum Error {
-    TrainDoesNotExist(TrainId),
-    SeatsDoNotExist(Vec<SeatId>),
-    SeatsAlreadyReserved(Vec<SeatId>),
+impl TryFrom<TrainData> for Train {
+    type Error = String;
+
+    fn try_from(data: TrainData) -> Result<Self, Self::Error> {
+        let mut unknown = data
+            .seats
+            .iter()
+            .flat_map(|(seat_id, seat)| {
+                seat.neighbours
+                    .iter()
+                    .filter(|neighbour| !data.seats.contains_key(neighbour))
+                    .map(move |neighbour| format!("{} (next to {})", neighbour, seat_id))
+            })
+            .collect::<Vec<_>>();
+        if !unknown.is_empty() {
+            unknown.sort();
+            return Err(format!(
+                "neighbouring seats {} do not exist",
+                unknown.join(", ")
+            ));
+        }
+        for (id, coach) in &data.coaches {
+            let Some(layout) = &coach.layout else {
+                continue;
+            };
+            layout
+                .validate()
+                .map_err(|message| format!("coach {}: {}", id, message))?;
+            let mut outside = data
+                .seats
+                .iter()
+                .filter(|(_, seat)| seat.coach == *id && layout.place(&seat.seat_number).is_none())
+                .map(|(seat_id, _)| seat_id.as_str())
+                .collect::<Vec<_>>();
+            if !outside.is_empty() {
+                outside.sort();
+                return Err(format!(
+                    "seats {} don't fit in the layout of coach {}",
+                    outside.join(", "),
+                    id
+                ));
+            }
+        }
+        let capacities = data
+            .coaches
+            .iter()
+            .filter_map(|(id, coach)| Some((format!("coach {}", id), coach.free_seating?)))
+            .chain(
+                data.standing
+                    .map(|standing| ("standing room".to_string(), standing)),
+            );
+        for (name, capacity) in capacities {
+            if capacity.reserved > capacity.capacity {
+                return Err(format!(
+                    "{} has room for {} places, but {} are reserved",
+                    name, capacity.capacity, capacity.reserved
+                ));
+            }
+        }
+        let mut train = Train::new(data.seats)
+            .with_coaches(data.coaches)
+            .with_standing(data.standing)
+            .with_resources(data.resources)
+            .with_fares(Fares::new(data.fares))
+            .with_departure(data.departure)
+            .with_archived(data.archived);
+        for booking in data.bookings {
+            train.restore_booking(booking)?;
+        }
+        Ok(train)
+    }
 }
 
 impl Train {
-    pub fn reserve(&mut self, reservation: &Reservation) -> Result<(), Error> {
-        // first check whether we have any non-existent seats, report error if any of them are
-        let mut non_existent_seat_ids = Vec::new();
-        for seat_id in &reservation.seats {
-            if !self.seats.contains_key(seat_id) {
-                non_existent_seat_ids.push(seat_id.clone());
+    pub fn new(seats: HashMap<SeatId, Seat>) -> Self {
+        let mut booked: HashMap<BookingReference, HashSet<SeatId>> = HashMap::new();
+        for (seat_id, seat) in &seats {
+            if let SeatState::Reserved { booking_reference } = &seat.state {
+                booked
+                    .entry(booking_reference.clone())
+                    .or_default()
+                    .insert(seat_id.clone());
             }
         }
-        if !non_existent_seat_ids.is_empty() {
-            return Err(Error::SeatsDoNotExist(non_existent_seat_ids));
-        }
+        let mut train = Train {
+            adjacency: Adjacency::new(&seats, &HashMap::new()),
+            seats,
+            coaches: HashMap::new(),
+            standing: None,
+            resources: HashMap::new(),
+            fares: Fares::default(),
+            departure: None,
+            archived: false,
+            occupancy: Occupancy::default(),
+            booked,
+            booked_places: HashMap::new(),
+            paid: HashMap::new(),
+        };
+        train.count_occupancy();
+        train
+    }
 
-        // then report error if any seat is already reserved
-        let mut seats_already_reserved = Vec::new();
-        for seat_id in &reservation.seats {
-            let seat = self.seats.get(seat_id).unwrap();
-            if seat.booking_reference.is_some() {
-                seats_already_reserved.push(seat_id.clone());
+    pub fn with_coaches(self, coaches: HashMap<String, Coach>) -> Self {
+        let mut train = Train {
+            adjacency: Adjacency::new(&self.seats, &coaches),
+            coaches,
+            ..self
+        };
+        train.count_occupancy();
+        train
+    }
+
+    /// Give the train room for standing passengers.
+    pub fn with_standing(self, standing: Option<Capacity>) -> Self {
+        let mut train = Train { standing, ..self };
+        train.count_occupancy();
+        train
+    }
+
+    pub fn with_resources(self, resources: HashMap<String, Resource>) -> Self {
+        Train { resources, ..self }
+    }
+
+    pub fn with_fares(self, fares: Fares) -> Self {
+        let mut train = Train { fares, ..self };
+        train.count_occupancy();
+        train
+    }
+
+    pub fn with_departure(self, departure: Option<u64>) -> Self {
+        Train { departure, ..self }
+    }
+
+    pub fn with_archived(self, archived: bool) -> Self {
+        Train { archived, ..self }
+    }
+
+    // the places and payment of a booking, as they were written; the places
+    // are counted in the capacities already
+    fn restore_booking(&mut self, booking: BookingData) -> Result<(), String> {
+        let reference = booking.booking_reference.as_str();
+        for coach in booking.places.keys() {
+            let free_seating = self
+                .coaches
+                .get(coach)
+                .is_some_and(|data| data.free_seating.is_some());
+            if !free_seating {
+                return Err(format!(
+                    "booking {} has places in coach {}, which isn't free seating",
+                    reference, coach
+                ));
             }
         }
+        if booking.standing > 0 && self.standing.is_none() {
+            return Err(format!(
+                "booking {} has standing places on a train without standing room",
+                reference
+            ));
+        }
+        let places = BookedPlaces {
+            coaches: booking.places,
+            standing: booking.standing,
+        };
+        if places.total() > 0 {
+            self.booked_places
+                .insert(booking.booking_reference.clone(), places);
+        }
+        if booking.paid > 0 {
+            self.paid.insert(booking.booking_reference, booking.paid);
+        }
+        Ok(())
+    }
 
-        if !seats_already_reserved.is_empty() {
-            return Err(Error::SeatsAlreadyReserved(seats_already_reserved));
+    pub fn is_archived(&self) -> bool {
+        self.archived
+    }
+
+    pub fn departure(&self) -> Option<u64> {
+        self.departure
+    }
+
+    pub fn fares(&self) -> &Fares {
+        &self.fares
+    }
+
+    pub fn adjacency(&self) -> &Adjacency {
+        &self.adjacency
+    }
+
+    /// Price seats from the cheapest fare buckets with room in their coach.
+    pub fn quote(&self, seat_ids: &[SeatId]) -> Result<Vec<SeatFare>, Error> {
+        let seats = seat_ids
+            .iter()
+            .map(|seat_id| self.seats.get(seat_id).map(|seat| (seat_id, seat.coach())))
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| {
+                Error::SeatsDoNotExist(
+                    seat_ids
+                        .iter()
+                        .filter(|seat_id| !self.seats.contains_key(seat_id))
+                        .cloned()
+                        .collect(),
+                )
+            })?;
+        let quote = self.fares.quote(seats);
+        let unpriced = quote
+            .iter()
+            .filter(|(_, bucket)| bucket.is_none())
+            .map(|(seat_id, _)| (*seat_id).clone())
+            .collect::<Vec<_>>();
+        if !unpriced.is_empty() {
+            return Err(Error::NoFareAvailable(unpriced));
         }
+        Ok(quote
+            .into_iter()
+            .filter_map(|(seat_id, bucket)| {
+                bucket.map(|bucket| SeatFare {
+                    seat_id: seat_id.clone(),
+                    fare: bucket.name.clone(),
+                    price: bucket.price,
+                })
+            })
+            .collect())
+    }
 
-        // finally reserve the seats
-        for seat_id in &reservation.seats {
-            let seat = self.seats.get_mut(seat_id).unwrap();
-            seat.booking_reference = Some(reservation.booking_reference.clone());
+    pub fn resources(&self) -> impl Iterator<Item = (&String, &Resource)> {
+        self.resources.iter()
+    }
+
+    /// How many resources of a kind are reserved, overall and per coach.
+    pub fn resource_occupancy(&self, kind: ResourceKind) -> Occupancy {
+        Occupancy::count(
+            self.resources
+                .values()
+                .filter(|resource| resource.kind() == kind && !self.is_closed(resource.coach())),
+        )
+    }
+
+    pub fn standing(&self) -> Option<&Capacity> {
+        self.standing.as_ref()
+    }
+
+    // blocked seats and seats in closed coaches aren't counted
+    fn count_occupancy(&mut self) {
+        self.occupancy = Occupancy::count(
+            self.seats
+                .values()
+                .filter(|seat| seat.blocked().is_none() && !self.is_closed(&seat.coach)),
+        );
+        for (id, coach) in &self.coaches {
+            if let Some(free_seating) = &coach.free_seating {
+                if !coach.closed {
+                    self.occupancy
+                        .add_places(id, free_seating.capacity, free_seating.reserved);
+                }
+            }
+        }
+        if let Some(standing) = &self.standing {
+            self.occupancy
+                .set_standing(standing.capacity, standing.reserved);
+        }
+        self.count_fares();
+    }
+
+    // limited fare buckets have that many seats in every coach
+    fn count_fares(&mut self) {
+        let coaches = self.occupancy.coaches().count();
+        for bucket in self.fares.buckets() {
+            if let Some(limit) = bucket.seats_per_coach {
+                self.occupancy
+                    .set_fare(&bucket.name, limit * coaches, bucket.sold.values().sum());
+            }
         }
+    }
+
+    pub fn coaches(&self) -> impl Iterator<Item = (&String, &Coach)> {
+        self.coaches.iter()
+    }
+
+    /// Whether seats in this coach are in a quiet zone.
+    pub fn is_quiet(&self, coach: &str) -> bool {
+        self.coaches.get(coach).is_some_and(|coach| coach.quiet)
+    }
 
+    pub fn class(&self, coach: &str) -> Class {
+        self.coaches
+            .get(coach)
+            .map_or(Class::default(), |coach| coach.class)
+    }
+
+    pub fn is_closed(&self, coach: &str) -> bool {
+        self.coaches.get(coach).is_some_and(|coach| coach.closed)
+    }
+
+    /// Whether a seat can be reserved: it's free and its coach is open.
+    pub fn is_available(&self, seat: &Seat) -> bool {
+        seat.is_free() && !self.is_closed(&seat.coach)
+    }
+
+    /// Close or reopen a coach. Seats in a closed coach can't be reserved and
+    /// don't count towards occupancy.
+    pub fn set_closed(&mut self, coach: &str, closed: bool) -> Result<(), Error> {
+        if !self.coaches.contains_key(coach) && !self.seats.values().any(|seat| seat.coach == coach)
+        {
+            return Err(Error::CoachDoesNotExist(coach.to_string()));
+        }
+        let data = self.coaches.entry(coach.to_string()).or_default();
+        data.closed = closed;
+        // plain coaches don't need to be listed
+        if *data == Coach::default() {
+            self.coaches.remove(coach);
+        }
+        self.count_occupancy();
         Ok(())
     }
 
-    pub fn reset(&mut self) {
+    pub fn occupancy(&self) -> &Occupancy {
+        &self.occupancy
+    }
+
+    pub fn seats(&self) -> impl Iterator<Item = (&SeatId, &Seat)> {
+        self.seats.iter()
+    }
+
+    pub fn get(&self, seat_id: &SeatId) -> Option<&Seat> {
+        self.seats.get(seat_id)
+    }
+
+    /// Give seats new ids, keeping their reservations. A new id can put a
+    /// seat in another coach.
+    pub fn renumber(&mut self, mapping: &SeatMapping) -> Result<(), Error> {
+        mapping.validate(self)?;
         for seat in self.seats.values_mut() {
-            seat.booking_reference = None;
+            for neighbour in &mut seat.neighbours {
+                if let Some(new_id) = mapping.get(neighbour) {
+                    *neighbour = new_id.clone();
+                }
+            }
+        }
+        self.seats = self
+            .seats
+            .drain()
+            .map(|(seat_id, mut seat)| match mapping.get(&seat_id) {
+                Some(new_id) => {
+                    let (seat_number, coach) = new_id.split();
+                    seat.seat_number = seat_number.to_string();
+                    seat.coach = coach.to_string();
+                    (new_id.clone(), seat)
+                }
+                None => (seat_id, seat),
+            })
+            .collect();
+        for seat_ids in self.booked.values_mut() {
+            *seat_ids = seat_ids
+                .drain()
+                .map(|seat_id| mapping.get(&seat_id).cloned().unwrap_or(seat_id))
+                .collect();
         }
+        self.adjacency = Adjacency::new(&self.seats, &self.coaches);
+        self.count_occupancy();
+        Ok(())
     }
-}
 
-impl TrainDataService {
-    pub fn new(trains: TrainsData) -> TrainDataService {
-        TrainDataService { trains }
+    /// The seats on this train held by a booking reference.
+    pub fn booked_seats(&self, booking_reference: &BookingReference) -> usize {
+        self.booked.get(booking_reference).map_or(0, HashSet::len)
     }
 
-    pub fn train(&self, train_id: &TrainId) -> Result<&Train, Error> {
-        self.trains
-            .0
-            .get(train_id)
-            .ok_or(Error::TrainDoesNotExist(train_id.clone()))
+    /// The seats held for a booking that it hasn't reserved yet.
+    pub fn held_seats(&self, booking_reference: &BookingReference) -> usize {
+        self.seats
+            .values()
+            .filter(|seat| seat.state.is_held_for(booking_reference))
+            .count()
     }
 
-    pub fn train_mut(&mut self, train_id: &TrainId) -> Result<&mut Train, Error> {
-        self.trains
-            .0
-            .get_mut(train_id)
-            .ok_or(Error::TrainDoesNotExist(train_id.clone()))
+    /// The seats of a reservation that are held for its booking already;
+    /// they're counted in the occupancy and against the quotas already.
+    pub fn held_for(&self, reservation: &Reservation) -> usize {
+        reservation
+            .seats
+            .iter()
+            .filter_map(|seat_id| self.seats.get(seat_id))
+            .filter(|seat| seat.state.is_held_for(&reservation.booking_reference))
+            .count()
+    }
+
+    /// The places without a seat number a booking holds, in free-seating
+    /// coaches and standing.
+    pub fn booked_places(&self, booking_reference: &BookingReference) -> usize {
+        self.booked_places
+            .get(booking_reference)
+            .map_or(0, BookedPlaces::total)
+    }
+
+    /// The places without a seat number of each booking that has them.
+    pub fn places_by_booking(&self) -> impl Iterator<Item = (&BookingReference, &BookedPlaces)> {
+        self.booked_places.iter()
+    }
+
+    /// What a booking paid for its seats, in cents.
+    pub fn paid(&self, booking_reference: &BookingReference) -> u64 {
+        self.paid
+            .get(booking_reference)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Set what a booking paid for its seats, in cents, for when it was
+    /// charged something else than the prices of their fares.
+    pub fn set_paid(&mut self, booking_reference: &BookingReference, paid: u64) {
+        if paid > 0 {
+            self.paid.insert(booking_reference.clone(), paid);
+        } else {
+            self.paid.remove(booking_reference);
+        }
+    }
+
+    /// What each booking that paid for its seats paid, in cents.
+    pub fn paid_by_booking(&self) -> impl Iterator<Item = (&BookingReference, u64)> {
+        self.paid
+            .iter()
+            .map(|(booking_reference, paid)| (booking_reference, *paid))
+    }
+
+    /// The ids of the resources a booking holds, like bicycle places.
+    pub fn booked_resources(&self, booking_reference: &BookingReference) -> Vec<String> {
+        self.resources
+            .iter()
+            .filter(|(_, resource)| resource.booking_reference() == Some(booking_reference))
+            .map(|(id, _)| id.clone())
+            .collect()
+    }
+
+    /// Whether a booking holds anything on this train.
+    pub fn has_booking(&self, booking_reference: &BookingReference) -> bool {
+        self.booked_seats(booking_reference) > 0
+            || self.booked_places(booking_reference) > 0
+            || !self.booked_resources(booking_reference).is_empty()
     }
 }
 
-#[cfg(test)]
-mod tests {
+#[derive(Debug, Default, PartialEq, Eq, Clone, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Coach {
+    #[serde(default)]
+    pub quiet: bool,
+    #[serde(default)]
+    pub closed: bool,
+    #[serde(default)]
+    pub class: Class,
+    /// Set for coaches without seat reservations, where passengers sit
+    /// wherever there's room.
+    #[serde(default)]
+    pub free_seating: Option<Capacity>,
+    /// How the seats are laid out, if that's known.
+    #[serde(default)]
+    pub layout: Option<Layout>,
+}
 
-    use super::*;
+/// The travel class of a coach.
+#[derive(
+    Debug,
+    Default,
+    PartialEq,
+    Eq,
+    Clone,
+    Copy,
+    serde::Serialize,
+    serde::Deserialize,
+    schemars::JsonSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum Class {
+    First,
+    #[default]
+    Second,
+}
 
-    #[test]
-    fn test_train_doesnt_exist() {
-        let service = TrainDataService::new(TrainsData::new());
-        let train_id = TrainId::new("doesnt_exist");
-        let train = service.train(&train_id).unwrap_err();
-        assert_eq!(train, Error::TrainDoesNotExist(train_id));
+impl Class {
+    pub fn is_second(&self) -> bool {
+        *self == Class::Second
     }
+}
 
-    #[test]
-    fn test_train_does_exist() {
-        let mut trains = HashMap::new();
-        let train = Train {
-            seats: HashMap::from([(
-                SeatId::new("1A"),
-                Seat {
-                    seat_number: "1".to_string(),
-                    coach: "A".to_string(),
-                    booking_reference: Some(BookingReference::new("123456")),
-                },
-            )]),
-        };
-        let train_id = TrainId::new("train_id");
-        trains.insert(train_id.clone(), train);
-        let service = TrainDataService::new(TrainsData(trains));
-        let train = service.train(&train_id).unwrap();
-        assert_eq!(
-            train,
-            &Train {
-                seats: HashMap::from([(
-                    SeatId::new("1A"),
-                    Seat {
-                        seat_number: "1".to_string(),
-                        coach: "A".to_string(),
-                        booking_reference: Some(BookingReference::new("123456")),
-                    },
-                )]),
-            }
-        );
+/// A number of places, some of which may be reserved.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Capacity {
+    pub capacity: usize,
+    #[serde(default)]
+    pub reserved: usize,
+}
+
+impl Capacity {
+    pub fn remaining(&self) -> usize {
+        self.capacity.saturating_sub(self.reserved)
     }
+}
 
-    #[test]
-    fn test_reserve_seat() {
-        let mut train = Train {
-            seats: HashMap::from([(
-                SeatId::new("1A"),
-                Seat {
-                    seat_number: "1".to_string(),
-                    coach: "A".to_string(),
-                    booking_reference: None,
-                },
-            )]),
+#[derive(
+    Debug, PartialEq, Eq, Clone, Copy, serde::Serialize, serde::Deserialize, schemars::JsonSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum SeatPosition {
+    Window,
+    Middle,
+    Aisle,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, serde::Deserialize)]
+#[serde(try_from = "SeatData")]
+pub struct Seat {
+    seat_number: String,
+    coach: String,
+    state: SeatState,
+    position: Option<SeatPosition>,
+    accessible: bool,
+    // the seats next to this one, if the train data lists them
+    neighbours: Vec<SeatId>,
+}
+
+/// Where a seat is: free, held for a booking for a while, reserved, or out of
+/// use. Seats only change state through the transitions below, which refuse
+/// the ones that make no sense, like reserving a blocked seat.
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub enum SeatState {
+    #[default]
+    Free,
+    /// Held for a booking until `expires`, in seconds since the Unix epoch,
+    /// so nobody else takes the seat while the booking is completed.
+    Held {
+        booking_reference: BookingReference,
+        expires: u64,
+    },
+    Reserved {
+        booking_reference: BookingReference,
+    },
+    /// Out of use, for the given reason.
+    Blocked {
+        reason: String,
+    },
+}
+
+impl SeatState {
+    // whether the seat is reserved or held for another booking
+    fn is_taken(&self, booking_reference: &BookingReference) -> bool {
+        match self {
+            SeatState::Reserved { .. } => true,
+            SeatState::Held {
+                booking_reference: holder,
+                ..
+            } => holder != booking_reference,
+            SeatState::Free | SeatState::Blocked { .. } => false,
+        }
+    }
+
+    // whether the seat is held for this booking
+    fn is_held_for(&self, booking_reference: &BookingReference) -> bool {
+        matches!(self, SeatState::Held { booking_reference: holder, .. } if holder == booking_reference)
+    }
+
+    // the error for taking a seat that can't be taken
+    fn refuse(&self, seat_id: &SeatId) -> Error {
+        match self {
+            SeatState::Blocked { .. } => Error::SeatsBlocked(vec![seat_id.clone()]),
+            _ => Error::SeatsAlreadyReserved(vec![seat_id.clone()], Vec::new()),
+        }
+    }
+
+    /// Hold a free seat, or extend a hold for the same booking.
+    fn hold(
+        &mut self,
+        seat_id: &SeatId,
+        booking_reference: &BookingReference,
+        expires: u64,
+    ) -> Result<(), Error> {
+        if !matches!(self, SeatState::Free | SeatState::Held { .. })
+            || self.is_taken(booking_reference)
+        {
+            return Err(self.refuse(seat_id));
+        }
+        *self = SeatState::Held {
+            booking_reference: booking_reference.clone(),
+            expires,
         };
-        train
-            .reserve(&Reservation {
-                seats: vec![SeatId::new("1A")],
-                booking_reference: BookingReference::new("123456"),
-            })
-            .unwrap();
-        let seat = train.seats.get(&SeatId::new("1A")).unwrap();
-        assert_eq!(
-            seat.booking_reference,
-            Some(BookingReference::new("123456"))
-        );
+        Ok(())
     }
 
-    #[test]
-    fn test_reserve_when_already_reserved() {
-        let mut train = Train {
-            seats: HashMap::from([(
-                SeatId::new("1A"),
-                Seat {
-                    seat_number: "1".to_string(),
-                    coach: "A".to_string(),
-                    booking_reference: Some(BookingReference::new("existing")),
-                },
-            )]),
+    /// Reserve a free seat, or one held for the same booking.
+    fn reserve(
+        &mut self,
+        seat_id: &SeatId,
+        booking_reference: &BookingReference,
+    ) -> Result<(), Error> {
+        if !matches!(self, SeatState::Free | SeatState::Held { .. })
+            || self.is_taken(booking_reference)
+        {
+            return Err(self.refuse(seat_id));
+        }
+        *self = SeatState::Reserved {
+            booking_reference: booking_reference.clone(),
         };
-        let result = train.reserve(&Reservation {
-            seats: vec![SeatId::new("1A")],
-            booking_reference: BookingReference::new("new"),
-        });
-        assert_eq!(
-            result,
-            Err(Error::SeatsAlreadyReserved(vec![SeatId::new("1A")]))
+        Ok(())
+    }
+
+    /// Free a seat that's reserved or held for a booking.
+    fn release(&mut self, booking_reference: &BookingReference) -> Result<(), Error> {
+        match self {
+            SeatState::Held {
+                booking_reference: holder,
+                ..
+            }
+            | SeatState::Reserved {
+                booking_reference: holder,
+            } if holder == booking_reference => {
+                *self = SeatState::Free;
+                Ok(())
+            }
+            _ => Err(Error::BookingDoesNotExist(booking_reference.clone())),
+        }
+    }
+
+    /// Take a seat out of use; blocking it again only changes the reason.
+    fn block(&mut self, seat_id: &SeatId, reason: String) -> Result<(), Error> {
+        match self {
+            SeatState::Free | SeatState::Blocked { .. } => {
+                *self = SeatState::Blocked { reason };
+                Ok(())
+            }
+            _ => Err(Error::SeatsAlreadyReserved(
+                vec![seat_id.clone()],
+                Vec::new(),
+            )),
+        }
+    }
+
+    /// Put a blocked seat back in use, returning whether it was blocked.
+    fn unblock(&mut self) -> bool {
+        if !matches!(self, SeatState::Blocked { .. }) {
+            return false;
+        }
+        *self = SeatState::Free;
+        true
+    }
+
+    /// Free a seat whose hold expired at `now`, returning whether it did.
+    fn expire(&mut self, now: u64) -> bool {
+        if !matches!(self, SeatState::Held { expires, .. } if *expires <= now) {
+            return false;
+        }
+        *self = SeatState::Free;
+        true
+    }
+
+    // reservations and holds go, but blocked seats stay blocked
+    fn reset(&mut self) {
+        if matches!(self, SeatState::Held { .. } | SeatState::Reserved { .. }) {
+            *self = SeatState::Free;
+        }
+    }
+}
+
+// the serialized form of a seat
+#[derive(serde::Deserialize)]
+struct SeatData {
+    seat_number: String,
+    coach: String,
+    #[serde(default, deserialize_with = "deserialize_booking_reference")]
+    booking_reference: Option<BookingReference>,
+    // a reserved seat that's only held until then
+    #[serde(default)]
+    held_until: Option<u64>,
+    #[serde(default)]
+    position: Option<SeatPosition>,
+    #[serde(default)]
+    accessible: bool,
+    // why the seat can't be used, if it can't
+    #[serde(default)]
+    blocked: Option<String>,
+    #[serde(default)]
+    neighbours: Vec<SeatId>,
+}
+
+impl TryFrom<SeatData> for Seat {
+    type Error = String;
+
+    fn try_from(data: SeatData) -> Result<Self, Self::Error> {
+        let state = match (data.booking_reference, data.held_until, data.blocked) {
+            (None, None, None) => SeatState::Free,
+            (Some(booking_reference), Some(expires), None) => SeatState::Held {
+                booking_reference,
+                expires,
+            },
+            (Some(booking_reference), None, None) => SeatState::Reserved { booking_reference },
+            (None, None, Some(reason)) => SeatState::Blocked { reason },
+            (None, Some(_), _) => return Err("a held seat needs a booking reference".to_string()),
+            (Some(_), _, Some(_)) => {
+                return Err("a seat can't be both reserved and blocked".to_string())
+            }
+        };
+        Ok(Seat {
+            seat_number: data.seat_number,
+            coach: data.coach,
+            state,
+            position: data.position,
+            accessible: data.accessible,
+            neighbours: data.neighbours,
+        })
+    }
+}
+
+// the original kata marks free seats with an empty booking reference
+fn deserialize_booking_reference<'de, D>(
+    deserializer: D,
+) -> Result<Option<BookingReference>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let booking_reference: Option<BookingReference> =
+        serde::Deserialize::deserialize(deserializer)?;
+    Ok(booking_reference.filter(|booking_reference| !booking_reference.as_str().is_empty()))
+}
+
+/// Changes to the attributes of a seat, as a JSON Merge Patch: attributes
+/// that are left out stay as they are, and `null` clears them. Seat ids,
+/// coaches and reservations can't be patched.
+#[derive(Debug, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SeatPatch {
+    #[serde(default, deserialize_with = "present")]
+    position: Option<Option<SeatPosition>>,
+    #[serde(default, deserialize_with = "present")]
+    accessible: Option<Option<bool>>,
+    #[serde(default, deserialize_with = "present")]
+    blocked: Option<Option<String>>,
+}
+
+// a field that's there, even if it's null
+fn present<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: serde::Deserialize<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
+impl Seat {
+    pub fn new(seat_number: String, coach: String) -> Self {
+        Seat {
+            seat_number,
+            coach,
+            state: SeatState::Free,
+            position: None,
+            accessible: false,
+            neighbours: Vec::new(),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn with_position(self, position: SeatPosition) -> Self {
+        Seat {
+            position: Some(position),
+            ..self
+        }
+    }
+
+    #[cfg(test)]
+    pub fn with_accessible(self, accessible: bool) -> Self {
+        Seat { accessible, ..self }
+    }
+
+    #[cfg(test)]
+    pub fn with_neighbours(self, neighbours: Vec<SeatId>) -> Self {
+        Seat { neighbours, ..self }
+    }
+
+    /// The seats listed as next to this one in the train data. Use
+    /// [`Train::adjacency`] to find out which seats are really next to each
+    /// other.
+    pub fn neighbours(&self) -> &[SeatId] {
+        &self.neighbours
+    }
+
+    pub fn with_booking_reference(self, booking_reference: Option<BookingReference>) -> Self {
+        let state = match booking_reference {
+            Some(booking_reference) => SeatState::Reserved { booking_reference },
+            None => SeatState::Free,
+        };
+        Seat { state, ..self }
+    }
+
+    pub fn seat_number(&self) -> &str {
+        &self.seat_number
+    }
+
+    pub fn coach(&self) -> &str {
+        &self.coach
+    }
+
+    /// The booking the seat is reserved or held for.
+    pub fn booking_reference(&self) -> Option<&BookingReference> {
+        match &self.state {
+            SeatState::Held {
+                booking_reference, ..
+            }
+            | SeatState::Reserved { booking_reference } => Some(booking_reference),
+            SeatState::Free | SeatState::Blocked { .. } => None,
+        }
+    }
+
+    /// Until when the seat is held, if it's only held.
+    pub fn held_until(&self) -> Option<u64> {
+        match self.state {
+            SeatState::Held { expires, .. } => Some(expires),
+            _ => None,
+        }
+    }
+
+    pub fn position(&self) -> Option<SeatPosition> {
+        self.position
+    }
+
+    pub fn is_accessible(&self) -> bool {
+        self.accessible
+    }
+
+    pub fn blocked(&self) -> Option<&str> {
+        match &self.state {
+            SeatState::Blocked { reason } => Some(reason),
+            _ => None,
+        }
+    }
+
+    /// Whether the seat can be reserved.
+    pub fn is_free(&self) -> bool {
+        self.state == SeatState::Free
+    }
+}
+
+#[derive(
+    Debug, PartialEq, Eq, Clone, serde::Serialize, serde::Deserialize, schemars::JsonSchema,
+)]
+#[serde(deny_unknown_fields)]
+pub struct Reservation {
+    pub seats: Vec<SeatId>,
+    pub booking_reference: BookingReference,
+    /// Whether the passengers need accessible seats; only then can those be
+    /// reserved, unless there are no other seats left.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub accessible: bool,
+}
+
+/// What cancelling a booking on a train released.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Cancellation {
+    pub seats: Vec<SeatId>,
+    /// Places without a seat number, in free-seating coaches or standing.
+    pub places: usize,
+    /// The ids of resources, like bicycle places.
+    pub resources: Vec<String>,
+    /// What was paid for the seats, in cents.
+    pub paid: u64,
+}
+
+/// Seats that are needed for an upgrade, but aren't free in first class.
+#[derive(Debug, PartialEq, Eq, Clone, serde::Serialize)]
+pub struct MissingSeats {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub position: Option<SeatPosition>,
+    pub accessible: bool,
+    pub seats: usize,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Error {
+    TrainDoesNotExist(TrainId),
+    TrainAlreadyExists(TrainId),
+    TrainArchived(TrainId),
+    InvalidTrainSpec(String),
+    TemplateDoesNotExist(String),
+    InvalidImport(String),
+    InvalidSeatMapping(String),
+    InvalidSeatFilter(String),
+    InvalidFields(String),
+    InvalidPolicy(String),
+    SeatsDoNotExist(Vec<SeatId>),
+    /// Seats that are asked for more than once.
+    DuplicateSeats(Vec<SeatId>),
+    SeatDoesNotExist(TrainId, SeatId),
+    ReservationDoesNotExist(ReservationId),
+    ReservationCancelled(ReservationId),
+    ReservationWithoutSeats,
+    /// Seats are to be held longer than the most seconds they can be.
+    HoldTooLong(u64),
+    /// The simulated clock can't be advanced this many seconds.
+    ClockOverflow(u64),
+    /// Seats that are taken, and free seats in the same coaches to try
+    /// instead.
+    SeatsAlreadyReserved(Vec<SeatId>, Vec<SeatId>),
+    NotEnoughFreeSeats(usize),
+    SeatsAccessible(Vec<SeatId>),
+    NotFreeSeating(String),
+    NotEnoughPlaces(String, usize),
+    NoStandingRoom,
+    NotEnoughStandingRoom(usize),
+    NotEnoughResources(ResourceKind, usize),
+    BookingDoesNotExist(BookingReference),
+    NothingToUpgrade(BookingReference),
+    UpgradeUnavailable(Vec<MissingSeats>),
+    NoFareAvailable(Vec<SeatId>),
+    StoreFailed(String),
+    CancellationClosed(TrainId),
+    InvalidVoucher(String, String),
+    VoucherDoesNotExist(String),
+    SeatsBlocked(Vec<SeatId>),
+    CoachDoesNotExist(String),
+    CoachesClosed(Vec<String>),
+    RuleViolated(&'static str, String),
+    ReservationNotAllowed(String),
+    QuotaExceeded(Allowance),
+    FeatureOff(Flag),
+}
+
+impl Train {
+    /// Reserve seats, if they exist, are free, and the business rules allow it.
+    /// Seats held for the same booking can be reserved too.
+    pub fn reserve(&mut self, reservation: &Reservation, rules: &Rules) -> Result<(), Error> {
+        let _span = tracing::info_span!(
+            "reserve",
+            seats = reservation.seats.len(),
+            booking_reference = reservation.booking_reference.as_str(),
+        )
+        .entered();
+        self.check_seats(reservation)?;
+        rules.check(self, reservation)?;
+        self.take_seats(reservation)
+    }
+
+    /// Change which seats a booking has: reserve the seats of `add` and
+    /// release those in `remove`, all at once or not at all. The new seats
+    /// are checked before anything is released, and the business rules as if
+    /// the released seats were free already.
+    pub fn change_seats(
+        &mut self,
+        remove: &[SeatId],
+        add: &Reservation,
+        rules: &Rules,
+    ) -> Result<(), Error> {
+        let booking_reference = &add.booking_reference;
+        let _span = tracing::info_span!(
+            "change_seats",
+            remove = remove.len(),
+            add = add.seats.len(),
+            booking_reference = booking_reference.as_str(),
+        )
+        .entered();
+        let booked = self
+            .booked
+            .get(booking_reference)
+            .ok_or_else(|| Error::BookingDoesNotExist(booking_reference.clone()))?;
+        if !remove.iter().all(|seat_id| booked.contains(seat_id)) {
+            return Err(Error::BookingDoesNotExist(booking_reference.clone()));
+        }
+        self.check_seats(add)?;
+        let mut released = self.clone();
+        released.release_seats(booking_reference, remove)?;
+        rules.check(&released, add)?;
+        self.release_seats(booking_reference, remove)?;
+        self.take_seats(add)
+    }
+
+    // release seats of a booking, which it's known to have
+    fn release_seats(
+        &mut self,
+        booking_reference: &BookingReference,
+        seat_ids: &[SeatId],
+    ) -> Result<(), Error> {
+        let booked = self.booked.get_mut(booking_reference).unwrap();
+        for seat_id in seat_ids {
+            let seat = self.seats.get_mut(seat_id).unwrap();
+            seat.state.release(booking_reference)?;
+            self.occupancy.release(&seat.coach);
+            booked.remove(seat_id);
+        }
+        Ok(())
+    }
+
+    // reserve seats that were checked already, and sell their fares
+    fn take_seats(&mut self, reservation: &Reservation) -> Result<(), Error> {
+        let booked = self
+            .booked
+            .entry(reservation.booking_reference.clone())
+            .or_default();
+        for seat_id in &reservation.seats {
+            let seat = self.seats.get_mut(seat_id).unwrap();
+            // held seats are counted already
+            let held = seat.state.is_held_for(&reservation.booking_reference);
+            seat.state
+                .reserve(seat_id, &reservation.booking_reference)?;
+            booked.insert(seat_id.clone());
+            if !held {
+                self.occupancy.reserve(&seat.coach);
+            }
+        }
+        let price = self.fares.sell(
+            reservation
+                .seats
+                .iter()
+                .map(|seat_id| self.seats[seat_id].coach.as_str()),
+        );
+        if price > 0 {
+            *self
+                .paid
+                .entry(reservation.booking_reference.clone())
+                .or_default() += price;
+        }
+        self.count_fares();
+
+        Ok(())
+    }
+
+    /// Hold seats for a booking until `expires`, in seconds since the Unix
+    /// epoch, so nobody else reserves them meanwhile. Reserving them with the
+    /// same booking reference turns the hold into a reservation. Held seats
+    /// count as taken, so the business rules apply to them as well.
+    pub fn hold(
+        &mut self,
+        reservation: &Reservation,
+        expires: u64,
+        rules: &Rules,
+    ) -> Result<(), Error> {
+        let _span = tracing::info_span!(
+            "hold",
+            seats = reservation.seats.len(),
+            booking_reference = reservation.booking_reference.as_str(),
+        )
+        .entered();
+        self.check_seats(reservation)?;
+        rules.check(self, reservation)?;
+        for seat_id in &reservation.seats {
+            let seat = self.seats.get_mut(seat_id).unwrap();
+            let held = seat.state.is_held_for(&reservation.booking_reference);
+            seat.state
+                .hold(seat_id, &reservation.booking_reference, expires)?;
+            if !held {
+                self.occupancy.reserve(&seat.coach);
+            }
+        }
+        Ok(())
+    }
+
+    /// Free the seats whose hold expired at `now`, returning them.
+    pub fn expire_holds(&mut self, now: u64) -> Vec<SeatId> {
+        let mut expired = Vec::new();
+        for (seat_id, seat) in &mut self.seats {
+            if seat.state.expire(now) {
+                self.occupancy.release(&seat.coach);
+                expired.push(seat_id.clone());
+            }
+        }
+        expired.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+        expired
+    }
+
+    /// When the first of the holds on the train expires, if there are any.
+    pub fn next_hold_expiry(&self) -> Option<u64> {
+        self.seats.values().filter_map(Seat::held_until).min()
+    }
+
+    // free seats in the coaches of taken seats, other than the ones asked for,
+    // by coach and seat number
+    fn alternatives(&self, taken: &[SeatId], requested: &[SeatId]) -> Vec<SeatId> {
+        let coaches: HashSet<&str> = taken
+            .iter()
+            .map(|seat_id| self.seats[seat_id].coach.as_str())
+            .collect();
+        let mut alternatives: Vec<(&Seat, &SeatId)> = self
+            .seats
+            .iter()
+            .filter(|(seat_id, seat)| {
+                coaches.contains(seat.coach.as_str())
+                    && self.is_available(seat)
+                    && !requested.contains(seat_id)
+            })
+            .map(|(seat_id, seat)| (seat, seat_id))
+            .collect();
+        alternatives.sort_by_key(|(seat, _)| {
+            (
+                seat.coach.as_str(),
+                seat.seat_number.parse::<u32>().unwrap_or(u32::MAX),
+            )
+        });
+        alternatives
+            .into_iter()
+            .take(MAX_ALTERNATIVES)
+            .map(|(_, seat_id)| seat_id.clone())
+            .collect()
+    }
+
+    // whether the seats of a reservation can be taken, leaving the business
+    // rules aside
+    fn check_seats(&self, reservation: &Reservation) -> Result<(), Error> {
+        // seats are taken one by one, so a seat that's asked for twice would
+        // be taken before the second time fails
+        let mut seen = HashSet::new();
+        let mut duplicate_seat_ids = Vec::new();
+        for seat_id in &reservation.seats {
+            if !seen.insert(seat_id) && !duplicate_seat_ids.contains(seat_id) {
+                duplicate_seat_ids.push(seat_id.clone());
+            }
+        }
+        if !duplicate_seat_ids.is_empty() {
+            return Err(Error::DuplicateSeats(duplicate_seat_ids));
+        }
+
+        // first check whether we have any non-existent seats, report error if any of them are
+        let mut non_existent_seat_ids = Vec::new();
+        for seat_id in &reservation.seats {
+            if !self.seats.contains_key(seat_id) {
+                non_existent_seat_ids.push(seat_id.clone());
+            }
+        }
+        if !non_existent_seat_ids.is_empty() {
+            return Err(Error::SeatsDoNotExist(non_existent_seat_ids));
+        }
+
+        // then report error if any seat is already reserved
+        let mut seats_already_reserved = Vec::new();
+        for seat_id in &reservation.seats {
+            let seat = self.seats.get(seat_id).unwrap();
+            if seat.state.is_taken(&reservation.booking_reference) {
+                seats_already_reserved.push(seat_id.clone());
+            }
+        }
+
+        if !seats_already_reserved.is_empty() {
+            let alternatives = self.alternatives(&seats_already_reserved, &reservation.seats);
+            return Err(Error::SeatsAlreadyReserved(
+                seats_already_reserved,
+                alternatives,
+            ));
+        }
+
+        let blocked_seats = reservation
+            .seats
+            .iter()
+            .filter(|seat_id| self.seats[*seat_id].blocked().is_some())
+            .cloned()
+            .collect::<Vec<_>>();
+        if !blocked_seats.is_empty() {
+            return Err(Error::SeatsBlocked(blocked_seats));
+        }
+
+        let mut closed_coaches = reservation
+            .seats
+            .iter()
+            .map(|seat_id| &self.seats[seat_id].coach)
+            .filter(|coach| self.is_closed(coach))
+            .cloned()
+            .collect::<Vec<_>>();
+        closed_coaches.sort();
+        closed_coaches.dedup();
+        if !closed_coaches.is_empty() {
+            return Err(Error::CoachesClosed(closed_coaches));
+        }
+
+        // accessible seats are kept for those who need them, unless the
+        // train has nothing else left
+        if !reservation.accessible {
+            let accessible_seats = reservation
+                .seats
+                .iter()
+                .filter(|seat_id| self.seats[*seat_id].accessible)
+                .cloned()
+                .collect::<Vec<_>>();
+            let other_seats_free = self.seats.iter().any(|(seat_id, seat)| {
+                !seat.accessible && self.is_available(seat) && !reservation.seats.contains(seat_id)
+            });
+            if !accessible_seats.is_empty() && other_seats_free {
+                return Err(Error::SeatsAccessible(accessible_seats));
+            }
+        }
+        Ok(())
+    }
+
+    /// Move the second-class seats of a booking to first class, returning
+    /// the old and new seat ids. Each new seat is equivalent to the one it
+    /// replaces: it has the same position, and is accessible if that one was.
+    /// Either all seats move or none do.
+    pub fn upgrade(
+        &mut self,
+        booking_reference: &BookingReference,
+    ) -> Result<Vec<(SeatId, SeatId)>, Error> {
+        let _span = tracing::info_span!("upgrade", booking_reference = booking_reference.as_str())
+            .entered();
+        let booked = self
+            .booked
+            .get(booking_reference)
+            .ok_or_else(|| Error::BookingDoesNotExist(booking_reference.clone()))?;
+        let mut old = booked
+            .iter()
+            .filter(|seat_id| self.class(&self.seats[*seat_id].coach) == Class::Second)
+            .cloned()
+            .collect::<Vec<_>>();
+        if old.is_empty() {
+            return Err(Error::NothingToUpgrade(booking_reference.clone()));
+        }
+        old.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+        let mut free = self
+            .seats
+            .iter()
+            .filter(|(_, seat)| self.class(&seat.coach) == Class::First && self.is_available(seat))
+            .map(|(seat_id, _)| seat_id.clone())
+            .collect::<Vec<_>>();
+        free.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+
+        let mut moves = Vec::new();
+        let mut missing: Vec<MissingSeats> = Vec::new();
+        for seat_id in old {
+            let seat = &self.seats[&seat_id];
+            let equivalent = free.iter().position(|free_id| {
+                let free_seat = &self.seats[free_id];
+                free_seat.position == seat.position && free_seat.accessible == seat.accessible
+            });
+            match equivalent {
+                Some(index) => moves.push((seat_id, free.remove(index))),
+                None => match missing.iter_mut().find(|missing| {
+                    missing.position == seat.position && missing.accessible == seat.accessible
+                }) {
+                    Some(missing) => missing.seats += 1,
+                    None => missing.push(MissingSeats {
+                        position: seat.position,
+                        accessible: seat.accessible,
+                        seats: 1,
+                    }),
+                },
+            }
+        }
+        if !missing.is_empty() {
+            return Err(Error::UpgradeUnavailable(missing));
+        }
+
+        let booked = self.booked.get_mut(booking_reference).unwrap();
+        for (from, to) in &moves {
+            let seat = self.seats.get_mut(from).unwrap();
+            seat.state.release(booking_reference)?;
+            self.occupancy.release(&seat.coach);
+            booked.remove(from);
+            let seat = self.seats.get_mut(to).unwrap();
+            seat.state.reserve(to, booking_reference)?;
+            self.occupancy.reserve(&seat.coach);
+            booked.insert(to.clone());
+        }
+        Ok(moves)
+    }
+
+    /// Release all seats, places and resources of a booking.
+    pub fn cancel(&mut self, booking_reference: &BookingReference) -> Result<Cancellation, Error> {
+        let _span =
+            tracing::info_span!("cancel", booking_reference = booking_reference.as_str()).entered();
+        let mut resource_ids = self.booked_resources(booking_reference);
+        if resource_ids.is_empty()
+            && !self.booked.contains_key(booking_reference)
+            && !self.booked_places.contains_key(booking_reference)
+        {
+            return Err(Error::BookingDoesNotExist(booking_reference.clone()));
+        }
+        resource_ids.sort();
+        for id in &resource_ids {
+            self.resources
+                .get_mut(id)
+                .unwrap()
+                .set_booking_reference(None);
+        }
+        let seats = self.booked.remove(booking_reference);
+        let places = self.booked_places.remove(booking_reference);
+        let mut seat_ids = seats.into_iter().flatten().collect::<Vec<_>>();
+        seat_ids.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+        for seat_id in &seat_ids {
+            let seat = self.seats.get_mut(seat_id).unwrap();
+            seat.state.release(booking_reference)?;
+            // seats in closed coaches aren't counted, so this leaves them be
+            self.occupancy.release(&seat.coach);
+        }
+        self.fares.unsell(
+            seat_ids
+                .iter()
+                .map(|seat_id| self.seats[seat_id].coach.as_str()),
+        );
+        self.count_fares();
+        let places = places.unwrap_or_default();
+        for (coach, count) in &places.coaches {
+            if let Some(free_seating) = self
+                .coaches
+                .get_mut(coach)
+                .and_then(|data| data.free_seating.as_mut())
+            {
+                free_seating.reserved -= count;
+            }
+            for _ in 0..*count {
+                self.occupancy.release(coach);
+            }
+        }
+        if let Some(standing) = &mut self.standing {
+            standing.reserved -= places.standing;
+            self.occupancy.release_standing(places.standing);
+        }
+        Ok(Cancellation {
+            seats: seat_ids,
+            places: places.total(),
+            resources: resource_ids,
+            paid: self.paid.remove(booking_reference).unwrap_or_default(),
+        })
+    }
+
+    /// Take a free seat out of use, for the given reason.
+    pub fn block(&mut self, seat_id: &SeatId, reason: String) -> Result<(), Error> {
+        let seat = self
+            .seats
+            .get_mut(seat_id)
+            .ok_or_else(|| Error::SeatsDoNotExist(vec![seat_id.clone()]))?;
+        let blocked = seat.blocked().is_some();
+        seat.state.block(seat_id, reason)?;
+        if !blocked {
+            self.occupancy.remove_seat(&seat.coach);
+        }
+        Ok(())
+    }
+
+    pub fn unblock(&mut self, seat_id: &SeatId) -> Result<(), Error> {
+        let seat = self
+            .seats
+            .get_mut(seat_id)
+            .ok_or_else(|| Error::SeatsDoNotExist(vec![seat_id.clone()]))?;
+        let unblocked = seat.state.unblock();
+        let coach = seat.coach.clone();
+        // seats in closed coaches aren't counted until the coach reopens
+        if unblocked && !self.is_closed(&coach) {
+            self.occupancy.add_seat(&coach, false);
+        }
+        Ok(())
+    }
+
+    /// Change the attributes of a seat. Nothing changes when the patch isn't
+    /// allowed, like blocking a reserved seat.
+    pub fn patch_seat(&mut self, seat_id: &SeatId, patch: &SeatPatch) -> Result<(), Error> {
+        let seat = self
+            .seats
+            .get_mut(seat_id)
+            .ok_or_else(|| Error::SeatsDoNotExist(vec![seat_id.clone()]))?;
+        if let Some(Some(_)) = &patch.blocked {
+            if seat.booking_reference().is_some() {
+                return Err(Error::SeatsAlreadyReserved(
+                    vec![seat_id.clone()],
+                    Vec::new(),
+                ));
+            }
+        }
+        if let Some(position) = patch.position {
+            seat.position = position;
+        }
+        if let Some(accessible) = patch.accessible {
+            seat.accessible = accessible.unwrap_or_default();
+        }
+        match &patch.blocked {
+            Some(Some(reason)) => self.block(seat_id, reason.clone()),
+            Some(None) => self.unblock(seat_id),
+            None => Ok(()),
+        }
+    }
+
+    /// Reserve places in a free-seating coach for a booking, returning how
+    /// many are left.
+    pub fn reserve_places(
+        &mut self,
+        coach: &str,
+        places: usize,
+        booking_reference: &BookingReference,
+        rules: &Rules,
+    ) -> Result<usize, Error> {
+        let _span = tracing::info_span!("reserve_places", coach, places).entered();
+        let free_seating = self
+            .coaches
+            .get(coach)
+            .and_then(|data| data.free_seating.as_ref())
+            .ok_or_else(|| Error::NotFreeSeating(coach.to_string()))?;
+        if self.is_closed(coach) {
+            return Err(Error::CoachesClosed(vec![coach.to_string()]));
+        }
+        if places == 0 || places > free_seating.remaining() {
+            return Err(Error::NotEnoughPlaces(
+                coach.to_string(),
+                free_seating.remaining(),
+            ));
+        }
+        rules.check_places(self, booking_reference, Some(coach), places)?;
+        let free_seating = self
+            .coaches
+            .get_mut(coach)
+            .and_then(|data| data.free_seating.as_mut())
+            .unwrap();
+        free_seating.reserved += places;
+        let remaining = free_seating.remaining();
+        for _ in 0..places {
+            self.occupancy.reserve(coach);
+        }
+        *self
+            .booked_places
+            .entry(booking_reference.clone())
+            .or_default()
+            .coaches
+            .entry(coach.to_string())
+            .or_default() += places;
+        Ok(remaining)
+    }
+
+    /// Reserve standing room for a booking, returning how many places are
+    /// left.
+    pub fn reserve_standing(
+        &mut self,
+        places: usize,
+        booking_reference: &BookingReference,
+        rules: &Rules,
+    ) -> Result<usize, Error> {
+        let _span = tracing::info_span!("reserve_standing", places).entered();
+        let standing = self.standing.ok_or(Error::NoStandingRoom)?;
+        if places == 0 || places > standing.remaining() {
+            return Err(Error::NotEnoughStandingRoom(standing.remaining()));
+        }
+        rules.check_places(self, booking_reference, None, places)?;
+        let standing = self.standing.as_mut().unwrap();
+        standing.reserved += places;
+        self.occupancy.reserve_standing(places);
+        self.booked_places
+            .entry(booking_reference.clone())
+            .or_default()
+            .standing += places;
+        Ok(standing.remaining())
+    }
+
+    /// Reserve resources of a kind for a booking reference. Resources in the
+    /// coaches where the booking has seats are picked first.
+    pub fn reserve_resources(
+        &mut self,
+        kind: ResourceKind,
+        count: usize,
+        booking_reference: &BookingReference,
+    ) -> Result<Vec<String>, Error> {
+        let _span = tracing::info_span!("reserve_resources", %kind, count).entered();
+        let booked_coaches = self
+            .booked
+            .get(booking_reference)
+            .into_iter()
+            .flatten()
+            .filter_map(|seat_id| self.seats.get(seat_id))
+            .map(|seat| seat.coach.as_str())
+            .collect::<HashSet<_>>();
+        let mut free = self
+            .resources
+            .iter()
+            .filter(|(_, resource)| {
+                resource.kind() == kind && resource.is_free() && !self.is_closed(resource.coach())
+            })
+            .map(|(id, resource)| (!booked_coaches.contains(resource.coach()), id.clone()))
+            .collect::<Vec<_>>();
+        if count == 0 || free.len() < count {
+            return Err(Error::NotEnoughResources(kind, free.len()));
+        }
+        free.sort();
+        let ids = free
+            .into_iter()
+            .take(count)
+            .map(|(_, id)| id)
+            .collect::<Vec<_>>();
+        for id in &ids {
+            self.resources
+                .get_mut(id)
+                .unwrap()
+                .set_booking_reference(Some(booking_reference.clone()));
+        }
+        Ok(ids)
+    }
+
+    /// A copy of the train without its reservations. Blocked seats and closed
+    /// coaches stay as they are.
+    pub fn copy_structure(&self) -> Train {
+        let mut train = self.clone().with_archived(false);
+        train.reset();
+        train
+    }
+
+    pub fn reset(&mut self) {
+        let _span = tracing::info_span!("reset").entered();
+        for resource in self.resources.values_mut() {
+            resource.set_booking_reference(None);
+        }
+        if let Some(standing) = &mut self.standing {
+            standing.reserved = 0;
+        }
+        for seat in self.seats.values_mut() {
+            seat.state.reset();
+        }
+        for free_seating in self
+            .coaches
+            .values_mut()
+            .filter_map(|coach| coach.free_seating.as_mut())
+        {
+            free_seating.reserved = 0;
+        }
+        self.booked.clear();
+        self.booked_places.clear();
+        self.paid.clear();
+        self.fares.reset();
+        self.occupancy.reset();
+    }
+}
+
+impl TrainDataService {
+    pub fn new(trains: TrainsData) -> TrainDataService {
+        TrainDataService {
+            trains,
+            store: None,
+            cache: None,
+            published: Arc::default(),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Expire holds by this clock rather than the system clock.
+    pub fn with_clock(self, clock: Arc<dyn Clock>) -> TrainDataService {
+        TrainDataService { clock, ..self }
+    }
+
+    /// The copies of trains that can be read without going through this
+    /// service.
+    pub fn published(&self) -> Arc<PublishedTrains> {
+        self.published.clone()
+    }
+
+    /// Start over with other trains, like after an import. These aren't
+    /// backed by the store anymore.
+    pub fn replace(&mut self, trains: TrainsData) {
+        self.published.withdraw_all();
+        *self = TrainDataService {
+            published: self.published.clone(),
+            clock: self.clock.clone(),
+            ..TrainDataService::new(trains)
+        };
+    }
+
+    pub fn with_store(self, store: Box<dyn TrainStore>) -> TrainDataService {
+        TrainDataService {
+            store: Some(store),
+            ..self
+        }
+    }
+
+    /// Keep at most as many trains loaded as the cache fits, dropping the least
+    /// recently used ones. Changed trains are saved to the store first.
+    pub fn with_cache(self, cache: TrainCache) -> TrainDataService {
+        TrainDataService {
+            cache: Some(cache),
+            ..self
+        }
+    }
+
+    /// The trains that are loaded.
+    pub fn trains(&self) -> &TrainsData {
+        &self.trains
+    }
+
+    // fetch a train from the store if it isn't loaded yet
+    fn load(&mut self, train_id: &TrainId) -> Result<(), Error> {
+        if self.trains.0.contains_key(train_id) {
+            return Ok(());
+        }
+        let Some(store) = &self.store else {
+            return Ok(());
+        };
+        let train = store
+            .fetch(train_id)
+            .map_err(|err| Error::StoreFailed(err.to_string()))?;
+        if let Some(train) = train {
+            tracing::info!(train_id = train_id.as_str(), "loaded train");
+            self.trains.0.insert(train_id.clone(), train);
+            if let Some(cache) = &mut self.cache {
+                cache.loaded(train_id);
+            }
+        }
+        Ok(())
+    }
+
+    // note the use of a train, and drop the least recently used trains that
+    // don't fit anymore
+    fn use_train(&mut self, train_id: &TrainId, changed: bool) {
+        let (Some(cache), Some(store)) = (&mut self.cache, &self.store) else {
+            return;
+        };
+        if changed {
+            cache.changed(train_id);
+        } else {
+            cache.used(train_id);
+        }
+        while let Some(evict) = cache.to_evict(self.trains.0.len(), train_id).cloned() {
+            if cache.is_dirty(&evict) {
+                if let Err(err) = store.save(&evict, &self.trains.0[&evict]) {
+                    // it stays loaded, so nothing is lost
+                    tracing::warn!(train_id = evict.as_str(), %err, "could not save train");
+                    break;
+                }
+            }
+            tracing::info!(train_id = evict.as_str(), "evicted train");
+            self.trains.0.remove(&evict);
+            self.published.withdraw(&evict);
+            cache.evicted(&evict);
+        }
+        cache.set_loaded(self.trains.0.len());
+    }
+
+    /// Load all trains in the store, like before exporting them. This may load
+    /// more trains than the cache fits, until the next train is used.
+    pub fn load_all(&mut self) -> Result<(), Error> {
+        let Some(store) = &self.store else {
+            return Ok(());
+        };
+        let train_ids = store
+            .train_ids()
+            .map_err(|err| Error::StoreFailed(err.to_string()))?;
+        for train_id in train_ids {
+            self.load(&train_id)?;
+        }
+        Ok(())
+    }
+
+    /// The ids of all trains, loaded or not.
+    pub fn train_ids(&self) -> Result<Vec<TrainId>, Error> {
+        let mut train_ids: Vec<TrainId> = self.trains.0.keys().cloned().collect();
+        if let Some(store) = &self.store {
+            let stored = store
+                .train_ids()
+                .map_err(|err| Error::StoreFailed(err.to_string()))?;
+            train_ids.extend(
+                stored
+                    .into_iter()
+                    .filter(|train_id| !self.trains.0.contains_key(train_id)),
+            );
+        }
+        Ok(train_ids)
+    }
+
+    // free the seats of a loaded train whose holds have expired, returning
+    // whether there were any
+    fn expire_holds(&mut self, train_id: &TrainId) -> bool {
+        let now = self.clock.now();
+        let Some(train) = self.trains.0.get_mut(train_id) else {
+            return false;
+        };
+        if train.expire_holds(now).is_empty() {
+            return false;
+        }
+        self.published.withdraw(train_id);
+        true
+    }
+
+    /// Free the seats whose holds have expired on all loaded trains, like
+    /// after the clock moved, returning the trains that changed. Trains in
+    /// the store have theirs expired when they're loaded.
+    pub fn expire_all_holds(&mut self) -> Vec<TrainId> {
+        let mut train_ids: Vec<TrainId> = self.trains.0.keys().cloned().collect();
+        train_ids.retain(|train_id| self.expire_holds(train_id));
+        for train_id in &train_ids {
+            if let Some(cache) = &mut self.cache {
+                cache.changed(train_id);
+            }
+        }
+        train_ids.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+        train_ids
+    }
+
+    /// A train as it is now, so with the seats of expired holds free.
+    pub fn train(&mut self, train_id: &TrainId) -> Result<&Train, Error> {
+        self.load(train_id)?;
+        if self.trains.0.contains_key(train_id) {
+            let expired = self.expire_holds(train_id);
+            self.use_train(train_id, expired);
+        }
+        self.trains
+            .0
+            .get(train_id)
+            .ok_or(Error::TrainDoesNotExist(train_id.clone()))
+    }
+
+    pub fn add_train(&mut self, train_id: TrainId, train: Train) -> Result<(), Error> {
+        self.load(&train_id)?;
+        if self.trains.0.contains_key(&train_id) {
+            return Err(Error::TrainAlreadyExists(train_id));
+        }
+        self.trains.0.insert(train_id.clone(), train);
+        self.use_train(&train_id, true);
+        Ok(())
+    }
+
+    /// A train to change, with the seats of expired holds free; archived
+    /// trains can't be changed.
+    pub fn train_mut(&mut self, train_id: &TrainId) -> Result<&mut Train, Error> {
+        self.load(train_id)?;
+        if self.trains.0.contains_key(train_id) {
+            self.expire_holds(train_id);
+            self.use_train(train_id, true);
+        }
+        self.published.withdraw(train_id);
+        let train = self
+            .trains
+            .0
+            .get_mut(train_id)
+            .ok_or(Error::TrainDoesNotExist(train_id.clone()))?;
+        if train.archived {
+            return Err(Error::TrainArchived(train_id.clone()));
+        }
+        Ok(train)
+    }
+
+    /// Take a train out of service, or put it back. Archived trains keep
+    /// their reservations, but can't be changed.
+    pub fn set_archived(&mut self, train_id: &TrainId, archived: bool) -> Result<&Train, Error> {
+        self.load(train_id)?;
+        if self.trains.0.contains_key(train_id) {
+            self.use_train(train_id, true);
+        }
+        self.published.withdraw(train_id);
+        let train = self
+            .trains
+            .0
+            .get_mut(train_id)
+            .ok_or(Error::TrainDoesNotExist(train_id.clone()))?;
+        train.archived = archived;
+        Ok(train)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::clock::SimulatedClock;
+    use crate::fixtures::TrainBuilder;
+    use crate::occupancy::Counts;
+    use crate::response::TrainResponse;
+    use crate::rules::{MaxOccupancy, MaxSeatsPerBooking};
+
+    #[test]
+    fn test_train_doesnt_exist() {
+        let mut service = TrainDataService::new(TrainsData::new());
+        let train_id = TrainId::new("doesnt_exist");
+        let train = service.train(&train_id).unwrap_err();
+        assert_eq!(train, Error::TrainDoesNotExist(train_id));
+    }
+
+    #[test]
+    fn test_train_does_exist() {
+        let mut trains = HashMap::new();
+        let train = TrainBuilder::coaches(1)
+            .seats_per_coach(1)
+            .reserve("1A", "123456")
+            .build();
+        let train_id = TrainId::new("train_id");
+        trains.insert(train_id.clone(), train);
+        let mut service = TrainDataService::new(TrainsData(trains));
+        let train = service.train(&train_id).unwrap();
+        assert_eq!(
+            train,
+            &TrainBuilder::coaches(1)
+                .seats_per_coach(1)
+                .reserve("1A", "123456")
+                .build()
+        );
+    }
+
+    #[test]
+    fn test_changes_withdraw_published_train() {
+        let train_id = TrainId::new("train_id");
+        let train = TrainBuilder::coaches(1).seats_per_coach(1).build();
+        let mut service =
+            TrainDataService::new(TrainsData(HashMap::from([(train_id.clone(), train)])));
+        let published = service.published();
+        published.publish(&train_id, service.train(&train_id).unwrap());
+
+        // reading leaves the copy alone, changing withdraws it
+        service.train(&train_id).unwrap();
+        assert!(published.get(&train_id).is_some());
+        service.train_mut(&train_id).unwrap();
+        assert!(published.get(&train_id).is_none());
+
+        published.publish(&train_id, service.train(&train_id).unwrap());
+        service.set_archived(&train_id, true).unwrap();
+        assert!(published.get(&train_id).is_none());
+
+        published.publish(&train_id, service.train(&train_id).unwrap());
+        service.replace(TrainsData::new());
+        assert!(published.get(&train_id).is_none());
+        assert!(Arc::ptr_eq(&published, &service.published()));
+    }
+
+    #[test]
+    fn test_reading_expires_holds() {
+        let train_id = TrainId::new("train_id");
+        let mut train = TrainBuilder::coaches(1).seats_per_coach(2).build();
+        let hold = Reservation {
+            seats: vec![SeatId::new("1A")],
+            booking_reference: BookingReference::new("abc"),
+            accessible: false,
+        };
+        train.hold(&hold, 100, &Rules::default()).unwrap();
+        let clock = SimulatedClock::new(50);
+        let mut service =
+            TrainDataService::new(TrainsData(HashMap::from([(train_id.clone(), train)])))
+                .with_clock(Arc::new(clock.clone()));
+        let published = service.published();
+        let copy = published.publish(&train_id, service.train(&train_id).unwrap());
+        assert!(copy.is_current(99));
+        assert!(!copy.is_current(100));
+
+        assert_eq!(service.expire_all_holds(), vec![]);
+        clock.set(100);
+        let train = service.train(&train_id).unwrap();
+        assert!(train.get(&SeatId::new("1A")).unwrap().is_free());
+        assert_eq!(train.occupancy().train().reserved, 0);
+        assert!(published.get(&train_id).is_none());
+    }
+
+    #[test]
+    fn test_expire_all_holds() {
+        let train_id = TrainId::new("train_id");
+        let mut train = TrainBuilder::coaches(1).seats_per_coach(2).build();
+        let hold = Reservation {
+            seats: vec![SeatId::new("1A")],
+            booking_reference: BookingReference::new("abc"),
+            accessible: false,
+        };
+        train.hold(&hold, 100, &Rules::default()).unwrap();
+        let clock = SimulatedClock::new(50);
+        let mut service =
+            TrainDataService::new(TrainsData(HashMap::from([(train_id.clone(), train)])))
+                .with_clock(Arc::new(clock.clone()));
+        let published = service.published();
+        published.publish(&train_id, service.train(&train_id).unwrap());
+        clock.set(200);
+        assert_eq!(service.expire_all_holds(), vec![train_id.clone()]);
+        assert!(published.get(&train_id).is_none());
+        assert_eq!(service.expire_all_holds(), vec![]);
+    }
+
+    #[test]
+    fn test_add_train() {
+        let mut service = TrainDataService::new(TrainsData::new());
+        let train_id = TrainId::new("new_train");
+        let train = Train::new(HashMap::new());
+        service.add_train(train_id.clone(), train.clone()).unwrap();
+        assert_eq!(service.train(&train_id).unwrap(), &train);
+        assert_eq!(
+            service.add_train(train_id.clone(), train),
+            Err(Error::TrainAlreadyExists(train_id))
+        );
+    }
+
+    #[test]
+    fn test_ids_serialize_as_plain_strings() {
+        let seat_id = SeatId::new("1A");
+        let json = serde_json::to_string(&seat_id).unwrap();
+        assert_eq!(json, r#""1A""#);
+        let deserialized: SeatId = serde_json::from_str(&json).unwrap();
+        assert_eq!(deserialized, seat_id);
+    }
+
+    #[test]
+    fn test_seat_id_parse() {
+        assert_eq!(SeatId::parse("12A").unwrap().as_str(), "12A");
+        assert_eq!(SeatId::parse("1B2").unwrap().as_str(), "1B2");
+        assert_eq!(
+            SeatId::parse("does_not_exist"),
+            Err(InvalidSeatId("does_not_exist".to_string()))
+        );
+        assert!(SeatId::parse("A1").is_err());
+        assert!(SeatId::parse("12").is_err());
+        assert!(SeatId::parse("").is_err());
+    }
+
+    #[test]
+    fn test_seat_id_from_parts() {
+        assert_eq!(SeatId::from_parts("3", "C"), Ok(SeatId::new("3C")));
+        assert!(SeatId::from_parts("", "C").is_err());
+        assert!(SeatId::from_parts("3", "").is_err());
+        assert!(SeatId::from_parts("3x", "C").is_err());
+    }
+
+    #[test]
+    fn test_seat_id_deserialize_invalid() {
+        let result = serde_json::from_str::<SeatId>(r#""does_not_exist""#);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_reserve_seat() {
+        let mut train = TrainBuilder::coaches(1).seats_per_coach(1).build();
+        train
+            .reserve(
+                &Reservation {
+                    seats: vec![SeatId::new("1A")],
+                    booking_reference: BookingReference::new("123456"),
+                    accessible: false,
+                },
+                &Rules::default(),
+            )
+            .unwrap();
+        let seat = train.seats.get(&SeatId::new("1A")).unwrap();
+        assert_eq!(
+            seat.booking_reference(),
+            Some(&BookingReference::new("123456"))
+        );
+        assert_eq!(train.booked_seats(&BookingReference::new("123456")), 1);
+        train.reset();
+        assert_eq!(train.booked_seats(&BookingReference::new("123456")), 0);
+    }
+
+    #[test]
+    fn test_reserve_when_already_reserved() {
+        let mut train = TrainBuilder::coaches(1)
+            .seats_per_coach(1)
+            .reserved(1.0)
+            .build();
+        let result = train.reserve(
+            &Reservation {
+                seats: vec![SeatId::new("1A")],
+                booking_reference: BookingReference::new("new"),
+                accessible: false,
+            },
+            &Rules::default(),
+        );
+        assert_eq!(
+            result,
+            Err(Error::SeatsAlreadyReserved(vec![SeatId::new("1A")], vec![]))
+        );
+    }
+
+    #[test]
+    fn test_reserve_duplicate_seats() {
+        let mut train = TrainBuilder::coaches(1).seats_per_coach(2).build();
+        let before = train.clone();
+        let result = train.reserve(
+            &Reservation {
+                seats: vec![SeatId::new("1A"), SeatId::new("2A"), SeatId::new("1A")],
+                booking_reference: BookingReference::new("abc"),
+                accessible: false,
+            },
+            &Rules::default(),
+        );
+        assert_eq!(result, Err(Error::DuplicateSeats(vec![SeatId::new("1A")])));
+        assert_eq!(train, before);
+    }
+
+    #[test]
+    fn test_reserve_rule_violated() {
+        let mut train = TrainBuilder::coaches(1).seats_per_coach(1).build();
+        let mut rules = Rules::default();
+        rules.add(MaxSeatsPerBooking(0));
+        let result = train.reserve(
+            &Reservation {
+                seats: vec![SeatId::new("1A")],
+                booking_reference: BookingReference::new("123456"),
+                accessible: false,
+            },
+            &rules,
+        );
+        assert!(matches!(
+            result,
+            Err(Error::RuleViolated("max_seats_per_booking", _))
+        ));
+        assert_eq!(
+            train.get(&SeatId::new("1A")).unwrap().booking_reference(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_reserve_accessible_seat() {
+        let mut train = TrainBuilder::coaches(1)
+            .seats_per_coach(2)
+            .seat("1A", |seat| seat.with_accessible(true))
+            .build();
+        let mut reservation = Reservation {
+            seats: vec![SeatId::new("1A")],
+            booking_reference: BookingReference::new("123456"),
+            accessible: false,
+        };
+        assert_eq!(
+            train.reserve(&reservation, &Rules::default()),
+            Err(Error::SeatsAccessible(vec![SeatId::new("1A")]))
+        );
+
+        reservation.accessible = true;
+        train.reserve(&reservation, &Rules::default()).unwrap();
+    }
+
+    #[test]
+    fn test_reserve_accessible_seat_when_otherwise_full() {
+        let mut train = TrainBuilder::coaches(1)
+            .seats_per_coach(2)
+            .seat("1A", |seat| seat.with_accessible(true))
+            .reserve("2A", "existing")
+            .build();
+        train
+            .reserve(
+                &Reservation {
+                    seats: vec![SeatId::new("1A")],
+                    booking_reference: BookingReference::new("123456"),
+                    accessible: false,
+                },
+                &Rules::default(),
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn test_block_seat() {
+        let mut train = TrainBuilder::coaches(1).seats_per_coach(2).build();
+        train.block(&SeatId::new("1A"), "crew".to_string()).unwrap();
+        assert_eq!(
+            train.get(&SeatId::new("1A")).unwrap().blocked(),
+            Some("crew")
+        );
+        assert_eq!(train.occupancy().train().total, 1);
+
+        let reservation = Reservation {
+            seats: vec![SeatId::new("1A")],
+            booking_reference: BookingReference::new("123456"),
+            accessible: false,
+        };
+        assert_eq!(
+            train.reserve(&reservation, &Rules::default()),
+            Err(Error::SeatsBlocked(vec![SeatId::new("1A")]))
+        );
+
+        // blocking twice doesn't count the seat twice
+        train
+            .block(&SeatId::new("1A"), "broken".to_string())
+            .unwrap();
+        assert_eq!(train.occupancy().train().total, 1);
+        train.reset();
+        assert_eq!(
+            train.get(&SeatId::new("1A")).unwrap().blocked(),
+            Some("broken")
+        );
+
+        train.unblock(&SeatId::new("1A")).unwrap();
+        assert_eq!(train.occupancy().train().total, 2);
+        train.reserve(&reservation, &Rules::default()).unwrap();
+        assert_eq!(
+            train.block(&SeatId::new("1A"), "crew".to_string()),
+            Err(Error::SeatsAlreadyReserved(vec![SeatId::new("1A")], vec![]))
+        );
+        assert_eq!(
+            train.unblock(&SeatId::new("9A")),
+            Err(Error::SeatsDoNotExist(vec![SeatId::new("9A")]))
+        );
+    }
+
+    #[test]
+    fn test_unblock_seat_in_closed_coach() {
+        let mut train = TrainBuilder::coaches(2).seats_per_coach(1).build();
+        train.block(&SeatId::new("1B"), "crew".to_string()).unwrap();
+        train.set_closed("B", true).unwrap();
+        train.unblock(&SeatId::new("1B")).unwrap();
+        assert_eq!(train.occupancy().train().total, 1);
+        assert_eq!(train.occupancy().coaches().count(), 1);
+
+        train.set_closed("B", false).unwrap();
+        assert_eq!(train.occupancy().train().total, 2);
+    }
+
+    #[test]
+    fn test_close_coach() {
+        let mut train = TrainBuilder::coaches(2)
+            .seats_per_coach(1)
+            .reserve("1B", "existing")
+            .build();
+        train.set_closed("B", true).unwrap();
+        assert!(train.is_closed("B"));
+        assert_eq!(
+            train.occupancy().train(),
+            Counts {
+                reserved: 0,
+                total: 1
+            }
+        );
+        train.set_closed("A", true).unwrap();
+        let result = train.reserve(
+            &Reservation {
+                seats: vec![SeatId::new("1A")],
+                booking_reference: BookingReference::new("123456"),
+                accessible: false,
+            },
+            &Rules::default(),
+        );
+        assert_eq!(result, Err(Error::CoachesClosed(vec!["A".to_string()])));
+
+        train.set_closed("A", false).unwrap();
+        train.set_closed("B", false).unwrap();
+        assert_eq!(train.coaches().count(), 0);
+        assert_eq!(train.occupancy().train().total, 2);
+        assert_eq!(
+            train.set_closed("C", true),
+            Err(Error::CoachDoesNotExist("C".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_reserve_places() {
+        let abc = BookingReference::new("abc");
+        let rules = Rules::default();
+        let mut train = TrainBuilder::coaches(1)
+            .seats_per_coach(1)
+            .build()
+            .with_coaches(HashMap::from([(
+                "F".to_string(),
+                Coach {
+                    free_seating: Some(Capacity {
+                        capacity: 10,
+                        reserved: 2,
+                    }),
+                    ..Coach::default()
+                },
+            )]));
+        assert_eq!(
+            train.occupancy().train(),
+            Counts {
+                reserved: 2,
+                total: 11
+            }
+        );
+        assert_eq!(train.reserve_places("F", 3, &abc, &rules), Ok(5));
+        assert_eq!(train.occupancy().train().reserved, 5);
+        assert_eq!(train.booked_places(&abc), 3);
+        assert_eq!(
+            train.reserve_places("F", 6, &abc, &rules),
+            Err(Error::NotEnoughPlaces("F".to_string(), 5))
+        );
+        assert_eq!(
+            train.reserve_places("A", 1, &abc, &rules),
+            Err(Error::NotFreeSeating("A".to_string()))
+        );
+        // the places are released with the booking
+        assert_eq!(
+            train.cancel(&abc),
+            Ok(Cancellation {
+                seats: Vec::new(),
+                places: 3,
+                resources: Vec::new(),
+                paid: 0,
+            })
+        );
+        assert_eq!(train.occupancy().train().reserved, 2);
+        assert_eq!(train.reserve_places("F", 8, &abc, &rules), Ok(0));
+
+        train.reset();
+        assert_eq!(train.reserve_places("F", 10, &abc, &rules), Ok(0));
+
+        train.set_closed("F", true).unwrap();
+        assert_eq!(train.occupancy().train().total, 1);
+        assert_eq!(
+            train.reserve_places("F", 1, &abc, &rules),
+            Err(Error::CoachesClosed(vec!["F".to_string()]))
+        );
+    }
+
+    #[test]
+    fn test_bookings_round_trip() {
+        let abc = BookingReference::new("abc");
+        let rules = Rules::default();
+        let mut train: Train = serde_json::from_value(serde_json::json!({
+            "seats": { "1A": { "seat_number": "1", "coach": "A" } },
+            "coaches": { "F": { "free_seating": { "capacity": 10 } } },
+            "standing": { "capacity": 5 },
+            "fares": [{ "name": "saver", "price": 1900 }],
+        }))
+        .unwrap();
+        train
+            .reserve(
+                &Reservation {
+                    seats: vec![SeatId::new("1A")],
+                    booking_reference: abc.clone(),
+                    accessible: false,
+                },
+                &rules,
+            )
+            .unwrap();
+        train.reserve_places("F", 3, &abc, &rules).unwrap();
+        train
+            .reserve_standing(2, &BookingReference::new("def"), &rules)
+            .unwrap();
+
+        let value = serde_json::to_value(TrainResponse::from(&train)).unwrap();
+        assert_eq!(
+            value["bookings"],
+            serde_json::json!([
+                { "booking_reference": "abc", "places": { "F": 3 }, "paid": 1900 },
+                { "booking_reference": "def", "standing": 2 },
+            ])
+        );
+        let mut reloaded: Train = serde_json::from_value(value).unwrap();
+        assert_eq!(reloaded, train);
+        assert_eq!(reloaded.cancel(&abc).unwrap().places, 3);
+
+        let err = serde_json::from_value::<Train>(serde_json::json!({
+            "seats": {},
+            "bookings": [{ "booking_reference": "abc", "places": { "A": 1 } }],
+        }))
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "booking abc has places in coach A, which isn't free seating"
+        );
+
+        let err = serde_json::from_value::<Train>(serde_json::json!({
+            "seats": {},
+            "coaches": { "F": { "free_seating": { "capacity": 2, "reserved": 3 } } },
+        }))
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "coach F has room for 2 places, but 3 are reserved"
+        );
+        let err = serde_json::from_value::<Train>(serde_json::json!({
+            "seats": {},
+            "standing": { "capacity": 2, "reserved": 5 },
+        }))
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "standing room has room for 2 places, but 5 are reserved"
+        );
+    }
+
+    #[test]
+    fn test_reserve_standing() {
+        let abc = BookingReference::new("abc");
+        let rules = Rules::default();
+        let mut train = Train::new(HashMap::new()).with_standing(Some(Capacity {
+            capacity: 5,
+            reserved: 1,
+        }));
+        assert_eq!(train.reserve_standing(3, &abc, &rules), Ok(1));
+        assert_eq!(
+            train.occupancy().standing(),
+            Some(Counts {
+                reserved: 4,
+                total: 5
+            })
+        );
+        assert_eq!(
+            train.reserve_standing(2, &abc, &rules),
+            Err(Error::NotEnoughStandingRoom(1))
+        );
+        assert_eq!(train.cancel(&abc).unwrap().places, 3);
+        assert_eq!(train.standing().unwrap().reserved, 1);
+        assert_eq!(train.occupancy().standing().unwrap().reserved, 1);
+        // standing room doesn't count as seats
+        assert_eq!(train.occupancy().train().total, 0);
+        train.reset();
+        assert_eq!(train.reserve_standing(5, &abc, &rules), Ok(0));
+
+        let mut train = Train::new(HashMap::new());
+        assert_eq!(
+            train.reserve_standing(1, &abc, &rules),
+            Err(Error::NoStandingRoom)
+        );
+    }
+
+    #[test]
+    fn test_reserve_resources() {
+        let resources = HashMap::from([
+            (
+                "bike1".to_string(),
+                Resource::new(ResourceKind::Bicycle, "A".to_string()),
+            ),
+            (
+                "bike2".to_string(),
+                Resource::new(ResourceKind::Bicycle, "B".to_string()),
+            ),
+            (
+                "rack1".to_string(),
+                Resource::new(ResourceKind::Luggage, "A".to_string()),
+            ),
+        ]);
+        let mut train = TrainBuilder::coaches(2)
+            .seats_per_coach(1)
+            .reserve("1B", "abc")
+            .build()
+            .with_resources(resources);
+
+        // the bicycle goes in the coach where the passenger sits
+        assert_eq!(
+            train.reserve_resources(ResourceKind::Bicycle, 1, &BookingReference::new("abc")),
+            Ok(vec!["bike2".to_string()])
+        );
+        assert_eq!(
+            train.reserve_resources(ResourceKind::Bicycle, 2, &BookingReference::new("def")),
+            Err(Error::NotEnoughResources(ResourceKind::Bicycle, 1))
+        );
+        let occupancy = train.resource_occupancy(ResourceKind::Bicycle);
+        assert_eq!(
+            occupancy.train(),
+            Counts {
+                reserved: 1,
+                total: 2
+            }
+        );
+        assert_eq!(occupancy.coach("B").unwrap().reserved, 1);
+        // resources don't count as seats
+        assert_eq!(train.occupancy().train().total, 2);
+
+        // cancelling the booking releases its resources too
+        train
+            .reserve_resources(ResourceKind::Luggage, 1, &BookingReference::new("def"))
+            .unwrap();
+        assert_eq!(
+            train.cancel(&BookingReference::new("abc")),
+            Ok(Cancellation {
+                seats: vec![SeatId::new("1B")],
+                places: 0,
+                resources: vec!["bike2".to_string()],
+                paid: 0,
+            })
+        );
+        assert_eq!(
+            train
+                .resource_occupancy(ResourceKind::Bicycle)
+                .train()
+                .reserved,
+            0
+        );
+        assert_eq!(
+            train.cancel(&BookingReference::new("def")),
+            Ok(Cancellation {
+                seats: Vec::new(),
+                places: 0,
+                resources: vec!["rack1".to_string()],
+                paid: 0,
+            })
+        );
+
+        train
+            .reserve_resources(ResourceKind::Bicycle, 1, &BookingReference::new("abc"))
+            .unwrap();
+        train.reset();
+        assert_eq!(
+            train
+                .resource_occupancy(ResourceKind::Bicycle)
+                .train()
+                .reserved,
+            0
+        );
+    }
+
+    // coach A is second class with 1A and 2A reserved; coach B is first class
+    fn upgrade_train() -> Train {
+        TrainBuilder::coaches(2)
+            .seats_per_coach(2)
+            .seat("1A", |seat| seat.with_position(SeatPosition::Window))
+            .seat("2A", |seat| seat.with_position(SeatPosition::Aisle))
+            .seat("1B", |seat| seat.with_position(SeatPosition::Aisle))
+            .seat("2B", |seat| seat.with_position(SeatPosition::Window))
+            .reserve("1A", "abc")
+            .reserve("2A", "abc")
+            .build()
+            .with_coaches(HashMap::from([(
+                "B".to_string(),
+                Coach {
+                    class: Class::First,
+                    ..Coach::default()
+                },
+            )]))
+    }
+
+    #[test]
+    fn test_upgrade() {
+        let mut train = upgrade_train();
+        assert_eq!(
+            train.upgrade(&BookingReference::new("abc")),
+            Ok(vec![
+                (SeatId::new("1A"), SeatId::new("2B")),
+                (SeatId::new("2A"), SeatId::new("1B")),
+            ])
+        );
+        assert!(train.get(&SeatId::new("1A")).unwrap().is_free());
+        assert_eq!(train.occupancy().coach("A").unwrap().reserved, 0);
+        assert_eq!(train.occupancy().coach("B").unwrap().reserved, 2);
+        assert_eq!(train.booked_seats(&BookingReference::new("abc")), 2);
+        assert_eq!(
+            train.upgrade(&BookingReference::new("abc")),
+            Err(Error::NothingToUpgrade(BookingReference::new("abc")))
+        );
+        assert_eq!(
+            train.upgrade(&BookingReference::new("def")),
+            Err(Error::BookingDoesNotExist(BookingReference::new("def")))
+        );
+    }
+
+    #[test]
+    fn test_upgrade_unavailable() {
+        let mut train = upgrade_train();
+        train
+            .block(&SeatId::new("2B"), "broken".to_string())
+            .unwrap();
+        assert_eq!(
+            train.upgrade(&BookingReference::new("abc")),
+            Err(Error::UpgradeUnavailable(vec![MissingSeats {
+                position: Some(SeatPosition::Window),
+                accessible: false,
+                seats: 1,
+            }]))
+        );
+        // nothing moved
+        assert_eq!(train.booked_seats(&BookingReference::new("abc")), 2);
+        assert!(!train.get(&SeatId::new("1A")).unwrap().is_free());
+    }
+
+    #[test]
+    fn test_cancel() {
+        let mut train = upgrade_train();
+        assert_eq!(
+            train.cancel(&BookingReference::new("abc")),
+            Ok(Cancellation {
+                seats: vec![SeatId::new("1A"), SeatId::new("2A")],
+                places: 0,
+                resources: Vec::new(),
+                paid: 0,
+            })
+        );
+        assert!(train.get(&SeatId::new("1A")).unwrap().is_free());
+        assert_eq!(train.occupancy().train().reserved, 0);
+        assert_eq!(
+            train.cancel(&BookingReference::new("abc")),
+            Err(Error::BookingDoesNotExist(BookingReference::new("abc")))
+        );
+    }
+
+    #[test]
+    fn test_archived_train_cant_be_changed() {
+        let train_id = TrainId::new("train_id");
+        let train = TrainBuilder::coaches(1).seats_per_coach(1).build();
+        let mut service =
+            TrainDataService::new(TrainsData(HashMap::from([(train_id.clone(), train)])));
+        assert!(service.set_archived(&train_id, true).unwrap().is_archived());
+        assert_eq!(
+            service.train_mut(&train_id).unwrap_err(),
+            Error::TrainArchived(train_id.clone())
+        );
+        assert!(service.train(&train_id).is_ok());
+        service.set_archived(&train_id, false).unwrap();
+        assert!(service.train_mut(&train_id).is_ok());
+    }
+
+    #[test]
+    fn test_patch_seat() {
+        let seat_id = SeatId::new("1A");
+        let mut train = TrainBuilder::coaches(1)
+            .seats_per_coach(1)
+            .seat("1A", |seat| seat.with_position(SeatPosition::Window))
+            .build();
+        let patch = |json: &str| serde_json::from_str::<SeatPatch>(json).unwrap();
+
+        train
+            .patch_seat(
+                &seat_id,
+                &patch(r#"{"accessible": true, "blocked": "crew"}"#),
+            )
+            .unwrap();
+        let seat = train.get(&seat_id).unwrap();
+        assert!(seat.is_accessible());
+        assert_eq!(seat.blocked(), Some("crew"));
+        assert_eq!(seat.position(), Some(SeatPosition::Window));
+        assert_eq!(train.occupancy().train().total, 0);
+
+        train
+            .patch_seat(&seat_id, &patch(r#"{"position": null, "blocked": null}"#))
+            .unwrap();
+        let seat = train.get(&seat_id).unwrap();
+        assert!(seat.is_accessible());
+        assert_eq!((seat.blocked(), seat.position()), (None, None));
+        assert_eq!(train.occupancy().train().total, 1);
+
+        assert!(serde_json::from_str::<SeatPatch>(r#"{"booking_reference": null}"#).is_err());
+    }
+
+    #[test]
+    fn test_seat_state_transitions() {
+        let seat_id = SeatId::new("1A");
+        let (abc, def) = (BookingReference::new("abc"), BookingReference::new("def"));
+        let mut state = SeatState::Free;
+        state.hold(&seat_id, &abc, 100).unwrap();
+        // another booking can't take a held seat, but the same one can
+        assert_eq!(
+            state.reserve(&seat_id, &def),
+            Err(Error::SeatsAlreadyReserved(vec![seat_id.clone()], vec![]))
+        );
+        state.reserve(&seat_id, &abc).unwrap();
+        assert_eq!(
+            state,
+            SeatState::Reserved {
+                booking_reference: abc.clone()
+            }
+        );
+        assert_eq!(
+            state.block(&seat_id, "crew".to_string()),
+            Err(Error::SeatsAlreadyReserved(vec![seat_id.clone()], vec![]))
+        );
+        assert!(!state.expire(1000));
+        assert_eq!(
+            state.release(&def),
+            Err(Error::BookingDoesNotExist(def.clone()))
+        );
+        state.release(&abc).unwrap();
+
+        state.block(&seat_id, "crew".to_string()).unwrap();
+        assert_eq!(
+            state.hold(&seat_id, &abc, 100),
+            Err(Error::SeatsBlocked(vec![seat_id.clone()]))
+        );
+        state.reset();
+        assert!(state.unblock());
+        assert!(!state.unblock());
+        assert_eq!(state, SeatState::Free);
+    }
+
+    #[test]
+    fn test_hold_seats() {
+        let mut train = TrainBuilder::coaches(1).seats_per_coach(2).build();
+        let reservation = |booking_reference: &str| Reservation {
+            seats: vec![SeatId::new("1A")],
+            booking_reference: BookingReference::new(booking_reference),
+            accessible: false,
+        };
+        train
+            .hold(&reservation("abc"), 100, &Rules::default())
+            .unwrap();
+        let seat = train.get(&SeatId::new("1A")).unwrap();
+        assert_eq!(seat.held_until(), Some(100));
+        assert_eq!(train.occupancy().train().reserved, 1);
+        assert_eq!(
+            train.reserve(&reservation("def"), &Rules::default()),
+            Err(Error::SeatsAlreadyReserved(
+                vec![SeatId::new("1A")],
+                vec![SeatId::new("2A")]
+            ))
+        );
+        assert_eq!(train.expire_holds(99), vec![]);
+        assert_eq!(train.expire_holds(100), vec![SeatId::new("1A")]);
+        assert_eq!(train.occupancy().train().reserved, 0);
+
+        // reserving a held seat keeps it counted once
+        train
+            .hold(&reservation("abc"), 200, &Rules::default())
+            .unwrap();
+        train
+            .reserve(&reservation("abc"), &Rules::default())
+            .unwrap();
+        assert_eq!(train.occupancy().train().reserved, 1);
+        assert_eq!(train.booked_seats(&BookingReference::new("abc")), 1);
+        assert_eq!(train.expire_holds(1000), vec![]);
+    }
+
+    #[test]
+    fn test_hold_checks_rules() {
+        let mut train = TrainBuilder::coaches(1).seats_per_coach(10).build();
+        let mut rules = Rules::default();
+        rules.add(MaxOccupancy(0.7));
+        let reservation = |seat_ids: &[&str], booking_reference: &str| Reservation {
+            seats: seat_ids
+                .iter()
+                .map(|seat_id| SeatId::new(seat_id))
+                .collect(),
+            booking_reference: BookingReference::new(booking_reference),
+            accessible: false,
+        };
+        let seven = ["1A", "2A", "3A", "4A", "5A", "6A", "7A"];
+        train
+            .hold(&reservation(&seven, "abc"), 100, &rules)
+            .unwrap();
+        // the held seats aren't counted twice
+        train.reserve(&reservation(&seven, "abc"), &rules).unwrap();
+        assert_eq!(train.occupancy().train().reserved, 7);
+        assert!(matches!(
+            train.hold(&reservation(&["8A"], "def"), 100, &rules),
+            Err(Error::RuleViolated("max_occupancy", _))
+        ));
+        assert_eq!(train.occupancy().train().reserved, 7);
+
+        let mut rules = Rules::default();
+        rules.add(MaxSeatsPerBooking(2));
+        train
+            .hold(&reservation(&["8A", "9A"], "def"), 100, &rules)
+            .unwrap();
+        assert!(matches!(
+            train.hold(&reservation(&["10A"], "def"), 100, &rules),
+            Err(Error::RuleViolated("max_seats_per_booking", _))
+        ));
+        train
+            .reserve(&reservation(&["8A", "9A"], "def"), &rules)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_deserialize_seat_state() {
+        let seat = |json: &str| serde_json::from_str::<Seat>(json);
+        let held = seat(
+            r#"{"seat_number": "1", "coach": "A", "booking_reference": "abc", "held_until": 100}"#,
+        )
+        .unwrap();
+        assert_eq!(held.held_until(), Some(100));
+        assert_eq!(
+            held.booking_reference(),
+            Some(&BookingReference::new("abc"))
+        );
+        let err = seat(
+            r#"{"seat_number": "1", "coach": "A", "booking_reference": "abc", "blocked": "crew"}"#,
+        )
+        .unwrap_err();
+        assert_eq!(err.to_string(), "a seat can't be both reserved and blocked");
+    }
+
+    #[test]
+    fn test_deserialize_neighbours() {
+        let train: Train = serde_json::from_str(
+            r#"{"seats": {
+                "1A": {"seat_number": "1", "coach": "A", "neighbours": ["3A"]},
+                "2A": {"seat_number": "2", "coach": "A"},
+                "3A": {"seat_number": "3", "coach": "A"}
+            }}"#,
+        )
+        .unwrap();
+        let adjacency = train.adjacency();
+        assert!(adjacency.are_adjacent(&SeatId::new("3A"), &SeatId::new("1A")));
+        assert!(!adjacency.are_adjacent(&SeatId::new("1A"), &SeatId::new("2A")));
+
+        let err = serde_json::from_str::<Train>(
+            r#"{"seats": {"1A": {"seat_number": "1", "coach": "A", "neighbours": ["9Z"]}}}"#,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "neighbouring seats 9Z (next to 1A) do not exist"
+        );
+    }
+
+    #[test]
+    fn test_deserialize_layout() {
+        let train = |layout: &str| {
+            serde_json::from_str::<Train>(&format!(
+                r#"{{"seats": {{
+                    "1A": {{"seat_number": "1", "coach": "A"}},
+                    "5A": {{"seat_number": "5", "coach": "A"}}
+                }},
+                "coaches": {{"A": {{"layout": {}}}}}}}"#,
+                layout
+            ))
+            .map_err(|err| err.to_string())
+        };
+        assert!(train(r#"{"rows": 2, "columns": 3}"#).is_ok());
+        assert_eq!(
+            train(r#"{"rows": 2, "columns": 2}"#),
+            Err("seats 5A don't fit in the layout of coach A".to_string())
+        );
+        assert_eq!(
+            train(r#"{"rows": 2, "columns": 3, "aisle_after": 3}"#),
+            Err("coach A: the aisle has to be between two columns".to_string())
+        );
+    }
+
+    #[test]
+    fn test_change_seats() {
+        let mut train = TrainBuilder::coaches(1)
+            .seats_per_coach(4)
+            .reserve("1A", "abc")
+            .reserve("2A", "abc")
+            .reserve("4A", "def")
+            .build();
+        let add = |seats: &[&str]| Reservation {
+            seats: seats.iter().map(|seat_id| SeatId::new(seat_id)).collect(),
+            booking_reference: BookingReference::new("abc"),
+            accessible: false,
+        };
+        // a taken seat leaves everything as it was
+        assert_eq!(
+            train.change_seats(&[SeatId::new("2A")], &add(&["4A"]), &Rules::default()),
+            Err(Error::SeatsAlreadyReserved(
+                vec![SeatId::new("4A")],
+                vec![SeatId::new("3A")]
+            ))
+        );
+        assert!(!train.get(&SeatId::new("2A")).unwrap().is_free());
+
+        // and so does a seat that's asked for twice
+        let before = train.clone();
+        assert_eq!(
+            train.change_seats(&[SeatId::new("2A")], &add(&["3A", "3A"]), &Rules::default()),
+            Err(Error::DuplicateSeats(vec![SeatId::new("3A")]))
+        );
+        assert_eq!(train, before);
+
+        // the rules see the released seat as free already
+        let mut rules = Rules::default();
+        rules.add(MaxSeatsPerBooking(2));
+        train
+            .change_seats(&[SeatId::new("2A")], &add(&["3A"]), &rules)
+            .unwrap();
+        assert!(train.get(&SeatId::new("2A")).unwrap().is_free());
+        assert_eq!(
+            train.get(&SeatId::new("3A")).unwrap().booking_reference(),
+            Some(&BookingReference::new("abc"))
+        );
+        assert_eq!(train.booked_seats(&BookingReference::new("abc")), 2);
+        assert_eq!(train.occupancy().train().reserved, 3);
+        assert_eq!(
+            train.change_seats(&[], &add(&["2A"]), &rules),
+            Err(Error::RuleViolated(
+                "max_seats_per_booking",
+                "Booking reference abc may hold at most 2 seats on this train".to_string()
+            ))
+        );
+        assert_eq!(
+            train.change_seats(&[SeatId::new("4A")], &add(&[]), &rules),
+            Err(Error::BookingDoesNotExist(BookingReference::new("abc")))
         );
     }
 }
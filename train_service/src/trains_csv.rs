@@ -0,0 +1,211 @@
+use std::collections::HashMap;
+
+use crate::booking_reference::BookingReference;
+use crate::train::{Seat, SeatId, Train, TrainId, TrainsData};
+
+/// Reads trains from CSV with one row per seat, like this:
+///
+/// ```text
+/// train_id,coach,seat_number,booking_reference
+/// local_1000,A,1,
+/// local_1000,A,2,75bcd15
+/// ```
+///
+/// The first row names the columns, which may come in any order. The
+/// `booking_reference` column is optional; an empty value means the seat is
+/// free.
+pub fn parse_trains(input: &str) -> Result<TrainsData, CsvError> {
+    let mut lines = input
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line))
+        .filter(|(_, line)| !line.trim().is_empty());
+
+    let (_, header) = lines.next().ok_or(CsvError {
+        line: 1,
+        message: "missing header row".to_string(),
+    })?;
+    let columns = Columns::new(&parse_row(header, 1)?)?;
+
+    let mut seats_by_train: HashMap<TrainId, HashMap<SeatId, Seat>> = HashMap::new();
+    for (line, row) in lines {
+        let fields = parse_row(row, line)?;
+        if fields.len() != columns.len {
+            return Err(CsvError {
+                line,
+                message: format!("expected {} fields, got {}", columns.len, fields.len()),
+            });
+        }
+        let train_id = TrainId::new(fields[columns.train_id].as_str());
+        let coach = &fields[columns.coach];
+        let seat_number = &fields[columns.seat_number];
+        let seat_id = SeatId::from_parts(seat_number, coach).map_err(|err| CsvError {
+            line,
+            message: err.to_string(),
+        })?;
+        let booking_reference = columns
+            .booking_reference
+            .map(|index| &fields[index])
+            .filter(|reference| !reference.is_empty())
+            .map(|reference| BookingReference::new(reference.as_str()));
+        let seat =
+            Seat::new(seat_number.clone(), coach.clone()).with_booking_reference(booking_reference);
+
+        let seats = seats_by_train.entry(train_id.clone()).or_default();
+        if seats.insert(seat_id.clone(), seat).is_some() {
+            return Err(CsvError {
+                line,
+                message: format!(
+                    "seat {} of train {} appears more than once",
+                    seat_id, train_id
+                ),
+            });
+        }
+    }
+
+    Ok(TrainsData::from_trains(
+        seats_by_train
+            .into_iter()
+            .map(|(train_id, seats)| (train_id, Train::new(seats)))
+            .collect(),
+    ))
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct CsvError {
+    pub line: usize,
+    pub message: String,
+}
+
+struct Columns {
+    len: usize,
+    train_id: usize,
+    coach: usize,
+    seat_number: usize,
+    booking_reference: Option<usize>,
+}
+
+impl Columns {
+    fn new(header: &[String]) -> Result<Self, CsvError> {
+        let position = |name: &str| header.iter().position(|column| column == name);
+        let required = |name: &str| {
+            position(name).ok_or_else(|| CsvError {
+                line: 1,
+                message: format!("missing column {}", name),
+            })
+        };
+        Ok(Columns {
+            len: header.len(),
+            train_id: required("train_id")?,
+            coach: required("coach")?,
+            seat_number: required("seat_number")?,
+            booking_reference: position("booking_reference"),
+        })
+    }
+}
+
+// split a row into fields, handling double-quoted fields the way
+// spreadsheets write them
+fn parse_row(row: &str, line: usize) -> Result<Vec<String>, CsvError> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut chars = row.chars().peekable();
+    let mut quoted = false;
+    while let Some(c) = chars.next() {
+        match (c, quoted) {
+            ('"', false) if field.trim().is_empty() => {
+                field.clear();
+                quoted = true;
+            }
+            ('"', true) if chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            ('"', true) => quoted = false,
+            (',', false) => fields.push(std::mem::take(&mut field).trim().to_string()),
+            (c, _) => field.push(c),
+        }
+    }
+    if quoted {
+        return Err(CsvError {
+            line,
+            message: "unterminated quoted field".to_string(),
+        });
+    }
+    fields.push(field.trim().to_string());
+    Ok(fields)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_trains() {
+        let trains = parse_trains(
+            "train_id,coach,seat_number,booking_reference\n\
+             local_1000,A,1,\n\
+             local_1000,A,2,75bcd15\n\
+             express_2000,B,1,\n",
+        )
+        .unwrap();
+        let train = trains.get(&TrainId::new("local_1000")).unwrap();
+        assert_eq!(train.occupancy().train().total, 2);
+        assert_eq!(
+            train.get(&SeatId::new("2A")).unwrap().booking_reference(),
+            Some(&BookingReference::new("75bcd15"))
+        );
+        assert_eq!(
+            train.get(&SeatId::new("1A")).unwrap().booking_reference(),
+            None
+        );
+        let train = trains.get(&TrainId::new("express_2000")).unwrap();
+        assert_eq!(train.get(&SeatId::new("1B")).unwrap().coach(), "B");
+    }
+
+    #[test]
+    fn test_parse_trains_columns_in_any_order() {
+        let trains = parse_trains("seat_number,coach,train_id\n1,A,local_1000\n").unwrap();
+        let train = trains.get(&TrainId::new("local_1000")).unwrap();
+        assert!(train.get(&SeatId::new("1A")).is_some());
+    }
+
+    #[test]
+    fn test_parse_trains_missing_column() {
+        let result = parse_trains("train_id,coach\nlocal_1000,A\n");
+        assert_eq!(
+            result.unwrap_err(),
+            CsvError {
+                line: 1,
+                message: "missing column seat_number".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_trains_duplicate_seat() {
+        let result = parse_trains("train_id,coach,seat_number\nt,A,1\nt,A,1\n");
+        assert_eq!(
+            result.unwrap_err(),
+            CsvError {
+                line: 3,
+                message: "seat 1A of train t appears more than once".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_trains_invalid_seat() {
+        let result = parse_trains("train_id,coach,seat_number\nt,A,x\n");
+        assert_eq!(result.unwrap_err().line, 2);
+    }
+
+    #[test]
+    fn test_parse_row_quoted() {
+        assert_eq!(
+            parse_row(r#"a, "b,c" ,"say ""hi""""#, 1).unwrap(),
+            vec!["a", "b,c", r#"say "hi""#]
+        );
+        assert!(parse_row(r#"a,"b"#, 1).is_err());
+    }
+}
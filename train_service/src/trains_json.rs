@@ -0,0 +1,228 @@
+//! Reading trains from a JSON trains file one train at a time, so that a large
+//! fleet never has to be in memory as text or as a JSON value all at once.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Read;
+use std::time::{Duration, Instant};
+
+use serde::de::{DeserializeSeed, Deserializer, Error as _, MapAccess, Visitor};
+use serde_json::Value;
+
+use crate::migrate;
+use crate::train::{Train, TrainId, TrainsData};
+
+// log how far loading got every this many trains
+const PROGRESS_INTERVAL: usize = 1000;
+
+/// Read trains from a JSON trains file of any version. Reading fails when it
+/// takes longer than the budget.
+pub fn read_trains(
+    reader: impl Read,
+    budget: Option<Duration>,
+) -> Result<TrainsData, serde_json::Error> {
+    let mut progress = Progress {
+        started: Instant::now(),
+        budget,
+        count: 0,
+    };
+    let mut deserializer = serde_json::Deserializer::from_reader(reader);
+    let trains = deserializer.deserialize_map(FileVisitor(&mut progress))?;
+    deserializer.end()?;
+    tracing::info!(
+        trains = trains.len(),
+        elapsed = ?progress.started.elapsed(),
+        "loaded trains"
+    );
+    Ok(TrainsData::from_trains(trains))
+}
+
+struct Progress {
+    started: Instant,
+    budget: Option<Duration>,
+    count: usize,
+}
+
+impl Progress {
+    fn add<E: serde::de::Error>(
+        &mut self,
+        trains: &mut HashMap<TrainId, Train>,
+        train_id: String,
+        train: Train,
+    ) -> Result<(), E> {
+        trains.insert(TrainId::new(train_id), train);
+        self.count += 1;
+        if self.count.is_multiple_of(PROGRESS_INTERVAL) {
+            tracing::info!(trains = self.count, "loading trains");
+        }
+        match self.budget {
+            Some(budget) if self.started.elapsed() > budget => Err(E::custom(format!(
+                "loading trains took longer than {:?}",
+                budget
+            ))),
+            _ => Ok(()),
+        }
+    }
+}
+
+// the whole file: either a map of trains, or a document with a version and
+// the trains
+struct FileVisitor<'a>(&'a mut Progress);
+
+impl<'de> Visitor<'de> for FileVisitor<'_> {
+    type Value = HashMap<TrainId, Train>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a map of trains, or a document with a version and trains")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let progress = self.0;
+        let mut trains = HashMap::new();
+        let mut version = None;
+        // trains found at the top, which a versioned document can't have
+        let mut plain = Vec::new();
+        // a "trains" entry from before the version, when it's not yet clear
+        // whether it's a train or all trains
+        let mut pending = None;
+        let mut has_trains = false;
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "version" => {
+                    let value: Value = map.next_value()?;
+                    if value.is_object() {
+                        let train = serde_json::from_value(value).map_err(A::Error::custom)?;
+                        plain.push(key.clone());
+                        progress.add(&mut trains, key, train)?;
+                    } else {
+                        version = Some(migrate::check_version(&value).map_err(A::Error::custom)?);
+                    }
+                }
+                "trains" if version.is_some() => {
+                    has_trains = true;
+                    map.next_value_seed(TrainsSeed {
+                        progress: &mut *progress,
+                        trains: &mut trains,
+                    })?;
+                }
+                "trains" => pending = Some(map.next_value::<Value>()?),
+                _ => {
+                    let train = map.next_value()?;
+                    plain.push(key.clone());
+                    progress.add(&mut trains, key, train)?;
+                }
+            }
+        }
+        if let Some(value) = pending {
+            if version.is_some() {
+                has_trains = true;
+                let pending: HashMap<String, Train> =
+                    serde_json::from_value(value).map_err(A::Error::custom)?;
+                for (train_id, train) in pending {
+                    progress.add(&mut trains, train_id, train)?;
+                }
+            } else {
+                let train = serde_json::from_value(value).map_err(A::Error::custom)?;
+                progress.add(&mut trains, "trains".to_string(), train)?;
+            }
+        }
+        if version.is_some() {
+            if let Some(key) = plain.first() {
+                return Err(A::Error::unknown_field(key, &["version", "trains"]));
+            }
+            if !has_trains {
+                return Err(A::Error::missing_field("trains"));
+            }
+        }
+        Ok(trains)
+    }
+}
+
+// the trains of a versioned document, added as they're read
+struct TrainsSeed<'a> {
+    progress: &'a mut Progress,
+    trains: &'a mut HashMap<TrainId, Train>,
+}
+
+impl<'de> DeserializeSeed<'de> for TrainsSeed<'_> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de> Visitor<'de> for TrainsSeed<'_> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a map of trains")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        while let Some(train_id) = map.next_key::<String>()? {
+            let train = map.next_value()?;
+            self.progress.add(self.trains, train_id, train)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(json: &str) -> Result<TrainsData, serde_json::Error> {
+        read_trains(json.as_bytes(), None)
+    }
+
+    #[test]
+    fn test_read_plain_and_versioned() {
+        let seats = r#"{"seats": {"1A": {"coach": "A", "seat_number": "1"}}}"#;
+        let plain = read(&format!(r#"{{"t": {0}, "version": {0}}}"#, seats)).unwrap();
+        assert!(plain.get(&TrainId::new("t")).is_some());
+        assert!(plain.get(&TrainId::new("version")).is_some());
+
+        let versioned = read(&format!(
+            r#"{{"version": 2, "trains": {{"t": {}}}}}"#,
+            seats
+        ));
+        assert_eq!(
+            versioned.unwrap(),
+            read(&format!(r#"{{"t": {}}}"#, seats)).unwrap()
+        );
+        // the version may come last
+        let versioned = read(&format!(
+            r#"{{"trains": {{"t": {}}}, "version": 2}}"#,
+            seats
+        ));
+        assert!(versioned.unwrap().get(&TrainId::new("t")).is_some());
+        // without a version, "trains" is just a train
+        let plain = read(&format!(r#"{{"trains": {}}}"#, seats)).unwrap();
+        assert!(plain.get(&TrainId::new("trains")).is_some());
+    }
+
+    #[test]
+    fn test_read_invalid() {
+        let err = read(r#"{"version": 9, "trains": {}}"#).unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("unknown version 9, expected at most 2"));
+        assert!(read(r#"{"version": 2}"#).is_err());
+        assert!(read(r#"{"version": 2, "trains": {}, "t": {"seats": {}}}"#).is_err());
+        assert!(read(r#"{"t": {"seats": {}}} trailing"#).is_err());
+    }
+
+    #[test]
+    fn test_read_many_trains() {
+        let trains: Vec<String> = (0..2500)
+            .map(|index| format!(r#""t{}": {{"seats": {{}}}}"#, index))
+            .collect();
+        let json = format!(r#"{{"version": 2, "trains": {{{}}}}}"#, trains.join(","));
+        assert_eq!(read(&json).unwrap().iter().count(), 2500);
+        let err = read_trains(json.as_bytes(), Some(Duration::ZERO)).unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("loading trains took longer than"));
+    }
+}
@@ -0,0 +1,162 @@
+//! Discount codes, managed by admins and given by clients when they ask for
+//! a quote or make a booking.
+
+use std::collections::BTreeMap;
+
+use crate::train::Error;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Discount {
+    /// A percentage off the total.
+    Percent(u64),
+    /// An amount off the total, in cents.
+    Fixed(u64),
+}
+
+impl Discount {
+    /// How much comes off a total, in cents; never more than the total.
+    pub fn amount(&self, total: u64) -> u64 {
+        match self {
+            Discount::Percent(percent) => total * percent / 100,
+            Discount::Fixed(amount) => *amount.min(&total),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Voucher {
+    pub discount: Discount,
+    /// How often the voucher can be used; there's no limit if not given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_uses: Option<usize>,
+    #[serde(default)]
+    pub uses: usize,
+    /// When the voucher stops being valid, in seconds since the Unix epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+}
+
+/// The vouchers, by code.
+#[derive(Debug, Default, PartialEq, Eq, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Vouchers(BTreeMap<String, Voucher>);
+
+impl Vouchers {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn insert(&mut self, code: String, voucher: Voucher) -> Result<(), Error> {
+        if let Discount::Percent(percent) = voucher.discount {
+            if percent > 100 {
+                return Err(Error::InvalidVoucher(
+                    code,
+                    format!("can't take off more than 100%, not {}%", percent),
+                ));
+            }
+        }
+        self.0.insert(code, voucher);
+        Ok(())
+    }
+
+    pub fn remove(&mut self, code: &str) -> Result<(), Error> {
+        self.0
+            .remove(code)
+            .map(|_| ())
+            .ok_or_else(|| Error::VoucherDoesNotExist(code.to_string()))
+    }
+
+    /// The discount of a voucher, if it can be used at time `now`.
+    pub fn check(&self, code: &str, now: u64) -> Result<Discount, Error> {
+        let invalid = |reason: &str| Error::InvalidVoucher(code.to_string(), reason.to_string());
+        let voucher = self.0.get(code).ok_or_else(|| invalid("does not exist"))?;
+        if voucher
+            .expires_at
+            .is_some_and(|expires_at| now >= expires_at)
+        {
+            return Err(invalid("has expired"));
+        }
+        if voucher
+            .max_uses
+            .is_some_and(|max_uses| voucher.uses >= max_uses)
+        {
+            return Err(invalid("has been used up"));
+        }
+        Ok(voucher.discount)
+    }
+
+    /// Count a use of a voucher that was checked.
+    pub fn redeem(&mut self, code: &str) {
+        if let Some(voucher) = self.0.get_mut(code) {
+            voucher.uses += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vouchers() -> Vouchers {
+        let mut vouchers = Vouchers::default();
+        vouchers
+            .insert(
+                "SPRING".to_string(),
+                Voucher {
+                    discount: Discount::Percent(10),
+                    max_uses: Some(1),
+                    uses: 0,
+                    expires_at: Some(1000),
+                },
+            )
+            .unwrap();
+        vouchers
+    }
+
+    #[test]
+    fn test_discount_amount() {
+        assert_eq!(Discount::Percent(10).amount(4500), 450);
+        assert_eq!(Discount::Fixed(500).amount(4500), 500);
+        assert_eq!(Discount::Fixed(5000).amount(4500), 4500);
+    }
+
+    #[test]
+    fn test_check_and_redeem() {
+        let mut vouchers = vouchers();
+        assert_eq!(vouchers.check("SPRING", 999), Ok(Discount::Percent(10)));
+        assert_eq!(
+            vouchers.check("SPRING", 1000),
+            Err(Error::InvalidVoucher(
+                "SPRING".to_string(),
+                "has expired".to_string()
+            ))
+        );
+        vouchers.redeem("SPRING");
+        assert_eq!(
+            vouchers.check("SPRING", 999),
+            Err(Error::InvalidVoucher(
+                "SPRING".to_string(),
+                "has been used up".to_string()
+            ))
+        );
+        assert!(vouchers.check("AUTUMN", 0).is_err());
+    }
+
+    #[test]
+    fn test_insert_invalid() {
+        let mut vouchers = Vouchers::default();
+        let voucher = Voucher {
+            discount: Discount::Percent(150),
+            max_uses: None,
+            uses: 0,
+            expires_at: None,
+        };
+        assert!(vouchers.insert("TOO_MUCH".to_string(), voucher).is_err());
+        assert!(vouchers.is_empty());
+        assert_eq!(
+            vouchers.remove("TOO_MUCH"),
+            Err(Error::VoucherDoesNotExist("TOO_MUCH".to_string()))
+        );
+    }
+}
@@ -0,0 +1,205 @@
+//! Scenarios that go through more than one service.
+
+mod harness;
+
+use std::collections::BTreeSet;
+
+use axum::http::StatusCode;
+use serde_json::{json, Value};
+
+use train_service::api_keys::ApiKeys;
+use train_service::http_client::ClientError;
+use train_service::rest::AppState;
+use train_service::ticket_office::{Booking, BookingRequest};
+
+use harness::Services;
+
+fn request(train_id: &str, seat_count: usize) -> BookingRequest {
+    BookingRequest {
+        train_id: train_id.to_string(),
+        seat_count,
+    }
+}
+
+#[tokio::test]
+async fn test_ticket_office_reserves_on_train_service() {
+    let services = Services::start(AppState::new())
+        .await
+        .with_ticket_office()
+        .await;
+
+    let booking: Booking = services
+        .client
+        .post_json(
+            &services.ticket_office("/reserve"),
+            &request("express_2000", 2),
+        )
+        .await
+        .unwrap();
+    let booking_reference = booking.booking_reference.unwrap();
+
+    let train: Value = services
+        .client
+        .get_json(&services.train_service("/train/express_2000"))
+        .await
+        .unwrap();
+    for seat in &booking.seats {
+        assert_eq!(train["seats"][seat]["booking_reference"], booking_reference);
+    }
+}
+
+#[tokio::test]
+async fn test_concurrent_bookings_keep_to_the_rules() {
+    let services = Services::start(AppState::new())
+        .await
+        .with_ticket_office()
+        .await;
+
+    let url = services.ticket_office("/reserve");
+    let request = request("local_1000", 2);
+    let bookings = (0..8).map(|_| services.client.post_json::<_, Booking>(&url, &request));
+    let bookings = futures_util::future::join_all(bookings).await;
+
+    let mut reserved = BTreeSet::new();
+    for booking in bookings {
+        for seat in booking.unwrap().seats {
+            assert!(reserved.insert(seat.clone()), "{} reserved twice", seat);
+        }
+    }
+    // no more than 70% of the 16 seats
+    assert!(reserved.len() <= 11, "{} seats reserved", reserved.len());
+    let train: Value = services
+        .client
+        .get_json(&services.train_service("/train/local_1000"))
+        .await
+        .unwrap();
+    let taken = train["seats"]
+        .as_object()
+        .unwrap()
+        .values()
+        .filter(|seat| !seat["booking_reference"].is_null())
+        .count();
+    assert_eq!(taken, reserved.len());
+}
+
+#[tokio::test]
+async fn test_ticket_office_passes_on_errors() {
+    let services = Services::start(AppState::new())
+        .await
+        .with_ticket_office()
+        .await;
+
+    let result = services
+        .client
+        .post_json::<_, Booking>(
+            &services.ticket_office("/reserve"),
+            &request("does_not_exist", 1),
+        )
+        .await;
+    assert!(matches!(
+        result,
+        Err(ClientError::Status(StatusCode::NOT_FOUND, _))
+    ));
+
+    let booking: Booking = services
+        .client
+        .post_json(
+            &services.ticket_office("/reserve"),
+            &request("local_1000", 12),
+        )
+        .await
+        .unwrap();
+    assert_eq!(booking.booking_reference, None);
+    assert!(booking.seats.is_empty());
+}
+
+#[tokio::test]
+async fn test_all_in_one() {
+    let services = Services::all_in_one(AppState::new()).await;
+
+    let booking: Booking = services
+        .client
+        .post_json(
+            &services.ticket_office("/reserve"),
+            &request("express_2000", 3),
+        )
+        .await
+        .unwrap();
+    assert_eq!(booking.seats.len(), 3);
+    let train: Value = services
+        .client
+        .get_json(&services.train_service("/train/express_2000"))
+        .await
+        .unwrap();
+    assert_eq!(
+        train["seats"][&booking.seats[0]]["booking_reference"],
+        json!(booking.booking_reference)
+    );
+    // the service's own routes and fallback are still there
+    let result = services
+        .client
+        .get_json::<Value>(&services.train_service("/does_not_exist"))
+        .await;
+    assert!(matches!(
+        result,
+        Err(ClientError::Status(StatusCode::NOT_FOUND, _))
+    ));
+}
+
+#[tokio::test]
+async fn test_all_in_one_with_api_keys() {
+    let api_keys: ApiKeys = serde_json::from_value(json!({
+        "agency-secret": { "name": "agency", "seat_quota": 4 },
+    }))
+    .unwrap();
+    let services = Services::all_in_one(AppState::new().with_api_keys(Some(api_keys))).await;
+
+    // the ticket office is behind the same middleware as the service
+    let result = services
+        .client
+        .post_json::<_, Booking>(
+            &services.ticket_office("/reserve"),
+            &request("express_2000", 3),
+        )
+        .await;
+    assert!(matches!(
+        result,
+        Err(ClientError::Status(StatusCode::UNAUTHORIZED, _))
+    ));
+
+    // and passes the key on, so bookings count against its quota
+    let client = services.client.clone().with_api_key("agency-secret");
+    let booking: Booking = client
+        .post_json(
+            &services.ticket_office("/reserve"),
+            &request("express_2000", 3),
+        )
+        .await
+        .unwrap();
+    assert_eq!(booking.seats.len(), 3);
+    let result = client
+        .post_json::<_, Booking>(
+            &services.ticket_office("/reserve"),
+            &request("express_2000", 2),
+        )
+        .await;
+    assert!(matches!(
+        result,
+        Err(ClientError::Status(StatusCode::TOO_MANY_REQUESTS, _))
+    ));
+}
+
+#[tokio::test]
+async fn test_booking_references_are_unique() {
+    let services = Services::start(AppState::new()).await;
+
+    let mut references = BTreeSet::new();
+    for _ in 0..5 {
+        let reference: String = services
+            .client
+            .post_json(&services.train_service("/booking_reference"), &json!(null))
+            .await
+            .unwrap();
+        assert!(references.insert(reference));
+    }
+}
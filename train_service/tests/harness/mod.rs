@@ -0,0 +1,86 @@
+//! Services started in the test process, each on a port of its own, so
+//! scenarios can go through HTTP from one service to the other like they do
+//! when deployed. Booking references come from the train service, like they
+//! do in the kata.
+
+use std::time::Duration;
+
+use axum::Router;
+
+use train_service::http_client::{HttpClient, Retry};
+use train_service::rest::{self, AppState};
+use train_service::ticket_office::TicketOffice;
+
+pub struct Services {
+    /// The URL of the train service.
+    pub train_service: String,
+    /// The URL of the ticket office, if it was started.
+    pub ticket_office: Option<String>,
+    /// A client for the scenarios to make requests with.
+    pub client: HttpClient,
+}
+
+impl Services {
+    /// Start the train service with this state.
+    pub async fn start(state: AppState) -> Self {
+        Services {
+            train_service: serve(rest::app(state)).await,
+            ticket_office: None,
+            client: client(),
+        }
+    }
+
+    /// Start the train service with the ticket office on the same port, like
+    /// `--all-in-one` does.
+    pub async fn all_in_one(state: AppState) -> Self {
+        let listener = bind().await;
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let router = rest::all_in_one(state, url.clone());
+        tokio::spawn(async move { axum::serve(listener, router).await });
+        Services {
+            train_service: url.clone(),
+            ticket_office: Some(format!("{}/ticket_office", url)),
+            client: client(),
+        }
+    }
+
+    /// Start a ticket office too, which uses the train service.
+    pub async fn with_ticket_office(self) -> Self {
+        let office = TicketOffice::new(client(), self.train_service.clone());
+        Services {
+            ticket_office: Some(serve(office.routes()).await),
+            ..self
+        }
+    }
+
+    pub fn train_service(&self, path: &str) -> String {
+        format!("{}{}", self.train_service, path)
+    }
+
+    pub fn ticket_office(&self, path: &str) -> String {
+        let url = self
+            .ticket_office
+            .as_ref()
+            .expect("the ticket office wasn't started");
+        format!("{}{}", url, path)
+    }
+}
+
+// the errors are part of the scenarios, so don't hide them by trying again
+fn client() -> HttpClient {
+    HttpClient::new(Duration::from_secs(5)).with_retry(Retry::never())
+}
+
+// serve a router on a port of its own, returning its URL
+async fn serve(router: Router) -> String {
+    let listener = bind().await;
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, router).await });
+    format!("http://{}", address)
+}
+
+async fn bind() -> tokio::net::TcpListener {
+    tokio::net::TcpListener::bind(("127.0.0.1", 0))
+        .await
+        .unwrap()
+}